@@ -0,0 +1,48 @@
+#let title = "{{title}}"
+#let authors = "{{authors}}"
+#let affiliations = "{{affiliations}}"
+#let abstract = "{{abstract}}"
+
+#set document(title: title, author: authors)
+#set page(
+    paper: "{{paper}}",
+    numbering : "1"
+)
+#set text(font: "{{font}}")
+#set cite(style: "author-date")
+#set bibliography(style: "{{citation-style}}", title: "References")
+#set align(center)
+#text(size: 24pt, weight: 700, title)
+#linebreak()
+#text(authors)
+#linebreak()
+#text(size: 10pt, style: "italic", affiliations)
+
+#block(width : 75%)[
+    #set par(justify: true, leading : 0.32em)
+    #set pad(left : 20pt, right : 20pt)
+= Abstract
+#abstract
+]
+
+*Keywords* #lorem(4)
+
+#set align(left)
+
+#show: columns
+
+= Introduction
+
+#lorem(500)
+
+= Methods
+
+#lorem(500)
+
+= Results
+
+#lorem(500)
+
+= Discussion
+
+#lorem(1000)
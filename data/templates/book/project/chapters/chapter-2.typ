@@ -0,0 +1,3 @@
+= Second chapter
+
+#lorem(600)
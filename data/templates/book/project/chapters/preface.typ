@@ -0,0 +1,3 @@
+= Preface
+
+#lorem(150)
@@ -0,0 +1,14 @@
+#import "style.typ": book
+
+#show: book.with(
+    title: "{{title}}",
+    author: "{{author}}"
+)
+
+#outline()
+
+#include "chapters/preface.typ"
+#include "chapters/chapter-1.typ"
+#include "chapters/chapter-2.typ"
+
+#bibliography("references.bib")
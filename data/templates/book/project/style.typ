@@ -0,0 +1,25 @@
+// Style shared by all files of the document. Edit it to change the layout of every chapter.
+
+#let book(title: "", author: "", body) = {
+    set document(title: title, author: author)
+    set page(paper: "{{paper}}", margin: (inside: 3cm, outside: 2cm, y: 2.5cm), numbering: "1")
+    set text(font: "{{font}}")
+    set par(justify: true)
+    set bibliography(style: "{{citation-style}}")
+    set heading(numbering: "1.1")
+    show heading.where(level: 1): it => {
+        pagebreak(weak: true)
+        v(64pt)
+        text(size: 24pt, it)
+        v(24pt)
+    }
+    align(center + horizon)[
+        #text(size: 32pt, weight: 700, title)
+
+        #v(24pt)
+        #text(size: 16pt, author)
+    ]
+    pagebreak()
+    pagebreak()
+    body
+}
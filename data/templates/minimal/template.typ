@@ -0,0 +1,3 @@
+#set page(paper: "a4", margin: 2.0cm)
+#set par(leading : 0.98em, first-line-indent : 15pt)
+#set text(font : "Liberation Serif", style : "normal", weight : "regular", size : 12pt)
@@ -0,0 +1,30 @@
+#let title = "{{title}}"
+#let author = "{{authors}}"
+#set page(
+    paper: "presentation-16-9",
+    margin: 2.0cm,
+    numbering : "1",
+    number-align:right
+)
+#set text(size : 18pt, font : "Liberation Sans")
+#show heading.where(level : 1) : head => {
+    pagebreak();
+    head
+    linebreak();
+}
+
+#set align(center + horizon)
+
+#text(size : 26pt, weight : "bold", title)
+
+#author
+
+#set align(left + top)
+
+= First slide
+
+#lorem(20)
+
+= Second slide
+
+#lorem(20)
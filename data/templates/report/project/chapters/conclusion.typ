@@ -0,0 +1,3 @@
+= Conclusion
+
+#lorem(200)
@@ -0,0 +1,6 @@
+= Introduction
+
+#lorem(300) @knuth1984
+
+// Figures are kept at the figures folder:
+// #figure(image("../figures/plot.png"), caption: [A figure.])
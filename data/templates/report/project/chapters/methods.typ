@@ -0,0 +1,3 @@
+= Methods
+
+#lorem(300)
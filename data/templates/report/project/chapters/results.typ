@@ -0,0 +1,3 @@
+= Results
+
+#lorem(300)
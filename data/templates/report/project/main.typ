@@ -0,0 +1,15 @@
+#import "style.typ": report
+
+#show: report.with(
+    title: "{{title}}",
+    author: "{{author}}"
+)
+
+#outline()
+
+#include "chapters/introduction.typ"
+#include "chapters/methods.typ"
+#include "chapters/results.typ"
+#include "chapters/conclusion.typ"
+
+#bibliography("references.bib")
@@ -0,0 +1,21 @@
+// Style shared by all files of the document. Edit it to change the layout of every chapter.
+
+#let report(title: "", author: "", body) = {
+    set document(title: title, author: author)
+    set page(paper: "{{paper}}", margin: 2.5cm, numbering: "1")
+    set text(font: "{{font}}")
+    set par(justify: true)
+    set bibliography(style: "{{citation-style}}")
+    set heading(numbering: "1.1")
+    show heading.where(level: 1): it => {
+        pagebreak(weak: true)
+        it
+    }
+    align(center + horizon)[
+        #text(size: 24pt, weight: 700, title)
+
+        #text(size: 14pt, author)
+    ]
+    pagebreak()
+    body
+}
@@ -0,0 +1 @@
+#lorem(250)
@@ -0,0 +1,3 @@
+= Discussion
+
+#lorem(500)
@@ -0,0 +1,3 @@
+= Literature review
+
+#lorem(600)
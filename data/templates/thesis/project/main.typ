@@ -0,0 +1,21 @@
+#import "style.typ": thesis
+
+#show: thesis.with(
+    title: "{{title}}",
+    author: "{{author}}",
+    affiliation: "{{affiliation}}"
+)
+
+#heading(outlined: false, numbering: none)[Abstract]
+
+#include "chapters/abstract.typ"
+
+#outline()
+
+#include "chapters/introduction.typ"
+#include "chapters/literature-review.typ"
+#include "chapters/methods.typ"
+#include "chapters/results.typ"
+#include "chapters/discussion.typ"
+
+#bibliography("references.bib")
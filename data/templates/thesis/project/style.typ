@@ -0,0 +1,29 @@
+// Style shared by all files of the document. Edit it to change the layout of every chapter.
+
+#let thesis(title: "", author: "", affiliation: "", body) = {
+    set document(title: title, author: author)
+    set page(paper: "{{paper}}", margin: (left: 3cm, right: 2cm, y: 2.5cm), numbering: "1")
+    set text(font: "{{font}}")
+    set par(justify: true)
+    set bibliography(style: "{{citation-style}}")
+    set heading(numbering: "1.1")
+    show heading.where(level: 1): it => {
+        pagebreak(weak: true)
+        v(64pt)
+        text(size: 24pt, it)
+        v(24pt)
+    }
+    align(center + horizon)[
+        #text(size: 24pt, weight: 700, title)
+
+        #v(24pt)
+        #text(size: 14pt, author)
+
+        #text(size: 12pt, affiliation)
+
+        #v(48pt)
+        A thesis submitted in partial fulfillment of the requirements for the degree of Doctor of Philosophy
+    ]
+    pagebreak()
+    body
+}
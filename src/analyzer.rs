@@ -37,7 +37,10 @@ pub enum AnalyzerAction {
     // Item selected from the left sidebar. Calculate char position from byte offset at current
     // document model. Then calculate line from char offset. Propagate line to editor, so the
     // mark can be positioned there.
-    ItemSelected(Vec<usize>)
+    ItemSelected(Vec<usize>),
+
+    // Stops the bibliography thread, when the document is closed.
+    Stop
 
 }
 
@@ -221,6 +224,10 @@ impl Analyzer {
                     AnalyzerAction::BibError(e) => {
                         on_doc_error.call(TexError { msg : e, line : 0 });
                     },
+                    AnalyzerAction::Stop => {
+                        // Dropping the bibliography sender ends its thread.
+                        return Continue(false);
+                    },
                     AnalyzerAction::ItemSelected(sel_ixs) => {
 
                         if let Some(line) = doc.get_line(&sel_ixs[..]) {
@@ -245,6 +252,11 @@ impl Analyzer {
         }
     }
 
+    /// Stops the bibliography thread. The analyzer does not respond to changes after this.
+    pub fn shutdown(&self) {
+        let _ = self.send.send(AnalyzerAction::Stop);
+    }
+
     pub fn connect_section_changed<F>(&self, f : F)
     where
         F : Fn(Difference) + 'static
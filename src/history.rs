@@ -0,0 +1,339 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::glib;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::hash::Hasher;
+use std::io::Write;
+use std::sync::{Mutex, mpsc};
+use once_cell::sync::Lazy;
+
+/*
+Local revision history. Each document has a directory under the app data dir
+(history/<hash of the document path>) holding one snapshot per save, named
+<milliseconds since the UNIX epoch>-<counter>.typ (the counter tells apart snapshots
+taken within the same millisecond). The word count of each snapshot is kept next to
+it, at <name>.words, so listing the history does not read the snapshots themselves.
+Saving and listing run in order at a background thread (see queue_save and queue_list),
+so the main loop never waits for the disk.
+*/
+
+// Older snapshots are removed when a document has more than this.
+pub const MAX_SNAPSHOTS : usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+
+    pub path : PathBuf,
+
+    // Milliseconds since the UNIX epoch.
+    pub time : u64,
+
+    pub words : usize
+
+}
+
+impl Snapshot {
+
+    pub fn content(&self) -> Option<String> {
+        std::fs::read_to_string(&self.path).ok()
+    }
+
+}
+
+enum Job {
+    Save(PathBuf, String),
+    SaveFile(PathBuf),
+    List(PathBuf, glib::Sender<Vec<Snapshot>>)
+}
+
+static WORKER : Lazy<Mutex<mpsc::Sender<Job>>> = Lazy::new(|| {
+    let (send, recv) = mpsc::channel::<Job>();
+    std::thread::spawn(move || {
+        for job in recv {
+            match job {
+                Job::Save(doc, content) => {
+                    save_snapshot(&doc, &content);
+                },
+                Job::SaveFile(doc) => {
+                    match std::fs::read_to_string(&doc) {
+                        Ok(content) => {
+                            save_snapshot(&doc, &content);
+                        },
+                        Err(e) => {
+                            log::warn!("Unable to read saved file for snapshot: {}", e);
+                        }
+                    }
+                },
+                Job::List(doc, send) => {
+                    let _ = send.send(list_snapshots(&doc));
+                }
+            }
+        }
+    });
+    Mutex::new(send)
+});
+
+fn queue(job : Job) {
+    if let Ok(send) = WORKER.lock() {
+        if let Err(e) = send.send(job) {
+            log::warn!("History worker is not running: {}", e);
+        }
+    }
+}
+
+/// Saves a snapshot of the document content at the history thread.
+pub fn queue_save(doc : &Path, content : String) {
+    queue(Job::Save(doc.to_owned(), content));
+}
+
+/// Saves a snapshot of the document as it is on disk at the history thread.
+pub fn queue_save_file(doc : &Path) {
+    queue(Job::SaveFile(doc.to_owned()));
+}
+
+/// Lists the snapshots of the document at the history thread, sending them back when done.
+/// Snapshots queued before are already listed.
+pub fn queue_list(doc : &Path, send : glib::Sender<Vec<Snapshot>>) {
+    queue(Job::List(doc.to_owned(), send));
+}
+
+pub fn history_dir(doc : &Path) -> Option<PathBuf> {
+    let doc = doc.canonicalize().unwrap_or(doc.to_owned());
+    let mut hasher = siphasher::sip::SipHasher::new();
+    hasher.write(doc.to_string_lossy().as_bytes());
+    let mut dir = filecase::get_datadir(crate::APP_ID)?;
+    dir.push("history");
+    dir.push(format!("{:016x}", hasher.finish()));
+    Some(dir)
+}
+
+// Time (in milliseconds) and counter of a snapshot file stem. Snapshots of older versions
+// are named after the time in seconds only.
+fn snapshot_key(stem : &str) -> Option<(u64, u32)> {
+    match stem.split_once('-') {
+        Some((time, counter)) => Some((time.parse().ok()?, counter.parse().ok()?)),
+        None => Some((stem.parse::<u64>().ok()? * 1000, 0))
+    }
+}
+
+// Snapshot files of the directory, from the most recent to the oldest.
+fn snapshot_files(dir : &Path) -> Vec<(PathBuf, u64, u32)> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut files : Vec<_> = entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "typ" {
+            return None;
+        }
+        let (time, counter) = snapshot_key(path.file_stem()?.to_str()?)?;
+        Some((path, time, counter))
+    }).collect();
+    files.sort_by(|a, b| (b.1, b.2).cmp(&(a.1, a.2)) );
+    files
+}
+
+fn words_path(snapshot : &Path) -> PathBuf {
+    snapshot.with_extension("words")
+}
+
+/// Snapshots of the document, from the most recent to the oldest.
+pub fn list_snapshots(doc : &Path) -> Vec<Snapshot> {
+    let Some(dir) = history_dir(doc) else { return Vec::new() };
+    snapshot_files(&dir).into_iter().map(|(path, time, _)| {
+        let words = std::fs::read_to_string(words_path(&path)).ok()
+            .and_then(|w| w.trim().parse().ok() )
+            .unwrap_or_else(|| {
+                let words = std::fs::read_to_string(&path).map(|txt| word_count(&txt) ).unwrap_or(0);
+                let _ = std::fs::write(words_path(&path), words.to_string());
+                words
+            });
+        Snapshot { path, time, words }
+    }).collect()
+}
+
+/// Saves a snapshot of the document content, unless it is equal to the last snapshot.
+pub fn save_snapshot(doc : &Path, content : &str) -> Option<Snapshot> {
+    let dir = history_dir(doc)?;
+    let files = snapshot_files(&dir);
+    if files.first().and_then(|(path, _, _)| std::fs::read_to_string(path).ok() ).as_deref() == Some(content) {
+        return None;
+    }
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::warn!("Unable to create history directory: {}", e);
+        return None;
+    }
+    let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok()?.as_millis() as u64;
+    let mut counter = 0;
+    let (path, mut file) = loop {
+        let path = dir.join(format!("{}-{}.typ", time, counter));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => break (path, file),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => {
+                log::warn!("Unable to create snapshot: {}", e);
+                return None;
+            }
+        }
+    };
+    if let Err(e) = file.write_all(content.as_bytes()) {
+        log::warn!("Unable to write snapshot: {}", e);
+        return None;
+    }
+    let words = word_count(content);
+    if let Err(e) = std::fs::write(words_path(&path), words.to_string()) {
+        log::warn!("Unable to write snapshot word count: {}", e);
+    }
+    for (old, _, _) in files.iter().skip(MAX_SNAPSHOTS - 1) {
+        let _ = std::fs::remove_file(old);
+        let _ = std::fs::remove_file(words_path(old));
+    }
+    Some(Snapshot { path, time, words })
+}
+
+pub fn word_count(txt : &str) -> usize {
+    txt.split_whitespace().filter(|w| w.chars().any(|c| c.is_alphanumeric() ) ).count()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String)
+}
+
+// Consecutive lines that differ between two versions. Lines are counted from zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old_start : usize,
+    pub old_lines : Vec<String>,
+    pub new_start : usize,
+    pub new_lines : Vec<String>
+}
+
+// Above this number of compared line pairs, the changed region is taken as fully replaced.
+const MAX_DIFF_CELLS : usize = 4_000_000;
+
+/// Line-level diff from the old to the new text (longest common subsequence
+/// of the lines after the common prefix and suffix are removed).
+pub fn diff_lines(old : &str, new : &str) -> Vec<DiffLine> {
+    let old : Vec<&str> = old.lines().collect();
+    let new : Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b ).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b ).count();
+    let old_mid = &old[prefix..(old.len() - suffix)];
+    let new_mid = &new[prefix..(new.len() - suffix)];
+
+    let mut diff : Vec<DiffLine> = old[..prefix].iter().map(|l| DiffLine::Same(l.to_string()) ).collect();
+    if old_mid.len() * new_mid.len() > MAX_DIFF_CELLS {
+        diff.extend(old_mid.iter().map(|l| DiffLine::Removed(l.to_string()) ));
+        diff.extend(new_mid.iter().map(|l| DiffLine::Added(l.to_string()) ));
+    } else {
+        diff.extend(lcs_diff(old_mid, new_mid));
+    }
+    diff.extend(old[(old.len() - suffix)..].iter().map(|l| DiffLine::Same(l.to_string()) ));
+    diff
+}
+
+fn lcs_diff(old : &[&str], new : &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (old.len(), new.len());
+
+    // lcs[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            diff.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    diff
+}
+
+pub fn hunks(diff : &[DiffLine]) -> Vec<Hunk> {
+    let mut hunks : Vec<Hunk> = Vec::new();
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut in_hunk = false;
+    for line in diff {
+        if !matches!(line, DiffLine::Same(_)) && !in_hunk {
+            hunks.push(Hunk { old_start : old_line, old_lines : Vec::new(), new_start : new_line, new_lines : Vec::new() });
+        }
+        in_hunk = !matches!(line, DiffLine::Same(_));
+        match line {
+            DiffLine::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            },
+            DiffLine::Removed(l) => {
+                hunks.last_mut().unwrap().old_lines.push(l.clone());
+                old_line += 1;
+            },
+            DiffLine::Added(l) => {
+                hunks.last_mut().unwrap().new_lines.push(l.clone());
+                new_line += 1;
+            }
+        }
+    }
+    hunks
+}
+
+/// Replaces the new lines of the hunk in the text by its old lines.
+pub fn apply_hunk(txt : &str, hunk : &Hunk) -> String {
+    let mut lines : Vec<&str> = txt.lines().collect();
+    let start = hunk.new_start.min(lines.len());
+    let end = (hunk.new_start + hunk.new_lines.len()).min(lines.len());
+    lines.splice(start..end, hunk.old_lines.iter().map(|l| &l[..] ));
+    let mut out = lines.join("\n");
+    if txt.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn line_diff() {
+    let old = "a\nb\nc\nd";
+    let new = "a\nB\nc\nd\ne";
+    let diff = diff_lines(old, new);
+    assert_eq!(diff, vec![
+        DiffLine::Same("a".into()),
+        DiffLine::Removed("b".into()),
+        DiffLine::Added("B".into()),
+        DiffLine::Same("c".into()),
+        DiffLine::Same("d".into()),
+        DiffLine::Added("e".into())
+    ]);
+    let hunks = hunks(&diff);
+    assert_eq!(hunks.len(), 2);
+    assert_eq!(hunks[0], Hunk { old_start : 1, old_lines : vec!["b".into()], new_start : 1, new_lines : vec!["B".into()] });
+    assert_eq!(hunks[1], Hunk { old_start : 4, old_lines : vec![], new_start : 4, new_lines : vec!["e".into()] });
+    let restored = hunks.iter().rev().fold(new.to_string(), |txt, h| apply_hunk(&txt, h) );
+    assert_eq!(restored, old);
+    assert_eq!(word_count("= Title\nSome *words* here - 2"), 5);
+}
+
+#[test]
+fn snapshot_names() {
+    assert_eq!(snapshot_key("1700000000123-2"), Some((1700000000123, 2)));
+    assert_eq!(snapshot_key("1700000000"), Some((1700000000000, 0)));
+    assert_eq!(snapshot_key("notes"), None);
+}
@@ -15,6 +15,21 @@ pub const RESOURCE_PREFIX : &'static str = "io/github/limads/drafts";
 
 pub const SETTINGS_FILE : &'static str = "user.json";
 
+/// Seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).map(|d| d.as_secs() ).unwrap_or(0)
+}
+
+/// Name of the current user (e.g. for tracked changes, comments and new templates).
+pub fn author() -> String {
+    let name = glib::real_name().to_string_lossy().to_string();
+    if name.is_empty() || name == "Unknown" {
+        glib::user_name().to_string_lossy().to_string()
+    } else {
+        name
+    }
+}
+
 pub mod ui;
 
 pub mod manager;
@@ -29,14 +44,34 @@ pub mod state;
 
 pub mod typst_tools;
 
+pub mod renderer;
+
+pub mod history;
+
+pub mod recovery;
+
+pub mod vcs;
+
+pub mod review;
+
+pub mod templates;
+
+pub mod settings;
+
+pub mod lsp;
+
 use std::collections::HashMap;
 use gtk4::*;
 use gtk4::prelude::*;
 use gdk_pixbuf::Pixbuf;
 
 pub fn adjust_dimension_for_page(da : &DrawingArea, zoom_action : gio::SimpleAction, page : &poppler::Page) {
+    adjust_dimension_for_size(da, &zoom_action, page.size());
+}
+
+// Sets the area dimensions for a page of the given size (in points).
+pub fn adjust_dimension_for_size(da : &DrawingArea, zoom_action : &gio::SimpleAction, (w, h) : (f64, f64)) {
     let z = zoom_action.state().unwrap().get::<f64>().unwrap();
-    let (w, h) = page.size();
     let page_w = (w * z) as i32;
     let page_h = (h * z) as i32;
     da.set_width_request(page_w);
@@ -54,6 +89,19 @@ pub fn draw_page_content(
     ctx.save();
 
     let (w, h) = (da.allocation().width() as f64, da.allocation().height() as f64);
+    draw_page_background(ctx, w, h, draw_borders);
+
+    // Poppler always render with the same dpi from the physical page resolution. We must
+    // apply a scale to the context if we want the content to be scaled.
+    ctx.scale(z, z);
+
+    // TODO remove the transmute when GTK/cairo version match.
+    page.render(unsafe { std::mem::transmute::<_, _>(ctx) });
+
+    ctx.restore();
+}
+
+pub fn draw_page_background(ctx : &cairo::Context, w : f64, h : f64, draw_borders : bool) {
 
     // Draw white background of page
     ctx.set_source_rgb(1., 1., 1.);
@@ -82,15 +130,6 @@ pub fn draw_page_content(
 
         ctx.stroke();
     }
-
-    // Poppler always render with the same dpi from the physical page resolution. We must
-    // apply a scale to the context if we want the content to be scaled.
-    ctx.scale(z, z);
-
-    // TODO remove the transmute when GTK/cairo version match.
-    page.render(unsafe { std::mem::transmute::<_, _>(ctx) });
-
-    ctx.restore();
 }
 
 pub fn configure_da_for_doc(da : &DrawingArea) {
@@ -120,3 +159,72 @@ pub fn draw_page_at_area(doc : &poppler::Document, page_ix : i32, da : &DrawingA
 
     da.queue_draw();
 }
+
+/// Like draw_page_content, but the page is painted from the surfaces rendered in background
+/// by the page renderer instead of being rendered at the draw function.
+pub fn draw_rendered_page_content(
+    da : &DrawingArea,
+    ctx : &cairo::Context,
+    zoom_action : &gio::SimpleAction,
+    renderer : &renderer::PageRenderer,
+    page_ix : usize,
+    draw_borders : bool
+) {
+    let z = zoom_action.state().unwrap().get::<f64>().unwrap();
+    ctx.save();
+    let (w, h) = (da.allocation().width() as f64, da.allocation().height() as f64);
+    draw_page_background(ctx, w, h, draw_borders);
+    renderer.draw_page(ctx, page_ix, z);
+    renderer.filter().apply(ctx, w, h);
+    ctx.restore();
+}
+
+/// Like draw_page_at_area, but the page content is only requested to the renderer while
+/// the area is visible at the scrolled window. Other pages are drawn as blank pages of the same
+/// size, so that long documents can be laid out without rendering all their pages.
+pub fn draw_visible_page_at_area(
+    size : (f64, f64),
+    page_ix : usize,
+    is_last : bool,
+    da : &DrawingArea,
+    zoom_action : &gio::SimpleAction,
+    scroll : &ScrolledWindow,
+    renderer : &renderer::PageRenderer,
+    highlights : &ui::Highlights
+) {
+    configure_da_for_doc(&da);
+    if is_last {
+        da.set_margin_bottom(16);
+    }
+    adjust_dimension_for_size(da, zoom_action, size);
+
+    da.set_draw_func({
+        let zoom_action = zoom_action.clone();
+        let scroll = scroll.clone();
+        let renderer = renderer.clone();
+        let highlights = highlights.clone();
+        move |da, ctx, _, _| {
+            adjust_dimension_for_size(da, &zoom_action, size);
+            if is_visible_at(da, &scroll) {
+                draw_rendered_page_content(da, ctx, &zoom_action, &renderer, page_ix, true);
+                let z = zoom_action.state().unwrap().get::<f64>().unwrap();
+                highlights.draw(ctx, page_ix, z);
+            } else {
+                let (w, h) = (da.allocation().width() as f64, da.allocation().height() as f64);
+                ctx.save();
+                draw_page_background(ctx, w, h, true);
+                renderer.filter().apply(ctx, w, h);
+                ctx.restore();
+            }
+        }
+    });
+
+    da.queue_draw();
+}
+
+fn is_visible_at(da : &DrawingArea, scroll : &ScrolledWindow) -> bool {
+    match da.compute_bounds(scroll) {
+        Some(bounds) => bounds.y() + bounds.height() >= 0.0 && bounds.y() <= scroll.height() as f32,
+        None => false
+    }
+}
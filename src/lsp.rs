@@ -0,0 +1,585 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use once_cell::sync::Lazy;
+use crate::typst_tools::{self, Fonts};
+use crate::typst_tools::complete;
+use crate::tex::{BibParser, Item, Object};
+
+/*
+Language server mode (drafts lsp), so typst documents can be edited in other editors with
+the same support Drafts gives. Messages are JSON-RPC over stdin/stdout, framed by a
+Content-Length header, and are handled one at a time:
+
+- Syntax errors are published as diagnostics at each change, and compilation errors
+(as the typesetter reports them) when the document is opened or saved. Compilation
+errors raised at included files are published at those files.
+- Document symbols are the sections and objects of the document tree (as the sidebar shows).
+- References after @ complete to labels of the project and to keys of its bibliographies.
+- Go to definition finds labels, bibliography entries and let bindings.
+
+Open documents are kept in memory, and the other files of the project are read from disk.
+The list of files of each project and the text of the files not open at the client are
+cached, and discarded when a document is opened or saved.
+*/
+
+const SERVER_NAME : &'static str = "drafts";
+
+// JSON-RPC error code for requests the server does not handle.
+const METHOD_NOT_FOUND : i64 = -32601;
+
+// LSP symbol and completion kinds used below.
+const SYMBOL_FILE : u32 = 1;
+
+const SYMBOL_STRING : u32 = 15;
+
+const SYMBOL_OBJECT : u32 = 19;
+
+const COMPLETION_REFERENCE : u32 = 18;
+
+static LABEL : Lazy<Regex> = Lazy::new(|| Regex::new(r"<([A-Za-z_][\w\-:.]*)>").unwrap() );
+
+/// Reads one message, or None when the input is closed.
+pub fn read_message(input : &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header") )?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e) )
+}
+
+pub fn write_message(output : &mut impl Write, msg : &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+pub fn uri_to_path(uri : &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut ix = 0;
+    while ix < bytes.len() {
+        if bytes[ix] == b'%' && ix + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[ix+1..ix+3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok() ) {
+                out.push(b);
+                ix += 3;
+                continue;
+            }
+        }
+        out.push(bytes[ix]);
+        ix += 1;
+    }
+    String::from_utf8(out).ok().map(PathBuf::from)
+}
+
+pub fn path_to_uri(path : &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri += &format!("%{:02X}", b);
+        }
+    }
+    uri
+}
+
+// Positions are sent as UTF-16 offsets within the line.
+
+fn utf16_col(line : &str, byte : usize) -> usize {
+    line[..byte.min(line.len())].encode_utf16().count()
+}
+
+fn byte_col(line : &str, utf16 : usize) -> usize {
+    let mut count = 0;
+    for (ix, c) in line.char_indices() {
+        if count >= utf16 {
+            return ix;
+        }
+        count += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(line : usize, start : usize, end : usize) -> Value {
+    json!({ "start" : { "line" : line, "character" : start }, "end" : { "line" : line, "character" : end } })
+}
+
+fn line_range(text : &str, line : usize) -> Value {
+    let len = text.lines().nth(line).map(|l| l.encode_utf16().count() ).unwrap_or(0);
+    range(line, 0, len)
+}
+
+/// A place something is defined at: file, line and byte range within the line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub path : PathBuf,
+    pub line : usize,
+    pub start : usize,
+    pub end : usize
+}
+
+impl Definition {
+
+    fn location(&self, text : &str) -> Value {
+        let line = text.lines().nth(self.line).unwrap_or("");
+        json!({
+            "uri" : path_to_uri(&self.path),
+            "range" : range(self.line, utf16_col(line, self.start), utf16_col(line, self.end))
+        })
+    }
+
+}
+
+/// Labels defined at a typst source (e.g. = Introduction <intro>).
+pub fn labels(path : &Path, src : &str) -> Vec<(String, Definition)> {
+    let mut out = Vec::new();
+    for (ix, line) in src.lines().enumerate() {
+        if line.trim_start().starts_with("//") {
+            continue;
+        }
+        for cap in LABEL.captures_iter(line) {
+            let m = cap.get(1).unwrap();
+            out.push((m.as_str().to_string(), Definition { path : path.to_owned(), line : ix, start : m.start(), end : m.end() }));
+        }
+    }
+    out
+}
+
+/// Let bindings of a typst source with the place they are defined at.
+pub fn let_definitions(path : &Path, src : &str) -> Vec<(String, Definition)> {
+    complete::let_binding_offsets(src, "").into_iter().map(|(binding, pos)| {
+        let line_start = src[..pos].rfind('\n').map(|ix| ix + 1 ).unwrap_or(0);
+        let line = src[..pos].matches('\n').count();
+        let start = pos - line_start;
+        (binding.name.clone(), Definition { path : path.to_owned(), line, start, end : start + binding.name.len() })
+    }).collect()
+}
+
+/// Reference being written before the cursor: the prefix after @, or the key being
+/// written at a cite call. The flag is set when labels (and not only keys) are accepted.
+pub fn reference_prefix(before : &str) -> Option<(String, bool)> {
+    let is_key_char = |c : char| c.is_alphanumeric() || "_-:.".contains(c);
+    let start = before.char_indices().rev()
+        .take_while(|(_, c)| is_key_char(*c) )
+        .last()
+        .map(|(ix, _)| ix )
+        .unwrap_or(before.len());
+    let prefix = &before[start..];
+    let lead = &before[..start];
+    if lead.ends_with('@') {
+        let at_word = lead[..lead.len()-1].chars().last().map(|c| c.is_alphanumeric() ).unwrap_or(false);
+        if !at_word {
+            return Some((prefix.to_string(), true));
+        }
+    }
+    if lead.ends_with('<') && lead[..lead.len()-1].trim_end().ends_with("ref(") {
+        return Some((prefix.to_string(), true));
+    }
+    if lead.ends_with('"') && lead[..lead.len()-1].trim_end().ends_with("cite(") {
+        return Some((prefix.to_string(), false));
+    }
+    None
+}
+
+// Identifier around a byte offset of a line, with the character before it.
+fn word_at(line : &str, offset : usize) -> Option<(&str, Option<char>)> {
+    let is_word = |c : char| c.is_alphanumeric() || "_-:.".contains(c);
+    let offset = offset.min(line.len());
+    let start = line[..offset].char_indices().rev()
+        .take_while(|(_, c)| is_word(*c) )
+        .last()
+        .map(|(ix, _)| ix )
+        .unwrap_or(offset);
+    let end = line[offset..].char_indices()
+        .find(|(_, c)| !is_word(*c) )
+        .map(|(ix, _)| offset + ix )
+        .unwrap_or(line.len());
+    let word = line[start..end].trim_end_matches(|c| c == '.' || c == ':' );
+    if word.is_empty() {
+        None
+    } else {
+        Some((word, line[..start].chars().last()))
+    }
+}
+
+struct BibKey {
+    key : String,
+    detail : String,
+    def : Definition
+}
+
+pub struct Server {
+
+    fonts : Fonts,
+
+    // Text of the documents open at the client.
+    docs : HashMap<PathBuf, String>,
+
+    // Files compilation errors were published at, for each compiled document.
+    published : HashMap<PathBuf, Vec<PathBuf>>,
+
+    // Typst files under each project root (see complete::sources_root).
+    projects : RefCell<HashMap<PathBuf, Vec<PathBuf>>>,
+
+    // Text of the project files that are not open at the client, as read from disk.
+    files : RefCell<HashMap<PathBuf, String>>,
+
+    shutdown : bool
+
+}
+
+impl Server {
+
+    pub fn new(fonts : Fonts) -> Self {
+        Self {
+            fonts,
+            docs : HashMap::new(),
+            published : HashMap::new(),
+            projects : RefCell::new(HashMap::new()),
+            files : RefCell::new(HashMap::new()),
+            shutdown : false
+        }
+    }
+
+    fn text(&self, path : &Path) -> Option<String> {
+        self.docs.get(path).cloned().or_else(|| std::fs::read_to_string(path).ok() )
+    }
+
+    fn cached_text(&self, path : &Path) -> Option<String> {
+        if let Some(text) = self.docs.get(path) {
+            return Some(text.clone());
+        }
+        if let Some(text) = self.files.borrow().get(path) {
+            return Some(text.clone());
+        }
+        let text = std::fs::read_to_string(path).ok()?;
+        self.files.borrow_mut().insert(path.to_owned(), text.clone());
+        Some(text)
+    }
+
+    // The document and the other typst sources of its project (or directory).
+    fn sources(&self, doc : &Path) -> Vec<(PathBuf, String)> {
+        let mut out = Vec::new();
+        if let Some(text) = self.text(doc) {
+            out.push((doc.to_owned(), text));
+        }
+        let Some((root, depth)) = complete::sources_root(doc) else { return out };
+        let paths = self.projects.borrow_mut()
+            .entry(root.clone())
+            .or_insert_with(|| complete::typst_files(&root, depth) )
+            .clone();
+        for path in paths {
+            if path == doc {
+                continue;
+            }
+            if let Some(text) = self.cached_text(&path) {
+                out.push((path, text));
+            }
+        }
+        out
+    }
+
+    // Files might have been created, changed or removed since they were cached.
+    fn discard_cache(&self) {
+        self.projects.borrow_mut().clear();
+        self.files.borrow_mut().clear();
+    }
+
+    fn bib_keys(&self, sources : &[(PathBuf, String)]) -> Vec<BibKey> {
+        let mut keys = Vec::new();
+        for (path, text) in sources {
+            let Ok(doc) = typst_tools::parse_doc(path, text.clone()) else { continue };
+            for obj in doc.objects() {
+                let Object::Bibliography(_, file) = obj else { continue };
+                let bib_path = path.parent().map(|p| p.join(&file) ).unwrap_or(PathBuf::from(&file));
+                let Ok(bib) = std::fs::read_to_string(&bib_path) else { continue };
+                let Ok(refs) = BibParser::parse(&bib) else { continue };
+                for entry in refs.as_ref() {
+                    let key = entry.key().to_string();
+                    let detail = [entry.author(), entry.title(), entry.year()].iter()
+                        .filter_map(|f| f.map(|f| f.trim_matches(|c| c == '{' || c == '}' ).to_string() ) )
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let (line, start) = bib.lines().enumerate()
+                        .find_map(|(ix, l)| l.find(&format!("{{{},", key)).map(|pos| (ix, pos + 1) ) )
+                        .unwrap_or((0, 0));
+                    let def = Definition { path : bib_path.clone(), line, start, end : start + key.len() };
+                    keys.push(BibKey { key, detail, def });
+                }
+            }
+        }
+        keys
+    }
+
+    fn publish(&self, out : &mut impl Write, path : &Path, errs : Vec<(usize, String)>) -> io::Result<()> {
+        let text = self.text(path).unwrap_or_default();
+        let diagnostics : Vec<Value> = errs.into_iter().map(|(line, msg)| {
+            json!({ "range" : line_range(&text, line), "severity" : 1, "source" : "typst", "message" : msg })
+        }).collect();
+        write_message(out, &json!({
+            "jsonrpc" : "2.0",
+            "method" : "textDocument/publishDiagnostics",
+            "params" : { "uri" : path_to_uri(path), "diagnostics" : diagnostics }
+        }))
+    }
+
+    fn check_syntax(&self, out : &mut impl Write, path : &Path) -> io::Result<()> {
+        let text = self.text(path).unwrap_or_default();
+        let errs = typst_tools::parse_doc(path, text).err().unwrap_or_default();
+        self.publish(out, path, errs)
+    }
+
+    // Errors are published at the file they were raised at, which might be a file included by
+    // the document. Files that had errors at the last compilation of the document and have none
+    // now are cleared.
+    fn check_compile(&mut self, out : &mut impl Write, path : &Path) -> io::Result<()> {
+        let errs = typst_tools::compile_sources(path, self.fonts.clone(), self.docs.get(path).cloned())
+            .err()
+            .unwrap_or_default();
+        let main = path.canonicalize().unwrap_or(path.to_owned());
+        let mut by_file : HashMap<PathBuf, Vec<(usize, String)>> = HashMap::new();
+        by_file.insert(path.to_owned(), Vec::new());
+        for (file, line, msg) in errs {
+            let file = file.canonicalize().unwrap_or(file);
+            let file = if file == main { path.to_owned() } else { file };
+            by_file.entry(file).or_default().push((line, msg));
+        }
+        let published = self.published.insert(path.to_owned(), by_file.keys().cloned().collect()).unwrap_or_default();
+        for file in published {
+            by_file.entry(file).or_default();
+        }
+        for (file, errs) in by_file {
+            self.publish(out, &file, errs)?;
+        }
+        Ok(())
+    }
+
+    fn symbols(&self, path : &Path) -> Value {
+        let text = self.text(path).unwrap_or_default();
+        let Ok(doc) = typst_tools::parse_doc(path, text.clone()) else { return json!([]) };
+        fn symbol(text : &str, item : &Item) -> Value {
+            let (name, kind, children) = match item {
+                Item::Section(sec, _) => (sec.name.clone(), SYMBOL_STRING, &sec.items[..]),
+                Item::Subsection(sub, _) => (sub.name.clone(), SYMBOL_STRING, &sub.items[..]),
+                Item::Object(obj, _) => {
+                    let (name, kind) = match obj {
+                        Object::Table(n, _, _) => (format!("Table {}", n), SYMBOL_OBJECT),
+                        Object::Image(n, _, _) => (format!("Image {}", n), SYMBOL_OBJECT),
+                        Object::Equation(n, _, _) => (format!("Equation {}", n), SYMBOL_OBJECT),
+                        Object::Code(n, _, _) => (format!("Code {}", n), SYMBOL_OBJECT),
+                        Object::Bibliography(_, file) => (file.clone(), SYMBOL_FILE)
+                    };
+                    (name, kind, &[][..])
+                }
+            };
+            let range = line_range(text, item.line());
+            let name = if name.trim().is_empty() { String::from("(Untitled)") } else { name };
+            json!({
+                "name" : name,
+                "kind" : kind,
+                "range" : range,
+                "selectionRange" : range,
+                "children" : children.iter().map(|it| symbol(text, it) ).collect::<Vec<_>>()
+            })
+        }
+        Value::Array(doc.items.iter().map(|it| symbol(&text, it) ).collect())
+    }
+
+    fn completion(&self, path : &Path, line : usize, character : usize) -> Value {
+        let text = self.text(path).unwrap_or_default();
+        let line = text.lines().nth(line).unwrap_or("");
+        let before = &line[..byte_col(line, character)];
+        let Some((prefix, with_labels)) = reference_prefix(before) else { return json!([]) };
+        let sources = self.sources(path);
+        let mut items = Vec::new();
+        if with_labels {
+            let mut seen = Vec::new();
+            for (src_path, src) in sources.iter() {
+                for (label, _) in labels(src_path, src) {
+                    if label.starts_with(&prefix) && !seen.contains(&label) {
+                        let file = src_path.file_name().map(|f| f.to_string_lossy().to_string() ).unwrap_or_default();
+                        items.push(json!({ "label" : label, "kind" : COMPLETION_REFERENCE, "detail" : file }));
+                        seen.push(label);
+                    }
+                }
+            }
+        }
+        for bib in self.bib_keys(&sources).iter().filter(|b| b.key.starts_with(&prefix) ) {
+            items.push(json!({ "label" : bib.key, "kind" : COMPLETION_REFERENCE, "detail" : bib.detail }));
+        }
+        Value::Array(items)
+    }
+
+    fn definition(&self, path : &Path, line : usize, character : usize) -> Value {
+        let text = self.text(path).unwrap_or_default();
+        let line = text.lines().nth(line).unwrap_or("");
+        let Some((word, before)) = word_at(line, byte_col(line, character)) else { return Value::Null };
+        let sources = self.sources(path);
+        let def = if before == Some('@') || before == Some('<') || before == Some('"') {
+            sources.iter()
+                .flat_map(|(p, src)| labels(p, src) )
+                .find(|(label, _)| label == word )
+                .map(|(_, def)| def )
+                .or_else(|| self.bib_keys(&sources).into_iter().find(|b| b.key == word ).map(|b| b.def ) )
+        } else {
+            sources.iter()
+                .flat_map(|(p, src)| let_definitions(p, src) )
+                .find(|(name, _)| name == word )
+                .map(|(_, def)| def )
+        };
+        match def {
+            Some(def) => {
+                let def_text = self.text(&def.path).unwrap_or_default();
+                def.location(&def_text)
+            },
+            None => Value::Null
+        }
+    }
+
+    // Handles a message, returning false when the server should exit.
+    pub fn handle(&mut self, msg : &Value, out : &mut impl Write) -> io::Result<bool> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let doc_path = params["textDocument"]["uri"].as_str().and_then(uri_to_path);
+        let (line, character) = (
+            params["position"]["line"].as_u64().unwrap_or(0) as usize,
+            params["position"]["character"].as_u64().unwrap_or(0) as usize
+        );
+        let result = match (method, doc_path) {
+            ("initialize", _) => json!({
+                "capabilities" : {
+                    "textDocumentSync" : { "openClose" : true, "change" : 1, "save" : { "includeText" : false } },
+                    "completionProvider" : { "triggerCharacters" : ["@", "<", "\""] },
+                    "definitionProvider" : true,
+                    "documentSymbolProvider" : true
+                },
+                "serverInfo" : { "name" : SERVER_NAME, "version" : env!("CARGO_PKG_VERSION") }
+            }),
+            ("shutdown", _) => {
+                self.shutdown = true;
+                Value::Null
+            },
+            ("exit", _) => return Ok(false),
+            ("textDocument/didOpen", Some(path)) => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                self.docs.insert(path.clone(), text);
+                self.discard_cache();
+                self.check_compile(out, &path)?;
+                return Ok(true);
+            },
+            ("textDocument/didChange", Some(path)) => {
+                // Only full synchronization is announced, so the last change holds the whole text.
+                if let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last() ).and_then(|c| c["text"].as_str() ) {
+                    self.docs.insert(path.clone(), text.to_string());
+                }
+                self.check_syntax(out, &path)?;
+                return Ok(true);
+            },
+            ("textDocument/didSave", Some(path)) => {
+                self.discard_cache();
+                self.check_compile(out, &path)?;
+                return Ok(true);
+            },
+            ("textDocument/didClose", Some(path)) => {
+                self.docs.remove(&path);
+                for file in self.published.remove(&path).unwrap_or_default() {
+                    if file != path {
+                        self.publish(out, &file, Vec::new())?;
+                    }
+                }
+                self.publish(out, &path, Vec::new())?;
+                return Ok(true);
+            },
+            ("textDocument/documentSymbol", Some(path)) => self.symbols(&path),
+            ("textDocument/completion", Some(path)) => self.completion(&path, line, character),
+            ("textDocument/definition", Some(path)) => self.definition(&path, line, character),
+            _ => {
+                // Notifications (without an id) the server does not use are ignored.
+                if !msg["id"].is_null() {
+                    write_message(out, &json!({
+                        "jsonrpc" : "2.0",
+                        "id" : msg["id"],
+                        "error" : { "code" : METHOD_NOT_FOUND, "message" : format!("Unsupported method {}", method) }
+                    }))?;
+                }
+                return Ok(true);
+            }
+        };
+        if !msg["id"].is_null() {
+            write_message(out, &json!({ "jsonrpc" : "2.0", "id" : msg["id"], "result" : result }))?;
+        }
+        Ok(true)
+    }
+
+}
+
+/// Serves the language server over stdin and stdout until the client exits.
+pub fn run(fonts : Fonts) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    let mut server = Server::new(fonts);
+    while let Some(msg) = read_message(&mut input)? {
+        if !server.handle(&msg, &mut output)? {
+            break;
+        }
+    }
+    if !server.shutdown {
+        log::warn!("Language server input closed before shutdown");
+    }
+    Ok(())
+}
+
+#[test]
+fn lsp_helpers() {
+    let mut buf = Vec::new();
+    let msg = json!({ "jsonrpc" : "2.0", "id" : 1, "method" : "shutdown" });
+    write_message(&mut buf, &msg).unwrap();
+    assert!(buf.starts_with(b"Content-Length: "));
+    assert_eq!(read_message(&mut &buf[..]).unwrap(), Some(msg));
+    assert_eq!(read_message(&mut &b""[..]).unwrap(), None);
+
+    let path = Path::new("/tmp/my notes/main.typ");
+    assert_eq!(path_to_uri(path), "file:///tmp/my%20notes/main.typ");
+    assert_eq!(uri_to_path(&path_to_uri(path)).as_deref(), Some(path));
+
+    let src = "#let title = \"Notes\"\n= Introduction <intro>\nSee @intro and @smith2020.\n#let note(body) = body";
+    let found = labels(path, src);
+    assert_eq!(found.len(), 1);
+    assert_eq!((&found[0].0[..], found[0].1.line, found[0].1.start), ("intro", 1, 16));
+    let defs : Vec<_> = let_definitions(path, src).into_iter().map(|(name, def)| (name, def.line, def.start) ).collect();
+    assert_eq!(defs, vec![(String::from("title"), 0, 5), (String::from("note"), 3, 5)]);
+
+    assert_eq!(reference_prefix("See @int"), Some((String::from("int"), true)));
+    assert_eq!(reference_prefix("mail me at me@int"), None);
+    assert_eq!(reference_prefix("#cite(\"smi"), Some((String::from("smi"), false)));
+    assert_eq!(reference_prefix("#ref(<in"), Some((String::from("in"), true)));
+    assert_eq!(word_at("See @intro and", 6), Some(("intro", Some('@'))));
+    assert_eq!(byte_col("αβ x", 2), 4);
+    assert_eq!(utf16_col("αβ x", 5), 3);
+}
@@ -14,6 +14,7 @@ use stateful::React;
 use stateful::PersistentState;
 use drafts::state::PapersState;
 use drafts::typst_tools::Fonts;
+use drafts::recovery::Autosave;
 use std::rc::Rc;
 
 fn register_resource() -> gio::Resource {
@@ -23,7 +24,92 @@ fn register_resource() -> gio::Resource {
     resource
 }
 
+// Builds one open document, with its own file manager, typesetter and analyzer.
+fn build_document(window : &ApplicationWindow, user_state : &PapersState, fonts : &Fonts, tab : &DocumentTab) -> PapersWindow {
+    let papers_win = PapersWindow::new(window.clone(), user_state.clone());
+    user_state.update_document(&papers_win);
+    papers_win.react(&papers_win.start_screen);
+
+    let manager = FileManager::new();
+    manager.react(&papers_win.titlebar.main_menu.open_dialog);
+    manager.react(&papers_win.titlebar.main_menu.save_dialog);
+    manager.react(&papers_win.titlebar.main_menu);
+    manager.react(&papers_win);
+    manager.react(&papers_win.editor);
+
+    papers_win.titlebar.main_menu.save_dialog.react(&manager);
+    papers_win.titlebar.main_menu.open_dialog.react(&manager);
+
+    papers_win.start_screen.recent_list.react(&manager);
+    papers_win.start_screen.recent_list.react(&papers_win.editor.pdf_viewer);
+    papers_win.history_dialog.react(&manager);
+    papers_win.git_panel.react(&manager);
+    papers_win.template_wizard.react(&manager);
+
+    let typesetter = Typesetter::new(fonts.clone());
+    typesetter.react(&papers_win);
+    typesetter.react(&manager);
+
+    papers_win.titlebar.react(&typesetter);
+
+    papers_win.editor.react(&typesetter);
+    papers_win.editor.pdf_viewer.react(&papers_win.titlebar);
+    papers_win.editor.react(&manager);
+    papers_win.editor.completion.react(&manager);
+
+    // Bound after the editor, but before the window restores recovered content.
+    let autosave = Autosave::new();
+    autosave.react(&papers_win.editor);
+    autosave.react(&manager);
+    autosave.react(&papers_win);
+    autosave.react(tab);
+
+    // Also after the editor, so the review of an opened file is loaded after its text.
+    papers_win.review_panel.react(&manager);
+    typesetter.react(&papers_win.review_panel);
+
+    papers_win.react(&manager);
+    tab.react(&manager);
+
+    let analyzer = Analyzer::new();
+    analyzer.react(&papers_win);
+    analyzer.react(&papers_win.doc_tree);
+    analyzer.react(&manager);
+
+    papers_win.titlebar.react(&analyzer);
+    papers_win.titlebar.react(&manager);
+    papers_win.titlebar.bib_popover.react(&analyzer);
+    papers_win.doc_tree.react(&analyzer);
+    papers_win.editor.react(&analyzer);
+    papers_win.react(&typesetter);
+
+    // The worker threads and dialogs of the document outlive its tab unless stopped here.
+    tab.connect_closed({
+        let papers_win = papers_win.clone();
+        move |_| {
+            typesetter.shutdown();
+            analyzer.shutdown();
+            papers_win.shutdown();
+        }
+    });
+
+    papers_win
+}
+
 fn main() {
+
+    // drafts lsp serves typst documents to other editors over stdio, without any window.
+    if std::env::args().nth(1).as_deref() == Some("lsp") {
+        systemd_journal_logger::init();
+        log::set_max_level(log::LevelFilter::Info);
+        let resource = register_resource();
+        if let Err(e) = drafts::lsp::run(Fonts::new(&resource)) {
+            eprintln!("Language server error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     gtk4::init().unwrap();
 
     let application = Application::builder()
@@ -44,8 +130,7 @@ fn main() {
     // create it otherwise.
     // let cache = std::env::var("XDG_CACHE_HOME");
 
-    let style_manager = libadwaita::StyleManager::default();
-    style_manager.set_color_scheme(libadwaita::ColorScheme::Default);
+    drafts::ui::follow_style_preference();
 
     /*match  {
         Some(style_manager) => {
@@ -79,6 +164,11 @@ fn main() {
 
     application.set_accels_for_action("win.save_as_file", &["<Ctrl><Shift>S"]);
     application.set_accels_for_action("win.typeset", &["F7"]);
+    application.set_accels_for_action("win.find_preview", &["<Ctrl><Shift>F"]);
+    application.set_accels_for_action("win.new_tab", &["<Ctrl>T"]);
+    application.set_accels_for_action("win.close_tab", &["<Ctrl>W"]);
+    application.set_accels_for_action("win.preferences", &["<Ctrl>comma"]);
+    application.set_accels_for_action("win.format_document", &["<Ctrl><Shift>I"]);
 
     application.connect_activate({
         let user_state = user_state.clone();
@@ -90,47 +180,20 @@ fn main() {
                 .default_height(768)
                 .build();
 
-            let papers_win = PapersWindow::new(window, user_state.clone());
-            user_state.update(&papers_win);
-            papers_win.react(&papers_win.start_screen);
-
-            let manager = FileManager::new();
-            manager.react(&papers_win.titlebar.main_menu.open_dialog);
-            manager.react(&papers_win.titlebar.main_menu.save_dialog);
-            manager.react(&papers_win.titlebar.main_menu);
-            manager.react(&papers_win);
-            manager.react(&papers_win.editor);
-
-            user_state.react(&papers_win);
-            papers_win.titlebar.main_menu.save_dialog.react(&manager);
-            papers_win.titlebar.main_menu.open_dialog.react(&manager);
-
-            papers_win.start_screen.recent_list.react(&manager);
-
-            let typesetter = Typesetter::new(fonts.clone());
-            typesetter.react(&papers_win);
-            typesetter.react(&manager);
-
-            papers_win.titlebar.react(&typesetter);
-
-            papers_win.editor.react(&typesetter);
-            papers_win.editor.pdf_viewer.react(&papers_win.titlebar);
-            papers_win.editor.react(&manager);
-            papers_win.react(&manager);
-
-            let analyzer = Analyzer::new();
-            analyzer.react(&papers_win);
-            analyzer.react(&papers_win.doc_tree);
-            analyzer.react(&manager);
-
-            papers_win.titlebar.react(&analyzer);
-            papers_win.titlebar.react(&manager);
-            papers_win.titlebar.bib_popover.react(&analyzer);
-            papers_win.doc_tree.react(&analyzer);
-            papers_win.editor.react(&analyzer);
-            papers_win.react(&typesetter);
-
-            papers_win.window.show();
+            let tabs = DocumentTabs::build(&window);
+            user_state.update(&tabs);
+            user_state.react(&tabs);
+            tabs.set_factory({
+                let window = window.clone();
+                let user_state = user_state.clone();
+                let fonts = fonts.clone();
+                move |tab| {
+                    build_document(&window, &user_state, &fonts, tab)
+                }
+            });
+            tabs.add_document();
+
+            window.show();
         }
     });
 
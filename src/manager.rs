@@ -14,6 +14,7 @@ use std::boxed;
 use std::thread::JoinHandle;
 use crate::ui::PapersEditor;
 use std::time::SystemTime;
+use std::path::Path;
 use glib::signal::SignalHandlerId;
 use filecase::SingleArchiver;
 use filecase::SingleArchiverAction;
@@ -26,7 +27,16 @@ pub struct FileManager(SingleArchiver);
 impl FileManager {
 
     pub fn new() -> Self {
-        FileManager(SingleArchiver::new())
+        let manager = FileManager(SingleArchiver::new());
+
+        // Keeps a snapshot of the document whenever it is opened or saved (see crate::history).
+        manager.connect_opened(move |(path, content)| {
+            crate::history::queue_save(Path::new(&path), content);
+        });
+        manager.connect_save(move |path| {
+            crate::history::queue_save_file(Path::new(&path));
+        });
+        manager
     }
 
 }
@@ -80,11 +90,13 @@ impl React<PapersWindow> for FileManager {
         filecase::connect_manager_responds_window(self.sender(), &win.window);
         win.start_screen.recent_list.list.connect_row_activated({
             let send = self.sender().clone();
+            let recent_list = win.start_screen.recent_list.clone();
             move |_, row| {
-                let child = row.child().unwrap().downcast::<Box>().unwrap();
-                let lbl = PackedImageLabel::extract(&child).unwrap();
-                let path = lbl.lbl.text().as_str().to_string();
-                send.send(SingleArchiverAction::OpenRequest(path)).unwrap();
+                if let Some(path) = recent_list.path_at(row.index()) {
+                    if Path::new(&path).exists() {
+                        send.send(SingleArchiverAction::OpenRequest(path)).unwrap();
+                    }
+                }
             }
         });
     }
@@ -0,0 +1,275 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use once_cell::sync::Lazy;
+use serde::{Serialize, Deserialize};
+use stateful::React;
+use filecase::SingleArchiverImpl;
+use crate::manager::FileManager;
+use crate::ui::PapersEditor;
+
+/*
+Crash recovery. While a buffer has changes not written to its file, its content, cursor and
+file (if any) are periodically written to datadir/recovery/<session>.json. The entry is removed
+when the buffer is saved or closed, or when the window is closed normally, so any entries found
+at startup were left by a session that ended abruptly.
+*/
+
+// Default seconds between autosaves (set by the autosave-interval preference).
+pub const AUTOSAVE_INTERVAL : u32 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryEntry {
+
+    pub id : String,
+
+    // File the buffer was associated with. None for documents never saved.
+    pub path : Option<String>,
+
+    pub content : String,
+
+    // Cursor position, as a character offset.
+    pub cursor : i32,
+
+    // Seconds since the UNIX epoch.
+    pub time : u64
+
+}
+
+pub fn recovery_dir() -> Option<PathBuf> {
+    let mut dir = filecase::get_datadir(crate::APP_ID)?;
+    dir.push("recovery");
+    Some(dir)
+}
+
+fn entry_path(id : &str) -> Option<PathBuf> {
+    let mut path = recovery_dir()?;
+    path.push(format!("{}.json", id));
+    Some(path)
+}
+
+pub fn write_entry(entry : &RecoveryEntry) -> Result<(), String> {
+    let dir = recovery_dir().ok_or(String::from("No data directory"))?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string() )?;
+    let path = entry_path(&entry.id).ok_or(String::from("No data directory"))?;
+    let json = serde_json::to_string(entry).map_err(|e| e.to_string() )?;
+
+    // Written to a temporary file first, so a crash while writing does not corrupt the last entry.
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json).map_err(|e| e.to_string() )?;
+    std::fs::rename(&tmp, &path).map_err(|e| e.to_string() )
+}
+
+pub fn remove_entry(id : &str) {
+    if let Some(path) = entry_path(id) {
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Unable to remove recovery entry: {}", e);
+            }
+        }
+    }
+}
+
+// Prefix of the entry ids of this process: <session start>-<process id>. The start time
+// tells this session apart from a crashed one that had the same process id (e.g. before a
+// reboot). Entry ids are the prefix followed by -<buffer counter>.
+static SESSION : Lazy<String> = Lazy::new(|| format!("{}-{}", crate::now(), std::process::id()) );
+
+fn is_current_session(id : &str) -> bool {
+    id.strip_prefix(&SESSION[..]).map(|rest| rest.starts_with('-') ).unwrap_or(false)
+}
+
+/// Entries left by previous sessions, from the most recent to the oldest.
+pub fn list_entries() -> Vec<RecoveryEntry> {
+    let Some(dir) = recovery_dir() else { return Vec::new() };
+    let Ok(files) = std::fs::read_dir(&dir) else { return Vec::new() };
+    let mut entries : Vec<RecoveryEntry> = files.filter_map(|file| {
+        let path = file.ok()?.path();
+        if path.extension()?.to_str()? != "json" {
+            return None;
+        }
+        let json = std::fs::read_to_string(&path).ok()?;
+        serde_json::from_str::<RecoveryEntry>(&json).ok()
+    }).filter(|entry| !is_current_session(&entry.id) ).collect();
+    entries.sort_by(|a, b| b.time.cmp(&a.time) );
+    entries
+}
+
+#[derive(Debug, Clone)]
+pub struct Autosave {
+
+    id : String,
+
+    path : Rc<RefCell<Option<String>>>,
+
+    // Whether the buffer changed since it was last saved or autosaved.
+    dirty : Rc<RefCell<bool>>,
+
+    // Whether there are changes not written to the file (so an entry should be kept).
+    unsaved : Rc<RefCell<bool>>,
+
+    // Periodic autosave, and its interval in seconds.
+    timeout : Rc<RefCell<Option<(glib::SourceId, u32)>>>,
+
+    // Clears the entry when the window is closed. Disconnected when the tab is closed first.
+    destroy_handler : Rc<RefCell<Option<(ApplicationWindow, glib::SignalHandlerId)>>>
+
+}
+
+static N_BUFFERS : AtomicUsize = AtomicUsize::new(0);
+
+impl Autosave {
+
+    pub fn new() -> Self {
+        Self {
+            id : format!("{}-{}", &SESSION[..], N_BUFFERS.fetch_add(1, Ordering::SeqCst)),
+            path : Default::default(),
+            dirty : Default::default(),
+            unsaved : Default::default(),
+            timeout : Default::default(),
+            destroy_handler : Default::default()
+        }
+    }
+
+    // (Re)starts the periodic autosave with the given interval.
+    fn schedule(&self, view : &sourceview5::View, interval : u32) {
+        if let Some((source, _)) = self.timeout.take() {
+            source.remove();
+        }
+        let source = glib::timeout_add_seconds_local(interval, {
+            let autosave = self.clone();
+            let view = view.clone();
+            move || {
+                // The editor is removed from the window when its tab is closed.
+                if view.root().is_none() {
+                    autosave.timeout.replace(None);
+                    return Continue(false);
+                }
+                if *autosave.dirty.borrow() && *autosave.unsaved.borrow() {
+                    let buffer = view.buffer();
+                    let entry = RecoveryEntry {
+                        id : autosave.id.clone(),
+                        path : autosave.path.borrow().clone(),
+                        content : buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string(),
+                        cursor : buffer.cursor_position(),
+                        time : crate::now()
+                    };
+                    std::thread::spawn(move || {
+                        if let Err(e) = write_entry(&entry) {
+                            log::warn!("Autosave error: {}", e);
+                        }
+                    });
+                    autosave.dirty.replace(false);
+                }
+                Continue(true)
+            }
+        });
+        self.timeout.replace(Some((source, interval)));
+    }
+
+    fn clear(&self) {
+        self.dirty.replace(false);
+        self.unsaved.replace(false);
+        remove_entry(&self.id);
+    }
+
+}
+
+impl React<PapersEditor> for Autosave {
+
+    fn react(&self, editor : &PapersEditor) {
+        editor.view.buffer().connect_changed({
+            let autosave = self.clone();
+            move |_| {
+                autosave.dirty.replace(true);
+                autosave.unsaved.replace(true);
+            }
+        });
+        self.schedule(&editor.view, crate::settings::Preferences::current().autosave_interval);
+        crate::settings::connect_changed({
+            let autosave = self.clone();
+            let view = editor.view.clone();
+            move |prefs| {
+                // Autosaves of closed tabs are stopped, and not restarted.
+                let Some(curr) = autosave.timeout.borrow().as_ref().map(|(_, interval)| *interval ) else {
+                    return Continue(false);
+                };
+                if curr != prefs.autosave_interval {
+                    autosave.schedule(&view, prefs.autosave_interval);
+                }
+                Continue(true)
+            }
+        });
+    }
+
+}
+
+impl React<FileManager> for Autosave {
+
+    // Must be called after the editor reacts to the manager, so the buffer
+    // changes of an opened file are cleared after they happen.
+    fn react(&self, manager : &FileManager) {
+        manager.connect_new({
+            let autosave = self.clone();
+            move |_| {
+                autosave.path.replace(None);
+                autosave.clear();
+            }
+        });
+        manager.connect_opened({
+            let autosave = self.clone();
+            move |(path, _)| {
+                autosave.path.replace(Some(path));
+                autosave.clear();
+            }
+        });
+        manager.connect_save({
+            let autosave = self.clone();
+            move |path| {
+                autosave.path.replace(Some(path));
+                autosave.clear();
+            }
+        });
+    }
+
+}
+
+impl React<crate::ui::PapersWindow> for Autosave {
+
+    fn react(&self, win : &crate::ui::PapersWindow) {
+        // Only reached when the window is actually closed (unsaved changes
+        // were already confirmed to be discarded).
+        let id = win.window.connect_destroy({
+            let autosave = self.clone();
+            move |_| {
+                autosave.clear();
+            }
+        });
+        self.destroy_handler.replace(Some((win.window.clone(), id)));
+    }
+
+}
+
+impl React<crate::ui::DocumentTab> for Autosave {
+
+    fn react(&self, tab : &crate::ui::DocumentTab) {
+        tab.connect_closed({
+            let autosave = self.clone();
+            move |_| {
+                autosave.clear();
+                if let Some((window, id)) = autosave.destroy_handler.take() {
+                    window.disconnect(id);
+                }
+            }
+        });
+    }
+
+}
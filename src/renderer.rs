@@ -0,0 +1,622 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::thread;
+use std::sync::{mpsc, Arc};
+use std::rc::Rc;
+use std::cell::{RefCell, Cell};
+use std::collections::{HashMap, HashSet};
+use stateful::Callbacks;
+use crate::typst_tools::PageFrame;
+use crate::typesetter::TypesetterTarget;
+
+/*
+Page rendering happens at a worker thread, which keeps its own poppler document (poppler
+documents cannot be shared across threads) and sends back the rendered pixels. The UI
+thread keeps the rendered pages as cairo image surfaces at a cache indexed by (page, zoom).
+While the surface for the current zoom is not ready, the draw functions paint any
+surface of the same page at another zoom (or a low-resolution version of it) scaled to
+the current zoom. When the preview comes from typst frames instead of a PDF, the surfaces
+of pages whose frame did not change since the last compilation are kept as they are.
+*/
+
+// Zoom used for the placeholders rendered before the high-resolution tiles.
+const PLACEHOLDER_ZOOM : f64 = 0.25;
+
+// Zoom the pages are rasterized at to be compared across versions.
+const DIFF_ZOOM : f64 = 0.5;
+
+// Upper bound on the memory taken by the cached surfaces.
+const MAX_CACHE_BYTES : usize = 256 * 1024 * 1024;
+
+// Zoom values are kept as integers (in 1/100 units) so they can be used as keys.
+fn zoom_key(zoom : f64) -> u32 {
+    (zoom * 100.0).round() as u32
+}
+
+// Color transformation applied to the previewed pages, for reading at night.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFilter {
+
+    None,
+
+    // White paper becomes black and black text becomes white.
+    Invert,
+
+    // White paper becomes a warm, dimmer tone.
+    Sepia
+
+}
+
+impl PageFilter {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Invert => "invert",
+            Self::Sepia => "sepia"
+        }
+    }
+
+    pub fn from_name(name : &str) -> Self {
+        match name {
+            "invert" => Self::Invert,
+            "sepia" => Self::Sepia,
+            _ => Self::None
+        }
+    }
+
+    /// Transforms what was painted at the given area of the context.
+    pub fn apply(&self, ctx : &cairo::Context, w : f64, h : f64) {
+        let (op, (r, g, b)) = match self {
+            Self::None => return,
+            Self::Invert => (cairo::Operator::Difference, (1.0, 1.0, 1.0)),
+            Self::Sepia => (cairo::Operator::Multiply, (0.94, 0.86, 0.72))
+        };
+        ctx.save();
+        ctx.set_operator(op);
+        ctx.set_source_rgb(r, g, b);
+        ctx.rectangle(0.0, 0.0, w, h);
+        let _ = ctx.fill();
+        ctx.restore();
+    }
+
+}
+
+// Requests sent to the worker.
+#[derive(Clone)]
+pub enum RenderAction {
+
+    // Carries the content of a newly typeset PDF, and the generation of the document.
+    Load(Arc<Vec<u8>>, u64),
+
+    // Carries the page frames of a newly compiled typst document, and the generation of the document.
+    LoadFrames(Vec<typst::doc::Frame>, u64),
+
+    // Requests the page at the given zoom, for the given document generation.
+    Render(usize, u32, u64),
+
+    // Stops the worker, when the viewer is gone.
+    Stop
+
+}
+
+pub struct RenderedPage {
+    generation : u64,
+    page : usize,
+    zoom : u32,
+    width : i32,
+    height : i32,
+    stride : i32,
+    data : Vec<u8>
+}
+
+struct Tile {
+    surface : cairo::ImageSurface,
+    generation : u64,
+    bytes : usize,
+
+    // Value of the cache clock when the tile was last painted.
+    last_used : u64
+}
+
+#[derive(Default)]
+struct TileCache {
+    tiles : HashMap<(usize, u32), Tile>,
+    bytes : usize,
+    clock : u64
+}
+
+impl TileCache {
+
+    fn insert(&mut self, page : usize, zoom : u32, generation : u64, surface : cairo::ImageSurface) {
+        let bytes = (surface.stride() * surface.height()) as usize;
+        self.clock += 1;
+        let tile = Tile { surface, generation, bytes, last_used : self.clock };
+        if let Some(old) = self.tiles.insert((page, zoom), tile) {
+            self.bytes -= old.bytes;
+        }
+        self.bytes += bytes;
+        self.evict((page, zoom));
+    }
+
+    // Removes the least recently used tiles (except the one just inserted) until the cache fits
+    // its memory budget.
+    fn evict(&mut self, keep : (usize, u32)) {
+        while self.bytes > MAX_CACHE_BYTES {
+            let oldest = self.tiles.iter()
+                .filter(|(key, _)| **key != keep )
+                .min_by_key(|(_, tile)| tile.last_used )
+                .map(|(key, _)| *key );
+            match oldest.and_then(|key| self.tiles.remove(&key) ) {
+                Some(tile) => self.bytes -= tile.bytes,
+                None => break
+            }
+        }
+    }
+
+    // Returns the surface for the page at the given zoom and generation if there is one. Otherwise,
+    // returns the surface for the page with the closest zoom (possibly from a previous generation)
+    // to be used as a placeholder. The boolean is true when the surface is an exact match.
+    fn best(&mut self, page : usize, zoom : u32, generation : u64) -> Option<(cairo::ImageSurface, u32, bool)> {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(tile) = self.tiles.get_mut(&(page, zoom)) {
+            if tile.generation == generation {
+                tile.last_used = clock;
+                return Some((tile.surface.clone(), zoom, true));
+            }
+        }
+        let closest = self.tiles.iter_mut()
+            .filter(|((p, _), _)| *p == page )
+            .min_by_key(|((_, z), tile)| (tile.generation != generation, (*z as i64 - zoom as i64).abs()) )?;
+        closest.1.last_used = clock;
+        Some((closest.1.surface.clone(), (closest.0).1, false))
+    }
+
+    // Moves the tiles of the given pages from the previous generation to the new one.
+    fn retag(&mut self, pages : &HashSet<usize>, previous : u64, generation : u64) {
+        for ((page, _), tile) in self.tiles.iter_mut() {
+            if tile.generation == previous && pages.contains(page) {
+                tile.generation = generation;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.tiles.clear();
+        self.bytes = 0;
+    }
+
+}
+
+#[derive(Clone)]
+pub struct PageRenderer {
+
+    // Sender of the rendered pages (kept to restart the worker) and sender of requests to
+    // the worker. Both are dropped when the renderer is shut down.
+    worker : Rc<RefCell<Option<(glib::Sender<RenderedPage>, mpsc::Sender<RenderAction>)>>>,
+
+    // Last document sent to the worker, sent again to a restarted worker.
+    loaded : Rc<RefCell<Option<RenderAction>>>,
+
+    cache : Rc<RefCell<TileCache>>,
+
+    // Requests sent to the worker and not yet answered, as (page, zoom, generation).
+    pending : Rc<RefCell<HashSet<(usize, u32, u64)>>>,
+
+    generation : Rc<RefCell<u64>>,
+
+    // Hashes of the page frames of the current generation (empty when showing a PDF).
+    hashes : Rc<RefCell<Vec<u128>>>,
+
+    // Applied when the pages are painted at the viewer, but not to the cached surfaces.
+    filter : Rc<Cell<PageFilter>>,
+
+    on_rendered : Callbacks<usize>
+
+}
+
+impl std::fmt::Debug for PageRenderer {
+
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PageRenderer")
+    }
+
+}
+
+fn spawn_worker(send : &glib::Sender<RenderedPage>) -> mpsc::Sender<RenderAction> {
+    let (req_send, req_recv) = mpsc::channel::<RenderAction>();
+    thread::spawn({
+        let send = send.clone();
+        move || {
+            render_pages(req_recv, send);
+        }
+    });
+    req_send
+}
+
+impl PageRenderer {
+
+    pub fn new() -> Self {
+        let (send, recv) = glib::MainContext::channel::<RenderedPage>(glib::PRIORITY_DEFAULT);
+        let cache : Rc<RefCell<TileCache>> = Default::default();
+        let pending : Rc<RefCell<HashSet<(usize, u32, u64)>>> = Default::default();
+        let generation = Rc::new(RefCell::new(0));
+        let on_rendered : Callbacks<usize> = Default::default();
+        let req_send = spawn_worker(&send);
+
+        recv.attach(None, {
+            let cache = cache.clone();
+            let pending = pending.clone();
+            let on_rendered = on_rendered.clone();
+            move |page| {
+                pending.borrow_mut().remove(&(page.page, page.zoom, page.generation));
+                let RenderedPage { generation, page, zoom, width, height, stride, data } = page;
+                match cairo::ImageSurface::create_for_data(data, cairo::Format::ARgb32, width, height, stride) {
+                    Ok(surface) => {
+                        cache.borrow_mut().insert(page, zoom, generation, surface);
+                        on_rendered.call(page);
+                    },
+                    Err(e) => {
+                        log::warn!("Unable to create page surface: {}", e);
+                    }
+                }
+                Continue(true)
+            }
+        });
+
+        Self {
+            worker : Rc::new(RefCell::new(Some((send, req_send)))),
+            loaded : Default::default(),
+            cache,
+            pending,
+            generation,
+            hashes : Default::default(),
+            filter : Rc::new(Cell::new(PageFilter::None)),
+            on_rendered
+        }
+    }
+
+    // Sends the request to the worker. If the worker is gone (e.g. it panicked at a page), a
+    // new one is started with the current document, and the request is sent to it.
+    fn send(&self, action : RenderAction) {
+        let mut worker = self.worker.borrow_mut();
+        let Some((send, req_send)) = worker.as_mut() else { return };
+        if let Err(mpsc::SendError(action)) = req_send.send(action) {
+            log::warn!("Page renderer is not running, restarting it");
+            *req_send = spawn_worker(send);
+            self.pending.borrow_mut().clear();
+            let is_load = matches!(action, RenderAction::Load(..) | RenderAction::LoadFrames(..));
+            if let Some(loaded) = self.loaded.borrow().clone().filter(|_| !is_load ) {
+                let _ = req_send.send(loaded);
+            }
+            if req_send.send(action).is_err() {
+                log::warn!("Unable to restart page renderer");
+            }
+        }
+    }
+
+    /// Sends the content of a new PDF to the worker. Surfaces of the previous
+    /// document are kept to be used as placeholders until the new pages are ready.
+    pub fn load(&self, pdf : Vec<u8>) {
+        let generation = self.next_generation();
+        self.hashes.borrow_mut().clear();
+        let action = RenderAction::Load(Arc::new(pdf), generation);
+        self.loaded.replace(Some(action.clone()));
+        self.send(action);
+    }
+
+    /// Sends the frames of a new typst document to the worker. Surfaces of pages
+    /// with the same content as in the previous document are re-used.
+    pub fn load_frames(&self, pages : &[PageFrame]) {
+        let previous = *self.generation.borrow();
+        let generation = self.next_generation();
+        let unchanged : HashSet<usize> = {
+            let old = self.hashes.borrow();
+            pages.iter().enumerate()
+                .filter(|(ix, page)| old.get(*ix) == Some(&page.hash) )
+                .map(|(ix, _)| ix )
+                .collect()
+        };
+        self.cache.borrow_mut().retag(&unchanged, previous, generation);
+        *self.hashes.borrow_mut() = pages.iter().map(|p| p.hash ).collect();
+        let frames = pages.iter().map(|p| p.frame.clone() ).collect();
+        let action = RenderAction::LoadFrames(frames, generation);
+        self.loaded.replace(Some(action.clone()));
+        self.send(action);
+    }
+
+    /// Loads a typeset PDF or the frames of a typst document.
+    pub fn load_target(&self, target : &TypesetterTarget) {
+        match target {
+            TypesetterTarget::PDFContent(pdf) => self.load(pdf.clone()),
+            TypesetterTarget::Frames(preview) => self.load_frames(&preview.pages),
+            _ => log::warn!("Unsupported render target")
+        }
+    }
+
+    fn next_generation(&self) -> u64 {
+        let mut generation = self.generation.borrow_mut();
+        *generation += 1;
+        self.pending.borrow_mut().clear();
+        *generation
+    }
+
+    pub fn set_filter(&self, filter : PageFilter) {
+        self.filter.set(filter);
+    }
+
+    pub fn filter(&self) -> PageFilter {
+        self.filter.get()
+    }
+
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+        self.hashes.borrow_mut().clear();
+        self.pending.borrow_mut().clear();
+    }
+
+    /// Paints the page at the given zoom, using the cached surface when there is one. Otherwise,
+    /// paints a placeholder (if any) and requests the page to the worker.
+    pub fn draw_page(&self, ctx : &cairo::Context, page : usize, zoom : f64) {
+        self.draw_page_with_alpha(ctx, page, zoom, 1.0);
+    }
+
+    pub fn draw_page_with_alpha(&self, ctx : &cairo::Context, page : usize, zoom : f64, alpha : f64) {
+        let generation = *self.generation.borrow();
+        let key = zoom_key(zoom);
+        let best = self.cache.borrow_mut().best(page, key, generation);
+        match best {
+            Some((surface, tile_zoom, exact)) => {
+                ctx.save();
+                let scale = key as f64 / tile_zoom as f64;
+                ctx.scale(scale, scale);
+                if ctx.set_source_surface(&surface, 0.0, 0.0).is_ok() {
+                    let _ = ctx.paint_with_alpha(alpha);
+                }
+                ctx.restore();
+                if !exact {
+                    self.request(page, key, generation);
+                }
+            },
+            None => {
+                self.request(page, zoom_key(PLACEHOLDER_ZOOM), generation);
+                self.request(page, key, generation);
+            }
+        }
+    }
+
+    fn request(&self, page : usize, zoom : u32, generation : u64) {
+        if self.pending.borrow_mut().insert((page, zoom, generation)) {
+            self.send(RenderAction::Render(page, zoom, generation));
+        }
+    }
+
+    /// Stops the worker thread. Pages are no longer rendered after this.
+    pub fn shutdown(&self) {
+        if let Some((_, req_send)) = self.worker.take() {
+            let _ = req_send.send(RenderAction::Stop);
+        }
+        self.loaded.replace(None);
+    }
+
+    /// Called when a page surface is ready to be painted.
+    pub fn connect_rendered<F>(&self, f : F)
+    where
+        F : Fn(usize) + 'static
+    {
+        self.on_rendered.bind(f);
+    }
+
+}
+
+// Content the worker renders pages from.
+enum Source {
+
+    // The document reads from the PDF content without copying it, so both are kept together.
+    Pdf(poppler::Document, Arc<Vec<u8>>),
+
+    Frames(Vec<typst::doc::Frame>)
+
+}
+
+impl Source {
+
+    fn from_target(target : &TypesetterTarget) -> Option<Self> {
+        match target {
+            TypesetterTarget::PDFContent(pdf) => {
+                let pdf = Arc::new(pdf.clone());
+                let doc = poppler::Document::from_data(&pdf[..], None).ok()?;
+                Some(Source::Pdf(doc, pdf))
+            },
+            TypesetterTarget::Frames(preview) => {
+                Some(Source::Frames(preview.pages.iter().map(|p| p.frame.clone() ).collect()))
+            },
+            _ => None
+        }
+    }
+
+    fn n_pages(&self) -> usize {
+        match self {
+            Source::Pdf(doc, _) => doc.n_pages().max(0) as usize,
+            Source::Frames(frames) => frames.len()
+        }
+    }
+
+    fn render(&self, req : (usize, u32, u64)) -> Option<RenderedPage> {
+        match self {
+            Source::Pdf(doc, _) => render_page(doc, req),
+            Source::Frames(frames) => render_frame_page(frames, req)
+        }
+    }
+
+}
+
+// Page whose rendering changed between two versions of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageDiff {
+
+    pub page : usize,
+
+    // Region (x, y, width, height, in points) containing all changed pixels of the new
+    // version, or None when the page exists only at one of the versions.
+    pub region : Option<(f64, f64, f64, f64)>
+
+}
+
+/// Compares the pages of two versions of a document. Frames with the same hash are taken as
+/// equal; other pages are rasterized and compared pixel by pixel. Blocks while rendering,
+/// so it should be called outside the main thread.
+pub fn diff_pages(old : &TypesetterTarget, new : &TypesetterTarget) -> Vec<PageDiff> {
+    let (Some(old_src), Some(new_src)) = (Source::from_target(old), Source::from_target(new)) else {
+        return Vec::new();
+    };
+    let zoom = zoom_key(DIFF_ZOOM);
+    let n_pages = old_src.n_pages().max(new_src.n_pages());
+    (0..n_pages).filter_map(|page| {
+        if let (TypesetterTarget::Frames(a), TypesetterTarget::Frames(b)) = (old, new) {
+            if let (Some(pa), Some(pb)) = (a.pages.get(page), b.pages.get(page)) {
+                if pa.hash == pb.hash {
+                    return None;
+                }
+            }
+        }
+        match (old_src.render((page, zoom, 0)), new_src.render((page, zoom, 0))) {
+            (Some(a), Some(b)) => changed_region(&a, &b).map(|region| PageDiff { page, region : Some(region) }),
+            (None, None) => None,
+            _ => Some(PageDiff { page, region : None })
+        }
+    }).collect()
+}
+
+// Bounding box (in points) of the pixels that differ between two renderings of a page.
+fn changed_region(a : &RenderedPage, b : &RenderedPage) -> Option<(f64, f64, f64, f64)> {
+    let z = b.zoom as f64 / 100.0;
+    if a.width != b.width || a.height != b.height {
+        return Some((0.0, 0.0, b.width as f64 / z, b.height as f64 / z));
+    }
+    let (mut x0, mut y0, mut x1, mut y1) = (i32::MAX, i32::MAX, -1, -1);
+    let row_len = (b.width * 4) as usize;
+    for y in 0..b.height {
+        let row_a = &a.data[(y * a.stride) as usize..][..row_len];
+        let row_b = &b.data[(y * b.stride) as usize..][..row_len];
+        if row_a == row_b {
+            continue;
+        }
+        for (x, (pa, pb)) in row_a.chunks_exact(4).zip(row_b.chunks_exact(4)).enumerate() {
+            if pa != pb {
+                x0 = x0.min(x as i32);
+                x1 = x1.max(x as i32);
+            }
+        }
+        y0 = y0.min(y);
+        y1 = y1.max(y);
+    }
+    if x1 < 0 {
+        return None;
+    }
+    Some((x0 as f64 / z, y0 as f64 / z, (x1 - x0 + 1) as f64 / z, (y1 - y0 + 1) as f64 / z))
+}
+
+// Worker loop. Placeholder requests are served first, then the most recent requests
+// (which are for the pages the user is looking at now).
+fn render_pages(recv : mpsc::Receiver<RenderAction>, send : glib::Sender<RenderedPage>) {
+    let mut doc : Option<(Source, u64)> = None;
+    let mut queue : Vec<(usize, u32, u64)> = Vec::new();
+    loop {
+        let first = if queue.is_empty() {
+            match recv.recv() {
+                Ok(action) => Some(action),
+                Err(_) => return
+            }
+        } else {
+            None
+        };
+        for action in first.into_iter().chain(recv.try_iter()) {
+            match action {
+                RenderAction::Load(pdf, generation) => {
+                    match poppler::Document::from_data(&pdf[..], None) {
+                        Ok(new_doc) => {
+                            doc = Some((Source::Pdf(new_doc, pdf), generation));
+                        },
+                        Err(e) => {
+                            log::warn!("Poppler error: {}", e);
+                        }
+                    }
+                    queue.retain(|(_, _, g)| *g == generation );
+                },
+                RenderAction::LoadFrames(frames, generation) => {
+                    doc = Some((Source::Frames(frames), generation));
+                    queue.retain(|(_, _, g)| *g == generation );
+                },
+                RenderAction::Render(page, zoom, generation) => {
+                    queue.push((page, zoom, generation));
+                },
+                RenderAction::Stop => {
+                    return;
+                }
+            }
+        }
+
+        let Some((source, curr_generation)) = &doc else {
+            queue.clear();
+            continue;
+        };
+        queue.retain(|(_, _, g)| g == curr_generation );
+        let placeholder = zoom_key(PLACEHOLDER_ZOOM);
+        let next = match queue.iter().position(|(_, z, _)| *z == placeholder ) {
+            Some(pos) => queue.remove(pos),
+            None => match queue.pop() {
+                Some(req) => req,
+                None => continue
+            }
+        };
+        if let Some(rendered) = source.render(next) {
+            if send.send(rendered).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn render_page(doc : &poppler::Document, (page_ix, zoom, generation) : (usize, u32, u64)) -> Option<RenderedPage> {
+    let page = doc.page(page_ix as i32)?;
+    let z = zoom as f64 / 100.0;
+    let (w, h) = page.size();
+    let (width, height) = ((w * z).ceil() as i32, (h * z).ceil() as i32);
+    let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width.max(1), height.max(1)).ok()?;
+    {
+        let ctx = cairo::Context::new(&surface).ok()?;
+        ctx.set_source_rgb(1., 1., 1.);
+        ctx.paint().ok()?;
+        ctx.scale(z, z);
+
+        // TODO remove the transmute when GTK/cairo version match (see crate::draw_page_content).
+        page.render(unsafe { std::mem::transmute::<_, _>(&ctx) });
+    }
+    surface.flush();
+    let stride = surface.stride();
+    let data = surface.data().ok()?.to_vec();
+    Some(RenderedPage { generation, page : page_ix, zoom, width : width.max(1), height : height.max(1), stride, data })
+}
+
+fn render_frame_page(frames : &[typst::doc::Frame], (page_ix, zoom, generation) : (usize, u32, u64)) -> Option<RenderedPage> {
+    let frame = frames.get(page_ix)?;
+    let (width, height, stride, data) = crate::typst_tools::render_frame(frame, zoom as f64 / 100.0);
+    Some(RenderedPage { generation, page : page_ix, zoom, width, height, stride, data })
+}
+
+#[test]
+fn page_changed_region() {
+    let page = |data : Vec<u8>| RenderedPage { generation : 0, page : 0, zoom : 50, width : 4, height : 2, stride : 16, data };
+    let blank = page(vec![255; 32]);
+    assert_eq!(changed_region(&blank, &page(vec![255; 32])), None);
+    let mut data = vec![255; 32];
+    data[16 + 8] = 0;
+    assert_eq!(changed_region(&blank, &page(data)), Some((4.0, 2.0, 2.0, 2.0)));
+}
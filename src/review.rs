@@ -0,0 +1,347 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+/*
+Tracked changes and review comments. Positions are character offsets into the document text.
+Inserted text stays in the document and is recorded by its position; deleted text leaves the
+document, and is recorded with the position it was removed from, so the file on disk always holds
+the current text. The review is kept at a sidecar file (<document>.review.json) next to the document.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Insertion,
+    Deletion
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+
+    pub id : u64,
+
+    pub kind : ChangeKind,
+
+    pub pos : usize,
+
+    // Inserted text (present at the document from pos) or deleted text (removed at pos).
+    pub text : String,
+
+    pub author : String,
+
+    // Seconds since the UNIX epoch.
+    pub time : u64
+
+}
+
+impl Change {
+
+    /// Number of document characters covered by the change (zero for deletions).
+    pub fn len(&self) -> usize {
+        match self.kind {
+            ChangeKind::Insertion => self.text.chars().count(),
+            ChangeKind::Deletion => 0
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.pos + self.len()
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub author : String,
+    pub time : u64,
+    pub text : String
+}
+
+// A comment anchored to the document range [start, end), with its replies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommentThread {
+    pub id : u64,
+    pub start : usize,
+    pub end : usize,
+    pub notes : Vec<Note>,
+    pub resolved : bool
+}
+
+// Document edit required to accept or reject a change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert(usize, String),
+    Delete(usize, usize)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Review {
+    pub changes : Vec<Change>,
+    pub comments : Vec<CommentThread>,
+    next_id : u64
+}
+
+fn insert_chars(txt : &mut String, char_ix : usize, ins : &str) {
+    let byte_ix = txt.char_indices().nth(char_ix).map(|(ix, _)| ix ).unwrap_or(txt.len());
+    txt.insert_str(byte_ix, ins);
+}
+
+fn remove_chars(txt : &mut String, from : usize, to : usize) {
+    *txt = txt.chars().enumerate().filter(|(ix, _)| *ix < from || *ix >= to ).map(|(_, c)| c ).collect();
+}
+
+impl Review {
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.comments.is_empty()
+    }
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Records text typed at the given position. Text typed inside or just after an
+    /// insertion extends it.
+    pub fn record_insert(&mut self, at : usize, text : &str, author : &str) {
+        let extended = self.changes.iter().position(|c| c.kind == ChangeKind::Insertion && c.pos <= at && at <= c.end() );
+        self.shift_insert(at, text, extended);
+        if let Some(ix) = extended {
+            let change = &mut self.changes[ix];
+            insert_chars(&mut change.text, at - change.pos, text);
+            change.time = crate::now();
+        } else {
+            let id = self.new_id();
+            self.changes.push(Change { id, kind : ChangeKind::Insertion, pos : at, text : text.to_string(), author : author.to_string(), time : crate::now() });
+        }
+    }
+
+    /// Records the removal of the given text from the given position. Removing text that
+    /// was itself inserted just shrinks the insertion; the rest becomes a deletion, merged
+    /// with the deletions found at the removed range.
+    pub fn record_delete(&mut self, start : usize, removed : &str, author : &str) {
+        let removed : Vec<char> = removed.chars().collect();
+        let end = start + removed.len();
+        let mut deleted = String::new();
+        for ix in 0..=removed.len() {
+            let pos = start + ix;
+            for c in self.changes.iter().filter(|c| c.kind == ChangeKind::Deletion && c.pos == pos ) {
+                deleted += &c.text;
+            }
+            if ix < removed.len() {
+                let inserted = self.changes.iter().any(|c| c.kind == ChangeKind::Insertion && c.pos <= pos && pos < c.end() );
+                if !inserted {
+                    deleted.push(removed[ix]);
+                }
+            }
+        }
+        self.changes.retain(|c| !(c.kind == ChangeKind::Deletion && c.pos >= start && c.pos <= end) );
+        self.shift_delete(start, end);
+        if !deleted.is_empty() {
+            let id = self.new_id();
+            self.changes.push(Change { id, kind : ChangeKind::Deletion, pos : start, text : deleted, author : author.to_string(), time : crate::now() });
+        }
+    }
+
+    /// Moves the changes and comments after text inserted without being tracked.
+    pub fn shift_insert(&mut self, at : usize, text : &str, skip : Option<usize>) {
+        let n = text.chars().count();
+        for (ix, c) in self.changes.iter_mut().enumerate() {
+            if Some(ix) == skip {
+                continue;
+            }
+            match c.kind {
+                ChangeKind::Insertion if c.pos >= at => c.pos += n,
+                ChangeKind::Insertion if at < c.end() => insert_chars(&mut c.text, at - c.pos, text),
+                ChangeKind::Deletion if c.pos > at => c.pos += n,
+                _ => { }
+            }
+        }
+        for comment in self.comments.iter_mut() {
+            if comment.start >= at {
+                comment.start += n;
+                comment.end += n;
+            } else if at < comment.end {
+                comment.end += n;
+            }
+        }
+    }
+
+    /// Moves and clips the changes and comments after the range [start, end) is removed
+    /// without being tracked.
+    pub fn shift_delete(&mut self, start : usize, end : usize) {
+        let n = end - start;
+        let map = |x : usize| if x >= end { x - n } else if x > start { start } else { x };
+        for c in self.changes.iter_mut() {
+            if c.kind == ChangeKind::Insertion {
+                let (from, to) = (start.max(c.pos), end.min(c.end()));
+                if from < to {
+                    remove_chars(&mut c.text, from - c.pos, to - c.pos);
+                }
+            }
+            c.pos = map(c.pos);
+        }
+        self.changes.retain(|c| !c.text.is_empty() );
+        for comment in self.comments.iter_mut() {
+            comment.start = map(comment.start);
+            comment.end = map(comment.end);
+        }
+    }
+
+    /// Accepting a change keeps the document as it is.
+    pub fn accept(&mut self, id : u64) {
+        self.changes.retain(|c| c.id != id );
+    }
+
+    /// Rejecting a change returns the document edit that undoes it. The edit should be
+    /// applied without tracking (the remaining changes are shifted by shift_insert or shift_delete).
+    pub fn reject(&mut self, id : u64) -> Option<Edit> {
+        let ix = self.changes.iter().position(|c| c.id == id )?;
+        let change = self.changes.remove(ix);
+        match change.kind {
+            ChangeKind::Insertion => Some(Edit::Delete(change.pos, change.end())),
+            ChangeKind::Deletion => Some(Edit::Insert(change.pos, change.text))
+        }
+    }
+
+    pub fn add_comment(&mut self, start : usize, end : usize, text : &str, author : &str) -> u64 {
+        let id = self.new_id();
+        let note = Note { author : author.to_string(), time : crate::now(), text : text.to_string() };
+        self.comments.push(CommentThread { id, start, end, notes : vec![note], resolved : false });
+        id
+    }
+
+    pub fn reply(&mut self, id : u64, text : &str, author : &str) {
+        if let Some(comment) = self.comments.iter_mut().find(|c| c.id == id ) {
+            comment.notes.push(Note { author : author.to_string(), time : crate::now(), text : text.to_string() });
+        }
+    }
+
+    pub fn set_resolved(&mut self, id : u64, resolved : bool) {
+        if let Some(comment) = self.comments.iter_mut().find(|c| c.id == id ) {
+            comment.resolved = resolved;
+        }
+    }
+
+    pub fn remove_comment(&mut self, id : u64) {
+        self.comments.retain(|c| c.id != id );
+    }
+
+    /// Removes the insertions that do not match the document text (e.g. after it was
+    /// edited outside Drafts) and anything beyond its end.
+    pub fn validate(&mut self, txt : &str) {
+        let chars : Vec<char> = txt.chars().collect();
+        self.changes.retain(|c| {
+            c.end() <= chars.len() && (c.kind == ChangeKind::Deletion || chars[c.pos..c.end()].iter().copied().eq(c.text.chars()))
+        });
+        self.comments.retain(|c| c.start <= c.end && c.end <= chars.len() );
+    }
+
+}
+
+pub fn review_path(doc : &Path) -> PathBuf {
+    let mut name = doc.file_name().map(|f| f.to_os_string() ).unwrap_or_default();
+    name.push(".review.json");
+    doc.with_file_name(name)
+}
+
+pub fn load(doc : &Path) -> Option<Review> {
+    let json = std::fs::read_to_string(review_path(doc)).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(review) => Some(review),
+        Err(e) => {
+            log::warn!("Invalid review file: {}", e);
+            None
+        }
+    }
+}
+
+/// Writes the review next to the document, or removes the review file if there is nothing to review.
+pub fn save(doc : &Path, review : &Review) -> Result<(), String> {
+    let path = review_path(doc);
+    if review.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| e.to_string() )?;
+        }
+        return Ok(());
+    }
+    let json = serde_json::to_string_pretty(review).map_err(|e| e.to_string() )?;
+    std::fs::write(&path, json).map_err(|e| e.to_string() )
+}
+
+// Only changes of plain text at markup lines are marked at the typeset document, since
+// wrapping code or math would change its meaning.
+fn is_markup_text(chars : &[char], pos : usize, text : &str) -> bool {
+    let plain = text.chars().all(|c| c.is_alphanumeric() || (c.is_whitespace() && c != '\n') || ".,;:'!?-()".contains(c) );
+    let line_start = chars[..pos].iter().rposition(|c| *c == '\n' ).map(|ix| ix + 1 ).unwrap_or(0);
+    let code_line = chars[line_start..].iter().find(|c| !c.is_whitespace() ).map(|c| *c == '#' || *c == '=' ).unwrap_or(false);
+    let in_math = chars[..pos].iter().filter(|c| **c == '$' ).count() % 2 == 1;
+    plain && !text.is_empty() && !code_line && !in_math
+}
+
+/// Source with the insertions colored and the deletions struck through, for the typeset preview.
+pub fn annotate_source(src : &str, changes : &[Change]) -> String {
+    let mut chars : Vec<char> = src.chars().collect();
+    let mut changes : Vec<&Change> = changes.iter().collect();
+
+    // From the end, so the positions of the changes not yet applied are still valid. At the same
+    // position, the insertion is wrapped before the deleted text is placed before it.
+    changes.sort_by(|a, b| b.pos.cmp(&a.pos).then((a.kind == ChangeKind::Deletion).cmp(&(b.kind == ChangeKind::Deletion))) );
+    for c in changes {
+        if c.end() > chars.len() || !is_markup_text(&chars, c.pos, &c.text) {
+            continue;
+        }
+        match c.kind {
+            ChangeKind::Insertion => {
+                if !chars[c.pos..c.end()].iter().copied().eq(c.text.chars()) {
+                    continue;
+                }
+                let wrapped = format!("#text(fill: rgb(\"#26a269\"))[{}]", c.text);
+                chars.splice(c.pos..c.end(), wrapped.chars());
+            },
+            ChangeKind::Deletion => {
+                let wrapped = format!("#text(fill: rgb(\"#c01c28\"))[#strike[{}]]", c.text);
+                chars.splice(c.pos..c.pos, wrapped.chars());
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+#[test]
+fn tracked_changes() {
+    let mut review = Review::default();
+
+    // "Hello world" -> type " big" after "Hello", then delete "world".
+    review.record_insert(5, " ", "A");
+    review.record_insert(6, "big", "A");
+    assert_eq!(review.changes.len(), 1);
+    assert_eq!(review.changes[0].text, " big");
+
+    // "Hello big world": removes "world" (at 10..15) and the "g" of the insertion (at 8).
+    review.record_delete(10, "world", "A");
+    review.record_delete(8, "g", "A");
+    assert_eq!(review.changes[0].text, " bi");
+    let deletion = review.changes.iter().find(|c| c.kind == ChangeKind::Deletion ).unwrap().clone();
+    assert_eq!((deletion.pos, &deletion.text[..]), (9, "world"));
+
+    let comment = review.add_comment(0, 5, "Greeting?", "B");
+    review.shift_insert(0, ">> ", None);
+    assert_eq!((review.comments[0].start, review.comments[0].end), (3, 8));
+    review.remove_comment(comment);
+
+    // "Hello bi " (without the prefix) with the deletion rejected.
+    review.shift_delete(0, 3);
+    let edit = review.reject(deletion.id);
+    assert_eq!(edit, Some(Edit::Insert(9, "world".into())));
+    let src = "Hello bi ";
+    assert_eq!(annotate_source(src, &review.changes), "Hello#text(fill: rgb(\"#26a269\"))[ bi] ");
+
+    // Changes past the end of the text (e.g. recorded against a longer buffer) are not marked.
+    let stale = Change { id : 100, kind : ChangeKind::Deletion, pos : 40, text : "gone".into(), author : "A".into(), time : 0 };
+    assert_eq!(annotate_source("Hello", &[stale]), "Hello");
+}
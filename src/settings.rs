@@ -0,0 +1,209 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::{gio, glib};
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/*
+User preferences are kept at GSettings keys under the application id (see
+data/io.github.limads.Drafts.gschema.xml). The schema is only found when it is installed
+(e.g. at the flatpak), so builds run in place read the defaults below and
+cannot change them. Widgets that depend on preferences read them once when built,
+then follow the changed signal, so edits at the preferences window apply live.
+*/
+
+pub const DEFAULT_EDITOR_FONT : &'static str = "Ubuntu Mono";
+
+pub const DEFAULT_EDITOR_FONT_SIZE : i32 = 16;
+
+pub const DEFAULT_COLOR_SCHEME : &'static str = "Adwaita";
+
+pub const DEFAULT_TAB_WIDTH : u32 = 4;
+
+pub const DEFAULT_FORMAT_WIDTH : u32 = 80;
+
+pub const DEFAULT_PAPER : &'static str = "a4";
+
+// Paper sizes offered at the preferences (typst paper names).
+pub const PAPERS : [&'static str; 6] = ["a3", "a4", "a5", "us-letter", "us-legal", "presentation-16-9"];
+
+thread_local! {
+    static SETTINGS : Option<gio::Settings> = {
+        let installed = gio::SettingsSchemaSource::default()
+            .and_then(|src| src.lookup(crate::APP_ID, true) )
+            .is_some();
+        if installed {
+            Some(gio::Settings::new(crate::APP_ID))
+        } else {
+            log::warn!("Settings schema {} not installed. Using default preferences.", crate::APP_ID);
+            None
+        }
+    };
+}
+
+/// The application settings, if the schema is installed.
+pub fn settings() -> Option<gio::Settings> {
+    SETTINGS.with(|s| s.clone() )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preferences {
+
+    pub editor_font : String,
+
+    pub editor_font_size : i32,
+
+    // One of system, light or dark
+    pub style : String,
+
+    pub color_scheme : String,
+
+    // One of none, invert or sepia
+    pub page_filter : String,
+
+    pub show_line_numbers : bool,
+
+    // One of none, char, word or word-char
+    pub wrap_mode : String,
+
+    pub tab_width : u32,
+
+    // Line width of code when formatting documents
+    pub format_width : u32,
+
+    // Seconds between autosaves
+    pub autosave_interval : u32,
+
+    pub live_preview : bool,
+
+    pub default_paper : String,
+
+    pub default_zoom : f64
+
+}
+
+impl Default for Preferences {
+
+    fn default() -> Self {
+        Self {
+            editor_font : String::from(DEFAULT_EDITOR_FONT),
+            editor_font_size : DEFAULT_EDITOR_FONT_SIZE,
+            style : String::from("system"),
+            color_scheme : String::from(DEFAULT_COLOR_SCHEME),
+            page_filter : String::from("none"),
+            show_line_numbers : true,
+            wrap_mode : String::from("word"),
+            tab_width : DEFAULT_TAB_WIDTH,
+            format_width : DEFAULT_FORMAT_WIDTH,
+            autosave_interval : crate::recovery::AUTOSAVE_INTERVAL,
+            live_preview : false,
+            default_paper : String::from(DEFAULT_PAPER),
+            default_zoom : crate::ui::DEFAULT_ZOOM_SCALE
+        }
+    }
+
+}
+
+impl Preferences {
+
+    pub fn read(settings : &gio::Settings) -> Self {
+        Self {
+            editor_font : settings.string("editor-font").to_string(),
+            editor_font_size : settings.int("editor-font-size"),
+            style : settings.string("style").to_string(),
+            color_scheme : settings.string("color-scheme").to_string(),
+            page_filter : settings.string("page-filter").to_string(),
+            show_line_numbers : settings.boolean("show-line-numbers"),
+            wrap_mode : settings.string("wrap-mode").to_string(),
+            tab_width : settings.int("tab-width").max(1) as u32,
+            format_width : settings.int("format-width").max(1) as u32,
+            autosave_interval : settings.int("autosave-interval").max(1) as u32,
+            live_preview : settings.boolean("live-preview"),
+            default_paper : settings.string("default-paper").to_string(),
+            default_zoom : settings.double("default-zoom")
+        }
+    }
+
+    /// Current preferences, or the defaults when the schema is not installed.
+    pub fn current() -> Self {
+        settings().map(|s| Self::read(&s) ).unwrap_or_default()
+    }
+
+    /// Options for formatting documents (indenting code by the tab width).
+    pub fn format_options(&self) -> crate::typst_tools::format::FormatOptions {
+        crate::typst_tools::format::FormatOptions { width : self.format_width as usize, indent : self.tab_width as usize }
+    }
+
+    /// CSS applied to the source editor.
+    pub fn editor_css(&self) -> String {
+        let family = self.editor_font.replace('\\', "").replace('"', "");
+        format!("textview {{ font-family: \"{}\"; font-size: {}pt; line-height : 1.5; }}", family, self.editor_font_size)
+    }
+
+}
+
+/// Variant of the style scheme matching the application style, among the available
+/// scheme ids (e.g. Adwaita-dark for Adwaita in dark mode). Schemes without a
+/// variant are kept as they are.
+pub fn matching_scheme(scheme : &str, dark : bool, available : &[String]) -> String {
+    let has = |id : &str| available.iter().any(|a| a == id );
+    let base = scheme.trim_end_matches("-dark").trim_end_matches("-light");
+    let candidates = if dark {
+        [format!("{}-dark", base), String::from(scheme), format!("{}-dark", DEFAULT_COLOR_SCHEME)]
+    } else if scheme.ends_with("-dark") {
+        [String::from(base), format!("{}-light", base), String::from(DEFAULT_COLOR_SCHEME)]
+    } else {
+        [String::from(scheme), String::from(base), String::from(DEFAULT_COLOR_SCHEME)]
+    };
+    candidates.into_iter()
+        .find(|id| has(id) )
+        .unwrap_or_else(|| scheme.to_string() )
+}
+
+/// Calls f with the new preferences whenever any of the settings change, until it
+/// returns Continue(false) (e.g. when the widgets it updates are gone).
+pub fn connect_changed<F>(f : F)
+where
+    F : Fn(&Preferences) -> glib::Continue + 'static
+{
+    if let Some(settings) = settings() {
+        let handler : Rc<RefCell<Option<glib::SignalHandlerId>>> = Default::default();
+        let id = settings.connect_changed(None, {
+            let handler = handler.clone();
+            move |settings, _| {
+                if !f(&Preferences::read(settings)).0 {
+                    if let Some(id) = handler.take() {
+                        settings.disconnect(id);
+                    }
+                }
+            }
+        });
+        handler.replace(Some(id));
+    }
+}
+
+#[test]
+fn editor_css() {
+    let mut prefs = Preferences::default();
+    assert_eq!(prefs.editor_css(), "textview { font-family: \"Ubuntu Mono\"; font-size: 16pt; line-height : 1.5; }");
+    prefs.editor_font = String::from("Fira \"Code\"");
+    prefs.editor_font_size = 12;
+    assert_eq!(prefs.editor_css(), "textview { font-family: \"Fira Code\"; font-size: 12pt; line-height : 1.5; }");
+}
+
+#[test]
+fn scheme_variants() {
+    let available : Vec<String> = ["Adwaita", "Adwaita-dark", "classic", "classic-dark", "solarized-light", "solarized-dark", "cobalt"]
+        .iter().map(|s| s.to_string() ).collect();
+    assert_eq!(matching_scheme("Adwaita", true, &available), "Adwaita-dark");
+    assert_eq!(matching_scheme("Adwaita-dark", false, &available), "Adwaita");
+    assert_eq!(matching_scheme("solarized-light", true, &available), "solarized-dark");
+    assert_eq!(matching_scheme("solarized-dark", false, &available), "solarized-light");
+    assert_eq!(matching_scheme("cobalt", false, &available), "cobalt");
+    assert_eq!(matching_scheme("cobalt", true, &available), "cobalt");
+    assert_eq!(matching_scheme("classic", false, &available), "classic");
+}
@@ -8,29 +8,145 @@ use std::cell::RefCell;
 use serde::{Serialize, Deserialize};
 use stateful::React;
 use std::ops::Deref;
-use crate::ui::PapersWindow;
+use crate::ui::{PapersWindow, DocumentTabs};
 use std::thread;
 use stateful::PersistentState;
 use gtk4::prelude::*;
 use filecase::SingleArchiverImpl;
+use std::path::{Path, PathBuf};
+use std::hash::Hasher;
+
+// Maximum number of recent files kept, besides the pinned ones.
+pub const MAX_RECENT : usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentFile {
+
+    pub path : String,
+
+    // Document title, if one could be found at the document.
+    #[serde(default)]
+    pub title : Option<String>,
+
+    // Seconds since the UNIX epoch (zero for files listed by older versions).
+    #[serde(default)]
+    pub opened : u64,
+
+    // Pinned files are listed first, and never removed to keep the list size.
+    #[serde(default)]
+    pub pinned : bool
+
+}
+
+impl RecentFile {
+
+    pub fn exists(&self) -> bool {
+        Path::new(&self.path).is_file()
+    }
+
+    /// Whether the title or path contain the search text (ignoring case).
+    pub fn matches(&self, search : &str) -> bool {
+        let search = search.trim().to_lowercase();
+        search.is_empty() ||
+            self.path.to_lowercase().contains(&search) ||
+            self.title.as_ref().map(|t| t.to_lowercase().contains(&search) ).unwrap_or(false)
+    }
+
+}
+
+/// Picture of the first page of the document, saved when it is first shown after being opened.
+pub fn thumbnail_path(doc : &str) -> Option<PathBuf> {
+    let doc = Path::new(doc).canonicalize().unwrap_or(PathBuf::from(doc));
+    let mut hasher = siphasher::sip::SipHasher::new();
+    hasher.write(doc.to_string_lossy().as_bytes());
+    let mut path = filecase::get_datadir(crate::APP_ID)?;
+    path.push("thumbnails");
+    path.push(format!("{:016x}.png", hasher.finish()));
+    Some(path)
+}
+
+fn remove_thumbnail(doc : &str) {
+    if let Some(path) = thumbnail_path(doc).filter(|p| p.exists() ) {
+        let _ = std::fs::remove_file(path);
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InnerState {
     pub paned : filecase::PanedState,
     pub window : filecase::WindowState,
-    pub recent_files : Vec<String>
+
+    // Paths saved by older versions, moved to recent when the state is recovered.
+    #[serde(default, skip_serializing)]
+    recent_files : Vec<String>,
+
+    #[serde(default)]
+    pub recent : Vec<RecentFile>,
+
+    // Absent from settings saved by older versions.
+    #[serde(default)]
+    pub viewer : crate::ui::ViewerState
 }
 
 impl InnerState {
 
-    pub fn push_if_not_present(&mut self, path : &str) -> bool {
-        if self.recent_files.iter().position(|f| &f[..] == path ).is_none() {
-            self.recent_files.insert(0, path.to_string());
-            true
-        } else {
-            false
+    fn migrate_recent(&mut self) {
+        for path in std::mem::take(&mut self.recent_files) {
+            if !self.recent.iter().any(|r| r.path == path ) {
+                self.recent.push(RecentFile { path, title : None, opened : 0, pinned : false });
+            }
         }
     }
+
+    /// Moves the file to the top of the recent list (adding it if needed), then removes
+    /// the oldest files that are not pinned beyond the maximum list size.
+    pub fn push_recent(&mut self, path : &str, title : Option<String>) {
+        let pinned = self.recent.iter().any(|r| r.path == path && r.pinned );
+        self.recent.retain(|r| r.path != path );
+        self.recent.insert(0, RecentFile { path : path.to_string(), title, opened : crate::now(), pinned });
+        let mut n_unpinned = 0;
+        let mut removed = Vec::new();
+        self.recent.retain(|r| {
+            if r.pinned {
+                return true;
+            }
+            n_unpinned += 1;
+            if n_unpinned > MAX_RECENT {
+                removed.push(r.path.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed.iter().for_each(|path| remove_thumbnail(path) );
+    }
+
+    pub fn set_pinned(&mut self, path : &str, pinned : bool) {
+        if let Some(r) = self.recent.iter_mut().find(|r| r.path == path ) {
+            r.pinned = pinned;
+        }
+    }
+
+    pub fn remove_recent(&mut self, path : &str) {
+        self.recent.retain(|r| r.path != path );
+        remove_thumbnail(path);
+    }
+
+    /// Removes all recent files, except the pinned ones.
+    pub fn clear_recent(&mut self) {
+        for r in self.recent.iter().filter(|r| !r.pinned ) {
+            remove_thumbnail(&r.path);
+        }
+        self.recent.retain(|r| r.pinned );
+    }
+
+    /// Pinned files, then the others, from the most recently opened.
+    pub fn recent_sorted(&self) -> Vec<RecentFile> {
+        let mut recent = self.recent.clone();
+        recent.sort_by(|a, b| b.pinned.cmp(&a.pinned).then(b.opened.cmp(&a.opened)) );
+        recent
+    }
+
 }
 
 #[derive(Clone, Debug)]
@@ -52,20 +168,38 @@ impl Default for PapersState {
         PapersState(Rc::new(RefCell::new(InnerState {
             paned : filecase::PanedState { primary : 100, secondary : 400 },
             window : filecase::WindowState { width : 1024, height : 768 },
-            recent_files : Vec::new()
+            recent_files : Vec::new(),
+            recent : Vec::new(),
+            viewer : crate::ui::ViewerState::default()
         })))
     }
 
 }
 
-impl React<crate::ui::PapersWindow> for PapersState {
+impl PapersState {
+
+    /// Shows a new document as the last one was left: the viewer state is applied to it,
+    /// and its start screen lists the recent files.
+    pub fn update_document(&self, papers_win : &PapersWindow) {
+        let viewer = self.borrow().viewer.clone();
+        papers_win.editor.pdf_viewer.set_view_state(&viewer);
+        papers_win.start_screen.recent_list.refresh();
+    }
+
+}
 
-    fn react(&self, win : &PapersWindow) {
+// Bound once for the application window, which is shared by all documents.
+impl React<DocumentTabs> for PapersState {
+
+    fn react(&self, tabs : &DocumentTabs) {
         let state = self.clone();
-        let sidebar_paned = win.editor.sub_paned.clone();
-        win.window.connect_close_request(move |win| {
+        let tabs = tabs.clone();
+        tabs.window.clone().connect_close_request(move |win| {
             let mut state = state.borrow_mut();
             filecase::set_win_dims_on_close(&win, &mut state.window);
+            if let Some(doc) = tabs.active() {
+                state.viewer = doc.editor.pdf_viewer.view_state();
+            }
             gtk4::Inhibit(false)
         });
     }
@@ -87,23 +221,41 @@ impl React<crate::ui::PapersWindow> for PapersState {
 
 }*/
 
-impl PersistentState<PapersWindow> for PapersState {
+impl PersistentState<DocumentTabs> for PapersState {
 
     fn recover(path : &str) -> Option<PapersState> {
-        Some(PapersState(filecase::load_shared_serializable(path)?))
+        let state = PapersState(filecase::load_shared_serializable(path)?);
+        state.borrow_mut().migrate_recent();
+        Some(state)
     }
 
     fn persist(&self, path : &str) -> thread::JoinHandle<bool> {
         filecase::save_shared_serializable(&self.0, path)
     }
 
-    fn update(&self, papers_win : &PapersWindow) {
+    fn update(&self, tabs : &DocumentTabs) {
         let state = self.borrow();
-        papers_win.window.set_default_size(state.window.width, state.window.height);
-        for path in state.recent_files.iter() {
-            papers_win.start_screen.recent_list.add_row(&path[..], false);
-        }
+        tabs.window.set_default_size(state.window.width, state.window.height);
     }
 
 }
 
+#[test]
+fn recent_files() {
+    let mut state = PapersState::default().borrow().clone();
+    for i in 0..(MAX_RECENT + 5) {
+        state.push_recent(&format!("/doc{}.typ", i), None);
+    }
+    assert_eq!(state.recent.len(), MAX_RECENT);
+    assert_eq!(state.recent[0].path, format!("/doc{}.typ", MAX_RECENT + 4));
+
+    // Pinned files are kept when the list is full or cleared, and listed first.
+    let pinned = state.recent.last().unwrap().path.clone();
+    state.set_pinned(&pinned, true);
+    state.push_recent("/new.typ", Some(String::from("New")));
+    assert!(state.recent.iter().any(|r| r.path == pinned ));
+    assert_eq!(state.recent_sorted()[0].path, pinned);
+    assert!(state.recent[0].matches("NEW") && !state.recent[0].matches("other"));
+    state.clear_recent();
+    assert_eq!(state.recent.len(), 1);
+}
@@ -0,0 +1,446 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use gtk4::gio;
+
+/*
+Document templates. Each template is a directory containing the template source (template.typ),
+its metadata (template.json) and optionally a thumbnail (preview.png or preview.svg). Bundled
+templates are compiled into the application resources (under templates/), and templates saved
+by the user are kept at datadir/templates, one directory per template.
+
+Project templates have the files of a multi-file document under project/ instead of a single
+source. They are written to a new directory, marked as the project root by a drafts-project.json file.
+
+Templates can declare named parameters at their metadata. The values chosen for them when the
+document is created replace the {{name}} placeholders at the template source (or at the .typ files
+of projects). Placeholders are expected inside typst strings, so the values are escaped as such.
+*/
+
+const RESOURCE_DIR : &'static str = "/io/github/limads/drafts/templates";
+
+const SOURCE_FILE : &'static str = "template.typ";
+
+const META_FILE : &'static str = "template.json";
+
+const PREVIEW_FILES : [&'static str; 2] = ["preview.png", "preview.svg"];
+
+const PROJECT_DIR : &'static str = "project";
+
+pub const PROJECT_FILE : &'static str = "drafts-project.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateKind {
+
+    // A single source file, written to the editor when the template is chosen.
+    #[default]
+    Document,
+
+    // A directory of files (chapters, bibliography, etc) created for the document.
+    Project
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterKind {
+
+    #[default]
+    Text,
+
+    // Text spanning multiple lines (e.g. an abstract).
+    Paragraph,
+
+    // One of the parameter options.
+    Choice
+
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateParameter {
+
+    // Name used at the {{name}} placeholders.
+    pub name : String,
+
+    pub label : String,
+
+    #[serde(default)]
+    pub kind : ParameterKind,
+
+    #[serde(default)]
+    pub default : String,
+
+    #[serde(default)]
+    pub options : Vec<String>
+
+}
+
+fn default_order() -> u32 {
+    u32::MAX
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateMeta {
+
+    pub name : String,
+
+    #[serde(default)]
+    pub description : String,
+
+    // Position at the start screen. Templates without it (such as the ones saved
+    // by the user) follow the bundled templates, sorted by name.
+    #[serde(default = "default_order")]
+    pub order : u32,
+
+    #[serde(default)]
+    pub kind : TemplateKind,
+
+    #[serde(default)]
+    pub author : Option<String>,
+
+    // Seconds since the UNIX epoch.
+    #[serde(default)]
+    pub created : Option<u64>,
+
+    // Main file of project templates, relative to the project root.
+    #[serde(default)]
+    pub main : Option<String>,
+
+    // Directories created empty with project templates (e.g. for figures).
+    #[serde(default)]
+    pub directories : Vec<String>,
+
+    #[serde(default)]
+    pub parameters : Vec<TemplateParameter>
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplatePreview {
+    Resource(String),
+    File(PathBuf)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+
+    // Name of the template directory.
+    pub id : String,
+
+    pub meta : TemplateMeta,
+
+    pub source : String,
+
+    pub preview : Option<TemplatePreview>,
+
+    // Files of project templates, as (path relative to the project root, content).
+    pub files : Vec<(String, String)>,
+
+    // Whether the template was saved by the user (rather than bundled).
+    pub user : bool
+
+}
+
+pub fn user_templates_dir() -> Option<PathBuf> {
+    let mut dir = filecase::get_datadir(crate::APP_ID)?;
+    dir.push("templates");
+    Some(dir)
+}
+
+fn parse_meta(json : &str) -> Option<TemplateMeta> {
+    match serde_json::from_str::<TemplateMeta>(json) {
+        Ok(meta) => Some(meta),
+        Err(e) => {
+            log::warn!("Invalid template metadata: {}", e);
+            None
+        }
+    }
+}
+
+fn resource_text(path : &str) -> Option<String> {
+    let bytes = gio::resources_lookup_data(path, gio::ResourceLookupFlags::NONE).ok()?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+// Files under the resource directory, with paths relative to it.
+fn resource_files(dir : &str, prefix : &str, files : &mut Vec<(String, String)>) {
+    let Ok(children) = gio::resources_enumerate_children(dir, gio::ResourceLookupFlags::NONE) else { return };
+    for child in children.iter() {
+        if child.ends_with('/') {
+            let name = child.trim_end_matches('/');
+            resource_files(&format!("{}/{}", dir, name), &format!("{}{}/", prefix, name), files);
+        } else if let Some(content) = resource_text(&format!("{}/{}", dir, child)) {
+            files.push((format!("{}{}", prefix, child), content));
+        }
+    }
+}
+
+// Files under the directory, with paths relative to it.
+fn dir_files(dir : &Path, prefix : &str, files : &mut Vec<(String, String)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.filter_map(|e| e.ok() ) {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str() ) else { continue };
+        if path.is_dir() {
+            dir_files(&path, &format!("{}{}/", prefix, name), files);
+        } else if let Ok(content) = std::fs::read_to_string(&path) {
+            files.push((format!("{}{}", prefix, name), content));
+        }
+    }
+}
+
+fn load_bundled() -> Vec<Template> {
+    let Ok(children) = gio::resources_enumerate_children(RESOURCE_DIR, gio::ResourceLookupFlags::NONE) else {
+        return Vec::new()
+    };
+    children.iter().filter_map(|child| {
+        let id = child.trim_end_matches('/').to_string();
+        let dir = format!("{}/{}", RESOURCE_DIR, id);
+        let meta = parse_meta(&resource_text(&format!("{}/{}", dir, META_FILE))?)?;
+        let source = resource_text(&format!("{}/{}", dir, SOURCE_FILE)).unwrap_or_default();
+        let preview = PREVIEW_FILES.iter()
+            .map(|f| format!("{}/{}", dir, f) )
+            .find(|path| gio::resources_get_info(path, gio::ResourceLookupFlags::NONE).is_ok() )
+            .map(TemplatePreview::Resource);
+        let mut files = Vec::new();
+        if meta.kind == TemplateKind::Project {
+            resource_files(&format!("{}/{}", dir, PROJECT_DIR), "", &mut files);
+        }
+        Some(Template { id, meta, source, preview, files, user : false })
+    }).collect()
+}
+
+/// Templates at the subdirectories of the given directory.
+pub fn load_dir(dir : &Path, user : bool) -> Vec<Template> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if !path.is_dir() {
+            return None;
+        }
+        let id = path.file_name()?.to_str()?.to_string();
+        let meta = parse_meta(&std::fs::read_to_string(path.join(META_FILE)).ok()?)?;
+        let source = std::fs::read_to_string(path.join(SOURCE_FILE)).unwrap_or_default();
+        let preview = PREVIEW_FILES.iter()
+            .map(|f| path.join(f) )
+            .find(|p| p.exists() )
+            .map(TemplatePreview::File);
+        let mut files = Vec::new();
+        if meta.kind == TemplateKind::Project {
+            dir_files(&path.join(PROJECT_DIR), "", &mut files);
+        }
+        Some(Template { id, meta, source, preview, files, user })
+    }).collect()
+}
+
+/// Bundled templates followed by the user templates.
+pub fn list_templates() -> Vec<Template> {
+    let mut templates = load_bundled();
+    if let Some(dir) = user_templates_dir() {
+        templates.extend(load_dir(&dir, true));
+    }
+    templates.sort_by(|a, b| a.meta.order.cmp(&b.meta.order).then_with(|| a.meta.name.cmp(&b.meta.name) ) );
+
+    // Templates offering a paper size suggest the one set at the preferences.
+    let paper = crate::settings::Preferences::current().default_paper;
+    for template in templates.iter_mut() {
+        set_default(template, "paper", &paper);
+    }
+    templates
+}
+
+/// Changes the default value of a template parameter. Choices only accept one of their options.
+pub fn set_default(template : &mut Template, name : &str, value : &str) {
+    if let Some(param) = template.meta.parameters.iter_mut().find(|p| p.name == name ) {
+        if param.kind != ParameterKind::Choice || param.options.iter().any(|o| o == value ) {
+            param.default = value.to_string();
+        }
+    }
+}
+
+/// Directory name for a new template (or project), derived from its name.
+pub fn template_id(name : &str) -> String {
+    let id : String = name.trim().to_lowercase().chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' } )
+        .collect();
+    let id = id.split('-').filter(|s| !s.is_empty() ).collect::<Vec<_>>().join("-");
+    if id.is_empty() { String::from("template") } else { id }
+}
+
+/// Writes a new template at a subdirectory of root, returning the template directory
+/// (where a thumbnail can be written as preview.png). Existing templates are not replaced.
+pub fn save_template_at(root : &Path, meta : &TemplateMeta, source : &str) -> Result<PathBuf, String> {
+    let id = template_id(&meta.name);
+    let mut dir = root.join(&id);
+    let mut n = 2;
+    while dir.exists() {
+        dir = root.join(format!("{}-{}", id, n));
+        n += 1;
+    }
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string() )?;
+    let json = serde_json::to_string_pretty(meta).map_err(|e| e.to_string() )?;
+    std::fs::write(dir.join(META_FILE), json).map_err(|e| e.to_string() )?;
+    std::fs::write(dir.join(SOURCE_FILE), source).map_err(|e| e.to_string() )?;
+    Ok(dir)
+}
+
+pub fn save_template(meta : &TemplateMeta, source : &str) -> Result<PathBuf, String> {
+    let root = user_templates_dir().ok_or(String::from("No data directory"))?;
+    save_template_at(&root, meta, source)
+}
+
+/// Removes a template saved by the user.
+pub fn remove_template(template : &Template) -> Result<(), String> {
+    if !template.user {
+        return Err(String::from("Bundled templates cannot be removed"));
+    }
+    let dir = user_templates_dir().ok_or(String::from("No data directory"))?.join(&template.id);
+    std::fs::remove_dir_all(&dir).map_err(|e| e.to_string() )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectInfo {
+    pub title : String,
+    pub main : String
+}
+
+fn escape_string(s : &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Replaces the {{name}} placeholders by the values, escaped for typst strings.
+pub fn fill_placeholders(src : &str, values : &[(String, String)]) -> String {
+    let mut out = src.to_string();
+    for (name, value) in values {
+        out = out.replace(&format!("{{{{{}}}}}", name), &escape_string(value));
+    }
+    out
+}
+
+/// Values of the template parameters when none is chosen.
+pub fn default_values(template : &Template) -> Vec<(String, String)> {
+    template.meta.parameters.iter().map(|p| (p.name.clone(), p.default.clone()) ).collect()
+}
+
+/// Source of a document template with the given parameter values.
+pub fn instantiate(template : &Template, values : &[(String, String)]) -> String {
+    fill_placeholders(&template.source, values)
+}
+
+/// Writes the files of a project template to the target directory (which must not contain any
+/// of them), returning the path to the main file.
+pub fn scaffold_project(template : &Template, target : &Path, values : &[(String, String)]) -> Result<PathBuf, String> {
+    if template.meta.kind != TemplateKind::Project {
+        return Err(String::from("Not a project template"));
+    }
+    if let Some((existing, _)) = template.files.iter().find(|(f, _)| target.join(f).exists() ) {
+        return Err(format!("File {} already exists at {}", existing, target.display()));
+    }
+    for (file, content) in template.files.iter() {
+        let path = target.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string() )?;
+        }
+        let content = if file.ends_with(".typ") { fill_placeholders(content, values) } else { content.clone() };
+        std::fs::write(&path, content).map_err(|e| e.to_string() )?;
+    }
+    for dir in template.meta.directories.iter() {
+        std::fs::create_dir_all(target.join(dir)).map_err(|e| e.to_string() )?;
+    }
+    let main = template.meta.main.clone().unwrap_or(String::from("main.typ"));
+    let title = values.iter().find(|(name, _)| name == "title" ).map(|(_, v)| v.clone() ).unwrap_or_default();
+    let info = ProjectInfo { title, main : main.clone() };
+    let json = serde_json::to_string_pretty(&info).map_err(|e| e.to_string() )?;
+    std::fs::write(target.join(PROJECT_FILE), json).map_err(|e| e.to_string() )?;
+    Ok(target.join(main))
+}
+
+/// Root directory of the project containing the file, if the file belongs to one.
+pub fn project_root(file : &Path) -> Option<PathBuf> {
+    file.parent()?.ancestors().find(|dir| dir.join(PROJECT_FILE).is_file() ).map(|dir| dir.to_owned() )
+}
+
+#[test]
+fn user_templates() {
+    let root = tempfile::tempdir().unwrap();
+    let meta = TemplateMeta {
+        name : String::from("Lab notes"),
+        description : String::from("Weekly notes"),
+        order : default_order(),
+        kind : TemplateKind::Document,
+        author : None,
+        created : Some(1700000000),
+        main : None,
+        directories : Vec::new(),
+        parameters : Vec::new()
+    };
+    let first = save_template_at(root.path(), &meta, "= Week").unwrap();
+    let second = save_template_at(root.path(), &meta, "= Week 2").unwrap();
+    assert!(first.ends_with("lab-notes") && second.ends_with("lab-notes-2"));
+    let mut templates = load_dir(root.path(), true);
+    templates.sort_by(|a, b| a.id.cmp(&b.id) );
+    assert_eq!(templates.len(), 2);
+    assert_eq!((&templates[0].meta, &templates[0].source[..]), (&meta, "= Week"));
+    assert!(templates[0].preview.is_none());
+
+    let meta = parse_meta(r#"{ "name" : "Book", "kind" : "project" }"#).unwrap();
+    assert_eq!((meta.kind, meta.order, meta.description.is_empty()), (TemplateKind::Project, u32::MAX, true));
+}
+
+#[test]
+fn project_scaffold() {
+    let target = tempfile::tempdir().unwrap();
+    let template = Template {
+        id : String::from("report"),
+        meta : parse_meta(r#"{ "name" : "Report", "kind" : "project", "directories" : ["figures"] }"#).unwrap(),
+        source : String::new(),
+        preview : None,
+        files : vec![
+            (String::from("main.typ"), String::from("#let title = \"{{title}}\"\n#include \"chapters/intro.typ\"")),
+            (String::from("chapters/intro.typ"), String::from("= Introduction"))
+        ],
+        user : false
+    };
+    let values = vec![(String::from("title"), String::from("A \"quoted\" title"))];
+    let main = scaffold_project(&template, target.path(), &values).unwrap();
+    assert_eq!(main, target.path().join("main.typ"));
+    let content = std::fs::read_to_string(&main).unwrap();
+    assert!(content.starts_with("#let title = \"A \\\"quoted\\\" title\""));
+    assert!(target.path().join("figures").is_dir());
+    assert_eq!(project_root(&target.path().join("chapters/intro.typ")).as_deref(), Some(target.path()));
+
+    // Files are never overwritten.
+    assert!(scaffold_project(&template, target.path(), &values).is_err());
+}
+
+#[test]
+fn template_parameters() {
+    let meta = parse_meta(r#"{
+        "name" : "Article",
+        "parameters" : [
+            { "name" : "title", "label" : "Title", "default" : "Title" },
+            { "name" : "paper", "label" : "Paper size", "kind" : "choice", "default" : "a4", "options" : ["a4", "us-letter"] }
+        ]
+    }"#).unwrap();
+    assert_eq!(meta.parameters[1].kind, ParameterKind::Choice);
+    let mut template = Template {
+        id : String::from("article"),
+        meta,
+        source : String::from("#let title = \"{{title}}\"\n#set page(paper: \"{{paper}}\")"),
+        preview : None,
+        files : Vec::new(),
+        user : false
+    };
+    assert_eq!(instantiate(&template, &default_values(&template)), "#let title = \"Title\"\n#set page(paper: \"a4\")");
+    let values = vec![(String::from("title"), String::from("Notes")), (String::from("paper"), String::from("us-letter"))];
+    assert_eq!(instantiate(&template, &values), "#let title = \"Notes\"\n#set page(paper: \"us-letter\")");
+    set_default(&mut template, "paper", "a3");
+    assert_eq!(template.meta.parameters[1].default, "a4");
+    set_default(&mut template, "paper", "us-letter");
+    assert_eq!(template.meta.parameters[1].default, "us-letter");
+}
@@ -0,0 +1,1117 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+
+/*
+Translation of LaTeX math into typst math. The lexer is used to find the math
+fragments at a pasted text (either as Token::Math with its MathQuote or as the
+content between \begin{env} and \end{env} commands of a math environment). The content
+of each fragment is then translated by a small recursive-descent translator that works
+directly over the characters, since the lexer does not keep the nesting structure of
+the command arguments, which is essential to translate math (e.g. x^{\frac{a}{b}}).
+*/
+
+// Greek letters, as (symbol, name). Except for epsilon and phi (see GREEK_VARIANTS), the
+// names are the same in LaTeX and typst math.
+pub const GREEK_SMALL : [(&'static str, &'static str); 24] = [
+    ("α", "alpha"),
+    ("β", "beta"),
+    ("γ", "gamma"),
+    ("δ", "delta"),
+    ("ε", "epsilon"),
+    ("ζ", "zeta"),
+    ("η", "eta"),
+    ("θ", "theta"),
+    ("ι", "iota"),
+    ("κ", "kappa"),
+    ("λ", "lambda"),
+    ("μ", "mu"),
+    ("ν", "nu"),
+    ("ξ", "xi"),
+    ("ο", "omicron"),
+    ("π", "pi"),
+    ("ρ", "rho"),
+    ("σ", "sigma"),
+    ("τ", "tau"),
+    ("υ", "upsilon"),
+    ("φ", "phi"),
+    ("χ", "chi"),
+    ("ψ", "psi"),
+    ("ω", "omega")
+];
+
+pub const GREEK_CAPITAL : [(&'static str, &'static str); 24] = [
+    ("Α", "Alpha"),
+    ("Β", "Beta"),
+    ("Γ", "Gamma"),
+    ("Δ", "Delta"),
+    ("Ε", "Epsilon"),
+    ("Ζ", "Zeta"),
+    ("Η", "Eta"),
+    ("Θ", "Theta"),
+    ("Ι", "Iota"),
+    ("Κ", "Kappa"),
+    ("Λ", "Lambda"),
+    ("Μ", "Mu"),
+    ("Ν", "Nu"),
+    ("Ξ", "Xi"),
+    ("Ο", "Omicron"),
+    ("Π", "Pi"),
+    ("Ρ", "Rho"),
+    ("Σ", "Sigma"),
+    ("Τ", "Tau"),
+    ("Υ", "Upsilon"),
+    ("Φ", "Phi"),
+    ("Χ", "Chi"),
+    ("Ψ", "Psi"),
+    ("Ω", "Omega")
+];
+
+// Environments that switch LaTeX to display math mode outside $$..$$.
+const MATH_ENVIRONMENTS : [&'static str; 12] = [
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "eqnarray",
+    "eqnarray*",
+    "displaymath",
+    "math"
+];
+
+// LaTeX commands that have a typst symbol equivalent. Greek letters are not listed
+// here (see GREEK_SMALL, GREEK_CAPITAL and GREEK_VARIANTS).
+// The typst side matches the names used at the OPERATORS table of the symbol popover.
+const LATEX_SYMBOLS : [(&'static str, &'static str); 96] = [
+    ("neq", "!="),
+    ("ne", "!="),
+    ("approx", "approx"),
+    ("geq", ">="),
+    ("ge", ">="),
+    ("leq", "<="),
+    ("le", "<="),
+    ("ll", "<<"),
+    ("gg", ">>"),
+    ("times", "times"),
+    ("div", "div"),
+    ("cdot", "dot"),
+    ("pm", "plus.minus"),
+    ("mp", "minus.plus"),
+    ("ast", "*"),
+    ("star", "star"),
+    ("circ", "compose"),
+    ("bullet", "bullet"),
+    ("sum", "sum"),
+    ("prod", "product"),
+    ("coprod", "product.co"),
+    ("partial", "diff"),
+    ("nabla", "nabla"),
+    ("int", "integral"),
+    ("iint", "integral.double"),
+    ("iiint", "integral.triple"),
+    ("oint", "integral.cont"),
+    ("sim", "tilde"),
+    ("simeq", "tilde.eq"),
+    ("cong", "tilde.equiv"),
+    ("equiv", "equiv"),
+    ("propto", "prop"),
+    ("perp", "perp"),
+    ("parallel", "parallel"),
+    ("mid", "divides"),
+    ("wedge", "and"),
+    ("land", "and"),
+    ("vee", "or"),
+    ("lor", "or"),
+    ("neg", "not"),
+    ("lnot", "not"),
+    ("forall", "forall"),
+    ("exists", "exists"),
+    ("nexists", "exists.not"),
+    ("triangle", "triangle"),
+    ("therefore", "therefore"),
+    ("because", "because"),
+    ("in", "in"),
+    ("notin", "in.not"),
+    ("ni", "in.rev"),
+    ("cup", "union"),
+    ("cap", "sect"),
+    ("bigcup", "union.big"),
+    ("bigcap", "sect.big"),
+    ("setminus", "without"),
+    ("subset", "subset"),
+    ("supset", "supset"),
+    ("subseteq", "subset.eq"),
+    ("supseteq", "supset.eq"),
+    ("emptyset", "emptyset"),
+    ("varnothing", "emptyset"),
+    ("infty", "infinity"),
+    ("to", "->"),
+    ("rightarrow", "->"),
+    ("leftarrow", "<-"),
+    ("gets", "<-"),
+    ("leftrightarrow", "<->"),
+    ("Rightarrow", "=>"),
+    ("implies", "=>"),
+    ("Leftarrow", "arrow.l.double"),
+    ("Leftrightarrow", "<=>"),
+    ("iff", "<=>"),
+    ("mapsto", "|->"),
+    ("uparrow", "arrow.t"),
+    ("downarrow", "arrow.b"),
+    ("ldots", "dots"),
+    ("dots", "dots"),
+    ("cdots", "dots.c"),
+    ("vdots", "dots.v"),
+    ("ddots", "dots.down"),
+    ("prime", "prime"),
+    ("angle", "angle"),
+    ("langle", "angle.l"),
+    ("rangle", "angle.r"),
+    ("lfloor", "floor.l"),
+    ("rfloor", "floor.r"),
+    ("lceil", "ceil.l"),
+    ("rceil", "ceil.r"),
+    ("hbar", "planck.reduce"),
+    ("ell", "ell"),
+    ("aleph", "aleph"),
+    ("Re", "Re"),
+    ("Im", "Im"),
+    ("degree", "degree"),
+    ("dagger", "dagger"),
+    ("top", "top")
+];
+
+// Greek letter variants, which have no entry at the greek symbol tables, and the letters
+// whose LaTeX and typst names differ. LaTeX \epsilon and \phi are the lunate/stroked ϵ and ϕ,
+// which typst calls epsilon.alt and phi.alt, while typst epsilon and phi are LaTeX \varepsilon
+// and \varphi. These are looked up before the greek symbol tables.
+const GREEK_VARIANTS : [(&'static str, &'static str); 9] = [
+    ("varepsilon", "epsilon"),
+    ("epsilon", "epsilon.alt"),
+    ("vartheta", "theta.alt"),
+    ("varpi", "pi.alt"),
+    ("varrho", "rho.alt"),
+    ("varsigma", "sigma.alt"),
+    ("varphi", "phi"),
+    ("phi", "phi.alt"),
+    ("varkappa", "kappa.alt")
+];
+
+// Operator names that are also typst math operators with the same name.
+const OPERATOR_NAMES : [&'static str; 32] = [
+    "arccos", "arcsin", "arctan", "arg", "cos", "cosh", "cot", "coth", "csc", "deg", "det", "dim",
+    "exp", "gcd", "hom", "inf", "ker", "lg", "lim", "liminf", "limsup", "ln", "log", "max", "min",
+    "Pr", "sec", "sin", "sinh", "sup", "tan", "tanh"
+];
+
+// Font style commands and the typst function applied to their argument.
+const STYLES : [(&'static str, &'static str); 12] = [
+    ("mathbf", "bold"),
+    ("boldsymbol", "bold"),
+    ("bm", "bold"),
+    ("mathit", "italic"),
+    ("mathrm", "upright"),
+    ("mathcal", "cal"),
+    ("mathscr", "cal"),
+    ("mathbb", "bb"),
+    ("mathfrak", "frak"),
+    ("mathsf", "sans"),
+    ("mathtt", "mono"),
+    ("mathnormal", "italic")
+];
+
+// Accent commands and the equivalent typst accent function.
+const ACCENTS : [(&'static str, &'static str); 15] = [
+    ("hat", "hat"),
+    ("widehat", "hat"),
+    ("tilde", "tilde"),
+    ("widetilde", "tilde"),
+    ("bar", "macron"),
+    ("overline", "overline"),
+    ("underline", "underline"),
+    ("vec", "arrow"),
+    ("overrightarrow", "arrow"),
+    ("dot", "dot"),
+    ("ddot", "dot.double"),
+    ("breve", "breve"),
+    ("acute", "acute"),
+    ("grave", "grave"),
+    ("check", "caron")
+];
+
+// Commands that only change sizes or numbering, and have no typst counterpart.
+const IGNORED : [&'static str; 9] = [
+    "displaystyle",
+    "textstyle",
+    "scriptstyle",
+    "scriptscriptstyle",
+    "limits",
+    "nolimits",
+    "nonumber",
+    "notag",
+    "middle"
+];
+
+/// Translates the content of a LaTeX math expression (without the enclosing
+/// dollar signs or environment commands) into a typst math expression.
+pub fn math_to_typst(latex : &str) -> String {
+    translate_math(latex).0
+}
+
+/// Translates a LaTeX fragment, replacing each math expression it contains ($...$, $$...$$, \[...\]
+/// or a math environment such as \begin{align}...\end{align}) by the equivalent typst equation.
+/// Text outside math is kept as is. Labels set with \label inside an equation are
+/// moved after the typst equation.
+pub fn latex_to_typst(txt : &str) -> Result<String, TexError> {
+    let txt = normalize_math_delimiters(txt);
+    if is_bare_math(&txt) {
+        return Ok(equation(&txt, false));
+    }
+    let info = Lexer::scan(&txt)?.to_owned();
+    let mut out = String::new();
+    let mut ix = 0;
+    while ix < info.kinds.len() {
+        let range = info.pos[ix].clone();
+        let tk_txt = &txt[range.clone()];
+        match info.kinds[ix] {
+            TokenKind::Math => {
+                match Token::from_str(tk_txt) {
+                    Ok(Token::Math(math, quote, _)) => {
+                        out += &equation(math, quote == MathQuote::Double);
+                    },
+                    _ => {
+                        out += tk_txt;
+                    }
+                }
+            },
+            TokenKind::Command => {
+                if let Some(env) = math_environment(tk_txt) {
+                    if let Some(end_ix) = environment_end(&info, ix, &env) {
+                        let body = &txt[range.end..info.pos[end_ix].start];
+                        out += &equation(body, true);
+                        ix = end_ix + 1;
+                        continue;
+                    }
+                }
+                out += tk_txt;
+            },
+            _ => {
+                out += tk_txt;
+            }
+        }
+        ix += 1;
+    }
+    Ok(out)
+}
+
+/// Verifies if a text has any LaTeX math that can be translated by latex_to_typst.
+/// Math fragments that do not use any LaTeX command or group are not considered,
+/// since they might be typst math already.
+pub fn contains_latex_math(txt : &str) -> bool {
+    let txt = normalize_math_delimiters(txt);
+    if is_bare_math(&txt) {
+        return true;
+    }
+    let Ok(tks) = Lexer::scan(&txt) else { return false };
+    tks.iter().any(|tk| {
+        match tk {
+            Token::Math(math, _, _) => is_latex_math(math),
+            Token::Command(cmd, _) => {
+                cmd.cmd == "begin" && cmd.arg.as_ref()
+                    .map(|arg| is_math_environment(&arg.to_string()) )
+                    .unwrap_or(false)
+            },
+            _ => false
+        }
+    })
+}
+
+/// LaTeX command (without the leading backslash) for a typst math symbol, such as
+/// "alpha", "->" or "arrow.l.double". The first LaTeX command listed for a typst
+/// symbol is preferred (e.g. \neq instead of \ne).
+pub fn typst_symbol_to_latex(name : &str) -> Option<&'static str> {
+    find_latex_in(&GREEK_VARIANTS[..], name)
+        .or_else(|| GREEK_SMALL.iter().chain(GREEK_CAPITAL.iter()).find(|(_, typ)| *typ == name ).map(|(_, typ)| *typ ) )
+        .or_else(|| find_latex_in(&LATEX_SYMBOLS[..], name) )
+        .or_else(|| OPERATOR_NAMES.iter().find(|op| **op == name ).map(|op| *op ) )
+}
+
+/// LaTeX font style command for a typst math style function (e.g. bb -> mathbb).
+pub fn typst_style_to_latex(func : &str) -> Option<&'static str> {
+    find_latex_in(&STYLES[..], func)
+}
+
+/// LaTeX accent command for a typst math accent function (e.g. arrow -> vec).
+pub fn typst_accent_to_latex(func : &str) -> Option<&'static str> {
+    find_latex_in(&ACCENTS[..], func)
+}
+
+fn find_latex_in(table : &[(&'static str, &'static str)], name : &str) -> Option<&'static str> {
+    table.iter().find(|(_, typ)| *typ == name ).map(|(tex, _)| *tex )
+}
+
+fn is_latex_math(math : &str) -> bool {
+    let mut chars = math.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => return true,
+            '\\' => if chars.peek().map(|c| c.is_ascii_alphabetic() ).unwrap_or(false) {
+                return true;
+            },
+            _ => { }
+        }
+    }
+    false
+}
+
+// Equations copied from Wikipedia come wrapped as {\displaystyle ...}, without any dollar signs.
+fn is_bare_math(txt : &str) -> bool {
+    txt.trim_start().starts_with("{\\displaystyle")
+}
+
+// Replaces \[...\] and \(...\) by $$...$$ and $...$, which are the only math delimiters
+// recognized by the lexer. Line breaks (\\) are kept, so \\[2pt] is not mistaken for \[.
+fn normalize_math_delimiters(txt : &str) -> String {
+    let mut out = String::with_capacity(txt.len());
+    let mut chars = txt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('\\') => {
+                out.push_str("\\\\");
+                chars.next();
+            },
+            Some('[') | Some(']') => {
+                out.push_str("$$");
+                chars.next();
+            },
+            Some('(') | Some(')') => {
+                out.push('$');
+                chars.next();
+            },
+            _ => {
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+fn equation(latex : &str, display : bool) -> String {
+    let (body, labels) = translate_math(latex);
+    let mut eq = if display {
+        format!("$ {} $", body)
+    } else {
+        format!("${}$", body)
+    };
+    for lbl in labels {
+        eq += &format!(" <{}>", lbl);
+    }
+    eq
+}
+
+fn is_math_environment(env : &str) -> bool {
+    MATH_ENVIRONMENTS.iter().any(|e| *e == env )
+}
+
+// Returns the environment name if this command token starts a math environment.
+fn math_environment(cmd_txt : &str) -> Option<String> {
+    let (_, cmd) = command(cmd_txt).ok()?;
+    if cmd.cmd == "begin" {
+        let env = cmd.arg?.to_string();
+        if is_math_environment(&env) {
+            return Some(env);
+        }
+    }
+    None
+}
+
+// Index of the token that closes the environment started at token start_ix.
+fn environment_end(info : &TokenInfo, start_ix : usize, env : &str) -> Option<usize> {
+    let mut depth = 0;
+    for ix in (start_ix+1)..info.kinds.len() {
+        if info.kinds[ix] != TokenKind::Command {
+            continue;
+        }
+        if let Ok((_, cmd)) = command(&info.txt[info.pos[ix].clone()]) {
+            let same_env = cmd.arg.as_ref().map(|a| &a.to_string()[..] == env ).unwrap_or(false);
+            if same_env && cmd.cmd == "begin" {
+                depth += 1;
+            } else if same_env && cmd.cmd == "end" {
+                if depth == 0 {
+                    return Some(ix);
+                }
+                depth -= 1;
+            }
+        }
+    }
+    None
+}
+
+// Splits the body of an environment into rows (separated by \\) and
+// cells (separated by &), ignoring any separators within groups or nested environments.
+fn split_rows(body : &str) -> Vec<Vec<String>> {
+    let chars : Vec<char> = body.chars().collect();
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut depth = 0;
+    let mut env_depth = 0;
+    let mut ix = 0;
+    while ix < chars.len() {
+        let c = chars[ix];
+        let rest : String = chars[ix..].iter().take(6).collect();
+        if c == '\\' && ix + 1 < chars.len() {
+            let next = chars[ix+1];
+            if next == '\\' && depth == 0 && env_depth == 0 {
+                row.push(cell.trim().to_string());
+                rows.push(std::mem::take(&mut row));
+                cell.clear();
+                ix += 2;
+
+                // Skip optional vertical spacing after the line break (e.g. \\[2pt])
+                let mut peek = ix;
+                while peek < chars.len() && chars[peek].is_whitespace() {
+                    peek += 1;
+                }
+                if peek < chars.len() && chars[peek] == '[' {
+                    while peek < chars.len() && chars[peek] != ']' {
+                        peek += 1;
+                    }
+                    ix = peek + 1;
+                }
+                continue;
+            }
+            if rest.starts_with("\\begin") {
+                env_depth += 1;
+            } else if rest.starts_with("\\end") {
+                env_depth -= 1;
+            }
+
+            // Escaped characters (\&, \{, \\ inside groups) are copied verbatim.
+            cell.push(c);
+            cell.push(next);
+            ix += 2;
+            continue;
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '&' if depth == 0 && env_depth == 0 => {
+                row.push(cell.trim().to_string());
+                cell.clear();
+                ix += 1;
+                continue;
+            },
+            _ => { }
+        }
+        cell.push(c);
+        ix += 1;
+    }
+    if !cell.trim().is_empty() || !row.is_empty() {
+        row.push(cell.trim().to_string());
+        rows.push(row);
+    }
+    rows
+}
+
+fn translate_math(latex : &str) -> (String, Vec<String>) {
+    let mut tr = MathTranslator::new(latex);
+    let out = tr.expr(false);
+    (out.trim().to_string(), tr.labels)
+}
+
+fn find_symbol(name : &str) -> Option<&'static str> {
+    GREEK_VARIANTS.iter()
+        .find(|(tex, _)| *tex == name )
+        .map(|(_, typ)| *typ )
+        .or_else(|| GREEK_SMALL.iter().chain(GREEK_CAPITAL.iter()).find(|(_, typ)| *typ == name ).map(|(_, typ)| *typ ) )
+        .or_else(|| LATEX_SYMBOLS.iter().find(|(tex, _)| *tex == name ).map(|(_, typ)| *typ ) )
+        .or_else(|| OPERATOR_NAMES.iter().find(|op| **op == name ).map(|op| *op ) )
+}
+
+fn find_in(table : &[(&'static str, &'static str)], name : &str) -> Option<&'static str> {
+    table.iter().find(|(tex, _)| *tex == name ).map(|(_, typ)| *typ )
+}
+
+// Typst delimiter equivalent to a LaTeX delimiter following \left, \right or \big.
+fn delimiter(delim : &str) -> String {
+    match delim {
+        "." => String::new(),
+        "\\{" | "\\lbrace" => String::from("{"),
+        "\\}" | "\\rbrace" => String::from("}"),
+        "\\|" | "\\Vert" => String::from("||"),
+        "\\vert" => String::from("|"),
+        other => {
+            if let Some(name) = other.strip_prefix('\\') {
+                find_symbol(name).map(|s| s.to_string() ).unwrap_or(name.to_string())
+            } else {
+                other.to_string()
+            }
+        }
+    }
+}
+
+// Attachment arguments that are not a single number, letter or symbol must be
+// wrapped in parenthesis (which are not shown by typst).
+fn attachment(arg : &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_alphanumeric() || c == '.' ) {
+        arg.to_string()
+    } else {
+        format!("({})", arg)
+    }
+}
+
+fn quoted(txt : &str) -> String {
+    format!("\"{}\"", txt.replace('"', "\\\""))
+}
+
+enum Atom {
+
+    // A translated piece of math, separated by a space from the previous atom if required.
+    Text(String),
+
+    // Whitespace at the LaTeX source (e.g. \quad, \ , ~)
+    Space,
+
+    // Opening of a \left delimiter
+    LeftDelim(String),
+
+    // Closing of a \right delimiter
+    RightDelim(String),
+
+    None
+
+}
+
+struct MathTranslator {
+
+    chars : Vec<char>,
+
+    pos : usize,
+
+    labels : Vec<String>
+
+}
+
+impl MathTranslator {
+
+    fn new(latex : &str) -> Self {
+        Self { chars : latex.chars().collect(), pos : 0, labels : Vec::new() }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().map(|c| c.is_whitespace() ).unwrap_or(false) {
+            self.pos += 1;
+        }
+    }
+
+    // Reads a command name, after the backslash was consumed. Commands are either
+    // a sequence of letters (\alpha) or a single non-letter character (\, or \{).
+    fn command_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphabetic() {
+                name.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            if let Some(c) = self.peek() {
+                name.push(c);
+                self.pos += 1;
+            }
+        }
+        name
+    }
+
+    // Reads the raw text of the next argument: the content of a {} group,
+    // a single command or a single character.
+    fn raw_arg(&mut self) -> String {
+        self.skip_spaces();
+        match self.peek() {
+            Some('{') => self.raw_delimited('{', '}'),
+            Some('\\') => {
+                self.pos += 1;
+                format!("\\{}", self.command_name())
+            },
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            },
+            None => String::new()
+        }
+    }
+
+    // Reads an optional bracketed argument (e.g. \sqrt[3]{x})
+    fn raw_opt_arg(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if self.peek() == Some('[') {
+            Some(self.raw_delimited('[', ']'))
+        } else {
+            None
+        }
+    }
+
+    fn raw_delimited(&mut self, open : char, close : char) -> String {
+        let mut depth = 0;
+        let mut raw = String::new();
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\\' {
+                raw.push(c);
+                if let Some(next) = self.peek() {
+                    raw.push(next);
+                    self.pos += 1;
+                }
+                continue;
+            }
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+            raw.push(c);
+        }
+        raw
+    }
+
+    // Reads the raw body of an environment up to its matching \end command.
+    fn raw_environment(&mut self, env : &str) -> String {
+        let begin = format!("\\begin{{{}}}", env);
+        let end = format!("\\end{{{}}}", env);
+        let mut depth = 0;
+        let start = self.pos;
+        while self.pos < self.chars.len() {
+            let rest : String = self.chars[self.pos..].iter().take(end.len().max(begin.len())).collect();
+            if rest.starts_with(&begin) {
+                depth += 1;
+            } else if rest.starts_with(&end) {
+                if depth == 0 {
+                    let body : String = self.chars[start..self.pos].iter().collect();
+                    self.pos += end.chars().count();
+                    return body;
+                }
+                depth -= 1;
+            }
+            self.pos += 1;
+        }
+        self.chars[start..].iter().collect()
+    }
+
+    // Translates a nested piece of LaTeX, keeping any labels found inside it.
+    fn sub(&mut self, raw : &str, escape : bool) -> String {
+        let mut tr = MathTranslator::new(raw);
+        let out = tr.expr(escape);
+        self.labels.extend(tr.labels);
+        out.trim().to_string()
+    }
+
+    // Translates the next argument to be used as an argument of a typst function call.
+    fn call_arg(&mut self) -> String {
+        let raw = self.raw_arg();
+        self.sub(&raw, true)
+    }
+
+    // Translates the remaining input. If escape is true, commas and semicolons at the top
+    // level are escaped, since the output will be used as an argument to a typst function.
+    fn expr(&mut self, escape : bool) -> String {
+        let mut out = String::new();
+        let mut pending_space = false;
+
+        // Parenthesis depth. Commas within parenthesis do not separate typst arguments.
+        let mut depth : usize = 0;
+
+        // Delimiters opened by \left that are not parenthesis, within which commas must be escaped.
+        let mut lr_stack : Vec<bool> = Vec::new();
+
+        while let Some(c) = self.peek() {
+            let must_escape = (escape && depth == 0) || lr_stack.last().copied().unwrap_or(false);
+            let atom = match c {
+                c if c.is_whitespace() => {
+                    self.pos += 1;
+                    Atom::Space
+                },
+                '%' => {
+                    while self.peek().map(|c| c != '\n' ).unwrap_or(false) {
+                        self.pos += 1;
+                    }
+                    Atom::None
+                },
+                '\\' => {
+                    self.pos += 1;
+                    let name = self.command_name();
+                    self.command(&name)
+                },
+                '{' => {
+                    let raw = self.raw_delimited('{', '}');
+                    Atom::Text(self.sub(&raw, must_escape))
+                },
+                '}' => {
+                    // Unbalanced closing brace: ignore it.
+                    self.pos += 1;
+                    Atom::None
+                },
+                '^' | '_' => {
+                    self.pos += 1;
+                    let raw = self.raw_arg();
+                    let arg = self.sub(&raw, false);
+                    out.push(c);
+                    out += &attachment(&arg);
+                    pending_space = false;
+                    continue;
+                },
+                '~' => {
+                    self.pos += 1;
+                    Atom::Space
+                },
+                '(' | '[' => {
+                    self.pos += 1;
+                    depth += 1;
+                    Atom::Text(c.to_string())
+                },
+                ')' | ']' => {
+                    self.pos += 1;
+                    depth = depth.saturating_sub(1);
+                    Atom::Text(c.to_string())
+                },
+                ',' | ';' if must_escape => {
+                    self.pos += 1;
+                    Atom::Text(format!("\\{}", c))
+                },
+                '/' | '"' | '#' | '$' => {
+                    self.pos += 1;
+                    Atom::Text(format!("\\{}", c))
+                },
+                c if c.is_ascii_digit() => {
+                    let mut num = String::new();
+                    while let Some(d) = self.peek() {
+                        let decimal_point = d == '.' && self.chars.get(self.pos+1).map(|n| n.is_ascii_digit() ).unwrap_or(false);
+                        if d.is_ascii_digit() || decimal_point {
+                            num.push(d);
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Atom::Text(num)
+                },
+                c => {
+                    // Letters are separate variables in LaTeX (ab is a times b), so each letter
+                    // is an atom on its own, which will be separated by a space from the previous one.
+                    self.pos += 1;
+                    Atom::Text(c.to_string())
+                }
+            };
+            match atom {
+                Atom::Text(txt) => {
+                    push_atom(&mut out, &txt, pending_space);
+                    pending_space = false;
+                },
+                Atom::Space => {
+                    pending_space = true;
+                },
+                Atom::LeftDelim(delim) => {
+                    lr_stack.push(delim != "(" && delim != "[");
+                    push_atom(&mut out, &format!("lr({}", delim), pending_space);
+                    pending_space = false;
+                },
+                Atom::RightDelim(delim) => {
+                    lr_stack.pop();
+                    if !delim.is_empty() {
+                        push_atom(&mut out, &delim, true);
+                    }
+                    out.push(')');
+                    pending_space = false;
+                },
+                Atom::None => { }
+            }
+        }
+        out
+    }
+
+    fn command(&mut self, name : &str) -> Atom {
+        match name {
+            "\\" => {
+                // Optional vertical spacing after line breaks has no effect here.
+                self.raw_opt_arg();
+                Atom::Text(String::from("\\\n"))
+            },
+            "," | ">" | ":" => Atom::Text(String::from("thin")),
+            ";" => Atom::Text(String::from("thick")),
+            "!" => Atom::None,
+            " " => Atom::Space,
+            "quad" => Atom::Text(String::from("quad")),
+            "qquad" => Atom::Text(String::from("wide")),
+            "{" | "}" | "$" | "#" | "_" | "&" => Atom::Text(format!("\\{}", name)),
+            "%" => Atom::Text(String::from("%")),
+            "|" => Atom::Text(String::from("||")),
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.call_arg();
+                let den = self.call_arg();
+                Atom::Text(format!("frac({}, {})", num, den))
+            },
+            "binom" | "dbinom" | "tbinom" => {
+                let n = self.call_arg();
+                let k = self.call_arg();
+                Atom::Text(format!("binom({}, {})", n, k))
+            },
+            "sqrt" => {
+                match self.raw_opt_arg() {
+                    Some(index) => {
+                        let index = self.sub(&index, true);
+                        let radicand = self.call_arg();
+                        Atom::Text(format!("root({}, {})", index, radicand))
+                    },
+                    None => {
+                        Atom::Text(format!("sqrt({})", self.call_arg()))
+                    }
+                }
+            },
+            "text" | "textrm" | "textnormal" | "mbox" => {
+                let raw = self.raw_arg();
+                Atom::Text(quoted(&raw))
+            },
+            "textbf" => {
+                let raw = self.raw_arg();
+                Atom::Text(format!("bold({})", quoted(&raw)))
+            },
+            "textit" | "emph" => {
+                let raw = self.raw_arg();
+                Atom::Text(format!("italic({})", quoted(&raw)))
+            },
+            "operatorname" => {
+                let limits = if self.peek() == Some('*') {
+                    self.pos += 1;
+                    true
+                } else {
+                    false
+                };
+                let raw = self.raw_arg();
+                if limits {
+                    Atom::Text(format!("op({}, limits: #true)", quoted(raw.trim())))
+                } else {
+                    Atom::Text(format!("op({})", quoted(raw.trim())))
+                }
+            },
+            "overbrace" | "underbrace" => {
+                let body = self.call_arg();
+
+                // The annotation is written as an attachment in LaTeX, but as a second argument in typst.
+                let attach = if name == "overbrace" { '^' } else { '_' };
+                self.skip_spaces();
+                if self.peek() == Some(attach) {
+                    self.pos += 1;
+                    let annotation = self.call_arg();
+                    Atom::Text(format!("{}({}, {})", name, body, annotation))
+                } else {
+                    Atom::Text(format!("{}({})", name, body))
+                }
+            },
+            "left" => {
+                let delim = self.raw_arg();
+                Atom::LeftDelim(delimiter(&delim))
+            },
+            "right" => {
+                let delim = self.raw_arg();
+                Atom::RightDelim(delimiter(&delim))
+            },
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl" | "biggr" | "Biggl" | "Biggr" => {
+                let delim = self.raw_arg();
+                Atom::Text(delimiter(&delim))
+            },
+            "not" => {
+                let raw = self.raw_arg();
+                match &raw[..] {
+                    "=" => Atom::Text(String::from("!=")),
+                    "\\in" => Atom::Text(String::from("in.not")),
+                    "\\subset" => Atom::Text(String::from("subset.not")),
+                    "\\exists" => Atom::Text(String::from("exists.not")),
+                    other => {
+                        // Most typst symbols have a negated variant (e.g. equiv.not)
+                        let sym = self.sub(other, true);
+                        if !sym.is_empty() && sym.chars().all(|c| c.is_alphabetic() || c == '.' ) {
+                            Atom::Text(format!("{}.not", sym))
+                        } else {
+                            Atom::Text(format!("not {}", sym))
+                        }
+                    }
+                }
+            },
+            "bmod" | "mod" => Atom::Text(String::from("mod")),
+            "pmod" => {
+                let arg = self.call_arg();
+                Atom::Text(format!("(mod {})", arg))
+            },
+            "label" => {
+                let raw = self.raw_arg();
+                self.labels.push(raw.trim().to_string());
+                Atom::None
+            },
+            "begin" => {
+                let env = self.raw_arg();
+                let body = self.raw_environment(&env);
+                Atom::Text(self.environment(&env, &body))
+            },
+            "end" => {
+                // Unbalanced \end: ignore it along with its argument.
+                self.raw_arg();
+                Atom::None
+            },
+            name => {
+                if IGNORED.iter().any(|ign| *ign == name ) {
+                    return Atom::None;
+                }
+                if let Some(style) = find_in(&STYLES, name) {
+                    if self.letters_arg_ahead() {
+                        let raw = self.raw_arg();
+                        return Atom::Text(format!("{}({})", style, quoted(&raw)));
+                    }
+                    return Atom::Text(format!("{}({})", style, self.call_arg()));
+                }
+                if let Some(accent) = find_in(&ACCENTS, name) {
+                    return Atom::Text(format!("{}({})", accent, self.call_arg()));
+                }
+                match find_symbol(name) {
+                    Some(sym) => Atom::Text(sym.to_string()),
+                    None => Atom::Text(name.to_string())
+                }
+            }
+        }
+    }
+
+    // Verifies if the next argument is a group with a word of two or more letters (e.g. \mathrm{max}),
+    // which must be quoted so typst won't interpret it as a variable.
+    fn letters_arg_ahead(&self) -> bool {
+        let mut ix = self.pos;
+        while self.chars.get(ix).map(|c| c.is_whitespace() ).unwrap_or(false) {
+            ix += 1;
+        }
+        if self.chars.get(ix) != Some(&'{') {
+            return false;
+        }
+        let word : Vec<char> = self.chars[(ix+1)..].iter().take_while(|c| **c != '}' ).copied().collect();
+        word.len() >= 2 && word.iter().all(|c| c.is_alphabetic() )
+    }
+
+    fn environment(&mut self, env : &str, body : &str) -> String {
+        let delim = match env.trim_end_matches('*') {
+            "matrix" | "smallmatrix" | "array" => Some("#none"),
+            "pmatrix" => Some("\"(\""),
+            "bmatrix" => Some("\"[\""),
+            "Bmatrix" => Some("\"{\""),
+            "vmatrix" => Some("\"|\""),
+            "Vmatrix" => Some("\"||\""),
+            _ => None
+        };
+        if let Some(delim) = delim {
+            let mut body = body.trim_start();
+
+            // Skip the column specification of arrays (e.g. \begin{array}{cc})
+            if env == "array" && body.starts_with('{') {
+                let mut tr = MathTranslator::new(body);
+                tr.raw_delimited('{', '}');
+                body = &body[body.char_indices().nth(tr.pos).map(|(ix, _)| ix ).unwrap_or(body.len())..];
+            }
+            let rows = self.translate_rows(body, ", ");
+            return format!("mat(delim: {}, {})", delim, rows.join("; "));
+        }
+        if env == "cases" {
+            let rows = self.translate_rows(body, " & ");
+            return format!("cases({})", rows.join(", "));
+        }
+
+        // Alignment environments (aligned, split, gathered) need no special treatment,
+        // since typst equations accept & and line breaks directly.
+        self.sub(body, false)
+    }
+
+    // Translates each cell of a matrix-like environment, joining the cells of each row by sep.
+    fn translate_rows(&mut self, body : &str, sep : &str) -> Vec<String> {
+        let mut rows = Vec::new();
+        for row in split_rows(body) {
+            let mut cells = Vec::new();
+            for cell in row {
+                cells.push(self.sub(&cell, true));
+            }
+            rows.push(cells.join(sep));
+        }
+        rows
+    }
+
+}
+
+fn push_atom(out : &mut String, atom : &str, pending_space : bool) {
+    let (Some(last), Some(first)) = (out.chars().last(), atom.chars().next()) else {
+        out.push_str(atom);
+        return;
+    };
+
+    // Adjacent letters or numbers would be merged into a single identifier by typst.
+    let merges = last.is_alphanumeric() && (first.is_alphanumeric() || first == '"');
+    let spaced = pending_space && !"(_^[ \n".contains(last) && !",;)]".contains(first);
+    if merges || spaced {
+        out.push(' ');
+    }
+    out.push_str(atom);
+}
+
+#[test]
+fn latex_math_translation() {
+    assert_eq!(math_to_typst(r"\frac{a}{b}"), "frac(a, b)");
+    assert_eq!(math_to_typst(r"\sum_{i=1}^n x_i"), "sum_(i=1)^n x_i");
+    assert_eq!(math_to_typst(r"\sqrt[3]{x} + \sqrt{y}"), "root(3, x) + sqrt(y)");
+    assert_eq!(math_to_typst(r"\mathbf{v} \cdot \alpha"), "bold(v) dot alpha");
+    assert_eq!(math_to_typst(r"\left( \frac{1}{2} \right)"), "lr((frac(1, 2)))");
+    assert_eq!(math_to_typst(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}"), "mat(delim: \"(\", a, b; c, d)");
+    assert_eq!(math_to_typst(r"\mathrm{max}(x, y)"), "upright(\"max\")(x, y)");
+    assert_eq!(math_to_typst(r"ab"), "a b");
+}
+
+#[test]
+fn latex_fragment_translation() {
+    assert_eq!(latex_to_typst(r"Let $x \in \mathbb{R}$ be").unwrap(), "Let $x in bb(R)$ be");
+    assert_eq!(
+        latex_to_typst("\\begin{align}\na &= b \\\\\nc &= d \\label{eq:sys}\n\\end{align}").unwrap(),
+        "$ a &= b \\\nc &= d $ <eq:sys>"
+    );
+    assert!(contains_latex_math(r"$$\int_0^1 f(x) dx$$"));
+    assert!(!contains_latex_math(r"$x^2 + y^2$"));
+}
+
+#[test]
+fn typst_symbol_lookup() {
+    assert_eq!(typst_symbol_to_latex("!="), Some("neq"));
+    assert_eq!(typst_symbol_to_latex("alpha"), Some("alpha"));
+    assert_eq!(typst_symbol_to_latex("theta.alt"), Some("vartheta"));
+    assert_eq!(typst_symbol_to_latex("epsilon"), Some("varepsilon"));
+    assert_eq!(typst_symbol_to_latex("epsilon.alt"), Some("epsilon"));
+    assert_eq!(typst_symbol_to_latex("phi"), Some("varphi"));
+    assert_eq!(typst_symbol_to_latex("phi.alt"), Some("phi"));
+    assert_eq!(math_to_typst(r"\varepsilon"), "epsilon");
+    assert_eq!(math_to_typst(r"\epsilon"), "epsilon.alt");
+    assert_eq!(math_to_typst(r"\varphi"), "phi");
+    assert_eq!(math_to_typst(r"\phi"), "phi.alt");
+    assert_eq!(typst_style_to_latex("bb"), Some("mathbb"));
+    assert_eq!(typst_accent_to_latex("arrow"), Some("vec"));
+}
@@ -7,8 +7,12 @@ mod lexer;
 
 mod parser;
 
+mod math;
+
 pub use lexer::*;
 
 pub use parser::*;
 
+pub use math::*;
+
 
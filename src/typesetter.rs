@@ -26,7 +26,7 @@ use std::path::{Path, PathBuf};
 use crate::manager::FileManager;
 use filecase::SingleArchiverImpl;
 use itertools::Itertools;
-use crate::typst_tools::Fonts;
+use crate::typst_tools::{Fonts, FramePreview, Navigation};
 
 #[derive(Debug, Clone)]
 pub enum TypesetterTarget {
@@ -38,7 +38,10 @@ pub enum TypesetterTarget {
     PDFContent(Vec<u8>),
 
     /// Carries UTF-8 encoded content of a recently typeset HTML file
-    HTMLContent(String)
+    HTMLContent(String),
+
+    /// Carries the page frames of a recently compiled typst document
+    Frames(FramePreview)
 
 }
 
@@ -61,7 +64,19 @@ pub enum TypesetterAction {
     // current file dir.
     ChangeBaseDir(Option<PathBuf>),
 
-    Error(String)
+    // Sets whether documents are previewed from the typst frames (true) or from the exported PDF (false).
+    SetFramePreview(bool),
+
+    // Sets the tracked changes to be marked at the typeset document (none to typeset it as it is).
+    SetReview(Vec<crate::review::Change>),
+
+    // Carries the links and outline of the document, sent just before it is done.
+    Navigation(Navigation),
+
+    Error(String),
+
+    // Stops the worker thread, when the document is closed.
+    Stop
 
 }
 
@@ -93,14 +108,32 @@ pub struct Typesetter {
 
     on_done : Callbacks<TypesetterTarget>,
 
+    on_navigation : Callbacks<Navigation>,
+
     on_error : Callbacks<String>
 
 }
 
-fn typeset_document_with_typst(ws : &mut Workspace, file : &Path, send : &glib::Sender<TypesetterAction>, fonts : Fonts) {
-    match crate::typst_tools::compile(file, fonts) {
-        Ok(pdf_bytes) => {
+fn preview_document_with_typst(file : &Path, main : Option<String>, send : &glib::Sender<TypesetterAction>, fonts : Fonts) {
+    match crate::typst_tools::compile_frames(file, fonts, main) {
+        Ok(preview) => {
+            let _ = send.send(TypesetterAction::Navigation(preview.navigation()));
+            let _ = send.send(TypesetterAction::Done(TypesetterTarget::Frames(preview)));
+        },
+        Err(errs) => {
+            for (line, msg) in errs.iter() {
+                send.send(TypesetterAction::Error(format!("(Line {}) {}", (line + 1), msg)));
+            }
+        }
+    }
+}
+
+fn typeset_document_with_typst(ws : &mut Workspace, file : &Path, main : Option<String>, send : &glib::Sender<TypesetterAction>, fonts : Fonts) {
+    match crate::typst_tools::compile_document(file, fonts, main) {
+        Ok((doc, world)) => {
             use std::io::Write;
+            let pdf_bytes = typst::export::pdf(&doc);
+            let _ = send.send(TypesetterAction::Navigation(crate::typst_tools::navigation(&doc, &world)));
             if let Some(fname) = file.file_stem().and_then(|f| f.to_str() ) {
                 let mut out_path = PathBuf::from(ws.outdir.path().display().to_string());
                 if !out_path.exists() || !out_path.is_dir() {
@@ -111,7 +144,7 @@ fn typeset_document_with_typst(ws : &mut Workspace, file : &Path, send : &glib::
                 match std::fs::File::create(&out_path) {
                     Ok(mut f) => {
                         if let Ok(_) = f.write_all(&pdf_bytes) {
-                            send.send(TypesetterAction::Done(TypesetterTarget::File(out_path.to_str().unwrap().to_string()))).unwrap();
+                            let _ = send.send(TypesetterAction::Done(TypesetterTarget::File(out_path.to_str().unwrap().to_string())));
                         } else {
                             eprintln!("Unable to write to temporary file");
                         }
@@ -138,7 +171,11 @@ pub struct TypesettingRequest {
 
     base_path : Option<PathBuf>,
 
-    file :  Option<PathBuf>
+    file :  Option<PathBuf>,
+
+    frames : bool,
+
+    review : Vec<crate::review::Change>
 
 }
 
@@ -148,6 +185,7 @@ impl Typesetter {
         let (send, recv) = glib::MainContext::channel::<TypesetterAction>(glib::PRIORITY_DEFAULT);
         let on_done : Callbacks<TypesetterTarget> = Default::default();
         let on_error : Callbacks<String> = Default::default();
+        let on_navigation : Callbacks<Navigation> = Default::default();
         let (content_send, content_recv) = mpsc::channel::<TypesettingRequest>();
 
         thread::spawn({
@@ -156,16 +194,29 @@ impl Typesetter {
                 let mut ws = Workspace::new();
                 loop {
                     match content_recv.recv() {
-                        Ok(TypesettingRequest { content, base_path, file }) => {
+                        Ok(TypesettingRequest { content, base_path, file, frames, review }) => {
                             // typeset_document_from_lib(&mut ws, &content, base_path.as_ref().map(|p| p.as_path() ), &send);
                             // typeset_document_from_cli(&mut ws, &content, base_path.as_ref().map(|p| p.as_path() ), &send)
                             if let Some(file) = file {
-                                typeset_document_with_typst(&mut ws, &file, &send, fonts.clone());
+                                // Tracked changes are marked at the buffer text (to which their positions
+                                // refer), compiled in place of the file.
+                                let main = if review.is_empty() || content.is_empty() {
+                                    None
+                                } else {
+                                    Some(crate::review::annotate_source(&content, &review))
+                                };
+                                if frames {
+                                    preview_document_with_typst(&file, main, &send, fonts.clone());
+                                } else {
+                                    typeset_document_with_typst(&mut ws, &file, main, &send, fonts.clone());
+                                }
                             } else {
                                 println!("Missing current file");
                             }
                         },
-                        _ => { }
+                        Err(_) => {
+                            return;
+                        }
                     }
                 }
             }
@@ -173,14 +224,17 @@ impl Typesetter {
 
         let mut base_path : Option<PathBuf> = None;
         let mut file : Option<PathBuf> = None;
+        let mut frames = false;
+        let mut review = Vec::new();
         recv.attach(None, {
             let send = send.clone();
             let on_done = on_done.clone();
             let on_error = on_error.clone();
+            let on_navigation = on_navigation.clone();
             move |action| {
                 match action {
                     TypesetterAction::Request(txt) => {
-                        content_send.send(TypesettingRequest { content : txt, base_path : base_path.clone(), file : file.clone() });
+                        content_send.send(TypesettingRequest { content : txt, base_path : base_path.clone(), file : file.clone(), frames, review : review.clone() });
                     },
                     TypesetterAction::Done(target) => {
                         on_done.call(target.clone());
@@ -188,10 +242,24 @@ impl Typesetter {
                     TypesetterAction::Error(e) => {
                         on_error.call(e.clone());
                     },
+                    TypesetterAction::Navigation(nav) => {
+                        on_navigation.call(nav);
+                    },
+                    TypesetterAction::SetFramePreview(use_frames) => {
+                        frames = use_frames;
+                    },
+                    TypesetterAction::SetReview(changes) => {
+                        review = changes;
+                    },
+                    TypesetterAction::Stop => {
+                        // Dropping the request sender ends the worker.
+                        return Continue(false);
+                    },
                     TypesetterAction::ChangeBaseDir(opt_path) => {
                         if let Some(path) = opt_path {
                             if let Some(parent) = Path::new(&path).parent() {
-                                base_path = Some(parent.to_owned());
+                                // Files of multi-file projects are typeset from the project root.
+                                base_path = Some(crate::templates::project_root(&path).unwrap_or(parent.to_owned()));
                                 file = Some(path.to_owned());
                             } else {
                                 log::warn!("File without valid parent path");
@@ -206,7 +274,12 @@ impl Typesetter {
             }
         });
 
-        Self { send, on_done, on_error }
+        Self { send, on_done, on_navigation, on_error }
+    }
+
+    /// Stops the worker thread. The typesetter does not respond to requests after this.
+    pub fn shutdown(&self) {
+        let _ = self.send.send(TypesetterAction::Stop);
     }
 
     pub fn connect_done<F>(&self, f : F)
@@ -216,6 +289,13 @@ impl Typesetter {
         self.on_done.bind(f);
     }
 
+    pub fn connect_navigation<F>(&self, f : F)
+    where
+        F : Fn(Navigation) + 'static
+    {
+        self.on_navigation.bind(f);
+    }
+
     pub fn connect_error<F>(&self, f : F)
     where
         F : Fn(String) + 'static
@@ -225,11 +305,16 @@ impl Typesetter {
 
 }
 
+// The file is typeset from disk. The buffer text is sent along, and only compiled in its place
+// when tracked changes are marked.
 fn request_typesetting_file(
     pdf_btn : &Button,
+    view : &sourceview5::View,
     send : &glib::Sender<TypesetterAction>
 ) {
-    send.send(TypesetterAction::Request(String::new())).unwrap();
+    let buffer = view.buffer();
+    let txt = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+    send.send(TypesetterAction::Request(txt)).unwrap();
     pdf_btn.set_icon_name("timer-symbolic");
     pdf_btn.set_sensitive(false);
 }
@@ -268,9 +353,21 @@ impl React<PapersWindow> for Typesetter {
             let send = self.send.clone();
             let pdf_btn = titlebar.pdf_btn.clone();
             move |_, _| {
-                request_typesetting_file(&pdf_btn, &send);
+                request_typesetting_file(&pdf_btn, &view, &send);
             }
         });
+        // The preview source takes effect at the next typesetting request.
+        let frame_preview_action = &editor.pdf_viewer.frame_preview_action;
+        let use_frames = frame_preview_action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+        self.send.send(TypesetterAction::SetFramePreview(use_frames)).unwrap();
+        frame_preview_action.connect_state_notify({
+            let send = self.send.clone();
+            move |action| {
+                let use_frames = action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+                send.send(TypesetterAction::SetFramePreview(use_frames)).unwrap();
+            }
+        });
+
         titlebar.pdf_btn.connect_clicked({
             let typeset_action = titlebar.typeset_action.clone();
             move |btn| {
@@ -321,4 +418,16 @@ impl React<FileManager> for Typesetter {
 
 }
 
+impl React<ReviewPanel> for Typesetter {
+
+    // Like the preview source, the marked changes take effect at the next typesetting request.
+    fn react(&self, panel : &ReviewPanel) {
+        panel.connect_preview_changed({
+            let send = self.send.clone();
+            move |changes| {
+                send.send(TypesetterAction::SetReview(changes)).unwrap();
+            }
+        });
+    }
 
+}
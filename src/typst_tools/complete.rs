@@ -0,0 +1,662 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+use typst::eval::{Value, CastInfo, Scope};
+use once_cell::sync::Lazy;
+use typst::syntax::{SyntaxNode, SyntaxKind};
+
+/*
+Completion of typst source. The functions, parameters and symbols offered are taken from
+the reflection data of the standard library (so they follow the pinned typst version), and
+are combined with the let bindings of the current project. The context is found by scanning
+the text before the cursor: the innermost unclosed call (if any), whether the cursor is at a
+named argument value, and whether it is inside an equation.
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct ParamEntry {
+
+    pub name : String,
+
+    // Accepted types (e.g. "length | auto").
+    pub ty : String,
+
+    pub docs : String,
+
+    // Enumerated values (e.g. paper names, as quoted strings, or alignments).
+    pub values : Vec<String>,
+
+    pub positional : bool,
+
+    pub named : bool,
+
+    // Whether the parameter takes all remaining positional arguments.
+    pub variadic : bool,
+
+    pub required : bool,
+
+    // Whether the parameter can be used at set rules.
+    pub settable : bool
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FuncEntry {
+
+    pub name : String,
+
+    // First paragraph of the function documentation.
+    pub docs : String,
+
+    pub params : Vec<ParamEntry>
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+
+    pub funcs : Vec<FuncEntry>,
+
+    // Functions only available inside equations.
+    pub math_funcs : Vec<FuncEntry>,
+
+    pub symbols : Vec<(String, char)>
+
+}
+
+// Values accepted by alignment parameters, which are not listed by the reflection data.
+const ALIGNMENTS : [&'static str; 8] = ["left", "center", "right", "start", "end", "top", "horizon", "bottom"];
+
+fn first_paragraph(docs : &str) -> String {
+    docs.trim().split("\n\n").next().unwrap_or("").replace('\n', " ")
+}
+
+fn describe_cast(cast : &CastInfo, types : &mut Vec<String>, values : &mut Vec<String>) {
+    match cast {
+        CastInfo::Any => types.push(String::from("any")),
+        CastInfo::Value(value, _) => {
+            let ty = value.type_name().to_string();
+            if !types.contains(&ty) {
+                types.push(ty);
+            }
+            values.push(value.repr().to_string());
+        },
+        CastInfo::Type(ty) => {
+            if ty.contains("alignment") {
+                values.extend(ALIGNMENTS.iter().map(|a| a.to_string() ));
+            }
+            if *ty == "boolean" {
+                values.extend([String::from("true"), String::from("false")]);
+            }
+            types.push(ty.to_string());
+        },
+        CastInfo::Union(casts) => {
+            for cast in casts {
+                describe_cast(cast, types, values);
+            }
+        }
+    }
+}
+
+fn scope_entries(scope : &Scope) -> (Vec<FuncEntry>, Vec<(String, char)>) {
+    let mut funcs = Vec::new();
+    let mut symbols = Vec::new();
+    for (name, value) in scope.iter() {
+        match value {
+            Value::Func(func) => {
+                let mut entry = FuncEntry { name : name.to_string(), ..Default::default() };
+                if let Some(info) = func.info() {
+                    entry.docs = first_paragraph(info.docs);
+                    entry.params = info.params.iter().map(|p| {
+                        let (mut types, mut values) = (Vec::new(), Vec::new());
+                        describe_cast(&p.cast, &mut types, &mut values);
+                        values.dedup();
+                        ParamEntry {
+                            name : p.name.to_string(),
+                            ty : types.join(" | "),
+                            docs : first_paragraph(p.docs),
+                            values,
+                            positional : p.positional,
+                            named : p.named,
+                            variadic : p.variadic,
+                            required : p.required,
+                            settable : p.settable
+                        }
+                    }).collect();
+                }
+                funcs.push(entry);
+            },
+            Value::Symbol(sym) => {
+                symbols.push((name.to_string(), sym.get()));
+            },
+            _ => { }
+        }
+    }
+    funcs.sort_by(|a, b| a.name.cmp(&b.name) );
+    symbols.sort_by(|a, b| a.0.cmp(&b.0) );
+    (funcs, symbols)
+}
+
+impl Catalog {
+
+    pub fn from_library(library : &Library) -> Self {
+        let (funcs, _) = scope_entries(library.global.scope());
+        let (math_funcs, symbols) = scope_entries(library.math.scope());
+        Self { funcs, math_funcs, symbols }
+    }
+
+    pub fn func(&self, name : &str) -> Option<&FuncEntry> {
+        let name = name.trim_start_matches("math.");
+        self.funcs.iter().chain(self.math_funcs.iter()).find(|f| f.name == name )
+    }
+
+}
+
+static CATALOG : Lazy<Catalog> = Lazy::new(|| Catalog::from_library(&typst_library::build()) );
+
+/// Completion data of the standard library, built on first use.
+pub fn catalog() -> &'static Catalog {
+    &CATALOG
+}
+
+/// A let binding found at the project sources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+
+    pub name : String,
+
+    // Parameters, when the binding is a function (named ones hold their default after the colon).
+    pub params : Option<Vec<String>>,
+
+    // File the binding was found at (empty for the open document).
+    pub file : String
+
+}
+
+pub(super) fn is_ident_char(c : char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+// Splits at the commas that are not nested in parentheses, brackets or strings.
+pub(super) fn split_top_level(s : &str) -> Vec<&str> {
+    let (mut depth, mut in_str, mut start) = (0, false, 0);
+    let mut parts = Vec::new();
+    for (ix, c) in s.char_indices() {
+        match c {
+            '"' => in_str = !in_str,
+            '(' | '[' | '{' if !in_str => depth += 1,
+            ')' | ']' | '}' if !in_str => depth -= 1,
+            ',' if !in_str && depth == 0 => {
+                parts.push(&s[start..ix]);
+                start = ix + 1;
+            },
+            _ => { }
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+// Name of a let binding node (with its byte offset) and its parameters, when it binds a
+// function. The name is the first identifier before the equals sign, either a child of
+// the node or of the closure it wraps (e.g. let note(body) = ...), where the parameters are.
+fn let_binding(node : &SyntaxNode, offset : usize) -> Option<(String, usize, Option<Vec<String>>)> {
+    let mut offset = offset;
+    for child in node.children() {
+        match child.kind() {
+            SyntaxKind::Eq => return None,
+            SyntaxKind::Ident => {
+                let params = node.children()
+                    .find(|c| c.kind() == SyntaxKind::Params )
+                    .map(|params| {
+                        params.children()
+                            .filter(|p| !matches!(p.kind(), SyntaxKind::LeftParen | SyntaxKind::RightParen | SyntaxKind::Comma |
+                                SyntaxKind::Space | SyntaxKind::LineComment | SyntaxKind::BlockComment) )
+                            .map(|p| format::text_of(p).trim().to_string() )
+                            .collect()
+                    });
+                return Some((child.text().to_string(), offset, params));
+            },
+            _ if child.children().len() > 0 => {
+                if let Some(found) = let_binding(child, offset) {
+                    return Some(found);
+                }
+            },
+            _ => { }
+        }
+        offset += child.len();
+    }
+    None
+}
+
+fn collect_let_bindings(node : &SyntaxNode, offset : usize, file : &str, out : &mut Vec<(Binding, usize)>) {
+    if node.kind() == SyntaxKind::LetBinding {
+        if let Some((name, pos, params)) = let_binding(node, offset) {
+            if !out.iter().any(|(b, _)| b.name == name ) {
+                out.push((Binding { name, params, file : file.to_string() }, pos));
+            }
+        }
+    }
+    let mut offset = offset;
+    for child in node.children() {
+        collect_let_bindings(child, offset, file, out);
+        offset += child.len();
+    }
+}
+
+/// Let bindings of a typst source with the byte offset of their names. They are taken from
+/// the syntax tree, so the word let in text (e.g. "we let x be") is not a binding.
+pub fn let_binding_offsets(src : &str, file : &str) -> Vec<(Binding, usize)> {
+    let source = Source::new(SourceId::detached(), Path::new(""), src.to_string());
+    let mut out = Vec::new();
+    collect_let_bindings(source.root(), 0, file, &mut out);
+    out
+}
+
+/// Let bindings of a typst source (e.g. #let title = "..." or #let note(body, color: red) = ...).
+pub fn let_bindings(src : &str, file : &str) -> Vec<Binding> {
+    let_binding_offsets(src, file).into_iter().map(|(b, _)| b ).collect()
+}
+
+/// Directory holding the other typst sources a document can refer to, with the depth it is
+/// searched to: the project root, for documents of a project, or the directory of the document
+/// otherwise (not recursing into subdirectories, so a document saved at a large directory such
+/// as the home directory does not start a scan of everything under it).
+pub fn sources_root(doc : &Path) -> Option<(PathBuf, usize)> {
+    match crate::templates::project_root(doc) {
+        Some(root) => Some((root, 3)),
+        None => doc.parent().map(|p| (p.to_owned(), 1) )
+    }
+}
+
+/// Typst files under a directory, up to the given depth (see sources_root).
+pub fn typst_files(root : &Path, depth : usize) -> Vec<PathBuf> {
+    WalkDir::new(root)
+        .max_depth(depth)
+        .into_iter()
+        .filter_map(|e| e.ok() )
+        .map(|e| e.into_path() )
+        .filter(|path| path.extension().and_then(|e| e.to_str() ) == Some("typ") )
+        .collect()
+}
+
+/// Let bindings of the other sources at the project of the document (see sources_root).
+/// This reads the files from disk, so it should not be called from the main thread.
+pub fn project_bindings(doc : &Path) -> Vec<Binding> {
+    let Some((root, depth)) = sources_root(doc) else { return Vec::new() };
+    let mut bindings = Vec::new();
+    for path in typst_files(&root, depth) {
+        if path == doc {
+            continue;
+        }
+        if let Ok(src) = std::fs::read_to_string(&path) {
+            let file = path.strip_prefix(&root).unwrap_or(&path).display().to_string();
+            bindings.extend(let_bindings(&src, &file));
+        }
+    }
+    bindings
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionContext {
+
+    // After a hash at markup (or at code), e.g. #hea or #set pa
+    Function { prefix : String },
+
+    // At an argument of a call, e.g. #page(mar. Named arguments already given are listed.
+    Argument { func : String, prefix : String, used : Vec<String> },
+
+    // At the value of a named argument, e.g. #page(paper: "a (the prefix includes the quote).
+    Value { func : String, param : String, prefix : String },
+
+    // Inside an equation, e.g. $al
+    Math { prefix : String },
+
+    None
+
+}
+
+impl CompletionContext {
+
+    /// Text before the cursor replaced by the completion.
+    pub fn prefix(&self) -> &str {
+        match self {
+            Self::Function { prefix } | Self::Argument { prefix, .. } |
+            Self::Value { prefix, .. } | Self::Math { prefix } => &prefix[..],
+            Self::None => ""
+        }
+    }
+
+}
+
+pub(super) enum Open {
+
+    // Byte offset just after the parenthesis, and the name of the function called (if any).
+    Call(usize, Option<String>),
+
+    // Parenthesis in markup text, which is not a call (e.g. results (see Fig).
+    Paren,
+
+    Content,
+
+    Code
+
+}
+
+// Name of the function called by a parenthesis at the end of the text (e.g. page, math.frac).
+fn callee(before : &str) -> Option<String> {
+    let start = before.char_indices().rev()
+        .take_while(|(_, c)| is_ident_char(*c) || *c == '.' )
+        .last()
+        .map(|(ix, _)| ix )?;
+    let name = &before[start..];
+    if name.is_empty() || name.starts_with('.') { None } else { Some(name.to_string()) }
+}
+
+// Whether a parenthesis at the end of markup text opens the arguments of a call, which
+// needs a hash before the callee (e.g. #page( or #set page().
+fn is_markup_call(before : &str) -> bool {
+    let start = before.char_indices().rev()
+        .take_while(|(_, c)| is_ident_char(*c) || *c == '.' )
+        .last()
+        .map(|(ix, _)| ix )
+        .unwrap_or(before.len());
+    if start == before.len() {
+        return false;
+    }
+    let lead = before[..start].trim_end_matches(|c : char| c == ' ' || c == '\t' );
+    before[..start].ends_with('#') || ((lead.ends_with("#set") || lead.ends_with("#show")) && lead.len() < start)
+}
+
+// State of the source at the end of a text: unclosed delimiters, and whether it ends
+// inside an equation, a string (starting at str_start), a comment or raw text.
+pub(super) struct Scan {
+
+    pub stack : Vec<Open>,
+
+    pub math : bool,
+
+    pub in_str : bool,
+
+    pub str_start : usize,
+
+    pub in_comment_or_raw : bool
+
+}
+
+pub(super) fn scan(before : &str) -> Scan {
+    let mut stack : Vec<Open> = Vec::new();
+    let mut math = false;
+    let (mut in_str, mut in_raw, mut line_comment, mut block_comment) = (false, false, false, false);
+    let mut str_start = 0;
+    let mut prev = '\0';
+    let mut chars = before.char_indices().peekable();
+    while let Some((ix, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c ).unwrap_or('\0');
+        if line_comment {
+            line_comment = c != '\n';
+        } else if block_comment {
+            if prev == '*' && c == '/' {
+                block_comment = false;
+            }
+        } else if in_str {
+            if c == '"' && prev != '\\' {
+                in_str = false;
+            }
+        } else if in_raw {
+            in_raw = c != '`';
+        } else {
+            let in_code = matches!(stack.last(), Some(Open::Call(..)) | Some(Open::Code));
+            match c {
+                '/' if next == '/' => line_comment = true,
+                '/' if next == '*' => block_comment = true,
+                '"' if in_code || math => {
+                    in_str = true;
+                    str_start = ix;
+                },
+                '`' if !in_code && !math => in_raw = true,
+                '$' if prev != '\\' && !in_code => math = !math,
+                '(' if in_code || math || is_markup_call(&before[..ix]) => {
+                    stack.push(Open::Call(ix + 1, callee(&before[..ix])));
+                },
+                '(' => stack.push(Open::Paren),
+                '[' => stack.push(Open::Content),
+                '{' => stack.push(Open::Code),
+                ')' | ']' | '}' => {
+                    stack.pop();
+                },
+                _ => { }
+            }
+        }
+        prev = c;
+    }
+    Scan { stack, math, in_str, str_start, in_comment_or_raw : line_comment || block_comment || in_raw }
+}
+
+/// Context of a completion requested with the cursor at the end of the text.
+pub fn context_at(before : &str) -> CompletionContext {
+    let Scan { stack, math, in_str, str_start, in_comment_or_raw } = scan(before);
+    if in_comment_or_raw {
+        return CompletionContext::None;
+    }
+
+    if let Some(Open::Call(start, Some(func))) = stack.last() {
+        let args = &before[*start..];
+        let parts = split_top_level(args);
+        let curr = parts.last().copied().unwrap_or("");
+        let used : Vec<String> = parts[..parts.len()-1].iter()
+            .filter_map(|p| p.split_once(':').map(|(name, _)| name.trim().to_string() ) )
+            .collect();
+        if let Some((name, value)) = curr.split_once(':') {
+            let name = name.trim();
+            if !name.is_empty() && name.chars().all(is_ident_char) {
+                let value = value.trim_start();
+                if in_str || value.chars().all(is_ident_char) {
+                    let prefix = if in_str { before[str_start..].to_string() } else { value.to_string() };
+                    return CompletionContext::Value { func : func.clone(), param : name.to_string(), prefix };
+                }
+            }
+            return CompletionContext::None;
+        }
+        if in_str {
+            return CompletionContext::None;
+        }
+        let curr = curr.trim_start();
+        if curr.chars().all(is_ident_char) {
+            return CompletionContext::Argument { func : func.clone(), prefix : curr.to_string(), used };
+        }
+        return CompletionContext::None;
+    }
+    if in_str {
+        return CompletionContext::None;
+    }
+
+    let prefix_start = before.char_indices().rev()
+        .take_while(|(_, c)| is_ident_char(*c) )
+        .last()
+        .map(|(ix, _)| ix )
+        .unwrap_or(before.len());
+    let prefix = before[prefix_start..].to_string();
+    if math {
+        return if prefix.is_empty() { CompletionContext::None } else { CompletionContext::Math { prefix } };
+    }
+    let lead = before[..prefix_start].trim_end_matches(|c : char| c == ' ' || c == '\t' );
+    let after_hash = before[..prefix_start].ends_with('#') ||
+        ((lead.ends_with("#set") || lead.ends_with("#show")) && lead.len() < prefix_start);
+    let in_code = matches!(stack.last(), Some(Open::Code) | Some(Open::Call(..)));
+    if after_hash || (in_code && !prefix.is_empty()) {
+        CompletionContext::Function { prefix }
+    } else {
+        CompletionContext::None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Function,
+    Parameter,
+    Value,
+    Symbol,
+    Binding
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionItem {
+
+    pub label : String,
+
+    // Text replacing the prefix.
+    pub insert : String,
+
+    // Characters the cursor is moved back after the insertion (e.g. to stay inside parentheses).
+    pub cursor_back : usize,
+
+    // Short description shown beside the label (type, symbol or source file).
+    pub detail : String,
+
+    pub docs : String,
+
+    pub kind : CompletionKind
+
+}
+
+fn function_item(name : &str, docs : &str, has_params : bool, kind : CompletionKind, detail : &str) -> CompletionItem {
+    let (insert, cursor_back) = if has_params { (format!("{}()", name), 1) } else { (name.to_string(), 0) };
+    CompletionItem { label : name.to_string(), insert, cursor_back, detail : detail.to_string(), docs : docs.to_string(), kind }
+}
+
+fn param_detail(p : &ParamEntry) -> String {
+    let mut detail = p.ty.clone();
+    if p.required {
+        detail += " (required)";
+    }
+    detail
+}
+
+/// Completions for the given context, from the standard library and the project bindings.
+pub fn complete(catalog : &Catalog, ctx : &CompletionContext, bindings : &[Binding]) -> Vec<CompletionItem> {
+    let mut items = Vec::new();
+    match ctx {
+        CompletionContext::Function { prefix } => {
+            for b in bindings.iter().filter(|b| b.name.starts_with(&prefix[..]) ) {
+                let params = b.params.as_ref().map(|p| p.join(", ") ).unwrap_or_default();
+                items.push(function_item(&b.name, &params, b.params.is_some(), CompletionKind::Binding, &b.file));
+            }
+            for f in catalog.funcs.iter().filter(|f| f.name.starts_with(&prefix[..]) ) {
+                let detail = if f.params.iter().any(|p| p.settable ) { "settable" } else { "" };
+                items.push(function_item(&f.name, &f.docs, !f.params.is_empty(), CompletionKind::Function, detail));
+            }
+        },
+        CompletionContext::Argument { func, prefix, used } => {
+            if let Some(f) = catalog.func(func) {
+                for p in f.params.iter().filter(|p| p.named && !used.contains(&p.name) && p.name.starts_with(&prefix[..]) ) {
+                    items.push(CompletionItem {
+                        label : format!("{}:", p.name),
+                        insert : format!("{}: ", p.name),
+                        cursor_back : 0,
+                        detail : param_detail(p),
+                        docs : p.docs.clone(),
+                        kind : CompletionKind::Parameter
+                    });
+                }
+            } else if let Some(params) = bindings.iter().find(|b| &b.name == func ).and_then(|b| b.params.as_ref() ) {
+                for (name, default) in params.iter().filter_map(|p| p.split_once(':') ) {
+                    let name = name.trim();
+                    if !used.iter().any(|u| u == name ) && name.starts_with(&prefix[..]) {
+                        items.push(CompletionItem {
+                            label : format!("{}:", name),
+                            insert : format!("{}: ", name),
+                            cursor_back : 0,
+                            detail : format!("default {}", default.trim()),
+                            docs : String::new(),
+                            kind : CompletionKind::Parameter
+                        });
+                    }
+                }
+            }
+        },
+        CompletionContext::Value { func, param, prefix } => {
+            let values = catalog.func(func)
+                .and_then(|f| f.params.iter().find(|p| &p.name == param ) )
+                .map(|p| (p.values.clone(), p.ty.clone()) );
+            if let Some((values, ty)) = values {
+                for v in values.iter().filter(|v| v.starts_with(&prefix[..]) ) {
+                    items.push(CompletionItem {
+                        label : v.clone(),
+                        insert : v.clone(),
+                        cursor_back : 0,
+                        detail : ty.clone(),
+                        docs : String::new(),
+                        kind : CompletionKind::Value
+                    });
+                }
+            }
+        },
+        CompletionContext::Math { prefix } => {
+            for f in catalog.math_funcs.iter().filter(|f| f.name.starts_with(&prefix[..]) ) {
+                items.push(function_item(&f.name, &f.docs, !f.params.is_empty(), CompletionKind::Function, ""));
+            }
+            for (name, c) in catalog.symbols.iter().filter(|(name, _)| name.starts_with(&prefix[..]) ) {
+                items.push(CompletionItem {
+                    label : name.clone(),
+                    insert : name.clone(),
+                    cursor_back : 0,
+                    detail : c.to_string(),
+                    docs : String::new(),
+                    kind : CompletionKind::Symbol
+                });
+            }
+            for b in bindings.iter().filter(|b| b.name.starts_with(&prefix[..]) ) {
+                items.push(function_item(&b.name, "", b.params.is_some(), CompletionKind::Binding, &b.file));
+            }
+        },
+        CompletionContext::None => { }
+    }
+    items
+}
+
+#[test]
+fn completion_context() {
+    use CompletionContext::*;
+    assert_eq!(context_at("Some text #hea"), Function { prefix : String::from("hea") });
+    assert_eq!(context_at("#set pa"), Function { prefix : String::from("pa") });
+    assert_eq!(context_at("#page(paper: \"a4\", mar"), Argument { func : String::from("page"), prefix : String::from("mar"), used : vec![String::from("paper")] });
+    assert_eq!(context_at("#set page(paper: \"us-"), Value { func : String::from("page"), param : String::from("paper"), prefix : String::from("\"us-") });
+    assert_eq!(context_at("#align(center)[Text #strong[a] and $x + al"), Math { prefix : String::from("al") });
+    assert_eq!(context_at("$x$ and al"), None);
+    assert_eq!(context_at("// #hea"), None);
+    assert_eq!(context_at("#text(size: 10pt)[in content"), None);
+    assert_eq!(context_at("results (see Fig"), None);
+    assert_eq!(context_at("#strong[results (see Fig"), None);
+    assert_eq!(context_at("#set page(mar"), Argument { func : String::from("page"), prefix : String::from("mar"), used : Vec::new() });
+    assert_eq!(context_at("#{ page(mar"), Argument { func : String::from("page"), prefix : String::from("mar"), used : Vec::new() });
+
+    let catalog = Catalog {
+        funcs : vec![FuncEntry {
+            name : String::from("page"),
+            docs : String::new(),
+            params : vec![
+                ParamEntry { name : String::from("paper"), ty : String::from("string"), values : vec![String::from("\"a4\""), String::from("\"us-letter\"")], named : true, settable : true, ..Default::default() },
+                ParamEntry { name : String::from("margin"), ty : String::from("length"), named : true, settable : true, ..Default::default() }
+            ]
+        }],
+        math_funcs : Vec::new(),
+        symbols : vec![(String::from("alpha"), 'α')]
+    };
+    let bindings = let_bindings("#let title = \"Notes\"\n#let note(body, color: red) = box(fill: color, body)", "");
+    assert_eq!(bindings[1].params, Some(vec![String::from("body"), String::from("color: red")]));
+    assert_eq!(bindings[0].params, Option::None);
+    assert!(let_bindings("In this proof we let x be a real number.", "").is_empty());
+    let nested = let_bindings("#{\n  let total = 1\n}", "");
+    assert_eq!(nested.iter().map(|b| &b.name[..] ).collect::<Vec<_>>(), vec!["total"]);
+    let labels = |ctx : &str| complete(&catalog, &context_at(ctx), &bindings).iter().map(|i| i.label.clone() ).collect::<Vec<_>>();
+    assert_eq!(labels("#pa"), vec!["page"]);
+    assert_eq!(labels("#page(paper: \"a4\", "), vec!["margin:"]);
+    assert_eq!(labels("#page(paper: \"us"), vec!["\"us-letter\""]);
+    assert_eq!(labels("$al"), vec!["alpha"]);
+    assert_eq!(labels("#ti"), vec!["title"]);
+    assert_eq!(labels("#note([Hi], c"), vec!["color:"]);
+}
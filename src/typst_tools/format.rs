@@ -0,0 +1,514 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+use typst::syntax::{SyntaxNode, SyntaxKind};
+
+/*
+Formatting of typst sources, by printing their syntax tree back with consistent spacing:
+
+- Arguments, parameters, arrays and dictionaries are written as (a, b: 1), and are
+broken one per line (with a trailing comma) when they do not fit the width, hold
+comments or items spanning many lines.
+- Keywords, operators and the colon of named arguments are separated by single spaces,
+and multi-line code blocks are indented.
+- List, enum and term markers are followed by a single space, and the nested items
+and continuation lines of an item are aligned with its body.
+- Enum items follow the numbering style of the first item of their list: after a +
+item, items numbered one more than the previous item are written with +, and after an
+explicitly numbered item (e.g. 1.), all items are numbered explicitly.
+- Headings get a blank line before and after them.
+
+Text, equations, raw blocks and comments are kept as they are. The result is parsed again
+and compared with the original tree (ignoring the whitespace the formatter is free to
+change), and is only returned when both match, so formatting never changes the output.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatOptions {
+
+    // Lines of code longer than this are broken at their arguments.
+    pub width : usize,
+
+    // Spaces for each indentation level.
+    pub indent : usize
+
+}
+
+impl Default for FormatOptions {
+
+    fn default() -> Self {
+        Self { width : 80, indent : 2 }
+    }
+
+}
+
+fn is_comment(kind : SyntaxKind) -> bool {
+    matches!(kind, SyntaxKind::LineComment | SyntaxKind::BlockComment)
+}
+
+fn has_comment(node : &SyntaxNode) -> bool {
+    node.children().any(|c| is_comment(c.kind()) )
+}
+
+fn verbatim(node : &SyntaxNode, out : &mut String) {
+    if node.children().len() == 0 {
+        out.push_str(node.text());
+    } else {
+        for child in node.children() {
+            verbatim(child, out);
+        }
+    }
+}
+
+pub(super) fn text_of(node : &SyntaxNode) -> String {
+    let mut s = String::new();
+    verbatim(node, &mut s);
+    s
+}
+
+// Position of an enum item at its list: its number, the number of the previous item
+// (none for the first item) and whether the list is explicitly numbered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct EnumPos {
+    number : usize,
+    prev : Option<usize>,
+    explicit : bool
+}
+
+impl EnumPos {
+
+    // Marker in the numbering style of the list, for the same number.
+    fn marker(&self) -> String {
+        if !self.explicit && self.number == self.prev.map(|n| n + 1 ).unwrap_or(1) {
+            String::from("+")
+        } else {
+            format!("{}.", self.number)
+        }
+    }
+
+}
+
+// Positions of the enum items among the children of a markup node. A list is a run of
+// enum items separated only by whitespace. Items marked with + take the number after
+// the previous one (or one, for the first item).
+fn enum_positions(children : &[&SyntaxNode]) -> Vec<Option<EnumPos>> {
+    let mut run : Option<(usize, bool)> = None;
+    children.iter().map(|child| {
+        match child.kind() {
+            SyntaxKind::EnumItem => {
+                let marker = child.children().next().map(|m| m.text().to_string() ).unwrap_or_default();
+                let prev = run.map(|(n, _)| n );
+                let explicit_marker = marker.trim_end_matches('.').parse::<usize>().ok();
+                let number = explicit_marker.unwrap_or(prev.map(|n| n + 1 ).unwrap_or(1));
+                let explicit = run.map(|(_, explicit)| explicit ).unwrap_or(explicit_marker.is_some());
+                run = Some((number, explicit));
+                Some(EnumPos { number, prev, explicit })
+            },
+            SyntaxKind::Space | SyntaxKind::Parbreak => None,
+            _ => {
+                run = None;
+                None
+            }
+        }
+    }).collect()
+}
+
+// An item of a parenthesized list, or a comment between them (with whether it
+// was at its own line).
+enum Entry {
+    Item(String, Option<String>),
+    Comment(String)
+}
+
+struct Formatter {
+
+    opts : FormatOptions,
+
+    out : String,
+
+    // Column the output starts at (for text formatted apart and inserted later).
+    base : usize
+
+}
+
+impl Formatter {
+
+    fn new(opts : FormatOptions, base : usize) -> Self {
+        Self { opts, out : String::new(), base }
+    }
+
+    fn col(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(pos) => self.out[pos+1..].chars().count(),
+            None => self.base + self.out.chars().count()
+        }
+    }
+
+    fn line_indent(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(pos) => self.out[pos+1..].chars().take_while(|c| *c == ' ' ).count(),
+            None => self.base
+        }
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let len = self.out.trim_end_matches(|c| c == ' ' || c == '\t' ).len();
+        self.out.truncate(len);
+    }
+
+    fn newline(&mut self, indent : usize) {
+        self.trim_trailing_spaces();
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    fn sub(&self, node : &SyntaxNode, base : usize) -> String {
+        let mut f = Formatter::new(self.opts, base);
+        f.expr(node);
+        f.out
+    }
+
+    // Markup, where item_indent is the indentation of the lines of the list item it
+    // belongs to (if any). Elsewhere, the original indentation of each line is kept.
+    fn markup(&mut self, node : &SyntaxNode, item_indent : Option<usize>) {
+        let children : Vec<&SyntaxNode> = node.children().collect();
+        let enum_pos = enum_positions(&children);
+        for (ix, child) in children.iter().enumerate() {
+            let prev = if ix > 0 { Some(children[ix-1].kind()) } else { None };
+            let next = children.get(ix+1).map(|c| c.kind() );
+            match child.kind() {
+                SyntaxKind::Space => {
+                    let text = child.text();
+                    match text.rfind('\n') {
+                        Some(pos) => {
+                            let around_heading = (prev == Some(SyntaxKind::Heading) || next == Some(SyntaxKind::Heading)) &&
+                                next.is_some() && next != Some(SyntaxKind::Label);
+                            if around_heading {
+                                self.trim_trailing_spaces();
+                                self.out.push('\n');
+                            }
+                            let indent = item_indent.unwrap_or_else(|| text[pos+1..].chars().count() );
+                            self.newline(indent);
+                        },
+                        None => self.out.push(' ')
+                    }
+                },
+                SyntaxKind::Parbreak => {
+                    let text = child.text();
+                    let indent = item_indent.unwrap_or_else(|| {
+                        text.rfind('\n').map(|pos| text[pos+1..].chars().count() ).unwrap_or(0)
+                    });
+                    self.trim_trailing_spaces();
+                    self.out.push('\n');
+                    self.newline(indent);
+                },
+                SyntaxKind::Heading => self.item(child, None, None),
+                SyntaxKind::ListItem | SyntaxKind::EnumItem | SyntaxKind::TermItem => {
+                    // Lines of the item are aligned with its body, just after the marker.
+                    let marker = enum_pos[ix].map(|pos| pos.marker() );
+                    let marker_len = marker.as_ref().map(|m| m.chars().count() )
+                        .or_else(|| child.children().next().map(|m| m.text().chars().count() ) )
+                        .unwrap_or(1);
+                    let indent = self.col() + marker_len + 1;
+                    self.item(child, Some(indent), marker);
+                },
+                SyntaxKind::Strong | SyntaxKind::Emph => {
+                    for c in child.children() {
+                        if c.kind() == SyntaxKind::Markup {
+                            self.markup(c, item_indent);
+                        } else {
+                            verbatim(c, &mut self.out);
+                        }
+                    }
+                },
+                _ => self.expr(child)
+            }
+        }
+    }
+
+    // Headings and list items: a single space after the marker (and after the colon
+    // of terms), and their body. Enum markers are replaced by the given one.
+    fn item(&mut self, node : &SyntaxNode, indent : Option<usize>, marker : Option<String>) {
+        for child in node.children() {
+            match child.kind() {
+                SyntaxKind::EnumMarker => self.out.push_str(marker.as_deref().unwrap_or(child.text())),
+                SyntaxKind::Space if !child.text().contains('\n') => self.out.push(' '),
+                SyntaxKind::Markup => self.markup(child, indent),
+                _ => verbatim(child, &mut self.out)
+            }
+        }
+    }
+
+    fn expr(&mut self, node : &SyntaxNode) {
+        match node.kind() {
+            SyntaxKind::Args | SyntaxKind::Params | SyntaxKind::Array | SyntaxKind::Dict => self.list(node),
+            SyntaxKind::CodeBlock => self.code_block(node),
+            SyntaxKind::ContentBlock => {
+                for child in node.children() {
+                    if child.kind() == SyntaxKind::Markup {
+                        self.markup(child, None);
+                    } else {
+                        verbatim(child, &mut self.out);
+                    }
+                }
+            },
+            SyntaxKind::FuncCall | SyntaxKind::FieldAccess | SyntaxKind::Spread if !has_comment(node) => {
+                for child in node.children().filter(|c| c.kind() != SyntaxKind::Space ) {
+                    self.expr(child);
+                }
+            },
+            SyntaxKind::Named | SyntaxKind::Keyed if !has_comment(node) => {
+                for child in node.children().filter(|c| c.kind() != SyntaxKind::Space ) {
+                    self.expr(child);
+                    if child.kind() == SyntaxKind::Colon {
+                        self.out.push(' ');
+                    }
+                }
+            },
+            SyntaxKind::Parenthesized if !has_comment(node) => {
+                for child in node.children().filter(|c| c.kind() != SyntaxKind::Space ) {
+                    self.expr(child);
+                }
+            },
+            SyntaxKind::Binary | SyntaxKind::LetBinding | SyntaxKind::SetRule | SyntaxKind::ShowRule |
+            SyntaxKind::Closure | SyntaxKind::Conditional | SyntaxKind::WhileLoop | SyntaxKind::ForLoop |
+            SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude | SyntaxKind::ImportItems |
+            SyntaxKind::FuncReturn if !has_comment(node) => self.spaced(node),
+            _ => verbatim(node, &mut self.out)
+        }
+    }
+
+    // Children separated by single spaces, except before arguments, parameters and
+    // punctuation (e.g. let f(x) = x, set text(red) if cond, import "a.typ": b, c).
+    fn spaced(&mut self, node : &SyntaxNode) {
+        let mut first = true;
+        for child in node.children().filter(|c| c.kind() != SyntaxKind::Space ) {
+            let tight = matches!(child.kind(), SyntaxKind::Args | SyntaxKind::Params | SyntaxKind::Colon | SyntaxKind::Comma);
+            if !first && !tight {
+                self.out.push(' ');
+            }
+            self.expr(child);
+            first = false;
+        }
+    }
+
+    // Parenthesized lists (arguments, parameters, arrays and dictionaries), followed
+    // by the trailing content blocks of calls.
+    fn list(&mut self, node : &SyntaxNode) {
+        let inner_indent = self.line_indent() + self.opts.indent;
+        let mut entries : Vec<Entry> = Vec::new();
+        let mut trailing : Vec<&SyntaxNode> = Vec::new();
+        let (mut in_parens, mut has_parens, mut empty_dict) = (false, false, false);
+        let mut newline_before = true;
+        for child in node.children() {
+            match child.kind() {
+                SyntaxKind::LeftParen => {
+                    in_parens = true;
+                    has_parens = true;
+                },
+                SyntaxKind::RightParen => in_parens = false,
+                SyntaxKind::Comma => { },
+                SyntaxKind::Colon if in_parens => empty_dict = true,
+                SyntaxKind::Space => {
+                    newline_before = child.text().contains('\n');
+                },
+                kind if is_comment(kind) && in_parens => {
+                    let comment = child.text().to_string();
+                    match entries.last_mut() {
+                        Some(Entry::Item(_, c @ None)) if !newline_before => *c = Some(comment),
+                        _ => entries.push(Entry::Comment(comment))
+                    }
+                    newline_before = false;
+                },
+                _ if in_parens => {
+                    entries.push(Entry::Item(self.sub(child, inner_indent), None));
+                    newline_before = false;
+                },
+                _ => trailing.push(child)
+            }
+        }
+
+        if has_parens {
+            let items : Vec<&String> = entries.iter().filter_map(|e| match e { Entry::Item(s, None) => Some(s), _ => None } ).collect();
+            let only_items = items.len() == entries.len();
+            let single_array = node.kind() == SyntaxKind::Array && items.len() == 1;
+            let inline = format!("({}{})", items.iter().map(|s| &s[..] ).collect::<Vec<_>>().join(", "), if single_array { "," } else { "" });
+            let fits = only_items && !inline.contains('\n') && self.col() + inline.chars().count() <= self.opts.width;
+            if entries.is_empty() {
+                self.out.push_str(if empty_dict { "(:)" } else { "()" });
+            } else if fits {
+                self.out.push_str(&inline);
+            } else {
+                let outer_indent = self.line_indent();
+                self.out.push('(');
+                for entry in entries.iter() {
+                    self.newline(inner_indent);
+                    match entry {
+                        Entry::Item(item, comment) => {
+                            self.out.push_str(item);
+                            self.out.push(',');
+                            if let Some(comment) = comment {
+                                self.out.push(' ');
+                                self.out.push_str(comment);
+                            }
+                        },
+                        Entry::Comment(comment) => self.out.push_str(comment)
+                    }
+                }
+                self.newline(outer_indent);
+                self.out.push(')');
+            }
+        }
+        for block in trailing {
+            self.expr(block);
+        }
+    }
+
+    // Code blocks: single-line blocks as { a; b }, and the others with one expression
+    // (or comment) per line, keeping blank lines and comments after expressions.
+    fn code_block(&mut self, node : &SyntaxNode) {
+        let Some(code) = node.children().find(|c| c.kind() == SyntaxKind::Code ) else {
+            verbatim(node, &mut self.out);
+            return;
+        };
+        let multiline = text_of(node).contains('\n');
+        let outer_indent = self.line_indent();
+        let inner_indent = outer_indent + self.opts.indent;
+
+        // Each part holds the newlines before it (none when at the same line as the previous one).
+        let mut parts : Vec<(usize, String)> = Vec::new();
+        let mut newlines = 1;
+        for child in code.children() {
+            match child.kind() {
+                SyntaxKind::Space => newlines = child.text().matches('\n').count(),
+                SyntaxKind::Semicolon => {
+                    if let Some(last) = parts.last_mut() {
+                        last.1.push(';');
+                    }
+                    newlines = 0;
+                },
+                _ => {
+                    let base = if multiline { inner_indent } else { self.col() };
+                    parts.push((newlines, self.sub(child, base)));
+                    newlines = 0;
+                }
+            }
+        }
+
+        if parts.is_empty() {
+            self.out.push_str("{}");
+        } else if !multiline {
+            let line = parts.iter().map(|(_, p)| &p[..] ).collect::<Vec<_>>().join(" ");
+            self.out.push_str(&format!("{{ {} }}", line));
+        } else {
+            self.out.push('{');
+            for (ix, (newlines, part)) in parts.iter().enumerate() {
+                if ix == 0 || *newlines > 0 {
+                    if ix > 0 && *newlines > 1 {
+                        self.trim_trailing_spaces();
+                        self.out.push('\n');
+                    }
+                    self.newline(inner_indent);
+                } else {
+                    self.out.push(' ');
+                }
+                self.out.push_str(part);
+            }
+            self.newline(outer_indent);
+            self.out.push('}');
+        }
+    }
+
+}
+
+// Tokens of the tree that formatting must keep: node kinds and leaf texts, without the
+// whitespace and trailing commas the formatter may change.
+fn canonical(node : &SyntaxNode, parent : SyntaxKind, prev : Option<SyntaxKind>, next : Option<SyntaxKind>, out : &mut Vec<String>) {
+    let kind = node.kind();
+    let children : Vec<&SyntaxNode> = node.children().collect();
+    if children.is_empty() {
+        match kind {
+            SyntaxKind::Space | SyntaxKind::Parbreak => {
+                let at_heading = prev == Some(SyntaxKind::Heading) || next == Some(SyntaxKind::Heading);
+                let at_edge = prev.is_none() || next.is_none();
+                if parent == SyntaxKind::Markup && !at_heading && !at_edge {
+                    out.push(if kind == SyntaxKind::Space { String::from(" ") } else { String::from("\n\n") });
+                }
+            },
+            SyntaxKind::Comma if next == Some(SyntaxKind::RightParen) => { },
+            SyntaxKind::Semicolon => { },
+            _ => out.push(node.text().to_string())
+        }
+        return;
+    }
+    out.push(format!("<{:?}", kind));
+    // Siblings are taken ignoring the whitespace between them, except in markup.
+    let significant : Vec<&SyntaxNode> = if kind == SyntaxKind::Markup {
+        children.clone()
+    } else {
+        children.iter().copied().filter(|c| !matches!(c.kind(), SyntaxKind::Space | SyntaxKind::Parbreak) || c.children().len() > 0 ).collect()
+    };
+    // Enum markers are compared by the number they give to their item.
+    let enum_pos = if kind == SyntaxKind::Markup { enum_positions(&significant) } else { vec![None; significant.len()] };
+    for (ix, child) in significant.iter().enumerate() {
+        let prev = if ix > 0 { Some(significant[ix-1].kind()) } else { None };
+        let next = significant[ix+1..].iter().map(|c| c.kind() ).find(|k| !is_comment(*k) || kind == SyntaxKind::Markup );
+        let start = out.len();
+        canonical(child, kind, prev, next, out);
+        if let (Some(pos), Some(marker)) = (enum_pos[ix], out.get_mut(start + 1)) {
+            *marker = format!("#{}", pos.number);
+        }
+    }
+    out.push(String::from(">"));
+}
+
+fn canonical_tokens(src : &str) -> Result<Vec<String>, String> {
+    let source = Source::new(SourceId::detached(), Path::new(""), src.to_string());
+    let root = source.root();
+    if root.erroneous() {
+        return Err(String::from("The document has syntax errors"));
+    }
+    let mut out = Vec::new();
+    canonical(root, SyntaxKind::Markup, None, None, &mut out);
+    Ok(out)
+}
+
+/// Formats a typst source. Sources with syntax errors are not formatted, and an error
+/// is also returned if the formatted text would not parse to the same document.
+pub fn format(src : &str, opts : &FormatOptions) -> Result<String, String> {
+    let source = Source::new(SourceId::detached(), Path::new(""), src.to_string());
+    let root = source.root();
+    if root.erroneous() {
+        return Err(String::from("The document has syntax errors"));
+    }
+    let mut f = Formatter::new(*opts, 0);
+    f.markup(root, None);
+    let mut out = f.out.trim_end().to_string();
+    out.push('\n');
+    if canonical_tokens(src)? != canonical_tokens(&out)? {
+        return Err(String::from("The document could not be formatted without changing it"));
+    }
+    Ok(out)
+}
+
+#[test]
+fn format_source() {
+    let opts = FormatOptions { width : 40, indent : 2 };
+    let src = "#set page( paper:\"a4\",margin : 2cm )\n= Intro\nSome text.\n-   first\n-  second\n    - nested\n#let f(x,y:1)=x+y\n#table(columns: 2, [Some long cell], [Another long cell], [Third])\n";
+    let expected = "#set page(paper: \"a4\", margin: 2cm)\n\n= Intro\n\nSome text.\n- first\n- second\n  - nested\n#let f(x, y: 1) = x + y\n#table(\n  columns: 2,\n  [Some long cell],\n  [Another long cell],\n  [Third],\n)\n";
+    assert_eq!(format(src, &opts).unwrap(), expected);
+    assert_eq!(format(expected, &opts).unwrap(), expected);
+
+    // Comments are kept, and single-element arrays keep their comma.
+    let src = "#let a = (1,)\n#let b = (\n  1, // one\n  2\n)\n";
+    assert_eq!(format(src, &opts).unwrap(), "#let a = (1,)\n#let b = (\n  1, // one\n  2,\n)\n");
+    assert!(format("#set page(", &opts).is_err());
+
+    // Enum items follow the numbering style of the first item of their list.
+    assert_eq!(format("+ a\n2. b\n+ c\n5. d\n", &opts).unwrap(), "+ a\n+ b\n+ c\n5. d\n");
+    assert_eq!(format("1. a\n+ b\n\n+ c\n7. d\n+ e\n", &opts).unwrap(), "1. a\n2. b\n\n3. c\n7. d\n8. e\n");
+    assert_eq!(format("1. a\n   +  nested\n10. b\n    more\n", &opts).unwrap(), "1. a\n   + nested\n10. b\n    more\n");
+}
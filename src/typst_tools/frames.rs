@@ -0,0 +1,327 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+use typst::doc::{Frame, Element, Meta, Destination};
+use typst::geom::{Point, Color};
+use typst::syntax::Span;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/*
+Preview of the typst frames, without going through the PDF export. Each page frame
+is kept with a hash of its content (so that pages that did not change between compilations
+can re-use their rendered surfaces) and with the source lines of the text, shapes and images
+it contains (so that a click at the page can be mapped back to the source). Links and the
+outline are extracted here as well, and used by the viewer whatever the preview source is.
+*/
+
+// Region of a page (in points, relative to the page top-left corner) produced by a source line.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceSpan {
+    pub x : f64,
+    pub y : f64,
+    pub width : f64,
+    pub height : f64,
+    pub line : usize
+}
+
+#[derive(Debug, Clone)]
+pub struct PageFrame {
+
+    pub frame : Frame,
+
+    // Page width and height, in points.
+    pub size : (f64, f64),
+
+    pub hash : u128,
+
+    pub spans : Vec<SourceSpan>,
+
+    // Characters in reading order, with their regions as (x, y, width, height) in points.
+    // Breaks between text runs are marked with spaces and newlines of empty width.
+    pub chars : Vec<(char, (f64, f64, f64, f64))>,
+
+    pub links : Vec<PageLink>
+
+}
+
+impl PageFrame {
+
+    /// Source line of the content closest to the given page position (in points).
+    pub fn line_at(&self, x : f64, y : f64) -> Option<usize> {
+        let contains = |s : &&SourceSpan| x >= s.x && x <= s.x + s.width && y >= s.y && y <= s.y + s.height;
+        if let Some(span) = self.spans.iter().find(contains) {
+            return Some(span.line);
+        }
+        self.spans.iter()
+            .min_by(|a, b| distance(a, x, y).total_cmp(&distance(b, x, y)) )
+            .map(|s| s.line )
+    }
+
+}
+
+fn distance(span : &SourceSpan, x : f64, y : f64) -> f64 {
+    let dx = (span.x - x).max(x - (span.x + span.width)).max(0.0);
+    let dy = (span.y - y).max(y - (span.y + span.height)).max(0.0);
+    dx * dx + dy * dy
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkTarget {
+
+    // Zero-based page index and vertical position (in points) of an internal destination.
+    Page(usize, f64),
+
+    Url(String)
+
+}
+
+#[derive(Debug, Clone)]
+pub struct PageLink {
+
+    // Clickable region as (x, y, width, height), in points.
+    pub rect : (f64, f64, f64, f64),
+
+    pub target : LinkTarget
+
+}
+
+impl PageLink {
+
+    pub fn contains(&self, x : f64, y : f64) -> bool {
+        let (lx, ly, w, h) = self.rect;
+        x >= lx && x <= lx + w && y >= ly && y <= ly + h
+    }
+
+}
+
+// A document heading, with the place it was typeset at.
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub title : String,
+    pub level : usize,
+    pub page : usize,
+    pub y : f64,
+    pub line : usize
+}
+
+// Links of each page and the document outline.
+#[derive(Debug, Clone, Default)]
+pub struct Navigation {
+    pub links : Vec<Vec<PageLink>>,
+    pub outline : Vec<OutlineEntry>
+}
+
+#[derive(Debug, Clone)]
+pub struct FramePreview {
+
+    // Kept so the previewed document can be exported without compiling it again.
+    pub doc : Arc<Document>,
+
+    pub pages : Vec<PageFrame>,
+
+    pub outline : Vec<OutlineEntry>
+
+}
+
+impl FramePreview {
+
+    pub fn navigation(&self) -> Navigation {
+        Navigation {
+            links : self.pages.iter().map(|p| p.links.clone() ).collect(),
+            outline : self.outline.clone()
+        }
+    }
+
+}
+
+/// Compiles the document into frames that can be rendered directly by the viewer (from
+/// the main text instead of the file at path, if given).
+pub fn compile_frames(path : &Path, fonts : Fonts, main : Option<String>) -> Result<FramePreview, Vec<(usize, String)>> {
+    let (doc, world) = compile_document(path, fonts, main)?;
+    let pages : Vec<PageFrame> = doc.pages.iter().map(|frame| {
+        let mut page = PageFrame {
+            frame : frame.clone(),
+            size : (frame.width().to_pt(), frame.height().to_pt()),
+            hash : frame_hash(frame),
+            spans : Vec::new(),
+            chars : Vec::new(),
+            links : Vec::new()
+        };
+        collect_content(&world, frame, Point::zero(), &mut page);
+        page
+    }).collect();
+    let outline = outline(&world, |line| {
+        pages.iter().enumerate().find_map(|(page, frame)| {
+            frame.spans.iter().find(|s| s.line == line ).map(|s| (page, s.y) )
+        })
+    });
+    Ok(FramePreview { doc : Arc::new(doc), pages, outline })
+}
+
+// Top-level headings of the main source, placed at the page and vertical position (if any)
+// where their line was typeset.
+fn outline(world : &SystemWorld, place : impl Fn(usize) -> Option<(usize, f64)>) -> Vec<OutlineEntry> {
+    let source = world.source(world.main);
+    let Ok(ast) = source.ast() else { return Vec::new() };
+    let mut entries = Vec::new();
+    for expr in ast.exprs() {
+        let Expr::Heading(head) = expr else { continue };
+        let Some(line) = source.byte_to_line(source.range(head.span()).start) else { continue };
+        if let Some((page, y)) = place(line) {
+            entries.push(OutlineEntry { title : first_text(&head.body()), level : head.level().get(), page, y, line });
+        }
+    }
+    entries
+}
+
+/// Links and outline of a compiled document, found without collecting the rest of the page
+/// content (as compile_frames does), for when the frames themselves are not previewed.
+pub fn navigation(doc : &Document, world : &SystemWorld) -> Navigation {
+    let mut links = Vec::with_capacity(doc.pages.len());
+    let mut starts : HashMap<usize, (usize, f64)> = HashMap::new();
+    for (page_ix, frame) in doc.pages.iter().enumerate() {
+        let mut page_links = Vec::new();
+        collect_navigation(world, frame, Point::zero(), page_ix, &mut page_links, &mut starts);
+        links.push(page_links);
+    }
+    let outline = outline(world, |line| starts.get(&line).copied() );
+    Navigation { links, outline }
+}
+
+/// Renders the frame with the given number of pixels per point, returning the width, height,
+/// stride and pixels in the layout of a cairo ARGB32 image surface.
+pub fn render_frame(frame : &Frame, zoom : f64) -> (i32, i32, i32, Vec<u8>) {
+    let pixmap = typst::export::render(frame, zoom as f32, Color::WHITE);
+    let (width, height) = (pixmap.width() as i32, pixmap.height() as i32);
+
+    // Both are premultiplied, but cairo stores each pixel as a native-endian
+    // u32 (BGRA at little-endian machines), while tiny-skia stores RGBA bytes.
+    let mut data = pixmap.data().to_vec();
+    for px in data.chunks_exact_mut(4) {
+        let argb = u32::from_be_bytes([px[3], px[0], px[1], px[2]]);
+        px.copy_from_slice(&argb.to_ne_bytes());
+    }
+    (width, height, width * 4, data)
+}
+
+fn frame_hash(frame : &Frame) -> u128 {
+    let mut state = SipHasher::new();
+    frame.hash(&mut state);
+    state.finish128().as_u128()
+}
+
+fn collect_content(world : &SystemWorld, frame : &Frame, origin : Point, page : &mut PageFrame) {
+    for (pos, elem) in frame.elements() {
+        let pos = origin + *pos;
+        match elem {
+            Element::Group(group) => {
+                // Group transforms (rotations and scalings) are ignored; only the
+                // translation is relevant to find the content.
+                collect_content(world, &group.frame, pos, page);
+            },
+            Element::Meta(Meta::Link(dest), size) => {
+                page.links.push(page_link(dest, pos, *size));
+            },
+            Element::Text(text) => {
+                let mut x = pos.x;
+                let size = text.size;
+                mark_text_break(&mut page.chars, x.to_pt(), (pos.y - size).to_pt(), size.to_pt());
+                for glyph in text.glyphs.iter() {
+                    let advance = glyph.x_advance.at(size);
+                    page.chars.push((glyph.c, (x.to_pt(), (pos.y - size).to_pt(), advance.to_pt(), size.to_pt())));
+                    if let Some(line) = span_line(world, glyph.span) {
+                        let (x_pt, y_pt) = (x.to_pt(), (pos.y - size).to_pt());
+                        match page.spans.last_mut() {
+
+                            // Glyphs from the same line are merged into a single span.
+                            Some(last) if last.line == line && (last.y - y_pt).abs() < 1.0 => {
+                                last.width = (x_pt + advance.to_pt()) - last.x;
+                            },
+                            _ => {
+                                page.spans.push(SourceSpan { x : x_pt, y : y_pt, width : advance.to_pt(), height : size.to_pt(), line });
+                            }
+                        }
+                    }
+                    x += advance;
+                }
+            },
+            Element::Shape(shape, span) => {
+                if let (Some(line), Some(size)) = (span_line(world, *span), shape_size(shape)) {
+                    page.spans.push(SourceSpan { x : pos.x.to_pt(), y : pos.y.to_pt(), width : size.0, height : size.1, line });
+                }
+            },
+            Element::Image(_, size, span) => {
+                if let Some(line) = span_line(world, *span) {
+                    page.spans.push(SourceSpan { x : pos.x.to_pt(), y : pos.y.to_pt(), width : size.x.to_pt(), height : size.y.to_pt(), line });
+                }
+            },
+            _ => { }
+        }
+    }
+}
+
+fn page_link(dest : &Destination, pos : Point, size : typst::geom::Size) -> PageLink {
+    let target = match dest {
+        Destination::Url(url) => LinkTarget::Url(url.to_string()),
+        Destination::Internal(loc) => LinkTarget::Page(loc.page.get() - 1, loc.pos.y.to_pt())
+    };
+    PageLink { rect : (pos.x.to_pt(), pos.y.to_pt(), size.x.to_pt(), size.y.to_pt()), target }
+}
+
+// Collects the links of a page, and the place where each source line first starts a text
+// run (as the page and vertical position, by line). Only the first glyph of each run is looked up,
+// which is enough to place the headings.
+fn collect_navigation(world : &SystemWorld, frame : &Frame, origin : Point, page_ix : usize, links : &mut Vec<PageLink>, starts : &mut HashMap<usize, (usize, f64)>) {
+    for (pos, elem) in frame.elements() {
+        let pos = origin + *pos;
+        match elem {
+            Element::Group(group) => {
+                collect_navigation(world, &group.frame, pos, page_ix, links, starts);
+            },
+            Element::Meta(Meta::Link(dest), size) => {
+                links.push(page_link(dest, pos, *size));
+            },
+            Element::Text(text) => {
+                let Some(line) = text.glyphs.first().and_then(|g| span_line(world, g.span) ) else { continue };
+                starts.entry(line).or_insert((page_ix, (pos.y - text.size).to_pt()));
+            },
+            _ => { }
+        }
+    }
+}
+
+// Separates a new text run from the previous one by a newline (when it starts at another line)
+// or by a space (when it starts after a gap at the same line).
+fn mark_text_break(chars : &mut Vec<(char, (f64, f64, f64, f64))>, x : f64, y : f64, size : f64) {
+    let Some((last_c, (last_x, last_y, last_w, last_h))) = chars.last().copied() else { return };
+    if last_c.is_whitespace() {
+        return;
+    }
+    let rect = (last_x + last_w, last_y, 0.0, last_h);
+    if (y - last_y).abs() > size.min(last_h) * 0.5 {
+        chars.push(('\n', rect));
+    } else if x - (last_x + last_w) > size * 0.1 {
+        chars.push((' ', rect));
+    }
+}
+
+fn shape_size(shape : &typst::geom::Shape) -> Option<(f64, f64)> {
+    use typst::geom::Geometry;
+    match &shape.geometry {
+        Geometry::Rect(size) => Some((size.x.to_pt(), size.y.to_pt())),
+        Geometry::Line(to) => Some((to.x.to_pt().abs(), to.y.to_pt().abs().max(1.0))),
+        _ => None
+    }
+}
+
+fn span_line(world : &SystemWorld, span : Span) -> Option<usize> {
+    if span.is_detached() || span.source() != world.main {
+        return None;
+    }
+    let src = world.source(span.source());
+    src.byte_to_line(src.range(span).start)
+}
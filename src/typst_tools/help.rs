@@ -0,0 +1,192 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::complete::{self, Catalog, FuncEntry, Binding, Open};
+
+/*
+Documentation shown while editing: the signature and docs of the function under the
+pointer, and the signature of the innermost call around the cursor with the argument
+being typed highlighted. Both use the same data as the completion (see complete::catalog),
+falling back to the let bindings of the project.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Help {
+
+    pub func : String,
+
+    // Parameter labels, in the order of the signature (e.g. "paper: string").
+    pub params : Vec<String>,
+
+    // Parameter of the argument at the cursor, if known.
+    pub active : Option<usize>,
+
+    pub docs : String
+
+}
+
+fn escape(s : &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl Help {
+
+    fn from_func(f : &FuncEntry) -> Self {
+        // Positional parameters are listed first, as they are written at calls.
+        let mut order : Vec<_> = f.params.iter().filter(|p| p.positional ).collect();
+        order.extend(f.params.iter().filter(|p| !p.positional ));
+        let params = order.iter().map(|p| {
+            let dots = if p.variadic { ".." } else { "" };
+            if p.positional {
+                format!("{}{}: {}", dots, p.name, p.ty)
+            } else {
+                format!("{}: {}", p.name, p.ty)
+            }
+        }).collect();
+        Self { func : f.name.clone(), params, active : None, docs : f.docs.clone() }
+    }
+
+    fn from_binding(b : &Binding) -> Self {
+        let docs = if b.file.is_empty() { String::new() } else { format!("Defined at {}", b.file) };
+        Self { func : b.name.clone(), params : b.params.clone().unwrap_or_default(), active : None, docs }
+    }
+
+    /// Signature as plain text, e.g. page(paper: string, margin: length).
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.func, self.params.join(", "))
+    }
+
+    /// Signature as Pango markup, with the active parameter in bold.
+    pub fn markup(&self) -> String {
+        let params : Vec<String> = self.params.iter().enumerate().map(|(ix, p)| {
+            if Some(ix) == self.active { format!("<b>{}</b>", escape(p)) } else { escape(p) }
+        }).collect();
+        format!("<tt>{}({})</tt>", escape(&self.func), params.join(", "))
+    }
+
+}
+
+fn param_name(label : &str) -> &str {
+    label.trim_start_matches("..").split(':').next().unwrap_or("").trim()
+}
+
+fn lookup(catalog : &Catalog, name : &str, bindings : &[Binding]) -> Option<Help> {
+    bindings.iter().find(|b| b.name == name && b.params.is_some() ).map(Help::from_binding)
+        .or_else(|| catalog.func(name).map(Help::from_func) )
+}
+
+/// Help for the innermost call enclosing the end of the text (i.e. the cursor), with the
+/// parameter of the argument being written marked as active.
+pub fn signature_at(catalog : &Catalog, before : &str, bindings : &[Binding]) -> Option<Help> {
+    let scan = complete::scan(before);
+    if scan.in_comment_or_raw {
+        return None;
+    }
+    let Some(Open::Call(start, Some(func))) = scan.stack.last() else { return None };
+    let mut help = lookup(catalog, func, bindings)?;
+    let parts = complete::split_top_level(&before[*start..]);
+    let curr = parts.last().copied().unwrap_or("");
+
+    // Named arguments are matched by name, the others by their position among the unnamed ones.
+    let named = |arg : &str| {
+        arg.split_once(':')
+            .map(|(name, _)| name.trim() )
+            .filter(|name| !name.is_empty() && name.chars().all(complete::is_ident_char) )
+            .map(|name| name.to_string() )
+    };
+    help.active = match named(curr) {
+        Some(name) => help.params.iter().position(|p| param_name(p) == name ),
+        None => {
+            let ix = parts[..parts.len()-1].iter().filter(|p| named(p).is_none() ).count();
+            let positional : Vec<usize> = help.params.iter().enumerate()
+                .filter(|(_, p)| !p.contains(':') || p.starts_with("..") || is_positional(catalog, &help.func, param_name(p)) )
+                .map(|(pos, _)| pos )
+                .collect();
+            positional.get(ix).copied().or_else(|| {
+                positional.last().copied().filter(|last| help.params[*last].starts_with("..") )
+            })
+        }
+    };
+    Some(help)
+}
+
+fn is_positional(catalog : &Catalog, func : &str, param : &str) -> bool {
+    catalog.func(func)
+        .and_then(|f| f.params.iter().find(|p| p.name == param ) )
+        .map(|p| p.positional )
+        .unwrap_or(false)
+}
+
+/// Help for the identifier at the byte offset of a line (e.g. the function under the pointer).
+pub fn hover_at(catalog : &Catalog, line : &str, offset : usize, bindings : &[Binding]) -> Option<Help> {
+    let is_word = |c : char| complete::is_ident_char(c) || c == '.';
+    let offset = offset.min(line.len());
+    if !line.is_char_boundary(offset) {
+        return None;
+    }
+    let start = line[..offset].char_indices().rev()
+        .take_while(|(_, c)| is_word(*c) )
+        .last()
+        .map(|(ix, _)| ix )
+        .unwrap_or(offset);
+    let end = line[offset..].char_indices()
+        .find(|(_, c)| !is_word(*c) )
+        .map(|(ix, _)| offset + ix )
+        .unwrap_or(line.len());
+    let word = line[start..end].trim_matches('.');
+    if word.is_empty() {
+        return None;
+    }
+
+    // Only identifiers in code (after a hash, or at a set/show rule or call) are looked up,
+    // so plain words of the text do not show documentation.
+    let lead = line[..start].trim_end();
+    let in_code = line[..start].ends_with('#') ||
+        lead.ends_with("#set") || lead.ends_with("#show") ||
+        line[end..].starts_with('(') || line[end..].starts_with('[');
+    if !in_code {
+        return None;
+    }
+    lookup(catalog, word, bindings)
+}
+
+#[test]
+fn signature_help() {
+    use super::complete::ParamEntry;
+    let param = |name : &str, ty : &str, positional : bool, variadic : bool| {
+        ParamEntry { name : name.to_string(), ty : ty.to_string(), positional, named : !positional, variadic, ..Default::default() }
+    };
+    let catalog = Catalog {
+        funcs : vec![
+            FuncEntry {
+                name : String::from("page"),
+                docs : String::from("Layouts its child onto one or multiple pages."),
+                params : vec![param("paper", "string", false, false), param("margin", "length", false, false), param("body", "content", true, false)]
+            },
+            FuncEntry {
+                name : String::from("stack"),
+                docs : String::new(),
+                params : vec![param("dir", "direction", false, false), param("children", "content", true, true)]
+            }
+        ],
+        math_funcs : Vec::new(),
+        symbols : Vec::new()
+    };
+    let bindings = complete::let_bindings("#let note(body, color: red) = box(fill: color, body)", "");
+
+    let help = signature_at(&catalog, "#set page(paper: \"a4\", margin: 2c", &bindings).unwrap();
+    assert_eq!(help.signature(), "page(body: content, paper: string, margin: length)");
+    assert_eq!(help.active, Some(2));
+    assert_eq!(help.markup(), "<tt>page(body: content, paper: string, <b>margin: length</b>)</tt>");
+    assert_eq!(signature_at(&catalog, "#page(paper: \"a4\")[Te", &bindings), None);
+    assert_eq!(signature_at(&catalog, "#stack(dir: ltr, [a], [b], ", &bindings).unwrap().active, Some(0));
+    assert_eq!(signature_at(&catalog, "#note(bo", &bindings).unwrap().active, Some(0));
+    assert_eq!(signature_at(&catalog, "#note([Hi], color: bl", &bindings).unwrap().active, Some(1));
+
+    let line = "#set page(paper: \"a4\") and a page";
+    assert_eq!(hover_at(&catalog, line, 7, &bindings).map(|h| h.docs ), Some(String::from("Layouts its child onto one or multiple pages.")));
+    assert_eq!(hover_at(&catalog, line, 30, &bindings), None);
+    assert_eq!(hover_at(&catalog, "#note[x]", 3, &bindings).map(|h| h.signature() ), Some(String::from("note(body, color: red)")));
+}
@@ -0,0 +1,691 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+use crate::tex::{Command, CommandArg, Token, MathQuote};
+use typst::syntax::ast::{self, Math, Args, Heading};
+use typst::syntax::SyntaxNode;
+use std::collections::HashSet;
+
+/*
+Export of typst sources to LaTeX, for venues that only accept LaTeX submissions. The
+exporter walks the typst AST and writes the equivalent LaTeX for the markup (headings, emphasis,
+lists, labels and references), the equations (translated to LaTeX math) and the
+most common functions (image, figure, table, cite and bibliography). Code that has
+no LaTeX counterpart (set, show and let rules, or custom functions) is not exported, but the
+content blocks passed to unknown functions are, so that no text is lost.
+*/
+
+#[derive(Debug, Clone)]
+pub struct LatexOptions {
+
+    // Document class (e.g. article, report, revtex4-2, elsarticle).
+    pub class : String,
+
+    // Options passed to the document class (e.g. a4paper, 12pt).
+    pub class_opts : Vec<String>,
+
+    // Bibliography style used when the document has a bibliography.
+    pub bib_style : String
+
+}
+
+impl Default for LatexOptions {
+
+    fn default() -> Self {
+        Self {
+            class : String::from("article"),
+            class_opts : Vec::new(),
+            bib_style : String::from("plain")
+        }
+    }
+
+}
+
+/// Exports the typst source to a standalone LaTeX document. Errors are the
+/// syntax errors of the typst source, as (line, message) pairs.
+pub fn export_latex(path : &Path, txt : String, opts : &LatexOptions) -> Result<String, Vec<(usize, String)>> {
+    let source = Source::new(SourceId::detached(), path, txt);
+    let ast = source.ast().map_err(|e| process_errors(&source, *e) )?;
+
+    let mut exporter = LatexExporter::new(opts);
+    exporter.collect_labels(ast.as_untyped());
+    let body = exporter.markup(&ast);
+
+    let mut out = String::new();
+    if opts.class_opts.is_empty() {
+        out += &command("documentclass", &opts.class);
+    } else {
+        let class_opts : Vec<&str> = opts.class_opts.iter().map(|o| &o[..] ).collect();
+        out += &command_with_opts("documentclass", class_opts, &opts.class);
+    }
+    out += "\n";
+    for pkg in ["amsmath", "amssymb", "graphicx", "hyperref"] {
+        out += &command("usepackage", pkg);
+        out += "\n";
+    }
+    if let Some(title) = &exporter.title {
+        out += &command("title", title);
+        out += "\n";
+    }
+    if let Some(author) = &exporter.author {
+        out += &command("author", author);
+        out += "\n";
+    }
+    out += "\n";
+    out += &command("begin", "document");
+    out += "\n\n";
+    if exporter.title.is_some() {
+        out += "\\maketitle\n\n";
+    }
+    out += body.trim();
+    out += "\n\n";
+    out += &command("end", "document");
+    out += "\n";
+    Ok(out)
+}
+
+struct LatexExporter<'a> {
+
+    opts : &'a LatexOptions,
+
+    // Labels defined at the document. References to anything else are citations.
+    labels : HashSet<String>,
+
+    // String constants bound with #let, used to resolve the document title and authors.
+    strings : HashMap<String, String>,
+
+    title : Option<String>,
+
+    author : Option<String>,
+
+    // Whether the next double quote opens a quotation.
+    open_quote : bool
+
+}
+
+impl<'a> LatexExporter<'a> {
+
+    fn new(opts : &'a LatexOptions) -> Self {
+        Self {
+            opts,
+            labels : HashSet::new(),
+            strings : HashMap::new(),
+            title : None,
+            author : None,
+            open_quote : true
+        }
+    }
+
+    fn collect_labels(&mut self, node : &SyntaxNode) {
+        if let Some(lbl) = node.cast::<ast::Label>() {
+            self.labels.insert(lbl.get().to_string());
+        }
+        for child in node.children() {
+            self.collect_labels(child);
+        }
+    }
+
+    fn markup(&mut self, markup : &Markup) -> String {
+        let exprs : Vec<Expr> = markup.exprs().collect();
+        let mut out = String::new();
+        let mut ix = 0;
+        while ix < exprs.len() {
+            match &exprs[ix] {
+                Expr::List(_) | Expr::Enum(_) | Expr::Term(_) => {
+                    ix = self.list(&exprs, ix, &mut out);
+                    continue;
+                },
+                Expr::Equation(eq) if eq.block() => {
+                    let label = next_label(&exprs, ix);
+                    out += &self.display_equation(&eq.body(), label.as_ref().map(|l| l.1.as_str() ));
+                    if let Some((lbl_ix, _)) = label {
+                        ix = lbl_ix;
+                    }
+                },
+                Expr::FuncCall(call) if callee_name(call).as_deref() == Some("figure") => {
+                    let label = next_label(&exprs, ix);
+                    out += &self.figure(&call.args(), label.as_ref().map(|l| l.1.as_str() ));
+                    if let Some((lbl_ix, _)) = label {
+                        ix = lbl_ix;
+                    }
+                },
+                expr => {
+                    out += &self.expr(expr);
+                }
+            }
+            ix += 1;
+        }
+        out
+    }
+
+    fn expr(&mut self, expr : &Expr) -> String {
+        match expr {
+            Expr::Text(txt) => escape_text(txt.get()),
+            Expr::Space(_) => {
+                if node_text(expr.as_untyped()).contains('\n') {
+                    String::from("\n")
+                } else {
+                    String::from(" ")
+                }
+            },
+            Expr::Linebreak(_) => String::from("\\\\\n"),
+            Expr::Parbreak(_) => String::from("\n\n"),
+            Expr::Escape(esc) => escape_text(&esc.get().to_string()),
+            Expr::Shorthand(_) => {
+                match &node_text(expr.as_untyped())[..] {
+                    "..." => String::from("\\ldots{}"),
+                    "-?" => String::from("\\-"),
+                    other => other.to_string()
+                }
+            },
+            Expr::SmartQuote(quote) => {
+                if quote.double() {
+                    let q = if self.open_quote { "``" } else { "''" };
+                    self.open_quote = !self.open_quote;
+                    q.to_string()
+                } else {
+                    String::from("'")
+                }
+            },
+            Expr::Strong(strong) => {
+                let body = self.markup(&strong.body());
+                command("textbf", &body)
+            },
+            Expr::Emph(emph) => {
+                let body = self.markup(&emph.body());
+                command("emph", &body)
+            },
+            Expr::Raw(raw) => {
+                if raw.block() {
+                    format!("\\begin{{verbatim}}\n{}\n\\end{{verbatim}}\n", raw.text().trim())
+                } else {
+                    command("texttt", &escape_text(&raw.text()))
+                }
+            },
+            Expr::Link(link) => command("url", link.get()),
+            Expr::Label(lbl) => command("label", lbl.get()),
+            Expr::Ref(r) => {
+                let target = r.target();
+                if self.labels.contains(target) {
+                    command("ref", target)
+                } else {
+                    command("cite", target)
+                }
+            },
+            Expr::Heading(head) => self.heading(head),
+            Expr::Equation(eq) => {
+                Token::Math(&math_to_latex(&eq.body()), MathQuote::Single, 0).to_string()
+            },
+            Expr::FuncCall(call) => self.func_call(call),
+            Expr::Content(content) => self.markup(&content.body()),
+            Expr::Let(binding) => {
+                if let (Some(Expr::Str(s)), id) = (binding.init(), binding.binding()) {
+                    self.strings.insert(id.get().to_string(), s.get().to_string());
+                }
+                String::new()
+            },
+            Expr::Set(set) => {
+                if let Expr::Ident(id) = set.target() {
+                    if id.get() == "document" {
+                        for arg in set.args().items() {
+                            if let Arg::Named(named) = arg {
+                                let value = self.string_value(&named.expr());
+                                match named.name().get().as_str() {
+                                    "title" => self.title = value,
+                                    "author" => self.author = value,
+                                    _ => { }
+                                }
+                            }
+                        }
+                    }
+                }
+                String::new()
+            },
+            _ => String::new()
+        }
+    }
+
+    fn string_value(&self, expr : &Expr) -> Option<String> {
+        match expr {
+            Expr::Str(s) => Some(escape_text(&s.get())),
+            Expr::Ident(id) => self.strings.get(id.get().as_str()).map(|s| escape_text(s) ),
+            Expr::Content(content) => Some(node_text(content.body().as_untyped())),
+            _ => None
+        }
+    }
+
+    fn heading(&mut self, head : &Heading) -> String {
+        // Books and reports have chapters above sections.
+        let levels : &[&str] = match &self.opts.class[..] {
+            "report" | "book" | "scrreprt" | "scrbook" => &["chapter", "section", "subsection", "subsubsection"],
+            _ => &["section", "subsection", "subsubsection", "paragraph"]
+        };
+        let level = (head.level().get() - 1).min(levels.len() - 1);
+        let body = self.markup(&head.body());
+
+        // A label at the end of the heading line is parsed as part of the heading.
+        let (body, label) = match body.rfind("\\label{") {
+            Some(pos) => (body[..pos].to_string(), body[pos..].to_string()),
+            None => (body, String::new())
+        };
+        format!("{}{}\n", command(levels[level], body.trim()), label.trim())
+    }
+
+    // Consumes a sequence of list, enumeration or term items, starting at ix,
+    // and returns the index of the first expression after the sequence.
+    fn list(&mut self, exprs : &[Expr], ix : usize, out : &mut String) -> usize {
+        let env = match &exprs[ix] {
+            Expr::Enum(_) => "enumerate",
+            Expr::Term(_) => "description",
+            _ => "itemize"
+        };
+        *out += &command("begin", env);
+        *out += "\n";
+        let mut last = ix;
+        let mut curr = ix;
+        while curr < exprs.len() {
+            let item = match &exprs[curr] {
+                Expr::List(it) if env == "itemize" => {
+                    format!("\\item {}\n", self.markup(&it.body()).trim())
+                },
+                Expr::Enum(it) if env == "enumerate" => {
+                    format!("\\item {}\n", self.markup(&it.body()).trim())
+                },
+                Expr::Term(it) if env == "description" => {
+                    let term = self.markup(&it.term());
+                    format!("\\item[{}] {}\n", term.trim(), self.markup(&it.description()).trim())
+                },
+                Expr::Space(_) => {
+                    curr += 1;
+                    continue;
+                },
+                _ => break
+            };
+            *out += &item;
+            last = curr;
+            curr += 1;
+        }
+        *out += &command("end", env);
+        *out += "\n";
+        last + 1
+    }
+
+    fn display_equation(&mut self, body : &Math, label : Option<&str>) -> String {
+        let math = math_to_latex(body);
+        let aligned = math.contains('&');
+        let env = match (aligned, label.is_some()) {
+            (true, true) => "align",
+            (true, false) => "align*",
+            (false, true) => "equation",
+            (false, false) => "equation*"
+        };
+        let label = label.map(|l| format!(" {}", command("label", l)) ).unwrap_or_default();
+        format!("{}\n{}{}\n{}\n", command("begin", env), math.trim(), label, command("end", env))
+    }
+
+    fn figure(&mut self, args : &Args, label : Option<&str>) -> String {
+        let mut content = String::new();
+        let mut caption = None;
+        let mut is_table = false;
+        for arg in args.items() {
+            match arg {
+                Arg::Pos(expr) => {
+                    if let Expr::FuncCall(call) = &expr {
+                        is_table = callee_name(call).as_deref() == Some("table");
+                    }
+                    content += &self.expr(&expr);
+                },
+                Arg::Named(named) if named.name().get() == "caption" => {
+                    caption = Some(self.expr(&named.expr()));
+                },
+                _ => { }
+            }
+        }
+        let env = if is_table { "table" } else { "figure" };
+        let mut out = format!("{}[h]\n\\centering\n{}\n", command("begin", env), content.trim());
+        if let Some(caption) = caption {
+            out += &command("caption", caption.trim());
+            out += "\n";
+        }
+        if let Some(label) = label {
+            out += &command("label", label);
+            out += "\n";
+        }
+        out += &command("end", env);
+        out += "\n";
+        out
+    }
+
+    fn func_call(&mut self, call : &ast::FuncCall) -> String {
+        let name = callee_name(call).unwrap_or_default();
+        let args = call.args();
+        let first_str = args.items().find_map(|arg| match arg {
+            Arg::Pos(Expr::Str(s)) => Some(s.get().to_string()),
+            _ => None
+        });
+        match &name[..] {
+            "image" => {
+                let Some(path) = first_str else { return String::new() };
+                let width = args.items().find_map(|arg| match arg {
+                    Arg::Named(named) if named.name().get() == "width" => Some(latex_length(&named.expr())),
+                    _ => None
+                }).flatten();
+                match width {
+                    Some(w) => command_with_opts("includegraphics", vec![&format!("width={}", w)[..]], &path),
+                    None => command("includegraphics", &path)
+                }
+            },
+            "table" => self.table(&args),
+            "cite" => first_str.map(|key| command("cite", &key) ).unwrap_or_default(),
+            "bibliography" => {
+                let Some(path) = first_str else { return String::new() };
+                let path = path.trim_end_matches(".bib");
+                format!("{}\n{}\n", command("bibliographystyle", &self.opts.bib_style), command("bibliography", path))
+            },
+            "pagebreak" => String::from("\\newpage\n"),
+            "linebreak" => String::from("\\\\\n"),
+            "v" | "h" => {
+                let len = args.items().find_map(|arg| match arg {
+                    Arg::Pos(expr) => latex_length(&expr),
+                    _ => None
+                });
+                let cmd = if name == "v" { "vspace" } else { "hspace" };
+                len.map(|l| command(cmd, &l) ).unwrap_or_default()
+            },
+            "align" => {
+                let centered = args.items().any(|arg| matches!(arg, Arg::Pos(Expr::Ident(id)) if id.get() == "center") );
+                let body = self.content_args(&args);
+                if centered {
+                    format!("{}\n{}\n{}\n", command("begin", "center"), body.trim(), command("end", "center"))
+                } else {
+                    body
+                }
+            },
+            "strong" => command("textbf", &self.content_args(&args)),
+            "emph" => command("emph", &self.content_args(&args)),
+            "underline" => command("underline", &self.content_args(&args)),
+            "footnote" => command("footnote", &self.content_args(&args)),
+            "ref" => first_str.map(|lbl| command("ref", &lbl) ).unwrap_or_default(),
+            _ => self.content_args(&args)
+        }
+    }
+
+    fn content_args(&mut self, args : &Args) -> String {
+        let mut out = String::new();
+        for arg in args.items() {
+            if let Arg::Pos(expr @ Expr::Content(_)) = arg {
+                out += &self.expr(&expr);
+            }
+        }
+        out
+    }
+
+    fn table(&mut self, args : &Args) -> String {
+        let mut ncols = 1;
+        let mut cells = Vec::new();
+        for arg in args.items() {
+            match arg {
+                Arg::Named(named) if named.name().get() == "columns" => {
+                    ncols = match named.expr() {
+                        Expr::Int(n) => n.get().max(1) as usize,
+                        Expr::Array(arr) => arr.items().count().max(1),
+                        _ => 1
+                    };
+                },
+                Arg::Pos(expr @ Expr::Content(_)) => {
+                    cells.push(self.expr(&expr).trim().to_string());
+                },
+                Arg::Pos(Expr::Str(s)) => {
+                    cells.push(escape_text(&s.get()));
+                },
+                Arg::Spread(_) => {
+                    // Content generated by code (e.g. read from a csv file) is only known at compile time.
+                    return String::from("% Table content generated by code was not exported\n");
+                },
+                _ => { }
+            }
+        }
+        let mut out = format!("\\begin{{tabular}}{{{}}}\n\\hline\n", "l".repeat(ncols));
+        for row in cells.chunks(ncols) {
+            out += &row.join(" & ");
+            out += " \\\\\n";
+        }
+        out += "\\hline\n";
+        out += &command("end", "tabular");
+        out
+    }
+
+}
+
+// Label that follows the expression at ix (separated by at most a space), with its index.
+fn next_label(exprs : &[Expr], ix : usize) -> Option<(usize, String)> {
+    for (i, expr) in exprs.iter().enumerate().skip(ix + 1) {
+        match expr {
+            Expr::Space(_) => { },
+            Expr::Label(lbl) => return Some((i, lbl.get().to_string())),
+            _ => return None
+        }
+    }
+    None
+}
+
+fn callee_name(call : &ast::FuncCall) -> Option<String> {
+    match call.callee() {
+        Expr::Ident(id) => Some(id.get().to_string()),
+        _ => None
+    }
+}
+
+fn node_text(node : &SyntaxNode) -> String {
+    node.clone().into_text().to_string()
+}
+
+fn command(cmd : &str, arg : &str) -> String {
+    Command { cmd, opts : None, arg : Some(CommandArg::Text(arg)), extra_arg : None }.to_string()
+}
+
+fn command_with_opts(cmd : &str, opts : Vec<&str>, arg : &str) -> String {
+    Command { cmd, opts : Some(opts), arg : Some(CommandArg::Text(arg)), extra_arg : None }.to_string()
+}
+
+fn escape_text(txt : &str) -> String {
+    let mut out = String::with_capacity(txt.len());
+    for c in txt.chars() {
+        match c {
+            '%' | '&' | '#' | '_' | '{' | '}' | '$' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '~' => out += "\\textasciitilde{}",
+            '^' => out += "\\textasciicircum{}",
+            '\\' => out += "\\textbackslash{}",
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+// LaTeX length for typst lengths and ratios (e.g. 1cm, 2em, 50%).
+fn latex_length(expr : &Expr) -> Option<String> {
+    let Expr::Numeric(_) = expr else { return None };
+    let txt = node_text(expr.as_untyped());
+    if let Some(pct) = txt.strip_suffix('%') {
+        let ratio = pct.parse::<f64>().ok()? / 100.0;
+        Some(format!("{}\\textwidth", ratio))
+    } else if txt.ends_with("fr") {
+        None
+    } else {
+        Some(txt)
+    }
+}
+
+/// Translates the body of a typst equation into LaTeX math.
+pub fn math_to_latex(math : &Math) -> String {
+    let mut out = String::new();
+    for expr in math.exprs() {
+        push_math(&mut out, &math_expr(&expr));
+    }
+    out.trim().to_string()
+}
+
+// Separates commands from letters that would otherwise be read as part of the command name.
+fn push_math(out : &mut String, atom : &str) {
+    let ends_with_cmd = out.rsplit('\\').next()
+        .map(|last| out.contains('\\') && !last.is_empty() && last.chars().all(|c| c.is_ascii_alphabetic() ) )
+        .unwrap_or(false);
+    if ends_with_cmd && atom.starts_with(|c : char| c.is_ascii_alphabetic() ) {
+        out.push(' ');
+    }
+    out.push_str(atom);
+}
+
+fn math_expr(expr : &Expr) -> String {
+    match expr {
+        Expr::Math(math) => math_to_latex(math),
+        Expr::Space(_) => String::from(" "),
+        Expr::Linebreak(_) => String::from(" \\\\\n"),
+        Expr::MathAlignPoint(_) => String::from("&"),
+        Expr::Escape(esc) => {
+            let c = esc.get();
+            if "#$%&_{}".contains(c) {
+                format!("\\{}", c)
+            } else {
+                c.to_string()
+            }
+        },
+        Expr::Str(s) => command("text", &escape_text(&s.get())),
+        Expr::MathIdent(_) | Expr::FieldAccess(_) | Expr::Shorthand(_) => {
+            let name = node_text(expr.as_untyped());
+            match crate::tex::typst_symbol_to_latex(&name) {
+                Some(cmd) if cmd.chars().all(|c| c.is_ascii_alphabetic() ) => format!("\\{}", cmd),
+                Some(sym) => sym.to_string(),
+                None if name.chars().count() > 1 && name.chars().all(|c| c.is_alphabetic() ) => command("mathrm", &name),
+                None => name
+            }
+        },
+        Expr::MathDelimited(delim) => {
+            let open = latex_delimiter(&node_text(delim.open().as_untyped()));
+            let close = latex_delimiter(&node_text(delim.close().as_untyped()));
+            format!("\\left{} {} \\right{}", open, math_to_latex(&delim.body()), close)
+        },
+        Expr::MathAttach(attach) => {
+            let mut out = math_expr(&attach.base());
+            if let Some(bottom) = attach.bottom() {
+                out += &format!("_{{{}}}", math_arg(&bottom));
+            }
+            if let Some(top) = attach.top() {
+                out += &format!("^{{{}}}", math_arg(&top));
+            }
+            out
+        },
+        Expr::MathFrac(frac) => {
+            format!("\\frac{{{}}}{{{}}}", math_arg(&frac.num()), math_arg(&frac.denom()))
+        },
+        Expr::FuncCall(call) => math_call(call),
+        _ => node_text(expr.as_untyped())
+    }
+}
+
+// Parenthesis around typst attachments and fractions only group their content,
+// and are not displayed.
+fn math_arg(expr : &Expr) -> String {
+    if let Expr::MathDelimited(delim) = expr {
+        let open = node_text(delim.open().as_untyped());
+        let close = node_text(delim.close().as_untyped());
+        if open == "(" && close == ")" {
+            return math_to_latex(&delim.body());
+        }
+    }
+    math_expr(expr).trim().to_string()
+}
+
+fn latex_delimiter(delim : &str) -> String {
+    match delim {
+        "" => String::from("."),
+        "{" => String::from("\\{"),
+        "}" => String::from("\\}"),
+        "||" => String::from("\\|"),
+        other => match crate::tex::typst_symbol_to_latex(other) {
+            Some(cmd) => format!("\\{}", cmd),
+            None => other.to_string()
+        }
+    }
+}
+
+fn math_call(call : &ast::FuncCall) -> String {
+    let name = node_text(call.callee().as_untyped());
+    let mut pos = Vec::new();
+    let mut delim = None;
+    for arg in call.args().items() {
+        match arg {
+            Arg::Pos(expr) => pos.push(expr),
+            Arg::Named(named) if named.name().get() == "delim" => {
+                delim = Some(node_text(named.expr().as_untyped()).trim_matches('"').to_string());
+            },
+            _ => { }
+        }
+    }
+    let args : Vec<String> = pos.iter().map(|a| math_arg(a) ).collect();
+    match (&name[..], &args[..]) {
+        ("frac", [num, den]) => format!("\\frac{{{}}}{{{}}}", num, den),
+        ("binom", [n, k]) => format!("\\binom{{{}}}{{{}}}", n, k),
+        ("sqrt", [x]) => command("sqrt", x),
+        ("root", [n, x]) => command_with_opts("sqrt", vec![n.as_str()], x),
+        ("op", [op]) => command("operatorname", op.trim_start_matches("\\text{").trim_end_matches('}')),
+        ("lr", [body]) => body.clone(),
+        ("abs", [x]) => format!("\\left| {} \\right|", x),
+        ("norm", [x]) => format!("\\left\\| {} \\right\\|", x),
+        ("floor", [x]) => format!("\\left\\lfloor {} \\right\\rfloor", x),
+        ("ceil", [x]) => format!("\\left\\lceil {} \\right\\rceil", x),
+        ("cases", rows) => {
+            format!("\\begin{{cases}}\n{}\n\\end{{cases}}", rows.join(" \\\\\n"))
+        },
+        ("vec", rows) => matrix(delim.as_deref(), rows.iter().map(|r| vec![r.clone()] ).collect()),
+        ("mat", _) => {
+            let rows = pos.iter().map(|row| match row {
+                Expr::Array(arr) => arr.items().filter_map(|it| match it {
+                    ast::ArrayItem::Pos(e) => Some(math_arg(&e)),
+                    _ => None
+                }).collect(),
+                other => vec![math_arg(other)]
+            }).collect();
+            matrix(delim.as_deref(), rows)
+        },
+        (func, [x]) => {
+            if let Some(style) = crate::tex::typst_style_to_latex(func) {
+                command(style, x)
+            } else if let Some(accent) = crate::tex::typst_accent_to_latex(func) {
+                command(accent, x)
+            } else {
+                format!("{}({})", command("operatorname", func), x)
+            }
+        },
+        (func, args) => format!("{}({})", command("operatorname", func), args.join(", "))
+    }
+}
+
+fn matrix(delim : Option<&str>, rows : Vec<Vec<String>>) -> String {
+    let env = match delim {
+        Some("[") => "bmatrix",
+        Some("{") => "Bmatrix",
+        Some("|") => "vmatrix",
+        Some("||") => "Vmatrix",
+        Some("none") | Some("") => "matrix",
+        _ => "pmatrix"
+    };
+    let rows : Vec<String> = rows.iter().map(|r| r.join(" & ") ).collect();
+    format!("{} {} {}", command("begin", env), rows.join(" \\\\ "), command("end", env))
+}
+
+#[test]
+fn typst_latex_export() {
+    let src = "= Intro <intro>\nSee @intro and @knuth84, with $x_(i+1) != alpha$.\n\n- *One*\n- _Two_\n";
+    let tex = export_latex(Path::new("test.typ"), src.to_string(), &LatexOptions::default()).unwrap();
+    assert!(tex.starts_with("\\documentclass{article}"));
+    assert!(tex.contains("\\section{Intro}\\label{intro}"));
+    assert!(tex.contains("See \\ref{intro} and \\cite{knuth84}, with $x_{i+1} \\neq \\alpha$."));
+    assert!(tex.contains("\\begin{itemize}\n\\item \\textbf{One}\n\\item \\emph{Two}\n\\end{itemize}"));
+}
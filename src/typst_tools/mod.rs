@@ -45,21 +45,57 @@ use typst::diag::{FileError, FileResult, SourceError, StrResult};
 use std::rc::Rc;
 use gtk4::gio;
 use std::sync::Arc;
+use typst::doc::Document;
+
+mod latex;
+
+mod frames;
+
+pub mod complete;
+
+pub mod help;
+
+pub mod format;
+
+pub use latex::*;
+
+pub use frames::*;
 
 pub fn compile(path : &Path, fonts : Fonts) -> Result<Vec<u8>, Vec<(usize, String)>> {
+    let (doc, _) = compile_document(path, fonts, None)?;
+    Ok(typst::export::pdf(&doc))
+}
+
+// Compiles the document, returning it with the world it was compiled with (from which the
+// sources of the spans at the document frames can be recovered). When main is given, it is
+// compiled in place of the content of the file at path (the other files are read from disk).
+pub fn compile_document(path : &Path, fonts : Fonts, main : Option<String>) -> Result<(Document, SystemWorld), Vec<(usize, String)>> {
+    compile_sources(path, fonts, main)
+        .map_err(|errs| errs.into_iter().map(|(_, line, msg)| (line, msg) ).collect() )
+}
+
+// As compile_document, but each error also carries the path of the source it was raised at,
+// since errors at files included by the document have lines of those files.
+pub fn compile_sources(path : &Path, fonts : Fonts, main : Option<String>) -> Result<(Document, SystemWorld), Vec<(PathBuf, usize, String)>> {
     let parent_path = path.parent()
         // .ok_or(vec![String::from("Missing parent directory"))?
         .unwrap()
         .to_owned();
-    let mut world = SystemWorld::new(parent_path, fonts);
+
+    // Files of multi-file projects can refer to any file under the project root.
+    let root = crate::templates::project_root(path).unwrap_or(parent_path);
+    let mut world = SystemWorld::new(root, fonts);
 
     world.reset();
-    world.main = world.resolve(&path).unwrap();
+    world.main = match main {
+        Some(text) => world.overlay(&path, text).map_err(|e| vec![(path.to_owned(), 0, e.to_string())] )?,
+        None => world.resolve(&path).unwrap()
+    };
         //.map_err(|err| err.to_string())?;
 
     match typst::compile(&world) {
         Ok(doc) => {
-            Ok(typst::export::pdf(&doc))
+            Ok((doc, world))
         },
         Err(errs) => {
             let mut out_errs = Vec::new();
@@ -67,7 +103,7 @@ pub fn compile(path : &Path, fonts : Fonts) -> Result<Vec<u8>, Vec<(usize, Strin
                 if let Some(src) = world.sources.iter().find(|s| s.id() == e.span.source() ) {
                     let line = src.byte_to_line(src.range(e.span).start).unwrap_or(0);
                     let msg = e.message.to_string();
-                    out_errs.push((line, msg));
+                    out_errs.push((src.path().to_owned(), line, msg));
                 }
             }
             Err(out_errs)
@@ -233,6 +269,37 @@ pub fn parse_doc(path : &Path, txt : String) -> Result<crate::tex::Document, Vec
     Ok(crate::tex::Document { items })
 }
 
+// String literal at the start of the text, if any.
+fn leading_str(txt : &str) -> Option<String> {
+    let rest = txt.trim_start().strip_prefix('"')?;
+    let end = rest.find('"')?;
+    Some(rest[..end].to_string()).filter(|s| !s.trim().is_empty() )
+}
+
+/// Title of the document: The title set for the document (or bound to a title variable, as
+/// the templates do), or the name of its first section.
+pub fn doc_title(txt : &str) -> Option<String> {
+    let declared = txt.lines().find_map(|line| {
+        let line = line.trim();
+        if line.starts_with("#set document(") {
+            leading_str(line.split("title:").nth(1)?)
+        } else if let Some(rest) = line.strip_prefix("#let title") {
+            leading_str(rest.trim_start().strip_prefix('=')?)
+        } else {
+            None
+        }
+    });
+    declared.or_else(|| {
+        let doc = parse_doc(Path::new(""), txt.to_string()).ok()?;
+        doc.items.iter().find_map(|item| {
+            match item {
+                crate::tex::Item::Section(sec, _) => Some(sec.name.clone()).filter(|n| !n.trim().is_empty() ),
+                _ => None
+            }
+        })
+    })
+}
+
 #[derive(Clone)]
 pub struct Fonts {
     pub book : Arc<Prehashed<FontBook>>,
@@ -467,6 +534,14 @@ mod imported {
             }))
         }
 
+        /// Uses the text as the source of the file, which is not read from disk.
+        pub fn overlay(&self, path: &Path, text: String) -> FileResult<SourceId> {
+            let slot = self.slot(path)?;
+            let id = self.insert(path, text);
+            let _ = slot.source.set(Ok(id));
+            Ok(id)
+        }
+
         fn insert(&self, path: &Path, text: String) -> SourceId {
             let id = SourceId::from_u16(self.sources.len() as u16);
             let source = Source::new(id, path, text);
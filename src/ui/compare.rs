@@ -0,0 +1,304 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::thread;
+use crate::renderer::{PageRenderer, PageDiff};
+use crate::typesetter::TypesetterTarget;
+
+/*
+Comparison between the last two typeset versions of the document. The changed pages are
+found at a separate thread (see crate::renderer::diff_pages) and listed at the left. The
+selected page is shown either with the previous version overlaid on the new one, or with
+both versions side by side, with the changed region marked on the new version.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareMode {
+    Overlay,
+    SideBySide
+}
+
+#[derive(Debug, Clone, Default)]
+struct Versions {
+    previous : Option<TypesetterTarget>,
+    current : Option<TypesetterTarget>,
+
+    // Page sizes (in points) of each version.
+    previous_sizes : Vec<(f64, f64)>,
+    current_sizes : Vec<(f64, f64)>
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparePanel {
+    pub bx : Box,
+    list : ListBox,
+    status_lbl : Label,
+    old_da : DrawingArea,
+    new_da : DrawingArea,
+    overlay_btn : ToggleButton,
+    versions : Rc<RefCell<Versions>>,
+    diffs : Rc<RefCell<Vec<PageDiff>>>,
+    selected : Rc<RefCell<Option<usize>>>,
+
+    // Renders the previous version. The current version is rendered by the viewer renderer.
+    old_renderer : PageRenderer,
+
+    // Incremented at each comparison, so results of outdated comparisons are ignored.
+    generation : Rc<RefCell<u64>>,
+
+    send : glib::Sender<(u64, Vec<PageDiff>)>
+}
+
+impl ComparePanel {
+
+    pub fn build(renderer : &PageRenderer, zoom_action : &gio::SimpleAction) -> Self {
+        let list = ListBox::new();
+        list.set_selection_mode(SelectionMode::Single);
+        list.style_context().add_class("navigation-sidebar");
+        let list_scroll = ScrolledWindow::new();
+        list_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        list_scroll.set_vexpand(true);
+        list_scroll.set_child(Some(&list));
+        let status_lbl = Label::new(None);
+        status_lbl.style_context().add_class("dim-label");
+        status_lbl.set_margin_top(6);
+        status_lbl.set_margin_bottom(6);
+        status_lbl.set_wrap(true);
+
+        let overlay_btn = ToggleButton::with_label("Overlay");
+        let side_btn = ToggleButton::with_label("Side by side");
+        side_btn.set_group(Some(&overlay_btn));
+        overlay_btn.set_active(true);
+        let mode_bx = Box::new(Orientation::Horizontal, 0);
+        mode_bx.style_context().add_class("linked");
+        mode_bx.set_halign(Align::Center);
+        mode_bx.set_margin_top(6);
+        mode_bx.set_margin_bottom(6);
+        mode_bx.append(&overlay_btn);
+        mode_bx.append(&side_btn);
+
+        let left_bx = Box::new(Orientation::Vertical, 0);
+        left_bx.set_width_request(180);
+        left_bx.append(&mode_bx);
+        left_bx.append(&status_lbl);
+        left_bx.append(&list_scroll);
+
+        let old_da = DrawingArea::new();
+        let new_da = DrawingArea::new();
+        crate::configure_da_for_doc(&old_da);
+        crate::configure_da_for_doc(&new_da);
+        let pages_bx = Box::new(Orientation::Horizontal, 0);
+        pages_bx.set_halign(Align::Center);
+        pages_bx.append(&old_da);
+        pages_bx.append(&new_da);
+        let pages_scroll = ScrolledWindow::new();
+        pages_scroll.set_hexpand(true);
+        pages_scroll.set_vexpand(true);
+        pages_scroll.set_child(Some(&pages_bx));
+
+        let bx = Box::new(Orientation::Horizontal, 0);
+        bx.append(&left_bx);
+        bx.append(&Separator::new(Orientation::Vertical));
+        bx.append(&pages_scroll);
+
+        let (send, recv) = glib::MainContext::channel::<(u64, Vec<PageDiff>)>(glib::PRIORITY_DEFAULT);
+        let panel = Self {
+            bx,
+            list,
+            status_lbl,
+            old_da,
+            new_da,
+            overlay_btn,
+            versions : Default::default(),
+            diffs : Default::default(),
+            selected : Default::default(),
+            old_renderer : PageRenderer::new(),
+            generation : Default::default(),
+            send
+        };
+
+        recv.attach(None, {
+            let panel = panel.clone();
+            move |(generation, diffs)| {
+                if generation == *panel.generation.borrow() {
+                    panel.show_diffs(diffs);
+                }
+                Continue(true)
+            }
+        });
+
+        panel.list.connect_row_selected({
+            let panel = panel.clone();
+            move |_, row| {
+                let page = row.and_then(|row| panel.diffs.borrow().get(row.index() as usize).map(|d| d.page ) );
+                panel.selected.replace(page);
+                panel.old_da.queue_draw();
+                panel.new_da.queue_draw();
+            }
+        });
+        panel.overlay_btn.connect_toggled({
+            let panel = panel.clone();
+            move |_| {
+                panel.old_da.set_visible(panel.mode() == CompareMode::SideBySide);
+                panel.new_da.queue_draw();
+            }
+        });
+        panel.old_da.set_visible(false);
+
+        for (old, da) in [(true, &panel.old_da), (false, &panel.new_da)] {
+            da.set_draw_func({
+                let panel = panel.clone();
+                let renderer = renderer.clone();
+                let zoom_action = zoom_action.clone();
+                move |da, ctx, _, _| {
+                    let Some(page) = *panel.selected.borrow() else { return };
+                    let z = zoom_action.state().unwrap().get::<f64>().unwrap();
+                    let versions = panel.versions.borrow();
+                    let sizes = if old { &versions.previous_sizes } else { &versions.current_sizes };
+                    let Some(size) = sizes.get(page).copied() else {
+                        da.set_size_request(0, 0);
+                        return;
+                    };
+                    crate::adjust_dimension_for_size(da, &zoom_action, size);
+                    let (w, h) = (da.allocation().width() as f64, da.allocation().height() as f64);
+                    ctx.save();
+                    crate::draw_page_background(ctx, w, h, true);
+                    if old {
+                        panel.old_renderer.draw_page(ctx, page, z);
+                    } else {
+                        renderer.draw_page(ctx, page, z);
+                        if panel.mode() == CompareMode::Overlay && page < versions.previous_sizes.len() {
+                            panel.old_renderer.draw_page_with_alpha(ctx, page, z, 0.5);
+                        }
+                        panel.draw_changed_region(ctx, page, z);
+                    }
+                    renderer.filter().apply(ctx, w, h);
+                    ctx.restore();
+                }
+            });
+        }
+        for r in [renderer, &panel.old_renderer] {
+            r.connect_rendered({
+                let panel = panel.clone();
+                move |page| {
+                    if *panel.selected.borrow() == Some(page) {
+                        panel.old_da.queue_draw();
+                        panel.new_da.queue_draw();
+                    }
+                }
+            });
+        }
+        panel
+    }
+
+    pub fn mode(&self) -> CompareMode {
+        if self.overlay_btn.is_active() {
+            CompareMode::Overlay
+        } else {
+            CompareMode::SideBySide
+        }
+    }
+
+    /// Stops the renderer of the previous version.
+    pub fn shutdown(&self) {
+        self.old_renderer.shutdown();
+    }
+
+    pub fn queue_draw(&self) {
+        self.old_da.queue_draw();
+        self.new_da.queue_draw();
+    }
+
+    fn draw_changed_region(&self, ctx : &cairo::Context, page : usize, z : f64) {
+        let diffs = self.diffs.borrow();
+        let Some(diff) = diffs.iter().find(|d| d.page == page ) else { return };
+        if let Some((x, y, w, h)) = diff.region {
+            ctx.save();
+            ctx.scale(z, z);
+            ctx.set_source_rgba(0.9, 0.1, 0.1, 0.8);
+            ctx.set_line_width(1.5 / z);
+            ctx.rectangle(x - 2.0, y - 2.0, w + 4.0, h + 4.0);
+            let _ = ctx.stroke();
+            ctx.restore();
+        }
+    }
+
+    /// Keeps the newly typeset version, and the version before it for comparison.
+    pub fn push_version(&self, target : TypesetterTarget, sizes : Vec<(f64, f64)>) {
+        {
+            let mut versions = self.versions.borrow_mut();
+            versions.previous = versions.current.take();
+            versions.previous_sizes = std::mem::take(&mut versions.current_sizes);
+            versions.current = Some(target);
+            versions.current_sizes = sizes;
+            if let Some(previous) = &versions.previous {
+                self.old_renderer.load_target(previous);
+            }
+        }
+        if self.bx.is_mapped() {
+            self.compare();
+        }
+    }
+
+    /// Starts comparing the last two versions.
+    pub fn compare(&self) {
+        let generation = {
+            let mut generation = self.generation.borrow_mut();
+            *generation += 1;
+            *generation
+        };
+        let versions = self.versions.borrow();
+        let (Some(previous), Some(current)) = (versions.previous.clone(), versions.current.clone()) else {
+            self.status_lbl.set_text("Typeset the document again to compare it with the current version");
+            self.show_diffs(Vec::new());
+            return;
+        };
+        self.status_lbl.set_text("Comparing…");
+        thread::spawn({
+            let send = self.send.clone();
+            move || {
+                let diffs = crate::renderer::diff_pages(&previous, &current);
+                let _ = send.send((generation, diffs));
+            }
+        });
+    }
+
+    fn show_diffs(&self, diffs : Vec<PageDiff>) {
+        while let Some(row) = self.list.row_at_index(0) {
+            self.list.remove(&row);
+        }
+        for diff in diffs.iter() {
+            let txt = match diff.region {
+                Some(_) => format!("Page {}", diff.page + 1),
+                None => format!("Page {} (added or removed)", diff.page + 1)
+            };
+            let lbl = Label::new(Some(&txt));
+            lbl.set_halign(Align::Start);
+            let row = ListBoxRow::new();
+            row.set_child(Some(&lbl));
+            self.list.append(&row);
+        }
+        if self.versions.borrow().previous.is_some() {
+            match diffs.len() {
+                0 => self.status_lbl.set_text("No changed pages"),
+                1 => self.status_lbl.set_text("1 changed page"),
+                n => self.status_lbl.set_text(&format!("{} changed pages", n))
+            }
+        }
+        self.diffs.replace(diffs);
+        if let Some(row) = self.list.row_at_index(0) {
+            self.list.select_row(Some(&row));
+        } else {
+            self.selected.replace(None);
+            self.old_da.queue_draw();
+            self.new_da.queue_draw();
+        }
+    }
+
+}
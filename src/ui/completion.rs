@@ -0,0 +1,391 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use gtk4::subclass::prelude::*;
+use sourceview5::prelude::*;
+use sourceview5::subclass::prelude::*;
+use sourceview5::CompletionColumn;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use stateful::React;
+use crate::manager::FileManager;
+use crate::typst_tools::complete::{self, CompletionItem, CompletionKind, Binding};
+use crate::typst_tools::help;
+
+/*
+Completion provider for the typst source (see crate::typst_tools::complete). Proposals are
+computed from the text before the cursor whenever the completion is shown or the typed word
+changes. Bindings of the other files of the project are gathered when the document is opened
+or saved, and the ones of the open document from the buffer at each request. The hover
+provider and the signature popover (see crate::typst_tools::help) share these bindings.
+*/
+
+mod imp {
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct TypstProposal {
+        pub item : RefCell<Option<CompletionItem>>
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TypstProposal {
+        const NAME : &'static str = "DraftsTypstProposal";
+        type Type = super::TypstProposal;
+        type Interfaces = (sourceview5::CompletionProposal,);
+    }
+
+    impl ObjectImpl for TypstProposal { }
+
+    impl CompletionProposalImpl for TypstProposal { }
+
+    #[derive(Default)]
+    pub struct TypstCompletion {
+
+        pub project : RefCell<Vec<Binding>>,
+
+        // Incremented at each document change, so bindings gathered for a previous
+        // document are discarded when they arrive late.
+        pub generation : Cell<u64>
+
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TypstCompletion {
+        const NAME : &'static str = "DraftsTypstCompletion";
+        type Type = super::TypstCompletion;
+        type Interfaces = (sourceview5::CompletionProvider,);
+    }
+
+    impl ObjectImpl for TypstCompletion { }
+
+    impl CompletionProviderImpl for TypstCompletion {
+
+        fn title(&self) -> Option<glib::GString> {
+            Some(glib::GString::from("Typst"))
+        }
+
+        fn priority(&self, _context : &sourceview5::CompletionContext) -> i32 {
+            100
+        }
+
+        fn is_trigger(&self, _iter : &TextIter, c : char) -> bool {
+            matches!(c, '#' | '(' | ',' | ':' | '$' | '"')
+        }
+
+        fn populate_future(
+            &self,
+            context : &sourceview5::CompletionContext
+        ) -> Pin<Box<dyn Future<Output = Result<gio::ListModel, glib::Error>> + 'static>> {
+            let store = gio::ListStore::new(super::TypstProposal::static_type());
+            self.fill(context, &store);
+            let model = store.upcast::<gio::ListModel>();
+            Box::pin(async move { Ok(model) })
+        }
+
+        fn refilter(&self, context : &sourceview5::CompletionContext, model : &gio::ListModel) {
+            if let Some(store) = model.downcast_ref::<gio::ListStore>() {
+                self.fill(context, store);
+            }
+        }
+
+        fn display(&self, _context : &sourceview5::CompletionContext, proposal : &sourceview5::CompletionProposal, cell : &sourceview5::CompletionCell) {
+            let Some(item) = proposal.downcast_ref::<super::TypstProposal>().and_then(|p| p.item() ) else { return };
+            match cell.column() {
+                CompletionColumn::Icon => cell.set_icon_name(kind_icon(item.kind)),
+                CompletionColumn::TypedText => cell.set_text(Some(&item.label)),
+                CompletionColumn::After => cell.set_text(Some(&item.detail)),
+                CompletionColumn::Comment => cell.set_text(Some(&item.docs)),
+                _ => cell.set_text(None)
+            }
+        }
+
+        fn activate(&self, context : &sourceview5::CompletionContext, proposal : &sourceview5::CompletionProposal) {
+            let Some(item) = proposal.downcast_ref::<super::TypstProposal>().and_then(|p| p.item() ) else { return };
+            let Some(buffer) = context.buffer() else { return };
+            let before = text_before_cursor(buffer.upcast_ref());
+            let n = complete::context_at(&before).prefix().chars().count() as i32;
+            let mut end = buffer.iter_at_mark(&buffer.get_insert());
+            let mut start = end.clone();
+            start.backward_chars(n);
+            buffer.begin_user_action();
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &item.insert);
+            if item.cursor_back > 0 {
+                start.backward_chars(item.cursor_back as i32);
+                buffer.place_cursor(&start);
+            }
+            buffer.end_user_action();
+        }
+
+    }
+
+    impl TypstCompletion {
+
+        // Replaces the proposals with the ones for the text before the cursor.
+        fn fill(&self, context : &sourceview5::CompletionContext, store : &gio::ListStore) {
+            store.remove_all();
+            let Some(buffer) = context.buffer() else { return };
+            let buffer : &TextBuffer = buffer.upcast_ref();
+            let ctx = complete::context_at(&text_before_cursor(buffer));
+            if ctx == complete::CompletionContext::None {
+                return;
+            }
+            let bindings = self.obj().bindings(buffer);
+            for item in complete::complete(complete::catalog(), &ctx, &bindings) {
+                store.append(&super::TypstProposal::new(item));
+            }
+        }
+
+    }
+
+    #[derive(Default)]
+    pub struct TypstHover {
+        pub completion : RefCell<Option<super::TypstCompletion>>
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TypstHover {
+        const NAME : &'static str = "DraftsTypstHover";
+        type Type = super::TypstHover;
+        type Interfaces = (sourceview5::HoverProvider,);
+    }
+
+    impl ObjectImpl for TypstHover { }
+
+    impl HoverProviderImpl for TypstHover {
+
+        fn populate(&self, context : &sourceview5::HoverContext, display : &sourceview5::HoverDisplay) -> Result<(), glib::Error> {
+            let not_found = || glib::Error::new(gio::IOErrorEnum::NotFound, "No documentation");
+            let (Some(iter), Some(buffer)) = (context.iter(), context.buffer()) else { return Err(not_found()) };
+            let buffer : &TextBuffer = buffer.upcast_ref();
+            let Some(start) = buffer.iter_at_line(iter.line()) else { return Err(not_found()) };
+            let mut end = start.clone();
+            end.forward_to_line_end();
+            let line = buffer.text(&start, &end, true);
+            let offset = iter.line_index().max(0) as usize;
+            let bindings = self.completion.borrow().as_ref().map(|c| c.bindings(buffer) ).unwrap_or_default();
+            let help = help::hover_at(complete::catalog(), &line, offset, &bindings).ok_or_else(not_found)?;
+            display.append(&help_box(&help));
+            Ok(())
+        }
+
+    }
+
+}
+
+glib::wrapper! {
+    pub struct TypstProposal(ObjectSubclass<imp::TypstProposal>)
+        @implements sourceview5::CompletionProposal;
+}
+
+impl TypstProposal {
+
+    fn new(item : CompletionItem) -> Self {
+        let proposal : Self = glib::Object::new(&[]);
+        proposal.imp().item.replace(Some(item));
+        proposal
+    }
+
+    fn item(&self) -> Option<CompletionItem> {
+        self.imp().item.borrow().clone()
+    }
+
+}
+
+glib::wrapper! {
+    pub struct TypstCompletion(ObjectSubclass<imp::TypstCompletion>)
+        @implements sourceview5::CompletionProvider;
+}
+
+impl TypstCompletion {
+
+    pub fn new() -> Self {
+        glib::Object::new(&[])
+    }
+
+    /// Let bindings of the buffer, followed by the ones of the other files at its project.
+    pub fn bindings(&self, buffer : &TextBuffer) -> Vec<Binding> {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        let mut bindings = complete::let_bindings(&text, "");
+        bindings.extend(self.imp().project.borrow().iter().cloned());
+        bindings
+    }
+
+    // The other files of the project are read at a separate thread, and their bindings
+    // replace the current ones when ready.
+    fn set_document(&self, path : Option<&Path>) {
+        let generation = self.imp().generation.get() + 1;
+        self.imp().generation.set(generation);
+        self.imp().project.replace(Vec::new());
+        let Some(path) = path.map(|p| p.to_owned() ) else { return };
+        let (send, recv) = glib::MainContext::channel::<Vec<Binding>>(glib::PRIORITY_DEFAULT);
+        std::thread::spawn(move || {
+            send.send(complete::project_bindings(&path)).ok();
+        });
+        let completion = self.clone();
+        recv.attach(None, move |bindings| {
+            if completion.imp().generation.get() == generation {
+                completion.imp().project.replace(bindings);
+            }
+            Continue(false)
+        });
+    }
+
+}
+
+glib::wrapper! {
+    pub struct TypstHover(ObjectSubclass<imp::TypstHover>)
+        @implements sourceview5::HoverProvider;
+}
+
+impl TypstHover {
+
+    pub fn new(completion : &TypstCompletion) -> Self {
+        let hover : Self = glib::Object::new(&[]);
+        hover.imp().completion.replace(Some(completion.clone()));
+        hover
+    }
+
+}
+
+// Signature (with the active parameter, if any, in bold) over the documentation.
+fn help_box(help : &help::Help) -> Box {
+    let bx = Box::new(Orientation::Vertical, 6);
+    let sig_lbl = Label::new(None);
+    sig_lbl.set_markup(&help.markup());
+    sig_lbl.set_halign(Align::Start);
+    sig_lbl.set_wrap(true);
+    sig_lbl.set_max_width_chars(72);
+    bx.append(&sig_lbl);
+    if !help.docs.is_empty() {
+        let docs_lbl = Label::new(Some(&help.docs));
+        docs_lbl.set_halign(Align::Start);
+        docs_lbl.set_xalign(0.0);
+        docs_lbl.set_wrap(true);
+        docs_lbl.set_max_width_chars(72);
+        docs_lbl.style_context().add_class("dim-label");
+        bx.append(&docs_lbl);
+    }
+    bx
+}
+
+/// Popover above the cursor showing the signature of the call being written, with the
+/// current argument highlighted. It follows the cursor while it is inside a known call.
+#[derive(Debug, Clone)]
+pub struct SignatureHelp {
+    pub popover : Popover
+}
+
+impl SignatureHelp {
+
+    pub fn build(view : &sourceview5::View, completion : &TypstCompletion) -> Self {
+        let popover = Popover::new();
+        popover.set_autohide(false);
+        popover.set_can_focus(false);
+        popover.set_has_arrow(false);
+        popover.set_position(PositionType::Top);
+        popover.set_parent(view);
+        view.connect_destroy({
+            let popover = popover.clone();
+            move |_| {
+                popover.unparent();
+            }
+        });
+
+        view.buffer().connect_notify_local(Some("cursor-position"), {
+            let view = view.clone();
+            let popover = popover.clone();
+            let completion = completion.clone();
+            move |buffer, _| {
+                let help = if view.has_focus() {
+                    help::signature_at(complete::catalog(), &text_before_cursor(buffer), &completion.bindings(buffer))
+                } else {
+                    None
+                };
+                match help {
+                    Some(help) => {
+                        popover.set_child(Some(&help_box(&help)));
+                        let rect = view.iter_location(&buffer.iter_at_mark(&buffer.get_insert()));
+                        let (x, y) = view.buffer_to_window_coords(TextWindowType::Widget, rect.x(), rect.y());
+                        popover.set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, rect.height())));
+                        if !popover.is_visible() {
+                            popover.popup();
+                        }
+                    },
+                    None => popover.popdown()
+                }
+            }
+        });
+
+        let focus = EventControllerFocus::new();
+        focus.connect_leave({
+            let popover = popover.clone();
+            move |_| {
+                popover.popdown();
+            }
+        });
+        view.add_controller(&focus);
+
+        let key = EventControllerKey::new();
+        key.connect_key_pressed({
+            let popover = popover.clone();
+            move |_, key, _, _| {
+                if key == gdk::Key::Escape && popover.is_visible() {
+                    popover.popdown();
+                }
+                Inhibit(false)
+            }
+        });
+        view.add_controller(&key);
+        Self { popover }
+    }
+
+}
+
+fn kind_icon(kind : CompletionKind) -> &'static str {
+    match kind {
+        CompletionKind::Function => "completion-function-symbolic",
+        CompletionKind::Parameter => "completion-parameter-symbolic",
+        CompletionKind::Value => "completion-value-symbolic",
+        CompletionKind::Symbol => "completion-symbol-symbolic",
+        CompletionKind::Binding => "completion-binding-symbolic"
+    }
+}
+
+fn text_before_cursor(buffer : &TextBuffer) -> String {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    buffer.text(&buffer.start_iter(), &cursor, true).to_string()
+}
+
+impl React<FileManager> for TypstCompletion {
+
+    fn react(&self, manager : &FileManager) {
+        manager.connect_opened({
+            let completion = self.clone();
+            move |(path, _)| {
+                completion.set_document(Some(Path::new(&path)));
+            }
+        });
+        manager.connect_save({
+            let completion = self.clone();
+            move |path| {
+                completion.set_document(Some(Path::new(&path)));
+            }
+        });
+        manager.connect_new({
+            let completion = self.clone();
+            move |_| {
+                completion.set_document(None);
+            }
+        });
+    }
+
+}
@@ -17,7 +17,10 @@ pub struct PapersEditor {
     pub buf_change_handler : Rc<RefCell<Option<SignalHandlerId>>>,
     pub curr_toast : Rc<RefCell<Option<libadwaita::Toast>>>,
     pub pdf_viewer : PdfViewer,
-    pub popover : Popover
+    pub popover : Popover,
+    pub completion : TypstCompletion,
+    pub signature_help : SignatureHelp,
+    pub format_action : gio::SimpleAction
 }
 
 const TEXT_WIDTH : i32 = 820;
@@ -32,17 +35,21 @@ impl PapersEditor {
 
         view.set_halign(Align::Center);
 
-        configure_view(&view);
-
+        /* This makes the seamless transition between editor and background
+        (set to the background of the style scheme at configure_view) */
         let scroll = ScrolledWindow::new();
+        let bg_provider = CssProvider::new();
+        scroll.style_context().add_provider(&bg_provider, 800);
+
+        configure_view(&view, &bg_provider);
+        let completion = TypstCompletion::new();
+        view.completion().add_provider(&completion);
+        view.hover().add_provider(&TypstHover::new(&completion));
+        let signature_help = SignatureHelp::build(&view, &completion);
+        connect_latex_paste(&view);
 
         view.set_margin_top(TEXT_VERTICAL_PADDING);
         view.set_margin_bottom(TEXT_VERTICAL_PADDING);
-
-        /* This makes the seamless transition between editor and background */
-        let provider = CssProvider::new();
-        provider.load_from_data("* { background-color : #ffffff; } ".as_bytes());
-        scroll.style_context().add_provider(&provider, 800);
         scroll.set_child(Some(&view));
 
         // Guarantees a good portion of the text is always visible.
@@ -73,7 +80,62 @@ impl PapersEditor {
         let curr_toast : Rc<RefCell<Option<libadwaita::Toast>>> = Rc::new(RefCell::new(None));
 
         let popover = Popover::new();
-        Self { scroll, view, overlay, sub_paned, ignore_file_save_action, buf_change_handler : Rc::new(RefCell::new(None)), curr_toast, pdf_viewer, popover }
+
+        // Double-clicking the preview moves the cursor to the corresponding source line.
+        pdf_viewer.connect_source_clicked({
+            let view = view.clone();
+            move |line| {
+                let buffer = view.buffer();
+                if let Some(mut iter) = buffer.iter_at_line(line as i32) {
+                    buffer.place_cursor(&iter);
+                    view.scroll_to_iter(&mut iter, 0.0, true, 0.0, 0.5);
+                    view.grab_focus();
+                }
+            }
+        });
+
+        // Formats the whole document as a single undoable change, keeping the cursor line.
+        let format_action = gio::SimpleAction::new("format_document", None);
+        format_action.connect_activate({
+            let view = view.clone();
+            let overlay = overlay.clone();
+            let curr_toast = curr_toast.clone();
+            move |_, _| {
+                let buffer = view.buffer();
+                let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+                let opts = crate::settings::Preferences::current().format_options();
+                match crate::typst_tools::format::format(&text, &opts) {
+                    Ok(formatted) => {
+                        if formatted != text.as_str() {
+                            let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+                            buffer.begin_user_action();
+                            buffer.delete(&mut buffer.start_iter(), &mut buffer.end_iter());
+                            buffer.insert(&mut buffer.start_iter(), &formatted);
+                            buffer.end_user_action();
+                            if let Some(mut iter) = buffer.iter_at_line(line) {
+                                buffer.place_cursor(&iter);
+                                view.scroll_to_iter(&mut iter, 0.0, false, 0.0, 0.0);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        let mut last_toast = curr_toast.borrow_mut();
+                        if let Some(t) = last_toast.take() {
+                            t.dismiss();
+                        }
+                        let toast = libadwaita::Toast::builder()
+                            .title(&e)
+                            .priority(libadwaita::ToastPriority::High)
+                            .timeout(5)
+                            .build();
+                        connect_toast_dismissed(&toast, &curr_toast);
+                        overlay.add_toast(&toast);
+                        *last_toast = Some(toast);
+                    }
+                }
+            }
+        });
+        Self { scroll, view, overlay, sub_paned, ignore_file_save_action, buf_change_handler : Rc::new(RefCell::new(None)), curr_toast, pdf_viewer, popover, completion, signature_help, format_action }
     }
 }
 
@@ -586,39 +648,133 @@ impl React<BibPopover> for PapersEditor {
 
 }
 
-fn configure_view(view : &View) {
+/// Translates pasted LaTeX math (e.g. equations copied from papers or Wikipedia)
+/// to typst math before it is inserted into the buffer. Pasted content without
+/// LaTeX math is inserted unchanged.
+fn connect_latex_paste(view : &View) {
+    view.connect_paste_clipboard(move |view| {
+        let clipboard = view.clipboard();
+        if !clipboard.formats().contain_gtype(glib::Type::STRING) {
+            return;
+        }
+
+        // The clipboard content is only available asynchronously, so the default
+        // handler is stopped and the text is inserted when it is read.
+        view.stop_signal_emission_by_name("paste-clipboard");
+        let view = view.clone();
+        clipboard.read_text_async(None::<&gio::Cancellable>, move |res| {
+            let txt = match res {
+                Ok(Some(txt)) => txt.to_string(),
+                _ => return
+            };
+            if crate::tex::contains_latex_math(&txt) {
+                match crate::tex::latex_to_typst(&txt) {
+                    Ok(translated) => {
+                        paste_text(&view, &translated);
+                        return;
+                    },
+                    Err(e) => {
+                        log::warn!("Unable to translate pasted math: {}", e);
+                    }
+                }
+            }
+            paste_text(&view, &txt);
+        });
+    });
+}
+
+// Replaces the selection by the pasted text as a single undo step, scrolling to the cursor
+// as the default paste does.
+fn paste_text(view : &View, txt : &str) {
+    let buffer = view.buffer();
+    buffer.begin_user_action();
+    buffer.delete_selection(true, view.is_editable());
+    buffer.insert_interactive_at_cursor(txt, view.is_editable());
+    buffer.end_user_action();
+    view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+fn configure_view(view : &View, bg_provider : &CssProvider) {
     let buffer = view.buffer()
         .downcast::<sourceview5::Buffer>().unwrap();
-    let manager = sourceview5::StyleSchemeManager::new();
-    let scheme = manager.scheme("Adwaita").unwrap();
-    buffer.set_style_scheme(Some(&scheme));
     buffer.set_highlight_syntax(true);
     buffer.set_max_undo_levels(40);
     let provider = CssProvider::new();
-    provider.load_from_data(b"textview { font-family: \"Ubuntu Mono\"; font-size: 16pt; line-height : 1.5; }");
     let ctx = view.style_context();
     ctx.add_provider(&provider, 800);
     let lang_manager = sourceview5::LanguageManager::default();
     lang_manager.append_search_path("resource:///io/github/limads/drafts/language-specs");
     let lang = lang_manager.language("typst").unwrap();
     buffer.set_language(Some(&lang));
-    view.set_tab_width(4);
-    view.set_indent_width(4);
     view.set_auto_indent(true);
     view.set_insert_spaces_instead_of_tabs(true);
     view.set_highlight_current_line(false);
     view.set_indent_on_tab(true);
     view.set_show_line_marks(true);
     view.set_enable_snippets(true);
-    view.set_wrap_mode(WrapMode::Word);
 
-    // Seems to be working, but only when you click on the the word
-    // and **then** press CTRL+Space (simply pressing CTRL+space does not work).
+    // Font, color scheme, wrapping, tab width and line numbers follow the user preferences,
+    // and the color scheme also follows the application style.
+    apply_preferences(view, &provider, bg_provider, &crate::settings::Preferences::current());
+    crate::settings::connect_changed({
+        let view = view.downgrade();
+        let provider = provider.clone();
+        let bg_provider = bg_provider.clone();
+        move |prefs| {
+            // Views of closed tabs are no longer at a window.
+            match view.upgrade().filter(|view| view.root().is_some() ) {
+                Some(view) => {
+                    apply_preferences(&view, &provider, &bg_provider, prefs);
+                    Continue(true)
+                },
+                None => Continue(false)
+            }
+        }
+    });
+    libadwaita::StyleManager::default().connect_dark_notify({
+        let view = view.downgrade();
+        let bg_provider = bg_provider.clone();
+        move |_| {
+            if let Some(view) = view.upgrade() {
+                apply_preferences(&view, &provider, &bg_provider, &crate::settings::Preferences::current());
+            }
+        }
+    });
+
+    // Words of the buffer are proposed after the typst completion (see ui::completion),
+    // for identifiers it does not know about.
     let completion = view.completion();
     let words = sourceview5::CompletionWords::new(Some("main"));
     words.register(&view.buffer());
     completion.add_provider(&words);
-    view.set_show_line_numbers(true);
+}
+
+fn apply_preferences(view : &View, provider : &CssProvider, bg_provider : &CssProvider, prefs : &crate::settings::Preferences) {
+    let buffer = view.buffer()
+        .downcast::<sourceview5::Buffer>().unwrap();
+    let manager = sourceview5::StyleSchemeManager::default();
+    let available : Vec<String> = manager.scheme_ids().iter().map(|id| id.to_string() ).collect();
+    let dark = libadwaita::StyleManager::default().is_dark();
+    let scheme = manager.scheme(&crate::settings::matching_scheme(&prefs.color_scheme, dark, &available))
+        .or_else(|| manager.scheme(crate::settings::DEFAULT_COLOR_SCHEME) );
+    buffer.set_style_scheme(scheme.as_ref());
+    let bg = scheme.as_ref()
+        .and_then(|s| s.style("text") )
+        .and_then(|style| style.background() )
+        .map(|bg| bg.to_string() )
+        .unwrap_or(String::from(if dark { "#1e1e1e" } else { "#ffffff" }));
+    bg_provider.load_from_data(format!("* {{ background-color : {}; }} ", bg).as_bytes());
+    provider.load_from_data(prefs.editor_css().as_bytes());
+    view.set_tab_width(prefs.tab_width);
+    view.set_indent_width(prefs.tab_width as i32);
+    view.set_show_line_numbers(prefs.show_line_numbers);
+    let wrap = match &prefs.wrap_mode[..] {
+        "none" => WrapMode::None,
+        "char" => WrapMode::Char,
+        "word-char" => WrapMode::WordChar,
+        _ => WrapMode::Word
+    };
+    view.set_wrap_mode(wrap);
 }
 
 #[derive(Debug, Clone)]
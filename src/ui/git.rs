@@ -0,0 +1,514 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use sourceview5::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use stateful::React;
+use crate::manager::FileManager;
+use crate::vcs::{GitRepo, Commit, LineChange, FileStatus};
+
+/*
+Changes panel for documents inside a git work tree. The lines changed since the last commit are
+marked at the editor gutter (as source marks of the categories below), and the dialog shows the
+file status, stages and commits the file, and shows the file at each commit that changed it.
+Git is run at a separate thread (git log --follow can take seconds at large repositories), which
+receives GitJobs and sends the GitReply of each back to the main thread.
+*/
+
+const ADDED_CATEGORY : &'static str = "git-added";
+
+const MODIFIED_CATEGORY : &'static str = "git-modified";
+
+const REMOVED_CATEGORY : &'static str = "git-removed";
+
+// Delay after the last edit before the gutter is updated, in milliseconds.
+const GUTTER_DELAY : u64 = 500;
+
+// Operation on the document run before its state is read again.
+#[derive(Debug, Clone)]
+enum GitOp {
+    Stage,
+    Commit(String)
+}
+
+#[derive(Debug)]
+enum GitJob {
+
+    // Finds the repository of the document and reads the document state.
+    Open(PathBuf),
+
+    // Reads the document state again, after running the operation (if any).
+    Refresh(GitRepo, PathBuf, Option<GitOp>),
+
+    // Reads the document at a commit.
+    Show(GitRepo, PathBuf, String)
+
+}
+
+// Document state as read from git.
+#[derive(Debug, Default)]
+struct GitInfo {
+    status : Option<FileStatus>,
+    branch : Option<String>,
+    head : Option<String>,
+    commits : Vec<Commit>
+}
+
+impl GitInfo {
+
+    fn read(repo : &GitRepo, path : &std::path::Path) -> Self {
+        Self {
+            status : repo.status(path),
+            branch : repo.branch(),
+            head : repo.content_at(path, "HEAD"),
+            commits : repo.log(path)
+        }
+    }
+
+}
+
+// Replies carry the document path, so replies for a document no longer open are ignored.
+#[derive(Debug)]
+enum GitReply {
+    Opened(PathBuf, Result<Option<GitRepo>, String>, GitInfo),
+    Refreshed(PathBuf, GitInfo, Option<(GitOp, Result<(), String>)>),
+    Shown(PathBuf, Option<String>)
+}
+
+fn run_job(job : GitJob) -> GitReply {
+    match job {
+        GitJob::Open(path) => {
+            let repo = GitRepo::discover(&path);
+            let info = match &repo {
+                Ok(Some(repo)) => GitInfo::read(repo, &path),
+                _ => GitInfo::default()
+            };
+            GitReply::Opened(path, repo, info)
+        },
+        GitJob::Refresh(repo, path, op) => {
+            let op = op.map(|op| {
+                let res = match &op {
+                    GitOp::Stage => repo.stage(&path),
+                    GitOp::Commit(msg) => repo.commit(&path, msg)
+                };
+                (op, res)
+            });
+            GitReply::Refreshed(path.clone(), GitInfo::read(&repo, &path), op)
+        },
+        GitJob::Show(repo, path, hash) => {
+            let content = repo.content_at(&path, &hash);
+            GitReply::Shown(path, content)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GitState {
+
+    path : Option<PathBuf>,
+
+    repo : Option<GitRepo>,
+
+    // Why git could not be run for the document, if it could not.
+    error : Option<String>,
+
+    // File content at HEAD, compared against the buffer to mark the gutter.
+    head : Option<String>,
+
+    // Whether the buffer has changes not written to the file.
+    modified : bool,
+
+    commits : Vec<Commit>
+
+}
+
+#[derive(Debug, Clone)]
+pub struct GitPanel {
+    pub dialog : Dialog,
+    pub action : gio::SimpleAction,
+    branch_lbl : Label,
+    status_lbl : Label,
+    msg_entry : Entry,
+    stage_btn : Button,
+    commit_btn : Button,
+    list : ListBox,
+    commit_view : sourceview5::View,
+    editor_view : sourceview5::View,
+    state : Rc<RefCell<GitState>>,
+    gutter_source : Rc<RefCell<Option<glib::SourceId>>>,
+    job_send : mpsc::Sender<GitJob>
+}
+
+impl GitPanel {
+
+    pub fn build(editor_view : &sourceview5::View) -> Self {
+        let dialog = Dialog::new();
+        dialog.set_title(Some("Changes"));
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(860, 560);
+
+        let branch_lbl = Label::new(None);
+        branch_lbl.set_halign(Align::Start);
+        branch_lbl.style_context().add_class("heading");
+        let status_lbl = Label::new(None);
+        status_lbl.set_halign(Align::Start);
+        status_lbl.set_wrap(true);
+        status_lbl.style_context().add_class("dim-label");
+
+        let msg_entry = Entry::new();
+        msg_entry.set_placeholder_text(Some("Commit message"));
+        let stage_btn = Button::with_label("Stage");
+        let commit_btn = Button::with_label("Commit");
+        commit_btn.style_context().add_class("suggested-action");
+        let btn_bx = Box::new(Orientation::Horizontal, 6);
+        btn_bx.set_halign(Align::End);
+        btn_bx.append(&stage_btn);
+        btn_bx.append(&commit_btn);
+
+        let list = ListBox::new();
+        list.set_selection_mode(SelectionMode::Single);
+        list.style_context().add_class("navigation-sidebar");
+        let list_scroll = ScrolledWindow::new();
+        list_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        list_scroll.set_vexpand(true);
+        list_scroll.set_child(Some(&list));
+        let history_lbl = Label::new(Some("History"));
+        history_lbl.set_halign(Align::Start);
+        history_lbl.style_context().add_class("heading");
+
+        let left_bx = Box::new(Orientation::Vertical, 6);
+        left_bx.set_width_request(300);
+        super::set_all_margins(&left_bx, 12);
+        left_bx.append(&branch_lbl);
+        left_bx.append(&status_lbl);
+        left_bx.append(&msg_entry);
+        left_bx.append(&btn_bx);
+        left_bx.append(&history_lbl);
+        left_bx.append(&list_scroll);
+
+        let commit_view = sourceview5::View::new();
+        commit_view.set_editable(false);
+        commit_view.set_monospace(true);
+        commit_view.set_show_line_numbers(true);
+        if let Ok(buffer) = commit_view.buffer().downcast::<sourceview5::Buffer>() {
+            if let Some(lang) = sourceview5::LanguageManager::default().language("typst") {
+                buffer.set_language(Some(&lang));
+            }
+        }
+        let commit_scroll = ScrolledWindow::new();
+        commit_scroll.set_hexpand(true);
+        commit_scroll.set_vexpand(true);
+        commit_scroll.set_child(Some(&commit_view));
+
+        let bx = Box::new(Orientation::Horizontal, 0);
+        bx.append(&left_bx);
+        bx.append(&Separator::new(Orientation::Vertical));
+        bx.append(&commit_scroll);
+        dialog.set_child(Some(&bx));
+
+        // Only enabled when the document is inside a git work tree (or git could not be run).
+        let action = gio::SimpleAction::new("git", None);
+        action.set_enabled(false);
+
+        configure_gutter(editor_view);
+
+        let (job_send, job_recv) = mpsc::channel::<GitJob>();
+        let (reply_send, reply_recv) = glib::MainContext::channel::<GitReply>(glib::PRIORITY_DEFAULT);
+        std::thread::spawn(move || {
+            // Returns when the panel (which holds the job sender) is dropped.
+            while let Ok(job) = job_recv.recv() {
+                if reply_send.send(run_job(job)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let panel = Self {
+            dialog,
+            action,
+            branch_lbl,
+            status_lbl,
+            msg_entry,
+            stage_btn,
+            commit_btn,
+            list,
+            commit_view,
+            editor_view : editor_view.clone(),
+            state : Default::default(),
+            gutter_source : Default::default(),
+            job_send
+        };
+        reply_recv.attach(None, {
+            let panel = panel.clone();
+            move |reply| {
+                panel.receive(reply);
+                Continue(true)
+            }
+        });
+        panel.action.connect_activate({
+            let panel = panel.clone();
+            move |_, _| {
+                panel.refresh();
+                panel.dialog.show();
+            }
+        });
+        panel.stage_btn.connect_clicked({
+            let panel = panel.clone();
+            move |_| {
+                panel.run(GitOp::Stage);
+            }
+        });
+        panel.commit_btn.connect_clicked({
+            let panel = panel.clone();
+            move |_| {
+                let msg = panel.msg_entry.text().to_string();
+                panel.run(GitOp::Commit(msg));
+            }
+        });
+        panel.list.connect_row_selected({
+            let panel = panel.clone();
+            move |_, row| {
+                panel.commit_view.buffer().set_text("");
+                let Some(row) = row else { return };
+                let state = panel.state.borrow();
+                let hash = state.commits.get(row.index() as usize).map(|c| c.hash.clone() );
+                if let (Some(repo), Some(path), Some(hash)) = (&state.repo, &state.path, hash) {
+                    panel.send(GitJob::Show(repo.clone(), path.clone(), hash));
+                }
+            }
+        });
+        editor_view.buffer().connect_changed({
+            let panel = panel.clone();
+            move |_| {
+                panel.queue_gutter_update();
+            }
+        });
+        panel
+    }
+
+    fn send(&self, job : GitJob) {
+        if let Err(e) = self.job_send.send(job) {
+            log::warn!("Unable to send git job: {}", e);
+        }
+    }
+
+    fn run(&self, op : GitOp) {
+        let state = self.state.borrow();
+        match (&state.repo, &state.path) {
+            (Some(repo), Some(path)) => self.send(GitJob::Refresh(repo.clone(), path.clone(), Some(op))),
+            _ => self.status_lbl.set_text("Document is not in a git repository")
+        }
+    }
+
+    fn set_path(&self, path : Option<PathBuf>) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.path = path.clone();
+            state.repo = None;
+            state.error = None;
+            state.modified = false;
+        }
+        match path {
+            Some(path) => self.send(GitJob::Open(path)),
+            None => {
+                self.action.set_enabled(false);
+                self.show_info(GitInfo::default());
+            }
+        }
+    }
+
+    /// Reads the file status, history and committed content again.
+    pub fn refresh(&self) {
+        let state = self.state.borrow();
+        match (&state.repo, &state.path) {
+            (Some(repo), Some(path)) => self.send(GitJob::Refresh(repo.clone(), path.clone(), None)),
+            _ => {
+                drop(state);
+                self.show_info(GitInfo::default());
+            }
+        }
+    }
+
+    fn receive(&self, reply : GitReply) {
+        let curr_path = self.state.borrow().path.clone();
+        match reply {
+            GitReply::Opened(path, repo, info) => {
+                if curr_path.as_ref() != Some(&path) {
+                    return;
+                }
+                let (repo, error) = match repo {
+                    Ok(repo) => (repo, None),
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        (None, Some(e))
+                    }
+                };
+
+                // When git is missing, the panel is still offered so the user can see why it is empty.
+                self.action.set_enabled(repo.is_some() || error.is_some());
+                {
+                    let mut state = self.state.borrow_mut();
+                    state.repo = repo;
+                    state.error = error;
+                }
+                self.show_info(info);
+            },
+            GitReply::Refreshed(path, info, op) => {
+                if curr_path.as_ref() != Some(&path) {
+                    return;
+                }
+                self.show_info(info);
+                match op {
+                    Some((GitOp::Commit(_), Ok(()))) => self.msg_entry.set_text(""),
+                    Some((_, Err(e))) => self.status_lbl.set_text(&e),
+                    _ => { }
+                }
+            },
+            GitReply::Shown(path, content) => {
+                if curr_path.as_ref() == Some(&path) {
+                    self.commit_view.buffer().set_text(&content.unwrap_or_default());
+                }
+            }
+        }
+    }
+
+    fn show_info(&self, info : GitInfo) {
+        let GitInfo { status, branch, head, commits } = info;
+        self.branch_lbl.set_text(&branch.map(|b| format!("Branch {}", b) ).unwrap_or_default());
+        let modified = self.state.borrow().modified;
+        let error = self.state.borrow().error.clone();
+        match (status, error) {
+            (_, Some(e)) => self.status_lbl.set_text(&e),
+            (Some(status), None) if modified => self.status_lbl.set_text(&format!("{} (save the document to include the unsaved changes)", status.description())),
+            (Some(status), None) => self.status_lbl.set_text(status.description()),
+            (None, None) => self.status_lbl.set_text("")
+        }
+        self.stage_btn.set_sensitive(status.is_some());
+        self.commit_btn.set_sensitive(status.is_some());
+
+        // The commits are replaced before the rows are, since removing the selected row
+        // triggers the row selection handler.
+        {
+            let mut state = self.state.borrow_mut();
+            state.head = head;
+            state.commits = commits.clone();
+        }
+        while let Some(row) = self.list.row_at_index(0) {
+            self.list.remove(&row);
+        }
+        for commit in commits.iter() {
+            let summary_lbl = Label::new(Some(&commit.summary));
+            summary_lbl.set_halign(Align::Start);
+            summary_lbl.set_ellipsize(pango::EllipsizeMode::End);
+            let time = glib::DateTime::from_unix_local(commit.time).ok()
+                .and_then(|dt| dt.format("%Y-%m-%d %H:%M").ok() )
+                .map(|s| s.to_string() )
+                .unwrap_or_default();
+            let sub_lbl = Label::new(Some(&format!("{} · {} · {}", &commit.hash[..commit.hash.len().min(7)], commit.author, time)));
+            sub_lbl.set_halign(Align::Start);
+            sub_lbl.set_ellipsize(pango::EllipsizeMode::End);
+            sub_lbl.style_context().add_class("dim-label");
+            let bx = Box::new(Orientation::Vertical, 2);
+            bx.append(&summary_lbl);
+            bx.append(&sub_lbl);
+            let row = ListBoxRow::new();
+            row.set_child(Some(&bx));
+            self.list.append(&row);
+        }
+        self.commit_view.buffer().set_text("");
+        self.update_gutter();
+    }
+
+    fn queue_gutter_update(&self) {
+        if let Some(source) = self.gutter_source.borrow_mut().take() {
+            source.remove();
+        }
+        let source = glib::timeout_add_local_once(std::time::Duration::from_millis(GUTTER_DELAY), {
+            let panel = self.clone();
+            move || {
+                panel.gutter_source.replace(None);
+                panel.update_gutter();
+            }
+        });
+        self.gutter_source.replace(Some(source));
+    }
+
+    // Marks the lines changed since the last commit.
+    fn update_gutter(&self) {
+        let Ok(buffer) = self.editor_view.buffer().downcast::<sourceview5::Buffer>() else { return };
+        let (start, end) = (buffer.start_iter(), buffer.end_iter());
+        for category in [ADDED_CATEGORY, MODIFIED_CATEGORY, REMOVED_CATEGORY] {
+            buffer.remove_source_marks(&start, &end, Some(category));
+        }
+        let state = self.state.borrow();
+
+        // Untracked files (without a committed version) are not marked.
+        let Some(head) = &state.head else { return };
+        let current = buffer.text(&start, &end, true).to_string();
+        for (line, change) in crate::vcs::line_changes(head, &current) {
+            let category = match change {
+                LineChange::Added => ADDED_CATEGORY,
+                LineChange::Modified => MODIFIED_CATEGORY,
+                LineChange::Removed => REMOVED_CATEGORY
+            };
+            let iter = buffer.iter_at_line(line as i32).unwrap_or(buffer.end_iter());
+            buffer.create_source_mark(None, category, &iter);
+        }
+    }
+
+}
+
+fn configure_gutter(view : &sourceview5::View) {
+    let marks = [
+        (ADDED_CATEGORY, "rgba(38, 162, 105, 0.15)", "list-add-symbolic"),
+        (MODIFIED_CATEGORY, "rgba(229, 165, 10, 0.15)", "document-edit-symbolic"),
+        (REMOVED_CATEGORY, "rgba(192, 28, 40, 0.15)", "list-remove-symbolic")
+    ];
+    for (category, color, icon) in marks {
+        let attrs = sourceview5::MarkAttributes::new();
+        if let Ok(color) = gdk::RGBA::parse(color) {
+            attrs.set_background(&color);
+        }
+        attrs.set_icon_name(icon);
+        view.set_mark_attributes(category, &attrs, 0);
+    }
+}
+
+impl React<FileManager> for GitPanel {
+
+    fn react(&self, manager : &FileManager) {
+        manager.connect_new({
+            let panel = self.clone();
+            move |_| {
+                panel.dialog.hide();
+                panel.set_path(None);
+            }
+        });
+        manager.connect_opened({
+            let panel = self.clone();
+            move |(path, _)| {
+                panel.set_path(Some(PathBuf::from(path)));
+            }
+        });
+        manager.connect_save({
+            let panel = self.clone();
+            move |path| {
+                panel.set_path(Some(PathBuf::from(path)));
+            }
+        });
+        manager.connect_file_changed({
+            let panel = self.clone();
+            move |_| {
+                panel.state.borrow_mut().modified = true;
+            }
+        });
+    }
+
+}
@@ -0,0 +1,292 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use stateful::React;
+use crate::manager::FileManager;
+use crate::history::{self, Snapshot, Hunk};
+
+/*
+Dialog listing the local snapshots of the open document (see crate::history). The changes
+between the selected snapshot and the current buffer are shown as hunks, each of which can be
+restored separately, or the whole snapshot can be restored at once.
+*/
+
+#[derive(Debug, Clone)]
+pub struct HistoryDialog {
+    pub dialog : Dialog,
+    list : ListBox,
+    hunk_list : ListBox,
+    status_lbl : Label,
+    restore_btn : Button,
+    view : sourceview5::View,
+    doc_path : Rc<RefCell<Option<PathBuf>>>,
+    snapshots : Rc<RefCell<Vec<Snapshot>>>,
+    list_send : glib::Sender<Vec<Snapshot>>,
+    selected : Rc<RefCell<Option<usize>>>
+}
+
+impl HistoryDialog {
+
+    pub fn build(view : &sourceview5::View) -> Self {
+        let dialog = Dialog::new();
+        dialog.set_title(Some("Revision history"));
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(860, 560);
+
+        let list = ListBox::new();
+        list.set_selection_mode(SelectionMode::Single);
+        list.style_context().add_class("navigation-sidebar");
+        let list_scroll = ScrolledWindow::new();
+        list_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        list_scroll.set_vexpand(true);
+        list_scroll.set_width_request(240);
+        list_scroll.set_child(Some(&list));
+
+        let status_lbl = Label::new(None);
+        status_lbl.style_context().add_class("dim-label");
+        status_lbl.set_halign(Align::Start);
+        status_lbl.set_hexpand(true);
+        let restore_btn = Button::with_label("Restore this version");
+        restore_btn.style_context().add_class("suggested-action");
+        restore_btn.set_sensitive(false);
+        let top_bx = Box::new(Orientation::Horizontal, 6);
+        top_bx.set_margin_start(12);
+        top_bx.set_margin_end(12);
+        top_bx.set_margin_top(6);
+        top_bx.set_margin_bottom(6);
+        top_bx.append(&status_lbl);
+        top_bx.append(&restore_btn);
+
+        let hunk_list = ListBox::new();
+        hunk_list.set_selection_mode(SelectionMode::None);
+        hunk_list.set_show_separators(true);
+        let hunk_scroll = ScrolledWindow::new();
+        hunk_scroll.set_hexpand(true);
+        hunk_scroll.set_vexpand(true);
+        hunk_scroll.set_child(Some(&hunk_list));
+
+        let right_bx = Box::new(Orientation::Vertical, 0);
+        right_bx.append(&top_bx);
+        right_bx.append(&hunk_scroll);
+
+        let bx = Box::new(Orientation::Horizontal, 0);
+        bx.append(&list_scroll);
+        bx.append(&Separator::new(Orientation::Vertical));
+        bx.append(&right_bx);
+        dialog.set_child(Some(&bx));
+
+        let (list_send, list_recv) = glib::MainContext::channel::<Vec<Snapshot>>(glib::PRIORITY_DEFAULT);
+        let history = Self {
+            dialog,
+            list,
+            hunk_list,
+            status_lbl,
+            restore_btn,
+            view : view.clone(),
+            doc_path : Default::default(),
+            snapshots : Default::default(),
+            list_send,
+            selected : Default::default()
+        };
+        list_recv.attach(None, {
+            let history = history.clone();
+            move |snapshots| {
+                history.set_snapshots(snapshots);
+                glib::Continue(true)
+            }
+        });
+        history.list.connect_row_selected({
+            let history = history.clone();
+            move |_, row| {
+                history.selected.replace(row.map(|row| row.index() as usize ));
+                history.show_changes();
+            }
+        });
+        history.restore_btn.connect_clicked({
+            let history = history.clone();
+            move |_| {
+                let Some(content) = history.selected_snapshot().and_then(|s| s.content() ) else { return };
+                history.set_text(&content, None);
+            }
+        });
+        history
+    }
+
+    pub fn show(&self) {
+        self.refresh();
+        self.dialog.show();
+    }
+
+    /// Lists the snapshots of the current document again (they are read at the history thread).
+    pub fn refresh(&self) {
+        match self.doc_path.borrow().as_ref() {
+            Some(path) => history::queue_list(path, self.list_send.clone()),
+            None => self.set_snapshots(Vec::new())
+        }
+    }
+
+    fn set_snapshots(&self, snapshots : Vec<Snapshot>) {
+        self.selected.replace(None);
+        while let Some(row) = self.list.row_at_index(0) {
+            self.list.remove(&row);
+        }
+        for (ix, snapshot) in snapshots.iter().enumerate() {
+            let time = glib::DateTime::from_unix_local((snapshot.time / 1000) as i64).ok()
+                .and_then(|dt| dt.format("%Y-%m-%d %H:%M:%S").ok() )
+                .map(|s| s.to_string() )
+                .unwrap_or_default();
+            let words = match snapshots.get(ix + 1) {
+                Some(prev) => format!("{} words ({:+})", snapshot.words, snapshot.words as i64 - prev.words as i64),
+                None => format!("{} words", snapshot.words)
+            };
+            let time_lbl = Label::new(Some(&time));
+            time_lbl.set_halign(Align::Start);
+            let words_lbl = Label::new(Some(&words));
+            words_lbl.set_halign(Align::Start);
+            words_lbl.style_context().add_class("dim-label");
+            let bx = Box::new(Orientation::Vertical, 2);
+            bx.append(&time_lbl);
+            bx.append(&words_lbl);
+            let row = ListBoxRow::new();
+            row.set_child(Some(&bx));
+            self.list.append(&row);
+        }
+        self.snapshots.replace(snapshots);
+        if let Some(row) = self.list.row_at_index(0) {
+            self.list.select_row(Some(&row));
+        } else {
+            self.show_changes();
+        }
+    }
+
+    fn selected_snapshot(&self) -> Option<Snapshot> {
+        let ix = (*self.selected.borrow())?;
+        self.snapshots.borrow().get(ix).cloned()
+    }
+
+    fn current_text(&self) -> String {
+        let buffer = self.view.buffer();
+        buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string()
+    }
+
+    // Replaces the buffer text, placing the cursor at the given line.
+    fn set_text(&self, txt : &str, line : Option<usize>) {
+        let buffer = self.view.buffer();
+        buffer.begin_user_action();
+        buffer.set_text(txt);
+        buffer.end_user_action();
+        if let Some(iter) = line.and_then(|line| buffer.iter_at_line(line as i32) ) {
+            buffer.place_cursor(&iter);
+            self.view.scroll_to_iter(&mut iter.clone(), 0.0, true, 0.0, 0.5);
+        }
+        self.show_changes();
+    }
+
+    // Shows the changes between the selected snapshot and the current buffer.
+    fn show_changes(&self) {
+        while let Some(row) = self.hunk_list.row_at_index(0) {
+            self.hunk_list.remove(&row);
+        }
+        let content = self.selected_snapshot().and_then(|s| s.content() );
+        self.restore_btn.set_sensitive(content.is_some());
+        let Some(content) = content else {
+            if self.snapshots.borrow().is_empty() {
+                self.status_lbl.set_text("No snapshots. Snapshots are taken when the document is opened or saved.");
+            } else {
+                self.status_lbl.set_text("");
+            }
+            return;
+        };
+        let current = self.current_text();
+        let hunks = history::hunks(&history::diff_lines(&content, &current));
+        match hunks.len() {
+            0 => self.status_lbl.set_text("No differences from the current text"),
+            1 => self.status_lbl.set_text("1 change from the current text"),
+            n => self.status_lbl.set_text(&format!("{} changes from the current text", n))
+        }
+        for hunk in hunks {
+            self.hunk_list.append(&self.hunk_row(hunk));
+        }
+    }
+
+    fn hunk_row(&self, hunk : Hunk) -> ListBoxRow {
+        let line_lbl = Label::new(Some(&format!("Line {}", hunk.new_start + 1)));
+        line_lbl.set_halign(Align::Start);
+        line_lbl.set_hexpand(true);
+        line_lbl.style_context().add_class("dim-label");
+        let btn = Button::with_label("Restore");
+        btn.style_context().add_class("flat");
+        let header = Box::new(Orientation::Horizontal, 6);
+        header.append(&line_lbl);
+        header.append(&btn);
+
+        let mut markup = String::new();
+        for line in hunk.old_lines.iter() {
+            markup += &format!("<span foreground=\"#c01c28\">- {}</span>\n", glib::markup_escape_text(line));
+        }
+        for line in hunk.new_lines.iter() {
+            markup += &format!("<span foreground=\"#26a269\">+ {}</span>\n", glib::markup_escape_text(line));
+        }
+        let diff_lbl = Label::new(None);
+        diff_lbl.set_markup(markup.trim_end());
+        diff_lbl.set_halign(Align::Start);
+        diff_lbl.set_selectable(true);
+        diff_lbl.style_context().add_class("monospace");
+
+        let bx = Box::new(Orientation::Vertical, 6);
+        bx.set_margin_start(12);
+        bx.set_margin_end(12);
+        bx.set_margin_top(6);
+        bx.set_margin_bottom(6);
+        bx.append(&header);
+        bx.append(&diff_lbl);
+        let row = ListBoxRow::new();
+        row.set_child(Some(&bx));
+
+        btn.connect_clicked({
+            let history = self.clone();
+            move |_| {
+                let txt = history::apply_hunk(&history.current_text(), &hunk);
+                history.set_text(&txt, Some(hunk.new_start));
+            }
+        });
+        row
+    }
+
+}
+
+impl React<FileManager> for HistoryDialog {
+
+    fn react(&self, manager : &FileManager) {
+        manager.connect_new({
+            let history = self.clone();
+            move |_| {
+                history.doc_path.replace(None);
+                history.dialog.hide();
+            }
+        });
+        manager.connect_opened({
+            let history = self.clone();
+            move |(path, _)| {
+                history.doc_path.replace(Some(PathBuf::from(path)));
+            }
+        });
+        manager.connect_save({
+            let history = self.clone();
+            move |path| {
+                history.doc_path.replace(Some(PathBuf::from(path)));
+                if history.dialog.is_visible() {
+                    history.refresh();
+                }
+            }
+        });
+    }
+
+}
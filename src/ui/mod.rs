@@ -5,13 +5,13 @@ For a copy, see http://www.gnu.org/licenses.*/
 
 use gtk4::*;
 use gtk4::prelude::*;
-use stateful::React;
+use stateful::{React, Callbacks};
 use sourceview5::*;
 use sourceview5::prelude::ViewExt;
 use sourceview5::prelude::BufferExt;
 use sourceview5::prelude::CompletionWordsExt;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{RefCell, Cell};
 use crate::manager::FileManager;
 use crate::typesetter::{Typesetter, TypesetterTarget};
 use glib::{types::Type, value::{Value, ToValue}};
@@ -22,6 +22,8 @@ use filecase::{OpenDialog, SaveDialog};
 use poppler::Document;
 use either::Either;
 use crate::state::PapersState;
+use crate::templates::{Template, TemplatePreview};
+use crate::tex::{GREEK_SMALL, GREEK_CAPITAL};
 
 mod doctree;
 
@@ -29,118 +31,84 @@ mod titlebar;
 
 mod editor;
 
-pub use titlebar::*;
+mod viewer;
 
-pub use doctree::*;
+mod search;
 
-pub use editor::*;
+mod sidebar;
 
-#[derive(Debug, Clone)]
-pub struct PapersWindow {
-    pub window : ApplicationWindow,
-    pub titlebar : Titlebar,
-    pub editor : PapersEditor,
-    pub doc_tree : DocTree,
-    pub stack : Stack,
-    pub start_screen : StartScreen,
-    pub export_pdf_dialog : SaveDialog,
-    pub import_csv_dialog : OpenDialog,
-    pub import_img_dialog : OpenDialog,
-    pub import_bib_dialog : OpenDialog,
-    pub import_src_dialog : OpenDialog,
-}
+mod compare;
 
-const EMPTY_TEMPLATE : &'static str = r#""#;
+mod history;
 
-const ARTICLE_TEMPLATE : &'static str = r#"
-#let title = "Title"
-#let authors = "Bob, A. and Alice, B."
-#let abstract = lorem(100)
+mod recovery;
 
-#set document(title: title, author: authors)
-#set page(
-    paper: "a4",
-    numbering : "1"
-)
-#set cite(style: "author-date")
-#set bibliography(style: "apa", title: "References")
-#set align(center)
-#text(size: 24pt, weight: 700, title)
-#linebreak()
-#text(authors)
+mod tabs;
 
-#block(width : 75%)[
-    #set par(justify: true, leading : 0.32em)
-    #set pad(left : 20pt, right : 20pt)
-= Abstract
-#abstract
-]
+mod git;
 
-*Keywords* #lorem(4)
+mod review;
 
-#set align(left)
+mod templates;
 
-#show: columns
+mod preferences;
 
-= Introduction
+mod completion;
 
-#lorem(500)
+pub use titlebar::*;
 
-= Methods
+pub use doctree::*;
 
-#lorem(500)
+pub use editor::*;
 
-= Results
+pub use viewer::*;
 
-#lorem(500)
+pub use search::*;
 
-= Discussion
+pub use sidebar::*;
 
-#lorem(1000)
-"#;
+pub use compare::*;
 
-const MINIMAL_TEMPLATE : &'static str = r#"
-#set page(paper: "a4", margin: 2.0cm)
-#set par(leading : 0.98em, first-line-indent : 15pt)
-#set text(font : "Liberation Serif", style : "normal", weight : "regular", size : 12pt)
-"#;
+pub use history::*;
 
-const REPORT_TEMPLATE : &'static str = r#""#;
-
-const BOOK_TEMPLATE : &'static str = r#""#;
-
-const PRESENTATION_TEMPLATE : &'static str = r#"
-#let title = "Presentation Title"
-#let author = "Bob, A. and Alice, B."
-#set page(
-    paper: "presentation-16-9",
-    margin: 2.0cm,
-    numbering : "1",
-    number-align:right
-)
-#set text(size : 18pt, font : "Liberation Sans")
-#show heading.where(level : 1) : head => {
-    pagebreak();
-    head
-    linebreak();
-}
+pub use recovery::*;
 
-#set align(center + horizon)
+pub use tabs::*;
 
-#text(size : 26pt, weight : "bold", title)
+pub use git::*;
 
-#author
+pub use review::*;
 
-#set align(left + top)
+pub use templates::*;
 
-= First slide
+pub use preferences::*;
 
-#lorem(20)
+pub use completion::*;
 
-= Second slide
+#[derive(Debug, Clone)]
+pub struct PapersWindow {
+    pub window : ApplicationWindow,
+    pub titlebar : Titlebar,
+    pub editor : PapersEditor,
+    pub doc_tree : DocTree,
+    pub stack : Stack,
+    pub start_screen : StartScreen,
+    pub export_pdf_dialog : SaveDialog,
+    pub export_latex_dialog : SaveDialog,
+    pub import_csv_dialog : OpenDialog,
+    pub import_img_dialog : OpenDialog,
+    pub import_bib_dialog : OpenDialog,
+    pub import_src_dialog : OpenDialog,
+    pub history_dialog : HistoryDialog,
+    pub git_panel : GitPanel,
+    pub review_panel : ReviewPanel,
+    pub template_dialog : SaveTemplateDialog,
+    pub template_wizard : TemplateWizard,
+    pub preferences_dialog : PreferencesDialog,
+}
 
-#lorem(20)
-"#;
+// Document classes offered when exporting to LaTeX. The first one is the default.
+const LATEX_CLASSES : [&'static str; 6] = ["article", "report", "book", "revtex4-2", "elsarticle", "IEEEtran"];
 
 fn start_document(view : &View, stack : &Stack, titlebar : &Titlebar, template : &str) {
     view.buffer().set_text(template);
@@ -152,32 +120,32 @@ fn start_document(view : &View, stack : &Stack, titlebar : &Titlebar, template :
     titlebar.set_edit(true);
 }
 
-fn start_with_template(
-    btn : &Button,
-    view : sourceview5::View,
-    stack : Stack,
-    titlebar : Titlebar,
-    template : &'static str
-) {
-    btn.connect_clicked({
-        move |_| {
-            start_document(&view, &stack, &titlebar, template);
-        }
-    });
+fn place_cursor_at_offset(view : &View, offset : i32) {
+    let buffer = view.buffer();
+    let iter = buffer.iter_at_offset(offset);
+    buffer.place_cursor(&iter);
+    view.scroll_to_iter(&mut iter.clone(), 0.0, true, 0.0, 0.5);
 }
 
 impl React<StartScreen> for PapersWindow {
 
     fn react(&self, start_screen : &StartScreen) {
-        let view = &self.editor.view;
-        let stack = &self.stack;
-        let tbar = &self.titlebar;
-        start_with_template(&start_screen.empty_btn, view.clone(), stack.clone(), tbar.clone(), EMPTY_TEMPLATE);
-        start_with_template(&start_screen.minimal_btn, view.clone(), stack.clone(), tbar.clone(), MINIMAL_TEMPLATE);
-        start_with_template(&start_screen.article_btn, view.clone(), stack.clone(), tbar.clone(), ARTICLE_TEMPLATE);
-        start_with_template(&start_screen.report_btn, view.clone(), stack.clone(), tbar.clone(), REPORT_TEMPLATE);
-        start_with_template(&start_screen.book_btn, view.clone(), stack.clone(), tbar.clone(), BOOK_TEMPLATE);
-        start_with_template(&start_screen.present_btn, view.clone(), stack.clone(), tbar.clone(), PRESENTATION_TEMPLATE);
+        start_screen.connect_template_selected({
+            let template_wizard = self.template_wizard.clone();
+            move |template| {
+                template_wizard.show(&template);
+            }
+        });
+
+        // Projects are written to disk first, then opened as any other file (see React<FileManager> for TemplateWizard).
+        self.template_wizard.connect_document({
+            let view = self.editor.view.clone();
+            let stack = self.stack.clone();
+            let titlebar = self.titlebar.clone();
+            move |source| {
+                start_document(&view, &stack, &titlebar, &source);
+            }
+        });
     }
 }
 
@@ -187,15 +155,20 @@ pub struct DocBtn {
 
 impl DocBtn {
 
-    pub fn build(image : &str, title : &str, sub : &str) -> Self {
+    pub fn build(template : &Template) -> Self {
         let btn = Button::new();
-        let img = Picture::for_resource(&format!("io/github/limads/drafts/icons/scalable/actions/{}.svg", image));
+        let img = match &template.preview {
+            Some(TemplatePreview::Resource(path)) => Picture::for_resource(path),
+            Some(TemplatePreview::File(path)) => Picture::for_filename(path),
+            None => Picture::for_resource("/io/github/limads/drafts/icons/scalable/actions/empty.svg")
+        };
         img.set_can_shrink(false);
         let lbl_bx = Box::new(Orientation::Vertical, 12);
-        let lbl = Label::new(Some(title));
+        let lbl = Label::new(Some(&template.meta.name));
         lbl.set_justify(Justification::Left);
         lbl.set_halign(Align::Start);
-        let sub_lbl = Label::builder().use_markup(true).label(&format!("<span font_weight='normal'>{}</span>", sub)).build();
+        let sub = glib::markup_escape_text(&template.meta.description);
+        let sub_lbl = Label::builder().use_markup(true).label(&format!("<span font_weight='normal'>\n{}</span>", sub)).build();
         sub_lbl.set_halign(Align::Start);
         sub_lbl.set_justify(Justification::Fill);
         lbl_bx.append(&lbl);
@@ -210,6 +183,7 @@ impl DocBtn {
         btn.set_vexpand(true);
         btn.set_valign(Align::Center);
         btn.set_width_request(480);
+
         Self { btn }
     }
 
@@ -224,68 +198,33 @@ const PAPERS_PRELUDE : &'static str = r"
     \usepackage{amsmath}
 ";
 
-const EMPTY_DESCRIPTION : &'static str = r#"
-Start a document without a template"#;
-
-const MINIMAL_DESCRIPTION : &'static str = r#"
-Useful for notes, drafts and other generic text"#;
-
-const ARTICLE_DESCRIPTION : &'static str = r#"
-Short document divided into sections,
-aimed at specialized journals."#;
+#[derive(Debug, Clone)]
+pub struct StartScreen {
+    bx : Box,
 
-const REPORT_DESCRIPTION : &'static str = r#"
-A template for technical reports, dissertations
-and thesis."#;
+    // Template buttons, two per row.
+    doc_grid : Grid,
 
-const BOOK_DESCRIPTION : &'static str = r#"
-Long document divided into chapters."#;
+    on_template_selected : Callbacks<Template>,
 
-const PRESENTATION_DESCRIPTION : &'static str = r#"
-A document focusing on visual communication,
-organized as a slideshow."#;
+    pub recent_list : RecentList,
 
-#[derive(Debug, Clone)]
-pub struct StartScreen {
-    bx : Box,
-    empty_btn : Button,
-    article_btn : Button,
-    minimal_btn : Button,
-    report_btn : Button,
-    book_btn : Button,
-    present_btn : Button,
-    pub recent_list : RecentList
+    pub recovery : RecoveryList
 }
 
 impl StartScreen {
 
     pub fn build(state : PapersState) -> Self {
-        let doc_upper_bx = Box::new(Orientation::Horizontal, 0);
-        let doc_middle_bx = Box::new(Orientation::Horizontal, 0);
-        let doc_lower_bx = Box::new(Orientation::Horizontal, 0);
-        let empty_btn = DocBtn::build("empty", "Empty", EMPTY_DESCRIPTION);
-        let minimal_btn = DocBtn::build("minimal", "Minimal", MINIMAL_DESCRIPTION);
-        let article_btn = DocBtn::build("article", "Article", ARTICLE_DESCRIPTION);
-        let report_btn = DocBtn::build("report", "Report", REPORT_DESCRIPTION);
-        let book_btn = DocBtn::build("book", "Book", BOOK_DESCRIPTION);
-        let present_btn = DocBtn::build("presentation", "Presentation", PRESENTATION_DESCRIPTION);
+        let doc_grid = Grid::new();
+        let doc_scroll = ScrolledWindow::new();
+        doc_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        doc_scroll.set_propagate_natural_height(true);
+        doc_scroll.set_child(Some(&doc_grid));
 
         let new_bx = Box::new(Orientation::Vertical, 16);
-        doc_upper_bx.append(&empty_btn.btn);
-        doc_upper_bx.append(&minimal_btn.btn);
-        doc_middle_bx.append(&article_btn.btn);
-        doc_middle_bx.append(&present_btn.btn);
-        doc_lower_bx.append(&report_btn.btn);
-        doc_lower_bx.append(&book_btn.btn);
-        report_btn.btn.set_sensitive(false);
-        book_btn.btn.set_sensitive(false);
-
-        let bx = Box::new(Orientation::Horizontal, 0);
         let title = title_label("New");
         new_bx.append(&title);
-        new_bx.append(&doc_upper_bx);
-        new_bx.append(&doc_middle_bx);
-        new_bx.append(&doc_lower_bx);
+        new_bx.append(&doc_scroll);
         new_bx.set_margin_end(128);
 
         new_bx.set_vexpand(true);
@@ -294,76 +233,90 @@ impl StartScreen {
         new_bx.set_halign(Align::End);
 
         let recent_list = RecentList::build(state);
-        bx.append(&recent_list.bx);
-        bx.append(&new_bx);
+        let inner_bx = Box::new(Orientation::Horizontal, 0);
+        inner_bx.set_vexpand(true);
+        inner_bx.append(&recent_list.bx);
+        inner_bx.append(&new_bx);
 
-        Self {
+        let recovery = RecoveryList::build();
+        let bx = Box::new(Orientation::Vertical, 0);
+        bx.append(&recovery.revealer);
+        bx.append(&inner_bx);
+
+        let start_screen = Self {
             bx,
-            empty_btn : empty_btn.btn.clone(),
-            article_btn : article_btn.btn.clone(),
+            doc_grid,
+            on_template_selected : Default::default(),
             recent_list,
-            report_btn : report_btn.btn.clone(),
-            book_btn : book_btn.btn.clone(),
-            present_btn : present_btn.btn.clone(),
-            minimal_btn : minimal_btn.btn.clone()
+            recovery
+        };
+        start_screen.reload_templates();
+        start_screen
+    }
+
+    /// Lists the bundled and user templates again.
+    pub fn reload_templates(&self) {
+        while let Some(child) = self.doc_grid.first_child() {
+            self.doc_grid.remove(&child);
+        }
+        for (ix, template) in crate::templates::list_templates().into_iter().enumerate() {
+            let doc_btn = DocBtn::build(&template);
+            if template.user {
+                self.connect_remove(&doc_btn.btn, &template);
+            }
+            doc_btn.btn.connect_clicked({
+                let on_selected = self.on_template_selected.clone();
+                move |_| {
+                    on_selected.call(template.clone());
+                }
+            });
+            self.doc_grid.attach(&doc_btn.btn, (ix % 2) as i32, (ix / 2) as i32, 1, 1);
         }
     }
 
-}
+    // User templates can be removed from a popover shown with a right click.
+    fn connect_remove(&self, btn : &Button, template : &Template) {
+        let remove_btn = Button::with_label("Remove template");
+        remove_btn.style_context().add_class("flat");
+        let popover = Popover::new();
+        popover.set_child(Some(&remove_btn));
+        popover.set_parent(btn);
+        btn.connect_destroy({
+            let popover = popover.clone();
+            move |_| {
+                popover.unparent();
+            }
+        });
+        let gesture = GestureClick::new();
+        gesture.set_button(gdk::BUTTON_SECONDARY);
+        gesture.connect_pressed({
+            let popover = popover.clone();
+            move |_, _, _, _| {
+                popover.popup();
+            }
+        });
+        btn.add_controller(&gesture);
+        remove_btn.connect_clicked({
+            let start_screen = self.clone();
+            let template = template.clone();
+            move |_| {
+                popover.popdown();
+                if let Err(e) = crate::templates::remove_template(&template) {
+                    log::warn!("Unable to remove template: {}", e);
+                }
+                start_screen.reload_templates();
+            }
+        });
+    }
 
-const GREEK_SMALL : [(&'static str, &'static str); 24] = [
-    ("α", "alpha"),
-    ("β", "beta"),
-    ("γ", "gamma"),
-    ("δ", "delta"),
-    ("ε", "epsilon"),
-    ("ζ", "zeta"),
-    ("η", "eta"),
-    ("θ", "theta"),
-    ("ι", "iota"),
-    ("κ", "kappa"),
-    ("λ", "lambda"),
-    ("μ", "mu"),
-    ("ν", "nu"),
-    ("ξ", "xi"),
-    ("ο", "omicron"),
-    ("π", "pi"),
-    ("ρ", "rho"),
-    ("σ", "sigma"),
-    ("τ", "tau"),
-    ("υ", "upsilon"),
-    ("φ", "phi"),
-    ("χ", "chi"),
-    ("ψ", "psi"),
-    ("ω", "omega")
-];
+    pub fn connect_template_selected<F>(&self, f : F)
+    where
+        F : Fn(Template) + 'static
+    {
+        self.on_template_selected.bind(f);
+    }
 
-const GREEK_CAPITAL : [(&'static str, &'static str); 24] = [
-    ("Α", "Alpha"),
-    ("Β", "Beta"),
-    ("Γ", "Gamma"),
-    ("Δ", "Delta"),
-    ("Ε", "Epsilon"),
-    ("Ζ", "Zeta"),
-    ("Η", "Eta"),
-    ("Θ", "Theta"),
-    ("Ι", "Iota"),
-    ("Κ", "Kappa"),
-    ("Λ", "Lambda"),
-    ("Μ", "Mu"),
-    ("Ν", "Nu"),
-    ("Ξ", "Xi"),
-    ("Ο", "Omicron"),
-    ("Π", "Pi"),
-    ("Ρ", "Rho"),
-    ("Σ", "Sigma"),
-    ("Τ", "Tau"),
-    ("Υ", "Upsilon"),
-    ("Φ", "Phi"),
-    ("Χ", "Chi"),
-    ("Ψ", "Psi"),
-    ("Ω", "Omega")
-];
+}
 
 // https://en.wikipedia.org/wiki/Mathematical_operators_and_symbols_in_Unicode
 const OPERATORS : [(&'static str, &'static str); 36] = [
@@ -493,7 +446,6 @@ impl PapersWindow {
         let titlebar = Titlebar::build();
         titlebar.set_edit(false);
 
-        window.set_titlebar(Some(&titlebar.header));
         window.set_decorated(true);
         let doc_tree = DocTree::build();
         let editor = PapersEditor::build(&titlebar.zoom_action);
@@ -508,6 +460,16 @@ impl PapersWindow {
         let export_pdf_dialog = filecase::SaveDialog::build(&["*.pdf"]);
         export_pdf_dialog.dialog.set_transient_for(Some(&window));
 
+        let export_latex_dialog = filecase::SaveDialog::build(&["*.tex"]);
+        export_latex_dialog.dialog.set_transient_for(Some(&window));
+        export_latex_dialog.dialog.add_choice(
+            "class",
+            "Document class",
+            &LATEX_CLASSES[..],
+            &LATEX_CLASSES[..]
+        );
+        export_latex_dialog.dialog.set_choice("class", LATEX_CLASSES[0]);
+
         let import_csv_dialog = filecase::OpenDialog::build(&["*.csv"]);
         import_csv_dialog.dialog.set_transient_for(Some(&window));
 
@@ -525,19 +487,45 @@ impl PapersWindow {
         show_on_action(&titlebar.object_actions.table, &import_csv_dialog.dialog);
         show_on_action(&titlebar.object_actions.bibfile, &import_bib_dialog.dialog);
         show_on_action(&titlebar.main_menu.export_action, &export_pdf_dialog.dialog);
+        show_on_action(&titlebar.main_menu.export_latex_action, &export_latex_dialog.dialog);
+
+        let history_dialog = HistoryDialog::build(&editor.view);
+        history_dialog.dialog.set_transient_for(Some(&window));
+        titlebar.main_menu.history_action.connect_activate({
+            let history_dialog = history_dialog.clone();
+            move |_, _| {
+                history_dialog.show();
+            }
+        });
+
+        let git_panel = GitPanel::build(&editor.view);
+        git_panel.dialog.set_transient_for(Some(&window));
+
+        let review_panel = ReviewPanel::build(&editor.view);
+        review_panel.dialog.set_transient_for(Some(&window));
+
+        let template_dialog = SaveTemplateDialog::build(&editor.view, &editor.pdf_viewer);
+        template_dialog.dialog.set_transient_for(Some(&window));
+        let template_wizard = TemplateWizard::build();
+        template_wizard.dialog.set_transient_for(Some(&window));
+        let preferences_dialog = PreferencesDialog::build();
+        preferences_dialog.dialog.set_transient_for(Some(&window));
+
+        template_dialog.connect_saved({
+            let start_screen = start_screen.clone();
+            move |_| {
+                start_screen.reload_templates();
+            }
+        });
 
         export_pdf_dialog.dialog.connect_response({
-            let doc = editor.pdf_viewer.doc.clone();
+            let pdf_viewer = editor.pdf_viewer.clone();
             move |dialog, resp| {
                 match resp {
                     ResponseType::Accept => {
                         if let Some(path) = dialog.file().and_then(|f| f.path() ) {
-                            if let Some(doc) = &*doc.borrow() {
-                                if let Err(e) = doc.save(&format!("file://{}", path.to_str().unwrap())) {
-                                    eprintln!("Document save error: {}", e);
-                                }
-                            } else {
-                                eprintln!("No current document to export");
+                            if let Err(e) = pdf_viewer.save_pdf(&path) {
+                                eprintln!("Document save error: {}", e);
                             }
                         } else {
                             eprintln!("No path available");
@@ -548,6 +536,57 @@ impl PapersWindow {
             }
         });
 
+        export_latex_dialog.dialog.connect_response({
+            let view = editor.view.clone();
+            let overlay = editor.overlay.clone();
+            let curr_toast = editor.curr_toast.clone();
+            let show_error = move |msg : String| {
+                let mut last_toast = curr_toast.borrow_mut();
+                if let Some(t) = last_toast.take() {
+                    t.dismiss();
+                }
+                let toast = libadwaita::Toast::builder()
+                    .title(&msg)
+                    .priority(libadwaita::ToastPriority::High)
+                    .timeout(0)
+                    .build();
+                connect_toast_dismissed(&toast, &curr_toast);
+                overlay.add_toast(&toast);
+                *last_toast = Some(toast);
+            };
+            move |dialog, resp| {
+                match resp {
+                    ResponseType::Accept => {
+                        let Some(path) = dialog.file().and_then(|f| f.path() ) else {
+                            eprintln!("No path available");
+                            return;
+                        };
+                        let mut opts = crate::typst_tools::LatexOptions::default();
+                        if let Some(class) = dialog.choice("class") {
+                            opts.class = class.to_string();
+                        }
+                        let buffer = view.buffer();
+                        let txt = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+                        match crate::typst_tools::export_latex(&path, txt, &opts) {
+                            Ok(tex) => {
+                                if let Err(e) = std::fs::write(&path, tex) {
+                                    show_error(format!("Unable to write LaTeX file: {}", e));
+                                }
+                            },
+                            Err(errs) => {
+                                let msg = match errs.first() {
+                                    Some((line, msg)) => format!("LaTeX export error (Line {}) {}", line + 1, msg),
+                                    None => String::from("LaTeX export error")
+                                };
+                                show_error(msg);
+                            }
+                        }
+                    },
+                    _ => { }
+                }
+            }
+        });
+
         titlebar.main_menu.save_dialog.dialog.set_transient_for(Some(&window));
         titlebar.main_menu.open_dialog.dialog.set_transient_for(Some(&window));
         titlebar.react(&editor.pdf_viewer);
@@ -604,17 +643,6 @@ impl PapersWindow {
 
         // let ws = Rc::new(RefCell::new(Workspace::new()));
 
-        window.add_action(&titlebar.main_menu.actions.new);
-        window.add_action(&titlebar.main_menu.actions.open);
-        window.add_action(&titlebar.main_menu.actions.save);
-        window.add_action(&titlebar.main_menu.actions.save_as);
-        window.add_action(&titlebar.main_menu.export_action);
-        window.add_action(&titlebar.typeset_action);
-
-        window.add_action(&titlebar.sidebar_hide_action);
-        window.add_action(&titlebar.zoom_action);
-        window.add_action(&editor.ignore_file_save_action);
-
         let stack = Stack::new();
         stack.add_named(&start_screen.bx, Some("start"));
         stack.add_named(&editor.overlay, Some("editor"));
@@ -649,7 +677,7 @@ impl PapersWindow {
         // editor.paned.set_position(0);
 
         // window.set_child(Some(&editor.paned));
-        window.set_child(Some(&stack));
+        // The stack is placed at a tab by DocumentTabs.
 
         let symbol_dialog = Dialog::new();
         symbol_dialog.set_title(Some("Symbols"));
@@ -659,16 +687,6 @@ impl PapersWindow {
         let symbol_popover = SymbolPopover::build(&editor);
         titlebar.symbol_btn.set_popover(Some(&symbol_popover.popover));
 
-        let titlebar_actions = titlebar.object_actions.iter()
-            .chain(titlebar.layout_actions.iter())
-            .chain(titlebar.sectioning_actions.iter())
-            .chain(titlebar.block_actions.iter())
-            .chain(titlebar.meta_actions.iter())
-            .chain(titlebar.indexing_actions.iter());
-        for action in titlebar_actions {
-            window.add_action(&action);
-        }
-
         Self {
             window,
             titlebar,
@@ -677,11 +695,95 @@ impl PapersWindow {
             stack,
             start_screen,
             export_pdf_dialog,
+            export_latex_dialog,
             import_csv_dialog,
             import_img_dialog,
             import_bib_dialog,
-            import_src_dialog
+            import_src_dialog,
+            history_dialog,
+            git_panel,
+            review_panel,
+            template_dialog,
+            template_wizard,
+            preferences_dialog
+        }
+    }
+
+    /// Makes this document the one shown at the window: its titlebar is set, and its actions
+    /// replace the actions of the same name of other documents, so the menus and accelerators
+    /// act on it.
+    pub fn activate(&self) {
+        self.window.set_titlebar(Some(&self.titlebar.header));
+        let titlebar = &self.titlebar;
+        let editor = &self.editor;
+        let actions = [
+            &titlebar.main_menu.actions.new,
+            &titlebar.main_menu.actions.open,
+            &titlebar.main_menu.actions.save,
+            &titlebar.main_menu.actions.save_as,
+            &titlebar.main_menu.export_action,
+            &titlebar.main_menu.export_latex_action,
+            &titlebar.main_menu.history_action,
+            &self.git_panel.action,
+            &self.review_panel.action,
+            &self.review_panel.track_action,
+            &self.review_panel.preview_action,
+            &self.template_dialog.action,
+            &self.preferences_dialog.action,
+            &editor.pdf_viewer.view_mode_action,
+            &editor.pdf_viewer.fit_mode_action,
+            &editor.pdf_viewer.frame_preview_action,
+            &editor.pdf_viewer.find_action,
+            &editor.pdf_viewer.copy_action,
+            &editor.pdf_viewer.sidebar_action,
+            &editor.pdf_viewer.compare_action,
+            &titlebar.typeset_action,
+            &titlebar.sidebar_hide_action,
+            &titlebar.zoom_action,
+            &editor.ignore_file_save_action,
+            &editor.format_action
+        ];
+        for action in actions {
+            self.window.add_action(action);
+        }
+        let titlebar_actions = titlebar.object_actions.iter()
+            .chain(titlebar.layout_actions.iter())
+            .chain(titlebar.sectioning_actions.iter())
+            .chain(titlebar.block_actions.iter())
+            .chain(titlebar.meta_actions.iter())
+            .chain(titlebar.indexing_actions.iter());
+        for action in titlebar_actions {
+            self.window.add_action(&action);
+        }
+    }
+
+}
+
+impl PapersWindow {
+
+    /// Releases what a closed document holds beyond its widgets at the tab: the page
+    /// renderers are stopped, and its dialogs (toplevels of their own) are destroyed.
+    pub fn shutdown(&self) {
+        self.editor.pdf_viewer.shutdown();
+        let dialogs : [&Window; 13] = [
+            self.titlebar.main_menu.open_dialog.dialog.upcast_ref(),
+            self.titlebar.main_menu.save_dialog.dialog.upcast_ref(),
+            self.export_pdf_dialog.dialog.upcast_ref(),
+            self.export_latex_dialog.dialog.upcast_ref(),
+            self.import_csv_dialog.dialog.upcast_ref(),
+            self.import_img_dialog.dialog.upcast_ref(),
+            self.import_bib_dialog.dialog.upcast_ref(),
+            self.import_src_dialog.dialog.upcast_ref(),
+            self.history_dialog.dialog.upcast_ref(),
+            self.git_panel.dialog.upcast_ref(),
+            self.review_panel.dialog.upcast_ref(),
+            self.template_dialog.dialog.upcast_ref(),
+            self.template_wizard.dialog.upcast_ref()
+        ];
+        for dialog in dialogs {
+            dialog.destroy();
         }
+        self.preferences_dialog.dialog.destroy();
     }
 
 }
@@ -763,6 +865,7 @@ impl React<FileManager> for PapersWindow {
     fn react(&self, manager : &FileManager) {
         filecase::connect_manager_with_app_window_and_actions(manager, &self.window, &self.titlebar.main_menu.actions, "typ");
         manager.connect_new({
+            let start_screen = self.start_screen.clone();
             let stack = self.stack.clone();
             let titlebar = self.titlebar.clone();
             let window = self.window.clone();
@@ -771,6 +874,7 @@ impl React<FileManager> for PapersWindow {
             move |_| {
                 window.set_title(Some("Drafts"));
                 paned.set_position(i32::MAX);
+                start_screen.reload_templates();
                 stack.set_visible_child_name("start");
                 titlebar.set_prepared(false);
                 titlebar.clear_pages();
@@ -785,12 +889,14 @@ impl React<FileManager> for PapersWindow {
             let stack = self.stack.clone();
             let titlebar = self.titlebar.clone();
             let export_pdf_dialog = self.export_pdf_dialog.clone();
+            let export_latex_dialog = self.export_latex_dialog.clone();
             let bar = self.editor.pdf_viewer.bar.clone();
             move |(path, _)| {
                 stack.set_visible_child_name("editor");
                 titlebar.set_prepared(true);
                 titlebar.clear_pages();
-                init_export_path(&export_pdf_dialog.dialog, path);
+                init_export_path(&export_pdf_dialog.dialog, &path, "pdf");
+                init_export_path(&export_latex_dialog.dialog, &path, "tex");
                 titlebar.set_edit(true);
                 bar.set_revealed(false);
             }
@@ -801,15 +907,66 @@ impl React<FileManager> for PapersWindow {
                 bar.set_revealed(true);
             }
         });
+
+        // Documents associated with a file are recovered by opening the file and replacing its
+        // content once it is loaded, so the buffer is kept associated with the file.
+        let pending_recovery : Rc<RefCell<Option<crate::recovery::RecoveryEntry>>> = Default::default();
+        self.start_screen.recovery.connect_recover({
+            let view = self.editor.view.clone();
+            let stack = self.stack.clone();
+            let titlebar = self.titlebar.clone();
+            let send = manager.sender().clone();
+            let pending_recovery = pending_recovery.clone();
+            move |entry| {
+                match entry.path.clone().filter(|path| Path::new(path).exists() ) {
+                    Some(path) => {
+                        pending_recovery.replace(Some(entry));
+                        send.send(filecase::SingleArchiverAction::OpenRequest(path)).unwrap();
+                    },
+                    None => {
+                        start_document(&view, &stack, &titlebar, &entry.content);
+                        place_cursor_at_offset(&view, entry.cursor);
+                    }
+                }
+            }
+        });
+        manager.connect_opened({
+            let view = self.editor.view.clone();
+            let pending_recovery = pending_recovery.clone();
+            move |(path, _)| {
+                let entry = pending_recovery.borrow_mut().take();
+                if let Some(entry) = entry.filter(|e| e.path.as_ref() == Some(&path) ) {
+                    view.buffer().set_text(&entry.content);
+                    place_cursor_at_offset(&view, entry.cursor);
+                }
+            }
+        });
         manager.connect_save({
             let export_pdf_dialog = self.export_pdf_dialog.clone();
+            let export_latex_dialog = self.export_latex_dialog.clone();
             let bar = self.editor.pdf_viewer.bar.clone();
             move |path| {
                 bar.set_revealed(false);
-                init_export_path(&export_pdf_dialog.dialog, path);
+                init_export_path(&export_pdf_dialog.dialog, &path, "pdf");
+                init_export_path(&export_latex_dialog.dialog, &path, "tex");
             }
         });
 
+        // With live preview, documents are typeset as soon as they are opened or saved.
+        let typeset_live = {
+            let typeset_action = self.titlebar.typeset_action.clone();
+            move || {
+                if crate::settings::Preferences::current().live_preview {
+                    typeset_action.activate(None);
+                }
+            }
+        };
+        manager.connect_opened({
+            let typeset_live = typeset_live.clone();
+            move |_| typeset_live()
+        });
+        manager.connect_save(move |_| typeset_live() );
+
         let csv_func = Rc::new(|path : &Path| -> String {
             let ncols = csv::Reader::from_path(path).ok()
                 .and_then(|mut rdr| rdr.records().next() )
@@ -826,12 +983,12 @@ impl React<FileManager> for PapersWindow {
 
 }
 
-fn init_export_path(export_pdf_dialog : &FileChooserDialog, source_path : String) {
-    if export_pdf_dialog.file().is_none() {
-        if let Some(parent) = Path::new(&source_path).parent() {
-            if let Ok(_) = export_pdf_dialog.set_current_folder(Some(&gio::File::for_path(parent.to_str().unwrap()))) {
-                if let Some(stem) = Path::new(&source_path).file_stem() {
-                    export_pdf_dialog.set_current_name(&format!("{}.pdf", stem.to_str().unwrap()));
+fn init_export_path(export_dialog : &FileChooserDialog, source_path : &str, ext : &str) {
+    if export_dialog.file().is_none() {
+        if let Some(parent) = Path::new(source_path).parent() {
+            if let Ok(_) = export_dialog.set_current_folder(Some(&gio::File::for_path(parent.to_str().unwrap()))) {
+                if let Some(stem) = Path::new(source_path).file_stem() {
+                    export_dialog.set_current_name(&format!("{}.{}", stem.to_str().unwrap(), ext));
                 }
             }
         }
@@ -1025,11 +1182,10 @@ fn update_titlebar(titlebar : &Titlebar, pdf_viewer : &PdfViewer) {
         }
     }*/
 
-    let doc = pdf_viewer.doc();
-    if let Some(doc) = &*doc.borrow() {
-        let n = doc.n_pages();
+    let n = pdf_viewer.n_pages();
+    if n > 0 {
         titlebar.page_button.set_label(&format!("of {}", n));
-        titlebar.page_entry.set_text("1");
+        titlebar.page_entry.set_text(&format!("{}", pdf_viewer.current_page() + 1));
     }
 }
 
@@ -1039,12 +1195,25 @@ impl React<Typesetter> for PapersWindow {
         let win = self.window.clone();
         let editor = self.editor.clone();
         let titlebar = self.titlebar.clone();
+        typesetter.connect_navigation({
+            let pdf_viewer = self.editor.pdf_viewer.clone();
+            move |nav| {
+                pdf_viewer.set_navigation(nav);
+            }
+        });
         typesetter.connect_done(move |target| {
             match target {
                 TypesetterTarget::File(path) => {
                     let doc = poppler::Document::from_file(&format!("file://{}", path), None).unwrap();
-                    editor.pdf_viewer.update(&doc, &titlebar.zoom_action);
-                    update_titlebar(&titlebar, &editor.pdf_viewer);
+                    match std::fs::read(&path) {
+                        Ok(pdf) => {
+                            editor.pdf_viewer.update(&doc, pdf, &titlebar.zoom_action);
+                            update_titlebar(&titlebar, &editor.pdf_viewer);
+                        },
+                        Err(e) => {
+                            eprintln!("Unable to read typeset file: {}", e);
+                        }
+                    }
                 },
                 TypesetterTarget::PDFContent(bytes) => {
                     use std::io::Write;
@@ -1052,7 +1221,7 @@ impl React<Typesetter> for PapersWindow {
                     // f.write_all(&bytes).unwrap();
                     match poppler::Document::from_data(&bytes[..], None) {
                         Ok(doc) => {
-                            editor.pdf_viewer.update(&doc, &titlebar.zoom_action);
+                            editor.pdf_viewer.update(&doc, bytes.clone(), &titlebar.zoom_action);
                             update_titlebar(&titlebar, &editor.pdf_viewer);
                         },
                         Err(e) => {
@@ -1060,6 +1229,10 @@ impl React<Typesetter> for PapersWindow {
                         }
                     }
                 },
+                TypesetterTarget::Frames(preview) => {
+                    editor.pdf_viewer.update_frames(&preview);
+                    update_titlebar(&titlebar, &editor.pdf_viewer);
+                },
                 _ => {
                     eprintln!("Unimplemented typesetting target");
                 }
@@ -1087,437 +1260,38 @@ fn show_with_evince(path : &str) {
         .unwrap();
 }
 
-#[derive(Debug, Clone)]
-pub struct PdfViewer {
-    scroll : ScrolledWindow,
-    pages_bx : Box,
-    das : Rc<RefCell<Vec<DrawingArea>>>,
-    doc : Rc<RefCell<Option<poppler::Document>>>,
-    da1 : DrawingArea,
-    da2 : DrawingArea,
-    curr_page : Rc<RefCell<usize>>,
-    stack : Stack,
-    turn_action : gio::SimpleAction,
-    bar : ActionBar,
-    bar_lbl : Label,
-    bx : Box
-}
-
-impl React<Titlebar> for PdfViewer {
-    fn react(&self, titlebar : &Titlebar) {
-        titlebar.zoom_action.connect_activate({
-            let das = self.das.clone();
-            let (da1, da2) = (self.da1.clone(), self.da2.clone());
-            move |_,_| {
-                das.borrow().iter().for_each(|da| da.queue_draw() );
-                da1.queue_draw();
-                da2.queue_draw();
-            }
-        });
-        /*titlebar.pdf_btn.connect_toggled({
-            let viewer = self.clone();
-            move |btn| {
-                // if !btn.is_active() {
-                //    viewer.clear_pages();
-                // }
-            }
-        });*/
-
-        // Called by event controller (instead of text changed event)
-        // because the application changes the text at the entry too frequently without
-        // user input (like button/arrow press or swipe).
-        // Here we are sure this happened after some user input.
-        let ev = EventControllerKey::new();
-        titlebar.page_entry.add_controller(&ev);
-        ev.connect_key_released({
-            let doc = self.doc.clone();
-            let da1 = self.da1.clone();
-            let da2 = self.da2.clone();
-            let stack = self.stack.clone();
-            let turn_action = self.turn_action.clone();
-            let page_entry = titlebar.page_entry.clone();
-            let curr_page = self.curr_page.clone();
-            move |_, _, _, _| {
-                let txt = page_entry.text();
-                if txt.is_empty() {
-                    return;
-                }
-
-                // The user pages count from 1..n. The internal state count from
-                // 0..n-1 (as poppler does).
-                if let Ok(new_page) = txt.parse::<i32>() {
-                    go_to_page(
-                        &doc,
-                        &da1,
-                        &da2,
-                        &curr_page,
-                        &turn_action,
-                        &stack,
-                        new_page
-                    );
-                }
-            }
-        });
-
-        titlebar.left_btn.connect_clicked({
-            let doc = self.doc.clone();
-            let da1 = self.da1.clone();
-            let da2 = self.da2.clone();
-            let stack = self.stack.clone();
-            let turn_action = self.turn_action.clone();
-            let page_entry = titlebar.page_entry.clone();
-            let curr_page = self.curr_page.clone();
-            move |_| {
-                turn_page(&stack, &doc, &curr_page, &da1, &da2, &turn_action, true);
-            }
-        });
-        titlebar.right_btn.connect_clicked({
-            let doc = self.doc.clone();
-            let da1 = self.da1.clone();
-            let da2 = self.da2.clone();
-            let stack = self.stack.clone();
-            let turn_action = self.turn_action.clone();
-            let page_entry = titlebar.page_entry.clone();
-            let curr_page = self.curr_page.clone();
-            move |_| {
-                turn_page(&stack, &doc, &curr_page, &da1, &da2, &turn_action, false);
-            }
-        });
-
-    }
-}
-
-fn go_to_page(
-    doc : &Rc<RefCell<Option<poppler::Document>>>,
-    da1 : &DrawingArea,
-    da2 : &DrawingArea,
-    curr_page : &Rc<RefCell<usize>>,
-    turn_action : &gio::SimpleAction,
-    stack : &Stack,
-    new_page : i32
-) {
-    if new_page >= 1 {
-        let doc = doc.borrow();
-        if new_page <= doc.as_ref().map(|d| d.n_pages() ).unwrap_or(0) {
-            let mut curr_page = curr_page.borrow_mut();
-            if new_page == *curr_page as i32 {
-                return;
-            }
-            if new_page > *curr_page as i32 {
-                stack.set_transition_type(StackTransitionType::SlideLeft);
-            } else {
-                stack.set_transition_type(StackTransitionType::SlideRight);
-            }
-            *curr_page = new_page as usize - 1;
-            turn_action.set_state(&(new_page - 1).to_variant());
-            draw_at_even_or_odd(&stack, &da1, &da2, new_page as usize - 1);
-        }
-    }
-}
-
-// Equivalent to 0xdc
-// const PAGE_BORDER_COLOR : f64 = 0.859375;
-
-// Equivalent to 0xcf
-pub const PAGE_BORDER_COLOR : f64 = 0.80859375;
-
-pub const PAGE_BORDER_WIDTH : f64 = 0.5;
-
-fn draw_at_even_or_odd(stack : &Stack, da1 : &DrawingArea, da2 : &DrawingArea, curr_page : usize) {
-    if curr_page % 2 == 0 {
-        stack.set_visible_child_name("left");
-        da1.queue_draw();
-    } else {
-        stack.set_visible_child_name("right");
-        da2.queue_draw();
-    }
-}
-
-fn turn_page(
-    stack : &Stack,
-    doc : &Rc<RefCell<Option<Document>>>,
-    curr_page : &Rc<RefCell<usize>>,
-    da1 : &DrawingArea,
-    da2 : &DrawingArea,
-    turn_action : &gio::SimpleAction,
-    left : bool
-) {
-    // da1.queue_draw();
-    // da2.queue_draw();
-    let mut cp = curr_page.borrow_mut();
-    let n_pages = if let Ok(doc) = doc.try_borrow() {
-        doc.as_ref().map(|d| d.n_pages() as usize ).unwrap_or(0)
-    } else {
-        return;
-    };
-    if n_pages == 0 {
-        return;
-    }
-    if *cp == 0 && left {
-        return;
-    }
-    if (*cp == n_pages-1 && !left) {
-        return;
-    }
-
-    if left {
-        *cp -= 1;
-        stack.set_transition_type(StackTransitionType::SlideRight);
-    } else {
-        *cp += 1;
-        stack.set_transition_type(StackTransitionType::SlideLeft);
-    }
-
-    draw_at_even_or_odd(stack, da1, da2, *cp);
-
-    turn_action.set_state(&(*cp as i32).to_variant());
-    turn_action.activate(None);
-}
-
-impl PdfViewer {
-
-    pub fn doc(&self) -> &Rc<RefCell<Option<poppler::Document>>> {
-        &self.doc
-    }
-
-    pub fn clear_pages(&self) {
-        while let Some(child) = self.pages_bx.last_child() {
-            self.pages_bx.remove(&child);
-        }
-        self.doc.replace(None);
-        *(self.curr_page.borrow_mut()) = 0;
-    }
-
-    pub fn new(zoom_action : &gio::SimpleAction) -> Self {
-        let scroll = ScrolledWindow::new();
-        scroll.set_policy(PolicyType::Automatic, PolicyType::Automatic);
-        let pages_bx = Box::new(Orientation::Vertical, 12);
-        // scroll.set_child(Some(&pages_bx));
-        let das = Rc::new(RefCell::new(Vec::new()));
-        let da1 = DrawingArea::new();
-        let da2 = DrawingArea::new();
-        let stack = Stack::new();
-        stack.set_vexpand(true);
-        let click = GestureClick::new();
-        let curr_page = Rc::new(RefCell::new(0));
-        let doc = Rc::new(RefCell::new(None));
-
-        click.connect_pressed({
-            let stack = stack.clone();
-            move|_, _, _, _| {
-                stack.grab_focus();
-            }
-        });
-        stack.add_controller(&click);
-        let controller = EventControllerKey::new();
-        stack.add_controller(&controller);
-        controller.connect_key_pressed(|ev, key, code, modifier| {
-            glib::signal::Inhibit(false)
-        });
-        let scroll_ev = EventControllerScroll::new(EventControllerScrollFlags::HORIZONTAL);
-        let turn_action = gio::SimpleAction::new_stateful("sidebar_hide", None, &(0i32).to_variant());
-        scroll_ev.connect_scroll({
-            let stack = stack.clone();
-            let sw = scroll.clone();
-            let doc = doc.clone();
-            let curr_page = curr_page.clone();
-            let (da1, da2) = (da1.clone(), da2.clone());
-            let turn_action = turn_action.clone();
-            move|ev, a, b| {
-
-                // Automatically handled at edge_overshoot in this case. When we have
-                // a horizontal bar, we should not move the page!
-                let has_hbar = sw.allocation().width() != stack.allocation().width();
-                if has_hbar {
-                    return glib::signal::Inhibit(false);
-                }
-
-                if a < 0.0 {
-                    turn_page(&stack, &doc, &curr_page, &da1, &da2, &turn_action, true);
-                } else if a > 0.0 {
-                    turn_page(&stack, &doc, &curr_page, &da1, &da2, &turn_action, false);
-                }
-
-                glib::signal::Inhibit(false)
-            }
-        });
-        /*scroll.connect_edge_reached({
-            let stack = stack.clone();
-            move |s, pos| {
-                println!("reached");
-            }
-        });
-        scroll.connect_edge_overshot({
-            let stack = stack.clone();
-            move|s, pos| {
-                println!("overshoot");
-                /*match pos {
-                    Position::Left => { turn_page(&stack, true); },
-                    Position::Right => { turn_page(&stack, false); },
-                    _ => { }
-                }*/
-            }
-        });*/
-        scroll.add_controller(&scroll_ev);
-
-        // When passing a page, return zoom to best window fit, so the user does not
-        // need to worry about moving to the edge of the screen again before moving
-        // to the next page.
-
-        // TODO only pass page at the second overshoot (never at the first).
-
-        /*scroll.connect_scroll_end({
-            // let trajx = trajx.clone();
-            let curr_page = self.curr_page.clone();
-            let doc = self.doc.clone();
-            let stack = stack.clone();
-            let sw = self.scroll.clone();
-            move |ev| {
-
-                println!("End {:?}", ev);
-                let has_hbar = sw.allocation().width != stack.allocation().width;
-
-                // Automatically handled at edge_overshoot
-                if has_hbar {
-                    return;
-                }
-
-                // let s = sw.hscrollbar().unwrap().downcast_ref::<Scrollbar>().unwrap();
-                println!("Scroll end: {}", has_hbar );
-
-                let mut trajx = trajx.borrow_mut();
-                if let (Some(fst), Some(lst)) = (trajx.first(), trajx.last()) {
-                    let dx = *lst - *fst;
-                    let tl = trajx.len();
-                    println!("traj len: {tl}, scroll: {dx}");
-
-                    // Move to next page
-                    if dx < 0.0 {
-                        stack.set_transition_type(StackTransitionType::SlideLeft);
-                        stack.set_visible_child_name("right");
-                    } else {
-                        // Move to prev page
-                        stack.set_transition_type(StackTransitionType::SlideRight);
-                        stack.set_visible_child_name("left");
-                    }
-                }
-                trajx.clear();
-            }
-        });*/
-
-        stack.add_named(&da1, Some("left"));
-        stack.add_named(&da2, Some("right"));
-        for (da_pos, da) in [(0, &da1), (1, &da2)] {
-            da.set_draw_func({
-                let zoom_action = zoom_action.clone();
-                let doc = doc.clone();
-                let curr_page = curr_page.clone();
-                move |da, ctx, _, _| {
-                    let cp = curr_page.borrow();
-                    let doc = doc.borrow();
-                    if let Some(doc) = &*doc {
-                        if let Some(page) = doc.page(*cp as i32) {
-                            crate::adjust_dimension_for_page(da, zoom_action.clone(), &page);
-                            crate::draw_page_content(da, ctx, &zoom_action.clone(), &page, true);
-                        } else {
-                            eprintln!("No page {} at draw", *cp);
-                        }
-                    } else {
-                        eprintln!("No doc at draw");
-                    }
-                }
-            });
-        }
-
-        crate::configure_da_for_doc(&da1);
-        crate::configure_da_for_doc(&da2);
-
-        scroll.set_child(Some(&stack));
-        let bar = ActionBar::new();
-        bar.set_height_request(48);
-        bar.set_revealed(true);
-        let bar_lbl = Label::new(Some("Unsaved changes must be committed to file before typesetting"));
-        let bar_bx = Box::new(Orientation::Horizontal, 0);
-        bar_bx.append(&bar_lbl);
-        bar_bx.set_hexpand(true);
-        bar_bx.set_halign(Align::Fill);
-        let close_btn = Button::from_icon_name("window-close-symbolic");
-        close_btn.connect_clicked({
-            let bar = bar.clone();
-            move |_| {
-                if bar.is_revealed() {
-                    bar.set_revealed(false);
-                }
-            }
-        });
-        bar_bx.append(&close_btn);
-        close_btn.style_context().add_class("flat");
-        close_btn.set_hexpand(true);
-        close_btn.set_halign(Align::End);
-        bar_lbl.set_hexpand(true);
-        bar_lbl.set_justify(Justification::Left);
-        bar_lbl.set_halign(Align::Start);
-
-        bar.set_center_widget(Some(&bar_bx));
-        let bx = Box::new(Orientation::Vertical, 0);
-        bx.append(&scroll);
-        bx.append(&bar);
-
-        Self { scroll, das, pages_bx, doc, da1, da2, curr_page, stack, turn_action, bar, bar_lbl, bx }
-    }
-
-    pub fn update_contiguous(&self, doc : &poppler::Document, zoom_action : &gio::SimpleAction) {
-        self.turn_action.set_state(&(0i32).to_variant());
-        self.turn_action.activate(None);
-        {
-            self.das.borrow_mut().clear();
-        }
-        self.clear_pages();
-        for page_ix in 0..doc.n_pages() {
-            let da = DrawingArea::new();
-            // let zoom = zoom.clone();
-            crate::draw_page_at_area(doc, page_ix, &da, zoom_action);
-            self.pages_bx.append(&da);
-            self.das.borrow_mut().push(da);
-        }
-        self.doc.replace(Some(doc.clone()));
-    }
-
-    pub fn update(&self, doc : &poppler::Document, zoom_action : &gio::SimpleAction) {
-        self.turn_action.set_state(&(0i32).to_variant());
-        self.turn_action.activate(None);
-        // crate::draw_page_at_area(doc, 0, &self.da1, zoom_action);
-        // if doc.n_pages() > 1 {
-        //    crate::draw_page_at_area(doc, 1, &self.da2, zoom_action);
-        // }
-        self.doc.replace(Some(doc.clone()));
-        {
-            *(self.curr_page.borrow_mut()) = 0;
-        }
-        self.da1.queue_draw();
-        self.da2.queue_draw();
-        self.stack.set_visible_child_name("left");
-    }
-
-}
-
 const BAR_WHITE_CSS : &str = r#"
-infobar { background-color : #EBEBEB; border-top : 1px solid #F0F0F0; }
+actionbar.preview-bar > revealer > box { background-color : #EBEBEB; border-top : 1px solid #F0F0F0; }
 "#;
 
 const BAR_DARK_CSS : &str = r#"
-infobar { background-color : #303030; border-top : 1px solid #454545; }
+actionbar.preview-bar > revealer > box { background-color : #303030; border-top : 1px solid #454545; }
 "#;
 
-fn add_bar_css(bar : &InfoBar) {
-    let provider = CssProvider::new();
-    if libadwaita::StyleManager::default().is_dark() {
-        provider.load_from_data(BAR_DARK_CSS.as_bytes());
-    } else {
-        provider.load_from_data(BAR_WHITE_CSS.as_bytes());
+// Styles the preview bar for the current application style, and again whenever it changes.
+// The bar background is drawn by its inner box, so the style is set for the whole display
+// (once for all tabs).
+fn add_bar_css(bar : &ActionBar) {
+    thread_local! {
+        static BAR_PROVIDER : RefCell<Option<CssProvider>> = RefCell::new(None);
     }
-    let ctx = bar.style_context();
-    ctx.add_provider(&provider,800);
+    bar.style_context().add_class("preview-bar");
+    if BAR_PROVIDER.with(|p| p.borrow().is_some() ) {
+        return;
+    }
+    let Some(display) = gdk::Display::default() else { return };
+    let provider = CssProvider::new();
+    let load = |provider : &CssProvider, dark : bool| {
+        provider.load_from_data(if dark { BAR_DARK_CSS.as_bytes() } else { BAR_WHITE_CSS.as_bytes() });
+    };
+    let style_manager = libadwaita::StyleManager::default();
+    load(&provider, style_manager.is_dark());
+    style_manager.connect_dark_notify({
+        let provider = provider.clone();
+        move |manager| load(&provider, manager.is_dark())
+    });
+    StyleContext::add_provider_for_display(&display, &provider, 800);
+    BAR_PROVIDER.with(|p| p.replace(Some(provider)) );
 }
 
 /*// #[cfg(feature="poppler")]
@@ -1609,11 +1383,26 @@ impl React<FileManager> for SaveDialog {
 }
 
 
+// Height (in pixels) of the first-page thumbnails shown at the recent list.
+const RECENT_THUMBNAIL_HEIGHT : i32 = 64;
+
 #[derive(Debug, Clone)]
 pub struct RecentList {
     pub open_btn : Button,
     pub list : ListBox,
     pub bx : Box,
+    search_entry : SearchEntry,
+    clear_btn : Button,
+
+    // Paths of the rows currently listed, in the same order.
+    paths : Rc<RefCell<Vec<String>>>,
+
+    // Document open at this window, and whether its thumbnail should be saved when
+    // its first page is next rendered.
+    current : Rc<RefCell<Option<String>>>,
+
+    thumbnail_pending : Rc<Cell<bool>>,
+
     state : PapersState
 }
 
@@ -1622,13 +1411,19 @@ impl RecentList {
     pub fn build(state : PapersState) -> Self {
         let open_btn = Button::from_icon_name("document-open-symbolic");
         open_btn.style_context().add_class("flat");
+        open_btn.set_tooltip_text(Some("Open"));
+        let clear_btn = Button::from_icon_name("edit-clear-all-symbolic");
+        clear_btn.style_context().add_class("flat");
+        clear_btn.set_tooltip_text(Some("Clear unpinned files"));
+        let search_entry = SearchEntry::new();
+        search_entry.set_placeholder_text(Some("Search recent files"));
         let list = ListBox::new();
         set_margins(&list, 1, 1);
         list.style_context().add_class("boxed-list");
         let scroll = ScrolledWindow::new();
         scroll.set_child(Some(&list));
         scroll.set_width_request(560);
-        scroll.set_height_request(442);
+        scroll.set_height_request(402);
         scroll.set_has_frame(false);
         list.set_activate_on_single_click(true);
         list.set_show_separators(true);
@@ -1637,31 +1432,169 @@ impl RecentList {
         let title = title_label("Recent");
         let title_bx = Box::new(Orientation::Horizontal, 0);
         title_bx.append(&title);
-        title_bx.append(&open_btn);
+        let btn_bx = Box::new(Orientation::Horizontal, 0);
+        btn_bx.append(&clear_btn);
+        btn_bx.append(&open_btn);
+        btn_bx.set_halign(Align::End);
+        btn_bx.set_hexpand(true);
+        title_bx.append(&btn_bx);
         title_bx.set_hexpand(true);
         title.set_halign(Align::Start);
-        open_btn.set_halign(Align::End);
-        open_btn.set_hexpand(true);
         bx.append(&title_bx);
+        bx.append(&search_entry);
         bx.append(&scroll);
         bx.set_valign(Align::Center);
         bx.set_hexpand(true);
         bx.set_margin_start(128);
-        Self { open_btn, list, bx, state }
+        let recent = Self {
+            open_btn,
+            list,
+            bx,
+            search_entry,
+            clear_btn,
+            paths : Rc::new(RefCell::new(Vec::new())),
+            current : Rc::new(RefCell::new(None)),
+            thumbnail_pending : Rc::new(Cell::new(false)),
+            state
+        };
+        recent.search_entry.connect_search_changed({
+            let recent = recent.clone();
+            move |_| recent.refresh()
+        });
+        recent.clear_btn.connect_clicked({
+            let recent = recent.clone();
+            move |_| {
+                recent.state.borrow_mut().clear_recent();
+                recent.refresh();
+            }
+        });
+        recent
     }
 
-    pub fn add_row(&self, path : &str, begin : bool) {
+    /// Rebuilds the rows from the recent files at the user state, keeping the ones
+    /// matching the search entry.
+    pub fn refresh(&self) {
+        while let Some(row) = self.list.row_at_index(0) {
+            self.list.remove(&row);
+        }
+        let search = self.search_entry.text().to_string();
+        let recent : Vec<_> = self.state.borrow().recent_sorted()
+            .into_iter()
+            .filter(|r| r.matches(&search) )
+            .collect();
+        let mut paths = self.paths.borrow_mut();
+        paths.clear();
+        for file in recent {
+            self.list.append(&self.build_row(&file));
+            paths.push(file.path);
+        }
+    }
+
+    /// Path of the file listed at the given row index.
+    pub fn path_at(&self, ix : i32) -> Option<String> {
+        self.paths.borrow().get(usize::try_from(ix).ok()?).cloned()
+    }
+
+    fn build_row(&self, file : &crate::state::RecentFile) -> ListBoxRow {
         let row = ListBoxRow::new();
-        row.set_height_request(64);
-        let lbl = PackedImageLabel::build("emblem-documents-symbolic", path);
-        row.set_child(Some(&lbl.bx));
+        row.set_height_request(72);
         row.set_selectable(false);
-        row.set_activatable(true);
-        if begin {
-            self.list.prepend(&row);
+        let exists = file.exists();
+        row.set_activatable(exists);
+
+        let bx = Box::new(Orientation::Horizontal, 12);
+        set_margins(&bx, 6, 6);
+        let thumb = crate::state::thumbnail_path(&file.path).filter(|p| exists && p.exists() );
+        if let Some(thumb) = thumb {
+            let pic = Picture::for_filename(&thumb);
+            pic.set_can_shrink(true);
+            pic.set_size_request(RECENT_THUMBNAIL_HEIGHT * 3 / 4, RECENT_THUMBNAIL_HEIGHT);
+            bx.append(&pic);
         } else {
-            self.list.append(&row);
+            let icon = if exists { "emblem-documents-symbolic" } else { "dialog-warning-symbolic" };
+            let img = Image::from_icon_name(icon);
+            img.set_pixel_size(32);
+            img.set_size_request(RECENT_THUMBNAIL_HEIGHT * 3 / 4, RECENT_THUMBNAIL_HEIGHT);
+            bx.append(&img);
+        }
+
+        let name = file.title.clone().unwrap_or_else(|| {
+            Path::new(&file.path).file_name().map(|f| f.to_string_lossy().to_string() ).unwrap_or(file.path.clone())
+        });
+        let title_lbl = Label::new(Some(&name));
+        title_lbl.set_halign(Align::Start);
+        title_lbl.set_ellipsize(pango::EllipsizeMode::End);
+        let path_lbl = Label::new(Some(&file.path));
+        path_lbl.set_halign(Align::Start);
+        path_lbl.set_ellipsize(pango::EllipsizeMode::Start);
+        path_lbl.style_context().add_class("dim-label");
+        let info = if !exists {
+            String::from("File not found")
+        } else if file.opened > 0 {
+            glib::DateTime::from_unix_local(file.opened as i64).ok()
+                .and_then(|dt| dt.format("%Y-%m-%d %H:%M").ok() )
+                .map(|s| s.to_string() )
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let info_lbl = Label::new(Some(&info));
+        info_lbl.set_halign(Align::Start);
+        info_lbl.style_context().add_class("dim-label");
+        info_lbl.style_context().add_class("caption");
+        let lbl_bx = Box::new(Orientation::Vertical, 2);
+        lbl_bx.set_valign(Align::Center);
+        lbl_bx.set_hexpand(true);
+        lbl_bx.append(&title_lbl);
+        lbl_bx.append(&path_lbl);
+        lbl_bx.append(&info_lbl);
+        bx.append(&lbl_bx);
+        if !exists {
+            lbl_bx.style_context().add_class("dim-label");
+            row.set_tooltip_text(Some("File not found"));
         }
+
+        let pin_btn = ToggleButton::new();
+        pin_btn.set_icon_name("view-pin-symbolic");
+        pin_btn.style_context().add_class("flat");
+        pin_btn.set_valign(Align::Center);
+        pin_btn.set_active(file.pinned);
+        pin_btn.set_tooltip_text(Some(if file.pinned { "Unpin" } else { "Pin" }));
+        pin_btn.connect_toggled({
+            let recent = self.clone();
+            let path = file.path.clone();
+            move |btn| {
+                recent.state.borrow_mut().set_pinned(&path, btn.is_active());
+
+                // Rebuilding the list removes this button, so it is done after the handler returns.
+                let recent = recent.clone();
+                glib::idle_add_local_once(move || recent.refresh() );
+            }
+        });
+        let remove_btn = Button::from_icon_name("window-close-symbolic");
+        remove_btn.style_context().add_class("flat");
+        remove_btn.set_valign(Align::Center);
+        remove_btn.set_tooltip_text(Some("Remove from list"));
+        remove_btn.connect_clicked({
+            let recent = self.clone();
+            let path = file.path.clone();
+            move |_| {
+                recent.state.borrow_mut().remove_recent(&path);
+                let recent = recent.clone();
+                glib::idle_add_local_once(move || recent.refresh() );
+            }
+        });
+        bx.append(&pin_btn);
+        bx.append(&remove_btn);
+        row.set_child(Some(&bx));
+        row
+    }
+
+    fn push(&self, path : &str, content : &str) {
+        self.state.borrow_mut().push_recent(path, crate::typst_tools::doc_title(content));
+        self.current.replace(Some(path.to_string()));
+        self.thumbnail_pending.set(true);
+        self.refresh();
     }
 
 }
@@ -1670,17 +1603,39 @@ impl React<FileManager> for RecentList {
 
     fn react(&self, manager : &FileManager) {
         let recent = self.clone();
-        let state = self.state.clone();
-        manager.connect_opened(move |(path, _)| {
-            if state.borrow_mut().push_if_not_present(&path) {
-                recent.add_row(&path, true);
-            }
+        manager.connect_opened(move |(path, content)| {
+            recent.push(&path, &content);
         });
         let recent = self.clone();
-        let state = self.state.clone();
         manager.connect_save(move |path| {
-            if state.borrow_mut().push_if_not_present(&path) {
-                recent.add_row(&path, true);
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            recent.push(&path, &content);
+        });
+        let recent = self.clone();
+        manager.connect_new(move |_| {
+            recent.current.replace(None);
+            recent.thumbnail_pending.set(false);
+            recent.refresh();
+        });
+    }
+
+}
+
+impl React<PdfViewer> for RecentList {
+
+    // The thumbnail of the open document is refreshed after it is opened or saved.
+    fn react(&self, viewer : &PdfViewer) {
+        let current = self.current.clone();
+        let pending = self.thumbnail_pending.clone();
+        let viewer_c = viewer.clone();
+        viewer.renderer.connect_rendered(move |page| {
+            if page != 0 || !pending.replace(false) {
+                return;
+            }
+            if let Some(thumb) = current.borrow().as_ref().and_then(|p| crate::state::thumbnail_path(p) ) {
+                if !viewer_c.save_thumbnail(RECENT_THUMBNAIL_HEIGHT, &thumb) {
+                    log::warn!("Unable to save thumbnail to {}", thumb.display());
+                }
             }
         });
     }
@@ -0,0 +1,203 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use libadwaita::ActionRow;
+use libadwaita::prelude::*;
+use crate::settings::{self, Preferences};
+
+/*
+Dialog editing the user preferences (see crate::settings). Widgets are bound to the GSettings
+keys, so changes are written as they are made, and the editors and autosaves of all open tabs
+follow them. When the schema is not installed, the defaults are shown but cannot be edited.
+*/
+
+#[derive(Debug, Clone)]
+pub struct PreferencesDialog {
+    pub dialog : Dialog,
+    pub action : gio::SimpleAction
+}
+
+fn row(title : &str, subtitle : &str, widget : &impl IsA<Widget>) -> ActionRow {
+    let row = ActionRow::builder().title(title).subtitle(subtitle).build();
+    widget.set_valign(Align::Center);
+    row.add_suffix(widget);
+    row.set_activatable_widget(Some(widget));
+    row
+}
+
+fn group(title : &str, rows : &[ActionRow]) -> Box {
+    let bx = Box::new(Orientation::Vertical, 6);
+    let lbl = Label::new(Some(title));
+    lbl.set_halign(Align::Start);
+    lbl.style_context().add_class("heading");
+    let list = ListBox::new();
+    list.set_selection_mode(SelectionMode::None);
+    list.style_context().add_class("boxed-list");
+    for row in rows {
+        list.append(row);
+    }
+    bx.append(&lbl);
+    bx.append(&list);
+    bx
+}
+
+fn spin(min : f64, max : f64, step : f64, digits : u32) -> SpinButton {
+    let spin = SpinButton::with_range(min, max, step);
+    spin.set_digits(digits);
+    spin
+}
+
+fn combo(items : &[(&str, &str)]) -> ComboBoxText {
+    let combo = ComboBoxText::new();
+    for (id, label) in items {
+        combo.append(Some(id), label);
+    }
+    combo
+}
+
+fn color_scheme(style : &str) -> libadwaita::ColorScheme {
+    match style {
+        "light" => libadwaita::ColorScheme::ForceLight,
+        "dark" => libadwaita::ColorScheme::ForceDark,
+        _ => libadwaita::ColorScheme::Default
+    }
+}
+
+/// Sets the application style (light, dark or following the system) from the preferences,
+/// and updates it when the preference changes.
+pub fn follow_style_preference() {
+    let style_manager = libadwaita::StyleManager::default();
+    style_manager.set_color_scheme(color_scheme(&Preferences::current().style));
+    settings::connect_changed(move |prefs| {
+        style_manager.set_color_scheme(color_scheme(&prefs.style));
+        Continue(true)
+    });
+}
+
+impl PreferencesDialog {
+
+    pub fn build() -> Self {
+        let dialog = Dialog::new();
+        dialog.set_title(Some("Preferences"));
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(560, 640);
+
+        let prefs = Preferences::current();
+
+        let font_btn = FontButton::new();
+        font_btn.set_use_font(true);
+        font_btn.set_font(&format!("{} {}", prefs.editor_font, prefs.editor_font_size));
+        let scheme_manager = sourceview5::StyleSchemeManager::default();
+        let scheme_combo = ComboBoxText::new();
+        for id in scheme_manager.scheme_ids() {
+            let name = scheme_manager.scheme(&id).map(|s| s.name().to_string() ).unwrap_or(id.to_string());
+            scheme_combo.append(Some(&id), &name);
+        }
+        scheme_combo.set_active_id(Some(&prefs.color_scheme));
+        let style_combo = combo(&[("system", "Follow system"), ("light", "Light"), ("dark", "Dark")]);
+        style_combo.set_active_id(Some(&prefs.style));
+        let filter_combo = combo(&[("none", "Normal"), ("invert", "Inverted"), ("sepia", "Sepia")]);
+        filter_combo.set_active_id(Some(&prefs.page_filter));
+        let line_numbers_switch = Switch::new();
+        line_numbers_switch.set_active(prefs.show_line_numbers);
+        let wrap_combo = combo(&[("none", "None"), ("char", "Characters"), ("word", "Words"), ("word-char", "Words and characters")]);
+        wrap_combo.set_active_id(Some(&prefs.wrap_mode));
+        let tab_spin = spin(1.0, 16.0, 1.0, 0);
+        tab_spin.set_value(prefs.tab_width as f64);
+        let format_spin = spin(40.0, 200.0, 1.0, 0);
+        format_spin.set_value(prefs.format_width as f64);
+
+        let autosave_spin = spin(5.0, 3600.0, 5.0, 0);
+        autosave_spin.set_value(prefs.autosave_interval as f64);
+        let live_switch = Switch::new();
+        live_switch.set_active(prefs.live_preview);
+        let papers : Vec<_> = settings::PAPERS.iter().map(|p| (*p, *p) ).collect();
+        let paper_combo = combo(&papers[..]);
+        paper_combo.set_active_id(Some(&prefs.default_paper));
+        let zoom_spin = spin(1.0, 5.0, crate::ui::ZOOM_SCALE_INCREMENT, 1);
+        zoom_spin.set_value(prefs.default_zoom);
+
+        let appearance_group = group("Appearance", &[
+            row("Style", "Light or dark application style", &style_combo),
+            row("Color scheme", "Syntax highlighting colors (its dark variant is used in dark style)", &scheme_combo),
+            row("Page colors", "Colors of the previewed pages, for reading at night", &filter_combo)
+        ]);
+        let editor_group = group("Editor", &[
+            row("Font", "Font family and size of the source", &font_btn),
+            row("Line numbers", "Show line numbers at the gutter", &line_numbers_switch),
+            row("Wrap mode", "How long lines are broken", &wrap_combo),
+            row("Tab width", "Spaces for each indentation level", &tab_spin),
+            row("Format width", "Line width of code when formatting the document", &format_spin)
+        ]);
+        let document_group = group("Documents", &[
+            row("Autosave interval", "Seconds between writes of unsaved changes", &autosave_spin),
+            row("Live preview", "Typeset documents when they are opened or saved", &live_switch),
+            row("Default paper size", "Suggested when creating documents from templates", &paper_combo),
+            row("Default zoom", "Zoom of the preview for new windows", &zoom_spin)
+        ]);
+
+        let bx = Box::new(Orientation::Vertical, 24);
+        super::set_all_margins(&bx, 18);
+        bx.append(&appearance_group);
+        bx.append(&editor_group);
+        bx.append(&document_group);
+
+        match settings::settings() {
+            Some(settings) => {
+                settings.bind("style", &style_combo, "active-id").build();
+                settings.bind("color-scheme", &scheme_combo, "active-id").build();
+                settings.bind("page-filter", &filter_combo, "active-id").build();
+                settings.bind("show-line-numbers", &line_numbers_switch, "active").build();
+                settings.bind("wrap-mode", &wrap_combo, "active-id").build();
+                settings.bind("tab-width", &tab_spin, "value").build();
+                settings.bind("format-width", &format_spin, "value").build();
+                settings.bind("autosave-interval", &autosave_spin, "value").build();
+                settings.bind("live-preview", &live_switch, "active").build();
+                settings.bind("default-paper", &paper_combo, "active-id").build();
+                settings.bind("default-zoom", &zoom_spin, "value").build();
+
+                // The font button holds family and size together, which are kept at separate keys.
+                font_btn.connect_font_set({
+                    let settings = settings.clone();
+                    move |btn| {
+                        let Some(desc) = btn.font_desc() else { return };
+                        if let Some(family) = desc.family() {
+                            let _ = settings.set_string("editor-font", &family);
+                        }
+                        if desc.size() > 0 {
+                            let _ = settings.set_int("editor-font-size", desc.size() / pango::SCALE);
+                        }
+                    }
+                });
+            },
+            None => {
+                let lbl = Label::new(Some("Preferences cannot be changed because the settings schema is not installed."));
+                lbl.set_wrap(true);
+                lbl.style_context().add_class("dim-label");
+                appearance_group.set_sensitive(false);
+                editor_group.set_sensitive(false);
+                document_group.set_sensitive(false);
+                bx.prepend(&lbl);
+            }
+        }
+
+        let scroll = ScrolledWindow::new();
+        scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        scroll.set_child(Some(&bx));
+        dialog.set_child(Some(&scroll));
+
+        let action = gio::SimpleAction::new("preferences", None);
+        action.connect_activate({
+            let dialog = dialog.clone();
+            move |_, _| {
+                dialog.show();
+            }
+        });
+        Self { dialog, action }
+    }
+
+}
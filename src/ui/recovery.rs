@@ -0,0 +1,121 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use stateful::Callbacks;
+use crate::recovery::{self, RecoveryEntry};
+
+// Prompt at the top of the start screen, listing the documents left unsaved by
+// a previous session that ended abruptly.
+#[derive(Debug, Clone)]
+pub struct RecoveryList {
+    pub revealer : Revealer,
+    list : ListBox,
+    on_recover : Callbacks<RecoveryEntry>
+}
+
+impl RecoveryList {
+
+    pub fn build() -> Self {
+        let title = Label::new(Some("Some documents were not saved when Drafts last closed"));
+        title.set_halign(Align::Start);
+        title.style_context().add_class("heading");
+        let list = ListBox::new();
+        list.set_selection_mode(SelectionMode::None);
+        list.style_context().add_class("boxed-list");
+        let bx = Box::new(Orientation::Vertical, 12);
+        bx.set_halign(Align::Center);
+        bx.set_width_request(560);
+        bx.set_margin_top(24);
+        bx.append(&title);
+        bx.append(&list);
+        let revealer = Revealer::new();
+        revealer.set_transition_type(RevealerTransitionType::SlideDown);
+        revealer.set_child(Some(&bx));
+        let recovery = Self { revealer, list, on_recover : Default::default() };
+        recovery.update();
+        recovery
+    }
+
+    /// Lists the recovery entries again, showing the prompt only if there is any.
+    pub fn update(&self) {
+        while let Some(row) = self.list.row_at_index(0) {
+            self.list.remove(&row);
+        }
+        let entries = recovery::list_entries();
+        for entry in entries.iter() {
+            self.list.append(&self.entry_row(entry.clone()));
+        }
+        self.revealer.set_reveal_child(!entries.is_empty());
+    }
+
+    fn entry_row(&self, entry : RecoveryEntry) -> ListBoxRow {
+        let name = entry.path.as_ref()
+            .and_then(|p| std::path::Path::new(p).file_name().map(|f| f.to_string_lossy().to_string() ) )
+            .unwrap_or(String::from("Untitled document"));
+        let time = glib::DateTime::from_unix_local(entry.time as i64).ok()
+            .and_then(|dt| dt.format("%Y-%m-%d %H:%M").ok() )
+            .map(|s| s.to_string() )
+            .unwrap_or_default();
+        let name_lbl = Label::new(Some(&name));
+        name_lbl.set_halign(Align::Start);
+        let sub = match &entry.path {
+            Some(path) => format!("{} · {}", path, time),
+            None => time
+        };
+        let sub_lbl = Label::new(Some(&sub));
+        sub_lbl.set_halign(Align::Start);
+        sub_lbl.set_ellipsize(pango::EllipsizeMode::Start);
+        sub_lbl.style_context().add_class("dim-label");
+        let lbl_bx = Box::new(Orientation::Vertical, 2);
+        lbl_bx.set_hexpand(true);
+        lbl_bx.append(&name_lbl);
+        lbl_bx.append(&sub_lbl);
+
+        let recover_btn = Button::with_label("Recover");
+        recover_btn.style_context().add_class("suggested-action");
+        let discard_btn = Button::with_label("Discard");
+        discard_btn.style_context().add_class("flat");
+        for btn in [&recover_btn, &discard_btn] {
+            btn.set_valign(Align::Center);
+        }
+        let bx = Box::new(Orientation::Horizontal, 6);
+        super::set_margins(&bx, 12, 6);
+        bx.append(&lbl_bx);
+        bx.append(&discard_btn);
+        bx.append(&recover_btn);
+        let row = ListBoxRow::new();
+        row.set_child(Some(&bx));
+        row.set_activatable(false);
+
+        discard_btn.connect_clicked({
+            let recovery = self.clone();
+            let id = entry.id.clone();
+            move |_| {
+                recovery::remove_entry(&id);
+                recovery.update();
+            }
+        });
+        recover_btn.connect_clicked({
+            let recovery = self.clone();
+            move |_| {
+                // The new session autosaves the recovered content under its own entry.
+                recovery::remove_entry(&entry.id);
+                recovery.update();
+                recovery.on_recover.call(entry.clone());
+            }
+        });
+        row
+    }
+
+    pub fn connect_recover<F>(&self, f : F)
+    where
+        F : Fn(RecoveryEntry) + 'static
+    {
+        self.on_recover.bind(f);
+    }
+
+}
@@ -0,0 +1,603 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use stateful::{React, Callbacks};
+use crate::manager::FileManager;
+use crate::review::{self, Review, Change, ChangeKind, Edit};
+
+/*
+Review mode. While changes are tracked, the edits to the buffer are recorded at the review (see
+crate::review); otherwise the recorded changes and comments are only moved along with the text.
+Changes and comments are shown as tags at the editor (with the details at tooltips), and listed
+at the review dialog, where they can be accepted, rejected, replied to or resolved.
+*/
+
+const INSERTION_TAG : &'static str = "review-insertion";
+
+const DELETION_TAG : &'static str = "review-deletion";
+
+const COMMENT_TAG : &'static str = "review-comment";
+
+#[derive(Debug, Clone)]
+pub struct ReviewPanel {
+    pub dialog : Dialog,
+
+    // Shows the dialog.
+    pub action : gio::SimpleAction,
+
+    pub track_action : gio::SimpleAction,
+
+    // Whether the changes are marked at the typeset preview.
+    pub preview_action : gio::SimpleAction,
+
+    view : sourceview5::View,
+    review : Rc<RefCell<Review>>,
+    path : Rc<RefCell<Option<PathBuf>>>,
+
+    // Set while the panel edits the buffer itself (when rejecting changes), so the edit is not tracked.
+    applying : Rc<RefCell<bool>>,
+
+    // Whether the tags and lists are already scheduled to be updated.
+    update_queued : Rc<RefCell<bool>>,
+
+    track_btn : CheckButton,
+    preview_btn : CheckButton,
+    change_list : ListBox,
+    comment_list : ListBox,
+    comment_entry : Entry,
+    on_preview_changed : Callbacks<Vec<Change>>
+}
+
+fn rgba(color : &str) -> gdk::RGBA {
+    gdk::RGBA::parse(color).unwrap_or(gdk::RGBA::new(0.0, 0.0, 0.0, 1.0))
+}
+
+fn configure_tags(buffer : &TextBuffer) {
+    let insertion = TextTag::new(Some(INSERTION_TAG));
+    insertion.set_background_rgba(Some(&rgba("rgba(38, 162, 105, 0.2)")));
+    insertion.set_underline(pango::Underline::Single);
+    let deletion = TextTag::new(Some(DELETION_TAG));
+    deletion.set_underline(pango::Underline::Error);
+    deletion.set_underline_rgba(Some(&rgba("#c01c28")));
+    let comment = TextTag::new(Some(COMMENT_TAG));
+    comment.set_background_rgba(Some(&rgba("rgba(246, 211, 45, 0.35)")));
+    for tag in [insertion, deletion, comment] {
+        buffer.tag_table().add(&tag);
+    }
+}
+
+fn excerpt(txt : &str) -> String {
+    let txt = txt.replace('\n', " ");
+    if txt.chars().count() > 60 {
+        format!("{}…", txt.chars().take(60).collect::<String>())
+    } else {
+        txt
+    }
+}
+
+fn buffer_text(buffer : &TextBuffer, start : usize, end : usize) -> String {
+    buffer.text(&buffer.iter_at_offset(start as i32), &buffer.iter_at_offset(end as i32), true).to_string()
+}
+
+impl ReviewPanel {
+
+    pub fn build(view : &sourceview5::View) -> Self {
+        let dialog = Dialog::new();
+        dialog.set_title(Some("Review"));
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(420, 600);
+        dialog.set_modal(false);
+
+        let track_btn = CheckButton::with_label("Track changes");
+        let preview_btn = CheckButton::with_label("Show changes in preview");
+        let accept_all_btn = Button::with_label("Accept all");
+        let reject_all_btn = Button::with_label("Reject all");
+        let all_bx = Box::new(Orientation::Horizontal, 6);
+        all_bx.set_halign(Align::End);
+        all_bx.append(&reject_all_btn);
+        all_bx.append(&accept_all_btn);
+
+        let change_list = ListBox::new();
+        change_list.set_selection_mode(SelectionMode::None);
+        change_list.style_context().add_class("boxed-list");
+
+        let comment_entry = Entry::new();
+        comment_entry.set_placeholder_text(Some("Comment on the selected text"));
+        comment_entry.set_hexpand(true);
+        let comment_btn = Button::from_icon_name("mail-send-symbolic");
+        comment_btn.set_tooltip_text(Some("Add comment"));
+        let comment_bx = Box::new(Orientation::Horizontal, 0);
+        comment_bx.style_context().add_class("linked");
+        comment_bx.append(&comment_entry);
+        comment_bx.append(&comment_btn);
+        let comment_list = ListBox::new();
+        comment_list.set_selection_mode(SelectionMode::None);
+        comment_list.style_context().add_class("boxed-list");
+
+        let changes_lbl = Label::new(Some("Changes"));
+        changes_lbl.set_halign(Align::Start);
+        changes_lbl.style_context().add_class("heading");
+        let comments_lbl = Label::new(Some("Comments"));
+        comments_lbl.set_halign(Align::Start);
+        comments_lbl.style_context().add_class("heading");
+
+        let bx = Box::new(Orientation::Vertical, 12);
+        super::set_all_margins(&bx, 12);
+        bx.append(&track_btn);
+        bx.append(&preview_btn);
+        bx.append(&changes_lbl);
+        bx.append(&change_list);
+        bx.append(&all_bx);
+        bx.append(&comments_lbl);
+        bx.append(&comment_bx);
+        bx.append(&comment_list);
+        let scroll = ScrolledWindow::new();
+        scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        scroll.set_vexpand(true);
+        scroll.set_child(Some(&bx));
+        dialog.set_child(Some(&scroll));
+
+        let action = gio::SimpleAction::new("review", None);
+        let track_action = gio::SimpleAction::new_stateful("track_changes", None, &false.to_variant());
+        let preview_action = gio::SimpleAction::new_stateful("review_preview", None, &false.to_variant());
+        for toggle in [&track_action, &preview_action] {
+            toggle.connect_activate(move |action, _| {
+                let active = action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+                action.set_state(&(!active).to_variant());
+            });
+        }
+
+        configure_tags(&view.buffer());
+        view.set_has_tooltip(true);
+
+        let panel = Self {
+            dialog,
+            action,
+            track_action,
+            preview_action,
+            view : view.clone(),
+            review : Default::default(),
+            path : Default::default(),
+            applying : Default::default(),
+            update_queued : Default::default(),
+            track_btn,
+            preview_btn,
+            change_list,
+            comment_list,
+            comment_entry,
+            on_preview_changed : Default::default()
+        };
+
+        panel.action.connect_activate({
+            let panel = panel.clone();
+            move |_, _| {
+                panel.dialog.show();
+            }
+        });
+        for (btn, action) in [(&panel.track_btn, &panel.track_action), (&panel.preview_btn, &panel.preview_action)] {
+            btn.connect_toggled({
+                let action = action.clone();
+                move |btn| {
+                    if action.state().and_then(|s| s.get::<bool>() ) != Some(btn.is_active()) {
+                        action.set_state(&btn.is_active().to_variant());
+                    }
+                }
+            });
+            action.connect_state_notify({
+                let btn = btn.clone();
+                move |action| {
+                    btn.set_active(action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false));
+                }
+            });
+        }
+        panel.preview_action.connect_state_notify({
+            let panel = panel.clone();
+            move |_| {
+                panel.notify_preview();
+            }
+        });
+        accept_all_btn.connect_clicked({
+            let panel = panel.clone();
+            move |_| {
+                panel.review.borrow_mut().changes.clear();
+                panel.queue_update();
+            }
+        });
+        reject_all_btn.connect_clicked({
+            let panel = panel.clone();
+            move |_| {
+                // From the end, so the edits do not move the changes not yet rejected.
+                let mut changes = panel.review.borrow().changes.clone();
+                changes.sort_by(|a, b| b.pos.cmp(&a.pos) );
+                for change in changes {
+                    panel.reject(change.id);
+                }
+            }
+        });
+        let add_comment = Rc::new({
+            let panel = panel.clone();
+            move || {
+                let txt = panel.comment_entry.text().to_string();
+                let Some((start, end)) = panel.view.buffer().selection_bounds() else { return };
+                if txt.trim().is_empty() {
+                    return;
+                }
+                panel.review.borrow_mut().add_comment(start.offset() as usize, end.offset() as usize, &txt, &crate::author());
+                panel.comment_entry.set_text("");
+                panel.queue_update();
+            }
+        });
+        comment_btn.connect_clicked({
+            let add_comment = add_comment.clone();
+            move |_| add_comment()
+        });
+        panel.comment_entry.connect_activate(move |_| add_comment() );
+
+        panel.connect_buffer();
+        panel.view.connect_query_tooltip({
+            let panel = panel.clone();
+            move |view, x, y, _, tooltip| {
+                let (bx, by) = view.window_to_buffer_coords(TextWindowType::Widget, x, y);
+                let Some(iter) = view.iter_at_location(bx, by) else { return false };
+                match panel.describe_at(iter.offset() as usize) {
+                    Some(txt) => {
+                        tooltip.set_text(Some(&txt));
+                        true
+                    },
+                    None => false
+                }
+            }
+        });
+        panel
+    }
+
+    fn is_tracking(&self) -> bool {
+        self.track_action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false)
+    }
+
+    fn connect_buffer(&self) {
+        let buffer = self.view.buffer();
+        buffer.connect_insert_text({
+            let panel = self.clone();
+            move |_, iter, text| {
+                let at = iter.offset() as usize;
+                let mut review = panel.review.borrow_mut();
+                if panel.is_tracking() && !*panel.applying.borrow() {
+                    review.record_insert(at, text, &crate::author());
+                } else {
+                    review.shift_insert(at, text, None);
+                }
+                drop(review);
+                panel.queue_update();
+            }
+        });
+        buffer.connect_delete_range({
+            let panel = self.clone();
+            move |buffer, start, end| {
+                let (from, to) = (start.offset() as usize, end.offset() as usize);
+                let mut review = panel.review.borrow_mut();
+                if panel.is_tracking() && !*panel.applying.borrow() {
+                    let removed = buffer.text(start, end, true).to_string();
+                    review.record_delete(from, &removed, &crate::author());
+                } else {
+                    review.shift_delete(from, to);
+                }
+                drop(review);
+                panel.queue_update();
+            }
+        });
+    }
+
+    // Tooltip text for the changes and comments at the position.
+    fn describe_at(&self, offset : usize) -> Option<String> {
+        let review = self.review.borrow();
+        let mut lines = Vec::new();
+        for c in review.changes.iter() {
+            match c.kind {
+                ChangeKind::Insertion if c.pos <= offset && offset < c.end() => {
+                    lines.push(format!("Inserted by {}", c.author));
+                },
+                ChangeKind::Deletion if c.pos == offset || (c.pos == offset + 1 && offset + 1 == self.view.buffer().char_count() as usize) => {
+                    lines.push(format!("Deleted by {}: “{}”", c.author, excerpt(&c.text)));
+                },
+                _ => { }
+            }
+        }
+        for comment in review.comments.iter().filter(|c| !c.resolved && c.start <= offset && offset < c.end ) {
+            for note in comment.notes.iter() {
+                lines.push(format!("{}: {}", note.author, note.text));
+            }
+        }
+        if lines.is_empty() { None } else { Some(lines.join("\n")) }
+    }
+
+    // Updates the tags and lists once the current buffer edit is done.
+    fn queue_update(&self) {
+        if *self.update_queued.borrow() {
+            return;
+        }
+        self.update_queued.replace(true);
+        glib::idle_add_local_once({
+            let panel = self.clone();
+            move || {
+                panel.update_queued.replace(false);
+                panel.update();
+            }
+        });
+    }
+
+    fn update(&self) {
+        self.update_tags();
+        self.update_lists();
+        self.notify_preview();
+    }
+
+    fn update_tags(&self) {
+        let buffer = self.view.buffer();
+        let (start, end) = (buffer.start_iter(), buffer.end_iter());
+        for tag in [INSERTION_TAG, DELETION_TAG, COMMENT_TAG] {
+            buffer.remove_tag_by_name(tag, &start, &end);
+        }
+        let n = buffer.char_count() as usize;
+        let review = self.review.borrow();
+        for comment in review.comments.iter().filter(|c| !c.resolved ) {
+            let (from, to) = (comment.start.min(n), comment.end.min(n));
+            buffer.apply_tag_by_name(COMMENT_TAG, &buffer.iter_at_offset(from as i32), &buffer.iter_at_offset(to as i32));
+        }
+        for c in review.changes.iter() {
+            let (from, to) = match c.kind {
+                ChangeKind::Insertion => (c.pos, c.end()),
+
+                // Deleted text is not at the buffer, so the character after it (or before
+                // it, at the end of the document) is marked.
+                ChangeKind::Deletion if c.pos < n => (c.pos, c.pos + 1),
+                ChangeKind::Deletion => (n.saturating_sub(1), n)
+            };
+            let tag = if c.kind == ChangeKind::Insertion { INSERTION_TAG } else { DELETION_TAG };
+            buffer.apply_tag_by_name(tag, &buffer.iter_at_offset(from.min(n) as i32), &buffer.iter_at_offset(to.min(n) as i32));
+        }
+    }
+
+    fn update_lists(&self) {
+        for list in [&self.change_list, &self.comment_list] {
+            while let Some(row) = list.row_at_index(0) {
+                list.remove(&row);
+            }
+        }
+        let review = self.review.borrow().clone();
+        let mut changes = review.changes.clone();
+        changes.sort_by_key(|c| c.pos );
+        for change in changes {
+            self.change_list.append(&self.change_row(&change));
+        }
+        for comment in review.comments.iter() {
+            self.comment_list.append(&self.comment_row(comment));
+        }
+    }
+
+    fn change_row(&self, change : &Change) -> ListBoxRow {
+        let buffer = self.view.buffer();
+        let (kind, txt) = match change.kind {
+            ChangeKind::Insertion => ("Inserted", buffer_text(&buffer, change.pos, change.end())),
+            ChangeKind::Deletion => ("Deleted", change.text.clone())
+        };
+        let title_lbl = Label::new(None);
+        title_lbl.set_markup(&format!("<b>{}</b> by {}", kind, glib::markup_escape_text(&change.author)));
+        title_lbl.set_halign(Align::Start);
+        let txt_lbl = Label::new(Some(&excerpt(&txt)));
+        txt_lbl.set_halign(Align::Start);
+        txt_lbl.set_ellipsize(pango::EllipsizeMode::End);
+        txt_lbl.style_context().add_class("dim-label");
+        let lbl_bx = Box::new(Orientation::Vertical, 2);
+        lbl_bx.set_hexpand(true);
+        lbl_bx.append(&title_lbl);
+        lbl_bx.append(&txt_lbl);
+
+        let accept_btn = Button::from_icon_name("object-select-symbolic");
+        accept_btn.set_tooltip_text(Some("Accept"));
+        let reject_btn = Button::from_icon_name("edit-undo-symbolic");
+        reject_btn.set_tooltip_text(Some("Reject"));
+        let show_btn = Button::from_icon_name("find-location-symbolic");
+        show_btn.set_tooltip_text(Some("Show at the editor"));
+        for btn in [&show_btn, &reject_btn, &accept_btn] {
+            btn.style_context().add_class("flat");
+            btn.set_valign(Align::Center);
+        }
+        let bx = Box::new(Orientation::Horizontal, 6);
+        super::set_margins(&bx, 12, 6);
+        bx.append(&lbl_bx);
+        bx.append(&show_btn);
+        bx.append(&reject_btn);
+        bx.append(&accept_btn);
+        let row = ListBoxRow::new();
+        row.set_child(Some(&bx));
+
+        let id = change.id;
+        accept_btn.connect_clicked({
+            let panel = self.clone();
+            move |_| {
+                panel.review.borrow_mut().accept(id);
+                panel.queue_update();
+            }
+        });
+        reject_btn.connect_clicked({
+            let panel = self.clone();
+            move |_| {
+                panel.reject(id);
+            }
+        });
+        show_btn.connect_clicked({
+            let panel = self.clone();
+            let (from, to) = (change.pos, change.end());
+            move |_| {
+                panel.show_range(from, to);
+            }
+        });
+        row
+    }
+
+    fn comment_row(&self, comment : &review::CommentThread) -> ListBoxRow {
+        let buffer = self.view.buffer();
+        let quote_lbl = Label::new(Some(&format!("“{}”", excerpt(&buffer_text(&buffer, comment.start, comment.end)))));
+        quote_lbl.set_halign(Align::Start);
+        quote_lbl.set_ellipsize(pango::EllipsizeMode::End);
+        quote_lbl.style_context().add_class("dim-label");
+        let bx = Box::new(Orientation::Vertical, 6);
+        super::set_margins(&bx, 12, 6);
+        bx.append(&quote_lbl);
+        for note in comment.notes.iter() {
+            let note_lbl = Label::new(None);
+            note_lbl.set_markup(&format!("<b>{}</b> {}", glib::markup_escape_text(&note.author), glib::markup_escape_text(&note.text)));
+            note_lbl.set_halign(Align::Start);
+            note_lbl.set_wrap(true);
+            note_lbl.set_xalign(0.0);
+            bx.append(&note_lbl);
+        }
+
+        let reply_entry = Entry::new();
+        reply_entry.set_placeholder_text(Some("Reply"));
+        reply_entry.set_hexpand(true);
+        let resolve_btn = ToggleButton::with_label("Resolved");
+        resolve_btn.set_active(comment.resolved);
+        let show_btn = Button::from_icon_name("find-location-symbolic");
+        show_btn.set_tooltip_text(Some("Show at the editor"));
+        let remove_btn = Button::from_icon_name("user-trash-symbolic");
+        remove_btn.set_tooltip_text(Some("Remove comment"));
+        for btn in [&show_btn, &remove_btn] {
+            btn.style_context().add_class("flat");
+        }
+        let action_bx = Box::new(Orientation::Horizontal, 6);
+        action_bx.append(&reply_entry);
+        action_bx.append(&resolve_btn);
+        action_bx.append(&show_btn);
+        action_bx.append(&remove_btn);
+        bx.append(&action_bx);
+        let row = ListBoxRow::new();
+        row.set_child(Some(&bx));
+
+        let id = comment.id;
+        reply_entry.connect_activate({
+            let panel = self.clone();
+            move |entry| {
+                let txt = entry.text().to_string();
+                if !txt.trim().is_empty() {
+                    panel.review.borrow_mut().reply(id, &txt, &crate::author());
+                    panel.queue_update();
+                }
+            }
+        });
+        resolve_btn.connect_toggled({
+            let panel = self.clone();
+            move |btn| {
+                panel.review.borrow_mut().set_resolved(id, btn.is_active());
+                panel.queue_update();
+            }
+        });
+        remove_btn.connect_clicked({
+            let panel = self.clone();
+            move |_| {
+                panel.review.borrow_mut().remove_comment(id);
+                panel.queue_update();
+            }
+        });
+        show_btn.connect_clicked({
+            let panel = self.clone();
+            let (from, to) = (comment.start, comment.end);
+            move |_| {
+                panel.show_range(from, to);
+            }
+        });
+        row
+    }
+
+    fn show_range(&self, from : usize, to : usize) {
+        let buffer = self.view.buffer();
+        let (start, end) = (buffer.iter_at_offset(from as i32), buffer.iter_at_offset(to as i32));
+        buffer.select_range(&start, &end);
+        self.view.scroll_to_iter(&mut start.clone(), 0.0, true, 0.0, 0.5);
+        self.view.grab_focus();
+    }
+
+    // Undoes the change at the buffer, without tracking the edit.
+    fn reject(&self, id : u64) {
+        let edit = self.review.borrow_mut().reject(id);
+        let Some(edit) = edit else { return };
+        let buffer = self.view.buffer();
+        self.applying.replace(true);
+        buffer.begin_user_action();
+        match edit {
+            Edit::Insert(at, txt) => {
+                buffer.insert(&mut buffer.iter_at_offset(at as i32), &txt);
+            },
+            Edit::Delete(from, to) => {
+                buffer.delete(&mut buffer.iter_at_offset(from as i32), &mut buffer.iter_at_offset(to as i32));
+            }
+        }
+        buffer.end_user_action();
+        self.applying.replace(false);
+        self.queue_update();
+    }
+
+    fn notify_preview(&self) {
+        let show = self.preview_action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+        let changes = if show { self.review.borrow().changes.clone() } else { Vec::new() };
+        self.on_preview_changed.call(changes);
+    }
+
+    /// Called with the changes to mark at the preview (empty when they should not be marked).
+    pub fn connect_preview_changed<F>(&self, f : F)
+    where
+        F : Fn(Vec<Change>) + 'static
+    {
+        self.on_preview_changed.bind(f);
+    }
+
+    fn set_path(&self, path : Option<PathBuf>) {
+        let mut review = path.as_ref().and_then(|p| review::load(p) ).unwrap_or_default();
+        let buffer = self.view.buffer();
+        review.validate(&buffer.text(&buffer.start_iter(), &buffer.end_iter(), true));
+        self.review.replace(review);
+        self.path.replace(path);
+        self.queue_update();
+    }
+
+}
+
+impl React<FileManager> for ReviewPanel {
+
+    // Must be called after the editor reacts to the manager, so the review of an
+    // opened file replaces anything recorded while its text was loaded.
+    fn react(&self, manager : &FileManager) {
+        manager.connect_new({
+            let panel = self.clone();
+            move |_| {
+                panel.dialog.hide();
+                panel.set_path(None);
+            }
+        });
+        manager.connect_opened({
+            let panel = self.clone();
+            move |(path, _)| {
+                panel.set_path(Some(PathBuf::from(path)));
+            }
+        });
+        manager.connect_save({
+            let panel = self.clone();
+            move |path| {
+                let path = PathBuf::from(path);
+                if let Err(e) = review::save(&path, &panel.review.borrow()) {
+                    log::warn!("Unable to save review: {}", e);
+                }
+                panel.path.replace(Some(path));
+            }
+        });
+    }
+
+}
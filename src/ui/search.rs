@@ -0,0 +1,280 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::typst_tools::PageFrame;
+
+/*
+Text search and selection at the preview. The text of each page is kept as a sequence of
+characters with one region per character (in points, with the origin at the top-left corner
+of the page), extracted either from poppler or from the typst frames. Search matches and
+the selection are painted over the rendered pages.
+*/
+
+// Region as (x, y, width, height), in points.
+pub type TextRect = (f64, f64, f64, f64);
+
+#[derive(Debug, Clone, Default)]
+pub struct PageText {
+    chars : Vec<char>,
+    rects : Vec<TextRect>
+}
+
+impl PageText {
+
+    pub fn from_poppler(page : &poppler::Page) -> Self {
+        let text = page.text().map(|t| t.to_string() ).unwrap_or_default();
+        let layout = page.text_layout().unwrap_or_default();
+        let (chars, rects) = text.chars()
+            .zip(layout.iter().map(|r| (r.x1(), r.y1(), r.x2() - r.x1(), r.y2() - r.y1()) ))
+            .unzip();
+        Self { chars, rects }
+    }
+
+    pub fn from_frame(page : &PageFrame) -> Self {
+        let (chars, rects) = page.chars.iter().copied().unzip();
+        Self { chars, rects }
+    }
+
+    /// Case-insensitive occurences of the query. Each match is given by the regions
+    /// of its characters, merged into one region per line.
+    pub fn find(&self, query : &str) -> Vec<Vec<TextRect>> {
+        let query : Vec<char> = query.chars().collect();
+        if query.is_empty() || query.len() > self.chars.len() {
+            return Vec::new();
+        }
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start + query.len() <= self.chars.len() {
+            let found = self.chars[start..(start + query.len())].iter()
+                .zip(query.iter())
+                .all(|(a, b)| same_char(*a, *b) );
+            if found {
+                matches.push(merge_rects(&self.rects[start..(start + query.len())]));
+                start += query.len();
+            } else {
+                start += 1;
+            }
+        }
+        matches
+    }
+
+    /// Text of the characters whose center is inside the area, with their merged regions.
+    pub fn select(&self, area : TextRect) -> (String, Vec<TextRect>) {
+        let (ax, ay, aw, ah) = area;
+        let mut text = String::new();
+        let mut rects = Vec::new();
+        for (c, r) in self.chars.iter().zip(self.rects.iter()) {
+            let (cx, cy) = (r.0 + r.2 / 2.0, r.1 + r.3 / 2.0);
+            if cx >= ax && cx <= ax + aw && cy >= ay && cy <= ay + ah {
+                text.push(*c);
+                rects.push(*r);
+            }
+        }
+        (text.trim().to_string(), merge_rects(&rects))
+    }
+
+}
+
+fn same_char(a : char, b : char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase()) || (a.is_whitespace() && b.is_whitespace())
+}
+
+// Merges the regions of consecutive characters at the same line.
+fn merge_rects(rects : &[TextRect]) -> Vec<TextRect> {
+    let mut merged : Vec<TextRect> = Vec::new();
+    for r in rects.iter().filter(|r| r.2 > 0.0 ) {
+        match merged.last_mut() {
+            Some(last) if (last.1 - r.1).abs() < last.3.min(r.3) * 0.5 && r.0 >= last.0 => {
+                let bottom = (last.1 + last.3).max(r.1 + r.3);
+                last.1 = last.1.min(r.1);
+                last.2 = (r.0 + r.2) - last.0;
+                last.3 = bottom - last.1;
+            },
+            _ => merged.push(*r)
+        }
+    }
+    merged
+}
+
+#[derive(Debug, Clone)]
+pub struct TextMatch {
+    pub page : usize,
+    pub rects : Vec<TextRect>
+}
+
+#[derive(Debug, Clone, Default)]
+struct HighlightState {
+
+    matches : Vec<TextMatch>,
+
+    current : Option<usize>,
+
+    // Page, regions and text of the current selection.
+    selection : Option<(usize, Vec<TextRect>, String)>
+
+}
+
+// Search matches and text selection, shared by the page draw functions.
+#[derive(Debug, Clone, Default)]
+pub struct Highlights(Rc<RefCell<HighlightState>>);
+
+impl Highlights {
+
+    pub fn set_matches(&self, matches : Vec<TextMatch>) {
+        let mut state = self.0.borrow_mut();
+        state.current = if matches.is_empty() { None } else { Some(0) };
+        state.matches = matches;
+    }
+
+    pub fn n_matches(&self) -> usize {
+        self.0.borrow().matches.len()
+    }
+
+    /// Moves to the next (or previous) match, returning it.
+    pub fn step(&self, forward : bool) -> Option<(usize, TextMatch)> {
+        let mut state = self.0.borrow_mut();
+        let n = state.matches.len();
+        if n == 0 {
+            return None;
+        }
+        let next = match state.current {
+            Some(curr) if forward => (curr + 1) % n,
+            Some(curr) => (curr + n - 1) % n,
+            None => 0
+        };
+        state.current = Some(next);
+        Some((next, state.matches[next].clone()))
+    }
+
+    pub fn current(&self) -> Option<(usize, TextMatch)> {
+        let state = self.0.borrow();
+        state.current.map(|ix| (ix, state.matches[ix].clone()) )
+    }
+
+    pub fn set_selection(&self, page : usize, rects : Vec<TextRect>, text : String) {
+        self.0.borrow_mut().selection = Some((page, rects, text));
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        self.0.borrow().selection.as_ref()
+            .map(|(_, _, text)| text.clone() )
+            .filter(|text| !text.is_empty() )
+    }
+
+    pub fn clear_selection(&self) {
+        self.0.borrow_mut().selection = None;
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.0.borrow_mut();
+        state.matches.clear();
+        state.current = None;
+        state.selection = None;
+    }
+
+    /// Paints the matches and selection of the page, for a context scaled to the page zoom.
+    pub fn draw(&self, ctx : &cairo::Context, page : usize, zoom : f64) {
+        let state = self.0.borrow();
+        ctx.save();
+        ctx.scale(zoom, zoom);
+        for (ix, m) in state.matches.iter().enumerate().filter(|(_, m)| m.page == page ) {
+            if state.current == Some(ix) {
+                ctx.set_source_rgba(1.0, 0.55, 0.0, 0.5);
+            } else {
+                ctx.set_source_rgba(1.0, 0.9, 0.0, 0.35);
+            }
+            fill_rects(ctx, &m.rects);
+        }
+        if let Some((sel_page, rects, _)) = &state.selection {
+            if *sel_page == page {
+                ctx.set_source_rgba(0.2, 0.45, 0.9, 0.3);
+                fill_rects(ctx, rects);
+            }
+        }
+        ctx.restore();
+    }
+
+}
+
+fn fill_rects(ctx : &cairo::Context, rects : &[TextRect]) {
+    for (x, y, w, h) in rects.iter() {
+        ctx.rectangle(*x, *y, *w, *h);
+    }
+    let _ = ctx.fill();
+}
+
+#[derive(Debug, Clone)]
+pub struct FindBar {
+    pub bar : SearchBar,
+    pub entry : SearchEntry,
+    pub prev_btn : Button,
+    pub next_btn : Button,
+    pub count_lbl : Label
+}
+
+impl FindBar {
+
+    pub fn build() -> Self {
+        let entry = SearchEntry::new();
+        entry.set_placeholder_text(Some("Find in preview"));
+        entry.set_width_chars(24);
+        let prev_btn = Button::from_icon_name("go-up-symbolic");
+        let next_btn = Button::from_icon_name("go-down-symbolic");
+        prev_btn.set_tooltip_text(Some("Previous match"));
+        next_btn.set_tooltip_text(Some("Next match"));
+        let count_lbl = Label::new(None);
+        count_lbl.set_margin_start(6);
+        count_lbl.set_width_chars(10);
+        count_lbl.style_context().add_class("dim-label");
+
+        let bx = Box::new(Orientation::Horizontal, 0);
+        bx.style_context().add_class("linked");
+        bx.append(&entry);
+        bx.append(&prev_btn);
+        bx.append(&next_btn);
+        let outer = Box::new(Orientation::Horizontal, 0);
+        outer.append(&bx);
+        outer.append(&count_lbl);
+
+        let bar = SearchBar::new();
+        bar.set_child(Some(&outer));
+        bar.connect_entry(&entry);
+        bar.set_show_close_button(true);
+        Self { bar, entry, prev_btn, next_btn, count_lbl }
+    }
+
+    pub fn set_count(&self, current : Option<usize>, n : usize) {
+        match (current, n) {
+            (_, 0) if self.entry.text().is_empty() => self.count_lbl.set_text(""),
+            (_, 0) => self.count_lbl.set_text("No matches"),
+            (Some(ix), n) => self.count_lbl.set_text(&format!("{} of {}", ix + 1, n)),
+            (None, n) => self.count_lbl.set_text(&format!("{} matches", n))
+        }
+    }
+
+}
+
+#[test]
+fn page_text_search() {
+    let text = "Hello world\nhello";
+    let chars : Vec<(char, TextRect)> = text.chars().enumerate().map(|(ix, c)| {
+        let (line, col) = if ix < 12 { (0.0, ix as f64) } else { (1.0, (ix - 12) as f64) };
+        let w = if c == '\n' { 0.0 } else { 5.0 };
+        (c, (col * 5.0, line * 12.0, w, 10.0))
+    }).collect();
+    let page = PageText { chars : chars.iter().map(|c| c.0 ).collect(), rects : chars.iter().map(|c| c.1 ).collect() };
+    let matches = page.find("HELLO");
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0], vec![(0.0, 0.0, 25.0, 10.0)]);
+    assert_eq!(matches[1], vec![(0.0, 12.0, 25.0, 10.0)]);
+    assert_eq!(page.find("world hello").len(), 1);
+    let (sel, rects) = page.select((28.0, 0.0, 30.0, 10.0));
+    assert_eq!(sel, "world");
+    assert_eq!(rects.len(), 1);
+}
@@ -0,0 +1,201 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use stateful::Callbacks;
+use crate::typst_tools::OutlineEntry;
+use crate::renderer::PageRenderer;
+
+// Zoom of the page thumbnails.
+const THUMBNAIL_ZOOM : f64 = 0.2;
+
+// Sidebar at the left of the preview, listing the page thumbnails and the outline
+// of the typeset document.
+#[derive(Debug, Clone)]
+pub struct PreviewSidebar {
+    pub revealer : Revealer,
+    pub stack : Stack,
+    outline_list : ListBox,
+    outline : Rc<RefCell<Vec<OutlineEntry>>>,
+    on_outline_selected : Callbacks<OutlineEntry>,
+    thumb_list : ListBox,
+    thumb_scroll : ScrolledWindow,
+    thumbs : Rc<RefCell<Vec<DrawingArea>>>,
+
+    // Fixed zoom for the thumbnails, so they can share the page drawing functions.
+    thumb_zoom : gio::SimpleAction,
+
+    on_page_selected : Callbacks<usize>
+}
+
+impl PreviewSidebar {
+
+    pub fn build() -> Self {
+        let outline_list = ListBox::new();
+        outline_list.set_selection_mode(SelectionMode::Single);
+        outline_list.style_context().add_class("navigation-sidebar");
+        let outline_scroll = ScrolledWindow::new();
+        outline_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        outline_scroll.set_vexpand(true);
+        outline_scroll.set_child(Some(&outline_list));
+
+        let thumb_list = ListBox::new();
+        thumb_list.set_selection_mode(SelectionMode::Single);
+        thumb_list.style_context().add_class("navigation-sidebar");
+        let thumb_scroll = ScrolledWindow::new();
+        thumb_scroll.set_policy(PolicyType::Never, PolicyType::Automatic);
+        thumb_scroll.set_vexpand(true);
+        thumb_scroll.set_child(Some(&thumb_list));
+
+        let stack = Stack::new();
+        stack.add_titled(&thumb_scroll, Some("pages"), "Pages");
+        stack.add_titled(&outline_scroll, Some("outline"), "Outline");
+        let switcher = StackSwitcher::new();
+        switcher.set_stack(Some(&stack));
+        switcher.set_halign(Align::Center);
+        switcher.set_margin_top(6);
+        switcher.set_margin_bottom(6);
+
+        let bx = Box::new(Orientation::Vertical, 0);
+        bx.append(&switcher);
+        bx.append(&stack);
+        bx.set_width_request(220);
+
+        let revealer = Revealer::new();
+        revealer.set_transition_type(RevealerTransitionType::SlideRight);
+        revealer.set_child(Some(&bx));
+        revealer.set_reveal_child(false);
+
+        let outline : Rc<RefCell<Vec<OutlineEntry>>> = Default::default();
+        let on_outline_selected : Callbacks<OutlineEntry> = Default::default();
+        outline_list.connect_row_activated({
+            let outline = outline.clone();
+            let on_outline_selected = on_outline_selected.clone();
+            move |_, row| {
+                let entry = outline.borrow().get(row.index() as usize).cloned();
+                if let Some(entry) = entry {
+                    on_outline_selected.call(entry);
+                }
+            }
+        });
+        let thumbs : Rc<RefCell<Vec<DrawingArea>>> = Default::default();
+        let on_page_selected : Callbacks<usize> = Default::default();
+        thumb_list.connect_row_activated({
+            let on_page_selected = on_page_selected.clone();
+            move |_, row| {
+                on_page_selected.call(row.index() as usize);
+            }
+        });
+
+        // Thumbnails are only rendered when scrolled into view.
+        thumb_scroll.vadjustment().connect_value_changed({
+            let thumbs = thumbs.clone();
+            move |_| {
+                thumbs.borrow().iter().for_each(|da| da.queue_draw() );
+            }
+        });
+        let thumb_zoom = gio::SimpleAction::new_stateful("thumbnail_zoom", None, &THUMBNAIL_ZOOM.to_variant());
+        Self {
+            revealer,
+            stack,
+            outline_list,
+            outline,
+            on_outline_selected,
+            thumb_list,
+            thumb_scroll,
+            thumbs,
+            thumb_zoom,
+            on_page_selected
+        }
+    }
+
+    /// Creates one thumbnail for each page, given the page sizes (in points).
+    pub fn set_pages(&self, sizes : &[(f64, f64)], renderer : &PageRenderer, highlights : &super::Highlights) {
+        while let Some(row) = self.thumb_list.row_at_index(0) {
+            self.thumb_list.remove(&row);
+        }
+        let mut thumbs = self.thumbs.borrow_mut();
+        thumbs.clear();
+        for (page_ix, size) in sizes.iter().enumerate() {
+            let da = DrawingArea::new();
+            let is_last = page_ix == sizes.len() - 1;
+            crate::draw_visible_page_at_area(*size, page_ix, is_last, &da, &self.thumb_zoom, &self.thumb_scroll, renderer, highlights);
+            let lbl = Label::new(Some(&format!("{}", page_ix + 1)));
+            lbl.style_context().add_class("dim-label");
+            let bx = Box::new(Orientation::Vertical, 6);
+            bx.append(&da);
+            bx.append(&lbl);
+            let row = ListBoxRow::new();
+            row.set_child(Some(&bx));
+            self.thumb_list.append(&row);
+            thumbs.push(da);
+        }
+    }
+
+    /// Highlights the thumbnail of the current page, scrolling the strip if required.
+    pub fn select_page(&self, page_ix : usize) {
+        let Some(row) = self.thumb_list.row_at_index(page_ix as i32) else { return };
+        self.thumb_list.select_row(Some(&row));
+        if let Some(bounds) = row.compute_bounds(&self.thumb_list) {
+            let adj = self.thumb_scroll.vadjustment();
+            let (top, bottom) = (bounds.y() as f64, (bounds.y() + bounds.height()) as f64);
+            if top < adj.value() || bottom > adj.value() + adj.page_size() {
+                adj.set_value(top);
+            }
+        }
+    }
+
+    pub fn queue_draw_page(&self, page_ix : usize) {
+        if let Some(da) = self.thumbs.borrow().get(page_ix) {
+            da.queue_draw();
+        }
+    }
+
+    pub fn queue_draw(&self) {
+        self.thumbs.borrow().iter().for_each(|da| da.queue_draw() );
+    }
+
+    /// Called with the page index when the user clicks a thumbnail.
+    pub fn connect_page_selected<F>(&self, f : F)
+    where
+        F : Fn(usize) + 'static
+    {
+        self.on_page_selected.bind(f);
+    }
+
+    pub fn set_outline(&self, entries : Vec<OutlineEntry>) {
+        while let Some(row) = self.outline_list.row_at_index(0) {
+            self.outline_list.remove(&row);
+        }
+        for entry in entries.iter() {
+            let lbl = Label::new(Some(&entry.title));
+            lbl.set_halign(Align::Start);
+            lbl.set_ellipsize(pango::EllipsizeMode::End);
+            lbl.set_margin_start(12 * (entry.level.max(1) as i32 - 1));
+            let page_lbl = Label::new(Some(&format!("{}", entry.page + 1)));
+            page_lbl.style_context().add_class("dim-label");
+            page_lbl.set_hexpand(true);
+            page_lbl.set_halign(Align::End);
+            let bx = Box::new(Orientation::Horizontal, 6);
+            bx.append(&lbl);
+            bx.append(&page_lbl);
+            let row = ListBoxRow::new();
+            row.set_child(Some(&bx));
+            self.outline_list.append(&row);
+        }
+        self.outline.replace(entries);
+    }
+
+    pub fn connect_outline_selected<F>(&self, f : F)
+    where
+        F : Fn(OutlineEntry) + 'static
+    {
+        self.on_outline_selected.bind(f);
+    }
+
+}
@@ -0,0 +1,279 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use stateful::{React, Callbacks};
+use libadwaita::{TabView, TabBar, TabPage};
+use crate::manager::FileManager;
+use super::PapersWindow;
+
+/*
+Multiple open documents at the same window. Each tab holds a full PapersWindow (titlebar, editor,
+preview and the file manager, typesetter and analyzer bound to them), built by the factory given
+to DocumentTabs. Only the selected document is active: its titlebar is shown and its actions are
+the ones registered at the window (see PapersWindow::activate).
+*/
+
+const UNTITLED : &'static str = "New document";
+
+#[derive(Debug, Clone)]
+pub struct DocumentTab {
+    pub page : TabPage,
+    path : Rc<RefCell<Option<String>>>,
+
+    // Whether there are changes not written to the file.
+    modified : Rc<RefCell<bool>>,
+
+    on_closed : Callbacks<()>
+}
+
+impl DocumentTab {
+
+    fn new(page : TabPage) -> Self {
+        page.set_title(UNTITLED);
+        Self { page, path : Default::default(), modified : Default::default(), on_closed : Default::default() }
+    }
+
+    pub fn path(&self) -> Option<String> {
+        self.path.borrow().clone()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        *self.modified.borrow()
+    }
+
+    fn window_title(&self) -> String {
+        if self.path.borrow().is_some() {
+            self.page.title().to_string()
+        } else {
+            String::from("Drafts")
+        }
+    }
+
+    fn set_path(&self, path : Option<String>) {
+        let title = path.as_ref()
+            .and_then(|p| std::path::Path::new(p).file_name().map(|f| f.to_string_lossy().to_string() ) )
+            .unwrap_or(String::from(UNTITLED));
+        self.page.set_title(&title);
+        self.page.set_tooltip(path.as_deref().unwrap_or(""));
+        self.path.replace(path);
+        self.set_modified(false);
+    }
+
+    fn set_modified(&self, modified : bool) {
+        self.modified.replace(modified);
+        let icon = if modified { Some(gio::ThemedIcon::new("document-modified-symbolic")) } else { None };
+        self.page.set_indicator_icon(icon.as_ref());
+    }
+
+    /// Called when the tab is closed and its document discarded.
+    pub fn connect_closed<F>(&self, f : F)
+    where
+        F : Fn(()) + 'static
+    {
+        self.on_closed.bind(f);
+    }
+
+}
+
+impl React<FileManager> for DocumentTab {
+
+    fn react(&self, manager : &FileManager) {
+        manager.connect_new({
+            let tab = self.clone();
+            move |_| {
+                tab.set_path(None);
+            }
+        });
+        manager.connect_opened({
+            let tab = self.clone();
+            move |(path, _)| {
+                tab.set_path(Some(path));
+            }
+        });
+        manager.connect_save({
+            let tab = self.clone();
+            move |path| {
+                tab.set_path(Some(path));
+            }
+        });
+        manager.connect_file_changed({
+            let tab = self.clone();
+            move |_| {
+                tab.set_modified(true);
+            }
+        });
+    }
+
+}
+
+#[derive(Clone)]
+pub struct DocumentTabs {
+    pub bx : Box,
+    pub view : TabView,
+    pub new_tab_action : gio::SimpleAction,
+    pub close_tab_action : gio::SimpleAction,
+    pub window : ApplicationWindow,
+    docs : Rc<RefCell<Vec<(DocumentTab, PapersWindow)>>>,
+
+    // Builds the document shown at a new tab.
+    factory : Rc<RefCell<Option<Rc<dyn Fn(&DocumentTab) -> PapersWindow>>>>
+}
+
+impl DocumentTabs {
+
+    pub fn build(window : &ApplicationWindow) -> Self {
+        let view = TabView::new();
+        view.set_vexpand(true);
+        let bar = TabBar::new();
+        bar.set_view(Some(&view));
+
+        // With a single document, the window looks as it did before tabs.
+        bar.set_autohide(true);
+
+        let bx = Box::new(Orientation::Vertical, 0);
+        bx.append(&bar);
+        bx.append(&view);
+        window.set_child(Some(&bx));
+
+        let new_tab_action = gio::SimpleAction::new("new_tab", None);
+        let close_tab_action = gio::SimpleAction::new("close_tab", None);
+        window.add_action(&new_tab_action);
+        window.add_action(&close_tab_action);
+
+        let tabs = Self {
+            bx,
+            view,
+            new_tab_action,
+            close_tab_action,
+            window : window.clone(),
+            docs : Default::default(),
+            factory : Default::default()
+        };
+        tabs.new_tab_action.connect_activate({
+            let tabs = tabs.clone();
+            move |_, _| {
+                tabs.add_document();
+            }
+        });
+        tabs.close_tab_action.connect_activate({
+            let tabs = tabs.clone();
+            move |_, _| {
+                if let Some(page) = tabs.view.selected_page() {
+                    tabs.view.close_page(&page);
+                }
+            }
+        });
+        tabs.view.connect_selected_page_notify({
+            let tabs = tabs.clone();
+            move |_| {
+                tabs.activate_selected();
+            }
+        });
+        tabs.view.connect_close_page({
+            let tabs = tabs.clone();
+            move |view, page| {
+                let Some(tab) = tabs.tab_for_page(page) else {
+                    view.close_page_finish(page, true);
+                    return true;
+                };
+                if tab.is_modified() {
+                    tabs.confirm_close(&tab);
+                } else {
+                    tabs.finish_close(&tab);
+                }
+                true
+            }
+        });
+        tabs
+    }
+
+    pub fn set_factory<F>(&self, f : F)
+    where
+        F : Fn(&DocumentTab) -> PapersWindow + 'static
+    {
+        self.factory.replace(Some(Rc::new(f)));
+    }
+
+    /// Opens a new tab showing the start screen, and selects it.
+    pub fn add_document(&self) -> Option<PapersWindow> {
+        let factory = self.factory.borrow().clone()?;
+        let holder = Box::new(Orientation::Vertical, 0);
+        let page = self.view.append(&holder);
+        let tab = DocumentTab::new(page.clone());
+        let doc = factory(&tab);
+        doc.stack.set_vexpand(true);
+        holder.append(&doc.stack);
+        self.docs.borrow_mut().push((tab, doc.clone()));
+
+        // The first page is selected as soon as it is appended (before the document is
+        // known here), so the document is activated explicitly.
+        self.view.set_selected_page(&page);
+        self.activate_selected();
+        Some(doc)
+    }
+
+    /// Document at the selected tab.
+    pub fn active(&self) -> Option<PapersWindow> {
+        let page = self.view.selected_page()?;
+        self.docs.borrow().iter().find(|(tab, _)| tab.page == page ).map(|(_, doc)| doc.clone() )
+    }
+
+    fn tab_for_page(&self, page : &TabPage) -> Option<DocumentTab> {
+        self.docs.borrow().iter().find(|(tab, _)| &tab.page == page ).map(|(tab, _)| tab.clone() )
+    }
+
+    fn activate_selected(&self) {
+        let Some(page) = self.view.selected_page() else { return };
+        let docs = self.docs.borrow();
+        if let Some((tab, doc)) = docs.iter().find(|(tab, _)| tab.page == page ) {
+            doc.activate();
+            self.window.set_title(Some(&tab.window_title()));
+        }
+    }
+
+    fn confirm_close(&self, tab : &DocumentTab) {
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &format!("{} has unsaved changes", tab.page.title())
+        );
+        dialog.add_button("Cancel", ResponseType::Cancel);
+        dialog.add_button("Close anyway", ResponseType::Accept);
+        if let Some(btn) = dialog.widget_for_response(ResponseType::Accept) {
+            btn.style_context().add_class("destructive-action");
+        }
+        dialog.connect_response({
+            let tabs = self.clone();
+            let tab = tab.clone();
+            move |dialog, resp| {
+                if resp == ResponseType::Accept {
+                    tabs.finish_close(&tab);
+                } else {
+                    tabs.view.close_page_finish(&tab.page, false);
+                }
+                dialog.close();
+            }
+        });
+        dialog.show();
+    }
+
+    fn finish_close(&self, tab : &DocumentTab) {
+        self.docs.borrow_mut().retain(|(t, _)| t.page != tab.page );
+        self.view.close_page_finish(&tab.page, true);
+        tab.on_closed.call(());
+
+        // The window always shows at least one document.
+        if self.view.n_pages() == 0 {
+            self.add_document();
+        }
+    }
+
+}
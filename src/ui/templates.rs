@@ -0,0 +1,425 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use gtk4::*;
+use gtk4::prelude::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use stateful::{React, Callbacks};
+use filecase::SingleArchiverImpl;
+use crate::manager::FileManager;
+use crate::templates::{self, Template, TemplateMeta, TemplateKind, TemplateParameter, ParameterKind};
+use super::PdfViewer;
+
+// Height of the thumbnails saved with user templates, the same as the bundled template pictures.
+const THUMBNAIL_HEIGHT : i32 = 128;
+
+/// Saves the current document as a user template, with the first page of the last typeset
+/// version (if any) as its thumbnail.
+#[derive(Debug, Clone)]
+pub struct SaveTemplateDialog {
+    pub dialog : Dialog,
+    pub action : gio::SimpleAction,
+    name_entry : Entry,
+    description_entry : Entry,
+    save_btn : Button,
+    status_lbl : Label,
+    on_saved : Callbacks<()>
+}
+
+impl SaveTemplateDialog {
+
+    pub fn build(view : &sourceview5::View, pdf_viewer : &PdfViewer) -> Self {
+        let dialog = Dialog::new();
+        dialog.set_title(Some("Save as template"));
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(420, -1);
+
+        let name_entry = Entry::new();
+        name_entry.set_placeholder_text(Some("Template name"));
+        let description_entry = Entry::new();
+        description_entry.set_placeholder_text(Some("Description"));
+        let status_lbl = Label::new(None);
+        status_lbl.set_halign(Align::Start);
+        status_lbl.set_wrap(true);
+        status_lbl.style_context().add_class("dim-label");
+        let save_btn = Button::with_label("Save");
+        save_btn.style_context().add_class("suggested-action");
+        save_btn.set_halign(Align::End);
+        save_btn.set_sensitive(false);
+
+        let bx = Box::new(Orientation::Vertical, 12);
+        super::set_all_margins(&bx, 18);
+        bx.append(&name_entry);
+        bx.append(&description_entry);
+        bx.append(&status_lbl);
+        bx.append(&save_btn);
+        dialog.set_child(Some(&bx));
+
+        let action = gio::SimpleAction::new("save_template", None);
+        let tmpl_dialog = Self { dialog, action, name_entry, description_entry, save_btn, status_lbl, on_saved : Default::default() };
+        tmpl_dialog.action.connect_activate({
+            let tmpl_dialog = tmpl_dialog.clone();
+            move |_, _| {
+                tmpl_dialog.name_entry.set_text("");
+                tmpl_dialog.description_entry.set_text("");
+                tmpl_dialog.status_lbl.set_text("");
+                tmpl_dialog.dialog.show();
+                tmpl_dialog.name_entry.grab_focus();
+            }
+        });
+        tmpl_dialog.name_entry.connect_changed({
+            let save_btn = tmpl_dialog.save_btn.clone();
+            move |entry| {
+                save_btn.set_sensitive(!entry.text().trim().is_empty());
+            }
+        });
+        tmpl_dialog.save_btn.connect_clicked({
+            let tmpl_dialog = tmpl_dialog.clone();
+            let view = view.clone();
+            let pdf_viewer = pdf_viewer.clone();
+            move |_| {
+                let buffer = view.buffer();
+                let source = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+                let meta = TemplateMeta {
+                    name : tmpl_dialog.name_entry.text().trim().to_string(),
+                    description : tmpl_dialog.description_entry.text().trim().to_string(),
+                    order : u32::MAX,
+                    kind : TemplateKind::Document,
+                    author : Some(crate::author()),
+                    created : Some(crate::now()),
+                    main : None,
+                    directories : Vec::new(),
+                    parameters : Vec::new()
+                };
+                match templates::save_template(&meta, &source) {
+                    Ok(dir) => {
+                        // Documents never typeset are saved without a thumbnail.
+                        pdf_viewer.save_thumbnail(THUMBNAIL_HEIGHT, &dir.join("preview.png"));
+                        tmpl_dialog.dialog.hide();
+                        tmpl_dialog.on_saved.call(());
+                    },
+                    Err(e) => {
+                        tmpl_dialog.status_lbl.set_text(&format!("Unable to save template: {}", e));
+                    }
+                }
+            }
+        });
+        tmpl_dialog
+    }
+
+    pub fn connect_saved<F>(&self, f : F)
+    where
+        F : Fn(()) + 'static
+    {
+        self.on_saved.bind(f);
+    }
+
+}
+
+// Input widget for a template parameter.
+#[derive(Debug, Clone)]
+enum ParameterField {
+    Text(Entry),
+    Paragraph(TextView),
+    Choice(DropDown, Vec<String>)
+}
+
+impl ParameterField {
+
+    fn build(param : &TemplateParameter) -> Self {
+        let default = if param.default.is_empty() && param.name.starts_with("author") {
+            crate::author()
+        } else {
+            param.default.clone()
+        };
+        match param.kind {
+            ParameterKind::Text => {
+                let entry = Entry::new();
+                entry.set_text(&default);
+                entry.set_hexpand(true);
+                ParameterField::Text(entry)
+            },
+            ParameterKind::Paragraph => {
+                let view = TextView::new();
+                view.set_wrap_mode(WrapMode::Word);
+                view.set_accepts_tab(false);
+                view.set_height_request(96);
+                view.buffer().set_text(&default);
+                super::set_all_margins(&view, 6);
+                ParameterField::Paragraph(view)
+            },
+            ParameterKind::Choice => {
+                let options : Vec<&str> = param.options.iter().map(|o| &o[..] ).collect();
+                let dropdown = DropDown::from_strings(&options);
+                if let Some(ix) = param.options.iter().position(|o| o == &param.default ) {
+                    dropdown.set_selected(ix as u32);
+                }
+                ParameterField::Choice(dropdown, param.options.clone())
+            }
+        }
+    }
+
+    fn widget(&self) -> Widget {
+        match self {
+            ParameterField::Text(entry) => entry.clone().upcast(),
+            ParameterField::Paragraph(view) => {
+                let frame = Frame::new(None);
+                frame.set_child(Some(view));
+                frame.upcast()
+            },
+            ParameterField::Choice(dropdown, _) => dropdown.clone().upcast()
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            ParameterField::Text(entry) => entry.text().trim().to_string(),
+            ParameterField::Paragraph(view) => {
+                let buffer = view.buffer();
+                buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).trim().to_string()
+            },
+            ParameterField::Choice(dropdown, options) => options.get(dropdown.selected() as usize).cloned().unwrap_or_default()
+        }
+    }
+
+}
+
+/// Collects the parameters declared by a template before the document is created. For project
+/// templates, also asks where the project folder is created, then writes the project files.
+#[derive(Debug, Clone)]
+pub struct TemplateWizard {
+    pub dialog : Dialog,
+    grid : Grid,
+    project_bx : Box,
+    folder_entry : Entry,
+    location_btn : Button,
+    folder_chooser : FileChooserNative,
+    create_btn : Button,
+    status_lbl : Label,
+    location : Rc<RefCell<PathBuf>>,
+    template : Rc<RefCell<Option<Template>>>,
+    fields : Rc<RefCell<Vec<(String, ParameterField)>>>,
+    on_document : Callbacks<String>,
+    on_project_created : Callbacks<PathBuf>
+}
+
+impl TemplateWizard {
+
+    pub fn build() -> Self {
+        let dialog = Dialog::new();
+        filecase::configure_dialog(&dialog);
+        dialog.set_default_size(520, -1);
+
+        let grid = Grid::new();
+        grid.set_row_spacing(12);
+        grid.set_column_spacing(12);
+
+        let folder_entry = Entry::new();
+        folder_entry.set_placeholder_text(Some("Folder name"));
+        folder_entry.set_hexpand(true);
+        let location_btn = Button::new();
+        location_btn.set_tooltip_text(Some("Choose where the project folder is created"));
+        let folder_bx = Box::new(Orientation::Horizontal, 0);
+        folder_bx.style_context().add_class("linked");
+        folder_bx.append(&location_btn);
+        folder_bx.append(&folder_entry);
+        let folder_lbl = Label::new(Some("Location"));
+        folder_lbl.set_halign(Align::Start);
+        folder_lbl.style_context().add_class("heading");
+        let project_bx = Box::new(Orientation::Vertical, 6);
+        project_bx.append(&folder_lbl);
+        project_bx.append(&folder_bx);
+
+        let status_lbl = Label::new(None);
+        status_lbl.set_halign(Align::Start);
+        status_lbl.set_wrap(true);
+        status_lbl.style_context().add_class("dim-label");
+        let create_btn = Button::with_label("Create");
+        create_btn.style_context().add_class("suggested-action");
+        create_btn.set_halign(Align::End);
+
+        let bx = Box::new(Orientation::Vertical, 12);
+        super::set_all_margins(&bx, 18);
+        bx.append(&grid);
+        bx.append(&project_bx);
+        bx.append(&status_lbl);
+        bx.append(&create_btn);
+        dialog.set_child(Some(&bx));
+
+        let folder_chooser = FileChooserNative::new(
+            Some("Project location"),
+            Some(&dialog),
+            FileChooserAction::SelectFolder,
+            Some("Select"),
+            Some("Cancel")
+        );
+        folder_chooser.set_modal(true);
+
+        let location = glib::user_special_dir(glib::UserDirectory::Documents).unwrap_or(glib::home_dir());
+        let wizard = Self {
+            dialog,
+            grid,
+            project_bx,
+            folder_entry,
+            location_btn,
+            folder_chooser,
+            create_btn,
+            status_lbl,
+            location : Rc::new(RefCell::new(location)),
+            template : Default::default(),
+            fields : Default::default(),
+            on_document : Default::default(),
+            on_project_created : Default::default()
+        };
+        wizard.update_location();
+
+        wizard.folder_entry.connect_changed({
+            let wizard = wizard.clone();
+            move |_| {
+                wizard.update_sensitive();
+            }
+        });
+        wizard.location_btn.connect_clicked({
+            let folder_chooser = wizard.folder_chooser.clone();
+            move |_| {
+                folder_chooser.show();
+            }
+        });
+        wizard.folder_chooser.connect_response({
+            let wizard = wizard.clone();
+            move |chooser, resp| {
+                if resp == ResponseType::Accept {
+                    if let Some(path) = chooser.file().and_then(|f| f.path() ) {
+                        wizard.location.replace(path);
+                        wizard.update_location();
+                    }
+                }
+            }
+        });
+        wizard.create_btn.connect_clicked({
+            let wizard = wizard.clone();
+            move |_| {
+                if let Err(e) = wizard.create() {
+                    wizard.status_lbl.set_text(&e);
+                }
+            }
+        });
+        wizard
+    }
+
+    fn is_project(&self) -> bool {
+        self.template.borrow().as_ref().map(|t| t.meta.kind == TemplateKind::Project ).unwrap_or(false)
+    }
+
+    fn update_sensitive(&self) {
+        self.create_btn.set_sensitive(!self.is_project() || !self.folder_entry.text().trim().is_empty());
+    }
+
+    fn update_location(&self) {
+        let location = self.location.borrow();
+        let name = location.file_name().and_then(|n| n.to_str() ).unwrap_or("/");
+        self.location_btn.set_label(&format!("{}/", name));
+    }
+
+    fn values(&self) -> Vec<(String, String)> {
+        self.fields.borrow().iter().map(|(name, field)| (name.clone(), field.value()) ).collect()
+    }
+
+    fn create(&self) -> Result<(), String> {
+        let template = self.template.borrow().clone().ok_or(String::from("No template selected"))?;
+        let values = self.values();
+        match template.meta.kind {
+            TemplateKind::Document => {
+                self.dialog.hide();
+                self.on_document.call(templates::instantiate(&template, &values));
+            },
+            TemplateKind::Project => {
+                let target = self.location.borrow().join(self.folder_entry.text().trim());
+                let main = std::fs::create_dir_all(&target)
+                    .map_err(|e| e.to_string() )
+                    .and_then(|_| templates::scaffold_project(&template, &target, &values) )
+                    .map_err(|e| format!("Unable to create project: {}", e) )?;
+                self.dialog.hide();
+                self.on_project_created.call(main);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a document from the template, asking for its parameters first (if it declares any).
+    pub fn show(&self, template : &Template) {
+        self.template.replace(Some(template.clone()));
+        if template.meta.kind == TemplateKind::Document && template.meta.parameters.is_empty() {
+            self.on_document.call(template.source.clone());
+            return;
+        }
+
+        while let Some(child) = self.grid.first_child() {
+            self.grid.remove(&child);
+        }
+        let mut fields = Vec::new();
+        for (row, param) in template.meta.parameters.iter().enumerate() {
+            let lbl = Label::new(Some(&param.label));
+            lbl.set_halign(Align::Start);
+            lbl.set_valign(Align::Start);
+            let field = ParameterField::build(param);
+            self.grid.attach(&lbl, 0, row as i32, 1, 1);
+            self.grid.attach(&field.widget(), 1, row as i32, 1, 1);
+            fields.push((param.name.clone(), field));
+        }
+
+        // The project folder is named after the title, unless the user names it.
+        self.folder_entry.set_text("");
+        if let Some((_, ParameterField::Text(entry))) = fields.iter().find(|(name, _)| name == "title" ) {
+            let update_folder = {
+                let folder_entry = self.folder_entry.clone();
+                move |entry : &Entry| {
+                    let title = entry.text();
+                    let folder = if title.trim().is_empty() { String::new() } else { templates::template_id(&title) };
+                    folder_entry.set_text(&folder);
+                }
+            };
+            update_folder(entry);
+            entry.connect_changed(update_folder);
+        }
+        self.fields.replace(fields);
+
+        self.dialog.set_title(Some(&format!("New {}", template.meta.name.to_lowercase())));
+        self.project_bx.set_visible(template.meta.kind == TemplateKind::Project);
+        self.status_lbl.set_text("");
+        self.update_sensitive();
+        self.dialog.show();
+    }
+
+    /// Called with the source of a new document template, with its parameters replaced.
+    pub fn connect_document<F>(&self, f : F)
+    where
+        F : Fn(String) + 'static
+    {
+        self.on_document.bind(f);
+    }
+
+    /// Called with the path to the main file of a new project.
+    pub fn connect_project_created<F>(&self, f : F)
+    where
+        F : Fn(PathBuf) + 'static
+    {
+        self.on_project_created.bind(f);
+    }
+
+}
+
+impl React<FileManager> for TemplateWizard {
+
+    fn react(&self, manager : &FileManager) {
+        let send = manager.sender().clone();
+        self.connect_project_created(move |path| {
+            send.send(filecase::SingleArchiverAction::OpenRequest(path.display().to_string())).unwrap();
+        });
+    }
+
+}
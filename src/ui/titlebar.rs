@@ -17,6 +17,8 @@ pub struct MainMenu {
     pub actions : FileActions,
     // pub action_close : gio::SimpleAction,
     pub export_action : gio::SimpleAction,
+    pub export_latex_action : gio::SimpleAction,
+    pub history_action : gio::SimpleAction,
     pub open_dialog : OpenDialog,
     pub save_dialog : SaveDialog,
 }
@@ -25,6 +27,7 @@ impl MainMenu {
 
     fn build() -> Self {
         let menu = gio::Menu::new();
+        menu.append(Some("New tab"), Some("win.new_tab"));
         menu.append(Some("Open"), Some("win.open_file"));
 
         // Actually, "closing" is creating a new file from scratch.
@@ -35,6 +38,14 @@ impl MainMenu {
         menu.append(Some("Save"), Some("win.save_file"));
         menu.append(Some("Save as"), Some("win.save_as_file"));
         menu.append(Some("Export"), Some("win.export"));
+        menu.append(Some("Export LaTeX"), Some("win.export_latex"));
+        menu.append(Some("Save as template"), Some("win.save_template"));
+        menu.append(Some("Format document"), Some("win.format_document"));
+        menu.append(Some("History"), Some("win.history"));
+        menu.append(Some("Changes"), Some("win.git"));
+        menu.append(Some("Review"), Some("win.review"));
+        menu.append(Some("Track changes"), Some("win.track_changes"));
+        menu.append(Some("Preferences"), Some("win.preferences"));
         let popover = PopoverMenu::from_model(Some(&menu));
         let actions = FileActions::new();
         let open_dialog = OpenDialog::build(&["*.typ"]);
@@ -43,7 +54,13 @@ impl MainMenu {
         let export_action = gio::SimpleAction::new("export", None);
         // let action_close = gio::SimpleAction::new("close_file", None);
         export_action.set_enabled(false);
-        Self { popover, actions, open_dialog, save_dialog, /*export_dialog,*/ export_action, /*action_close*/ }
+        let export_latex_action = gio::SimpleAction::new("export_latex", None);
+        export_latex_action.set_enabled(false);
+
+        // Only enabled when the document has a path, since snapshots are kept per file.
+        let history_action = gio::SimpleAction::new("history", None);
+        history_action.set_enabled(false);
+        Self { popover, actions, open_dialog, save_dialog, /*export_dialog,*/ export_action, export_latex_action, history_action, /*action_close*/ }
     }
 
 }
@@ -81,6 +98,7 @@ pub struct Titlebar {
     // pub refresh_btn : Button,
     pub zoom_in_btn : Button,
     pub zoom_out_btn : Button,
+    pub view_btn : MenuButton,
     pub left_btn : Button,
     pub right_btn : Button,
     pub zoom_action : gio::SimpleAction,
@@ -847,6 +865,7 @@ impl Titlebar {
     pub fn set_prepared(&self, prepared : bool) {
         self.main_menu.actions.save.set_enabled(prepared);
         self.main_menu.actions.save_as.set_enabled(prepared);
+        self.main_menu.export_latex_action.set_enabled(prepared);
         self.pdf_btn.set_sensitive(prepared);
         self.view_pdf_btn.set_sensitive(prepared);
         self.set_typeset_mode(false);
@@ -861,6 +880,7 @@ impl Titlebar {
         self.view_pdf_btn.set_active(active);
         self.zoom_in_btn.set_sensitive(active);
         self.zoom_out_btn.set_sensitive(active);
+        self.view_btn.set_sensitive(active);
         self.left_btn.set_sensitive(active);
         self.right_btn.set_sensitive(active);
         self.main_menu.export_action.set_enabled(active);
@@ -1048,6 +1068,29 @@ impl Titlebar {
         zoom_in_btn.set_icon_name("zoom-in-symbolic");
         zoom_out_btn.set_icon_name("zoom-out-symbolic");
 
+        // The view_mode, fit_mode and frame_preview actions are stateful actions of the PDF viewer.
+        let view_menu = gio::Menu::new();
+        let mode_section = gio::Menu::new();
+        mode_section.append(Some("Single page"), Some("win.view_mode::single"));
+        mode_section.append(Some("Continuous"), Some("win.view_mode::continuous"));
+        mode_section.append(Some("Two pages"), Some("win.view_mode::spread"));
+        mode_section.append(Some("Two pages with cover"), Some("win.view_mode::spread-cover"));
+        let fit_section = gio::Menu::new();
+        fit_section.append(Some("Free zoom"), Some("win.fit_mode::manual"));
+        fit_section.append(Some("Fit page"), Some("win.fit_mode::page"));
+        fit_section.append(Some("Fit width"), Some("win.fit_mode::width"));
+        view_menu.append_section(None, &mode_section);
+        view_menu.append_section(None, &fit_section);
+        let source_section = gio::Menu::new();
+        source_section.append(Some("Render with typst"), Some("win.frame_preview"));
+        source_section.append(Some("Show sidebar"), Some("win.preview_sidebar"));
+        source_section.append(Some("Compare with previous version"), Some("win.compare_versions"));
+        source_section.append(Some("Find in preview"), Some("win.find_preview"));
+        source_section.append(Some("Copy selection"), Some("win.copy_preview"));
+        view_menu.append_section(None, &source_section);
+        let view_btn = MenuButton::builder().icon_name("view-paged-symbolic").menu_model(&view_menu).build();
+        view_btn.set_sensitive(false);
+
         let nav_bx = Box::new(Orientation::Horizontal, 0);
         let left_btn = Button::from_icon_name("carousel-arrow-previous-symbolic");
         let right_btn = Button::from_icon_name("carousel-arrow-next-symbolic");
@@ -1092,6 +1135,7 @@ impl Titlebar {
         // header.pack_end(&hide_pdf_btn);
         header.pack_end(&nav_bx);
         header.pack_end(&zoom_bx);
+        header.pack_end(&view_btn);
         // header.pack_end(&toggle_bx);
         header.pack_end(&view_pdf_btn);
 
@@ -1114,7 +1158,8 @@ impl Titlebar {
         });*/
 
         // let zoom = Rc::new(RefCell::new(DEFAULT_SCALE));
-        let zoom_action = gio::SimpleAction::new_stateful("zoom_change", None, &(DEFAULT_ZOOM_SCALE).to_variant());
+        let default_zoom = crate::settings::Preferences::current().default_zoom;
+        let zoom_action = gio::SimpleAction::new_stateful("zoom_change", None, &default_zoom.to_variant());
         let das : Rc<RefCell<Vec<DrawingArea>>> = Rc::new(RefCell::new(Vec::new()));
         zoom_in_btn.connect_clicked({
             // let zoom = zoom.clone();
@@ -1197,6 +1242,7 @@ impl Titlebar {
             paper_popover,
             zoom_in_btn,
             zoom_out_btn,
+            view_btn,
             left_btn,
             right_btn,
             zoom_action,
@@ -1406,8 +1452,16 @@ impl React<FileManager> for Titlebar {
     fn react(&self, manager : &FileManager) {
         manager.connect_opened({
             let pdf_btn = self.pdf_btn.clone();
+            let history_action = self.main_menu.history_action.clone();
             move |_| {
                 pdf_btn.set_sensitive(true);
+                history_action.set_enabled(true);
+            }
+        });
+        manager.connect_new({
+            let history_action = self.main_menu.history_action.clone();
+            move |_| {
+                history_action.set_enabled(false);
             }
         });
         /*manager.connect_new({
@@ -1418,8 +1472,10 @@ impl React<FileManager> for Titlebar {
         });*/
         manager.connect_save({
             let pdf_btn = self.pdf_btn.clone();
+            let history_action = self.main_menu.history_action.clone();
             move |_| {
                 pdf_btn.set_sensitive(true);
+                history_action.set_enabled(true);
             }
         });
         manager.connect_file_changed({
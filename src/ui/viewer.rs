@@ -0,0 +1,1402 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use super::*;
+use serde::{Serialize, Deserialize};
+use crate::renderer::{PageRenderer, PageFilter};
+use crate::typst_tools::{FramePreview, Navigation, LinkTarget};
+use stateful::Callbacks;
+use std::collections::HashMap;
+use std::cell::Cell;
+
+// How the typeset pages are laid out at the viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViewMode {
+
+    // A single page at a time, turned with the navigation buttons or horizontal scroll.
+    Single,
+
+    // All pages in a vertical column.
+    Continuous,
+
+    // Pairs of pages side by side, in a vertical column.
+    Spread,
+
+    // Pairs of pages side by side, with the first page (the cover) alone at the first row.
+    SpreadCover
+
+}
+
+impl ViewMode {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Single => "single",
+            Self::Continuous => "continuous",
+            Self::Spread => "spread",
+            Self::SpreadCover => "spread-cover"
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "single" => Some(Self::Single),
+            "continuous" => Some(Self::Continuous),
+            "spread" => Some(Self::Spread),
+            "spread-cover" => Some(Self::SpreadCover),
+            _ => None
+        }
+    }
+
+    fn pages_per_row(&self) -> usize {
+        match self {
+            Self::Spread | Self::SpreadCover => 2,
+            _ => 1
+        }
+    }
+
+    // Whether the page at the given index is the first one of its row.
+    fn starts_row(&self, page_ix : usize) -> bool {
+        match self {
+            Self::Spread => page_ix % 2 == 0,
+            Self::SpreadCover => page_ix == 0 || page_ix % 2 == 1,
+            _ => true
+        }
+    }
+
+}
+
+// How the zoom is set. Fitting modes re-calculate the zoom whenever the viewer is resized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FitMode {
+
+    // Zoom set by the user with the zoom buttons.
+    Manual,
+
+    // Whole page (or page spread) visible.
+    Page,
+
+    // Page (or page spread) occupies the full viewer width.
+    Width
+
+}
+
+impl FitMode {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Manual => "manual",
+            Self::Page => "page",
+            Self::Width => "width"
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "manual" => Some(Self::Manual),
+            "page" => Some(Self::Page),
+            "width" => Some(Self::Width),
+            _ => None
+        }
+    }
+
+}
+
+// View preferences persisted across sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewerState {
+    pub mode : ViewMode,
+    pub fit : FitMode,
+
+    // Whether pages are rendered from the typst frames instead of the exported PDF.
+    #[serde(default)]
+    pub frame_preview : bool
+}
+
+impl Default for ViewerState {
+
+    fn default() -> Self {
+        Self { mode : ViewMode::Single, fit : FitMode::Manual, frame_preview : false }
+    }
+
+}
+
+// Space around pages, set at crate::configure_da_for_doc
+const PAGE_MARGIN : f64 = 16.0;
+
+const MIN_FIT_ZOOM : f64 = 0.5;
+
+const MAX_FIT_ZOOM : f64 = 5.0;
+
+// Position of the viewer, kept across recompilations.
+#[derive(Debug, Clone)]
+struct ViewAnchor {
+
+    page : usize,
+
+    // Distance (in points) from the page top to the top of the viewport.
+    offset : f64,
+
+    // Title and level of the closest heading above the viewport at the same page, and
+    // the distance (in points) from the heading to the top of the viewport. The heading
+    // is followed when the text before it changes the page it is at.
+    heading : Option<(String, usize, f64)>
+
+}
+
+#[derive(Debug, Clone)]
+pub struct PdfViewer {
+    scroll : ScrolledWindow,
+    pages_bx : Box,
+    das : Rc<RefCell<Vec<DrawingArea>>>,
+    pub doc : Rc<RefCell<Option<poppler::Document>>>,
+
+    // Set instead of doc when the pages are rendered from the typst frames.
+    pub frames : Rc<RefCell<Option<FramePreview>>>,
+
+    // Size (in points) of each page of the current document.
+    pages : Rc<RefCell<Vec<(f64, f64)>>>,
+    da1 : DrawingArea,
+    da2 : DrawingArea,
+    curr_page : Rc<RefCell<usize>>,
+    stack : Stack,
+    pub turn_action : gio::SimpleAction,
+    pub view_mode_action : gio::SimpleAction,
+    pub fit_mode_action : gio::SimpleAction,
+    pub frame_preview_action : gio::SimpleAction,
+    pub find_action : gio::SimpleAction,
+    pub copy_action : gio::SimpleAction,
+    pub find_bar : FindBar,
+    highlights : Highlights,
+    pub sidebar_action : gio::SimpleAction,
+    pub sidebar : PreviewSidebar,
+    pub compare_action : gio::SimpleAction,
+    pub compare : ComparePanel,
+    view_stack : Stack,
+    nav : Rc<RefCell<Navigation>>,
+
+    // Position before the last recompilation, restored when the new pages are shown.
+    anchor : Rc<RefCell<Option<ViewAnchor>>>,
+
+    // Text of the pages searched or selected so far, extracted on demand.
+    texts : Rc<RefCell<HashMap<usize, PageText>>>,
+
+    on_source_clicked : Callbacks<usize>,
+    zoom_action : gio::SimpleAction,
+    pub renderer : PageRenderer,
+    pub bar : ActionBar,
+    bar_lbl : Label,
+    pub bx : Box
+}
+
+impl React<Titlebar> for PdfViewer {
+    fn react(&self, titlebar : &Titlebar) {
+        titlebar.zoom_action.connect_activate({
+            let das = self.das.clone();
+            let (da1, da2) = (self.da1.clone(), self.da2.clone());
+            move |_,_| {
+                das.borrow().iter().for_each(|da| da.queue_draw() );
+                da1.queue_draw();
+                da2.queue_draw();
+            }
+        });
+        /*titlebar.pdf_btn.connect_toggled({
+            let viewer = self.clone();
+            move |btn| {
+                // if !btn.is_active() {
+                //    viewer.clear_pages();
+                // }
+            }
+        });*/
+
+        // Called by event controller (instead of text changed event)
+        // because the application changes the text at the entry too frequently without
+        // user input (like button/arrow press or swipe).
+        // Here we are sure this happened after some user input.
+        let ev = EventControllerKey::new();
+        titlebar.page_entry.add_controller(&ev);
+        ev.connect_key_released({
+            let viewer = self.clone();
+            let pages = self.pages.clone();
+            let da1 = self.da1.clone();
+            let da2 = self.da2.clone();
+            let stack = self.stack.clone();
+            let turn_action = self.turn_action.clone();
+            let page_entry = titlebar.page_entry.clone();
+            let curr_page = self.curr_page.clone();
+            move |_, _, _, _| {
+                let txt = page_entry.text();
+                if txt.is_empty() {
+                    return;
+                }
+
+                // The user pages count from 1..n. The internal state count from
+                // 0..n-1 (as poppler does).
+                if let Ok(new_page) = txt.parse::<i32>() {
+                    if viewer.view_mode() != ViewMode::Single {
+                        if new_page >= 1 {
+                            viewer.scroll_to_page(new_page as usize - 1);
+                        }
+                        return;
+                    }
+                    go_to_page(
+                        &pages,
+                        &da1,
+                        &da2,
+                        &curr_page,
+                        &turn_action,
+                        &stack,
+                        new_page
+                    );
+                }
+            }
+        });
+
+        titlebar.left_btn.connect_clicked({
+            let viewer = self.clone();
+            let pages = self.pages.clone();
+            let da1 = self.da1.clone();
+            let da2 = self.da2.clone();
+            let stack = self.stack.clone();
+            let turn_action = self.turn_action.clone();
+            let page_entry = titlebar.page_entry.clone();
+            let curr_page = self.curr_page.clone();
+            move |_| {
+                if viewer.view_mode() != ViewMode::Single {
+                    viewer.scroll_by_rows(true);
+                    return;
+                }
+                turn_page(&stack, &pages, &curr_page, &da1, &da2, &turn_action, true);
+            }
+        });
+        titlebar.right_btn.connect_clicked({
+            let viewer = self.clone();
+            let pages = self.pages.clone();
+            let da1 = self.da1.clone();
+            let da2 = self.da2.clone();
+            let stack = self.stack.clone();
+            let turn_action = self.turn_action.clone();
+            let page_entry = titlebar.page_entry.clone();
+            let curr_page = self.curr_page.clone();
+            move |_| {
+                if viewer.view_mode() != ViewMode::Single {
+                    viewer.scroll_by_rows(false);
+                    return;
+                }
+                turn_page(&stack, &pages, &curr_page, &da1, &da2, &turn_action, false);
+            }
+        });
+
+        // Zooming with the buttons leaves the fitting modes.
+        for btn in [&titlebar.zoom_in_btn, &titlebar.zoom_out_btn] {
+            btn.connect_clicked({
+                let fit_mode_action = self.fit_mode_action.clone();
+                move |_| {
+                    fit_mode_action.set_state(&FitMode::Manual.name().to_variant());
+                }
+            });
+        }
+
+    }
+}
+
+fn go_to_page(
+    pages : &Rc<RefCell<Vec<(f64, f64)>>>,
+    da1 : &DrawingArea,
+    da2 : &DrawingArea,
+    curr_page : &Rc<RefCell<usize>>,
+    turn_action : &gio::SimpleAction,
+    stack : &Stack,
+    new_page : i32
+) {
+    if new_page >= 1 {
+        if new_page as usize <= pages.borrow().len() {
+            let mut curr_page = curr_page.borrow_mut();
+            if new_page as usize - 1 == *curr_page {
+                return;
+            }
+            if new_page as usize - 1 > *curr_page {
+                stack.set_transition_type(StackTransitionType::SlideLeft);
+            } else {
+                stack.set_transition_type(StackTransitionType::SlideRight);
+            }
+            *curr_page = new_page as usize - 1;
+            turn_action.set_state(&(new_page - 1).to_variant());
+            draw_at_even_or_odd(&stack, &da1, &da2, new_page as usize - 1);
+        }
+    }
+}
+
+// Equivalent to 0xdc
+// const PAGE_BORDER_COLOR : f64 = 0.859375;
+
+// Equivalent to 0xcf
+pub const PAGE_BORDER_COLOR : f64 = 0.80859375;
+
+pub const PAGE_BORDER_WIDTH : f64 = 0.5;
+
+fn draw_at_even_or_odd(stack : &Stack, da1 : &DrawingArea, da2 : &DrawingArea, curr_page : usize) {
+    if curr_page % 2 == 0 {
+        stack.set_visible_child_name("left");
+        da1.queue_draw();
+    } else {
+        stack.set_visible_child_name("right");
+        da2.queue_draw();
+    }
+}
+
+fn turn_page(
+    stack : &Stack,
+    pages : &Rc<RefCell<Vec<(f64, f64)>>>,
+    curr_page : &Rc<RefCell<usize>>,
+    da1 : &DrawingArea,
+    da2 : &DrawingArea,
+    turn_action : &gio::SimpleAction,
+    left : bool
+) {
+    // da1.queue_draw();
+    // da2.queue_draw();
+    let mut cp = curr_page.borrow_mut();
+    let n_pages = if let Ok(pages) = pages.try_borrow() {
+        pages.len()
+    } else {
+        return;
+    };
+    if n_pages == 0 {
+        return;
+    }
+    if *cp == 0 && left {
+        return;
+    }
+    if (*cp == n_pages-1 && !left) {
+        return;
+    }
+
+    if left {
+        *cp -= 1;
+        stack.set_transition_type(StackTransitionType::SlideRight);
+    } else {
+        *cp += 1;
+        stack.set_transition_type(StackTransitionType::SlideLeft);
+    }
+
+    draw_at_even_or_odd(stack, da1, da2, *cp);
+
+    turn_action.set_state(&(*cp as i32).to_variant());
+    turn_action.activate(None);
+}
+
+impl PdfViewer {
+
+    pub fn doc(&self) -> &Rc<RefCell<Option<poppler::Document>>> {
+        &self.doc
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.pages.borrow().len()
+    }
+
+    pub fn clear_pages(&self) {
+        self.remove_page_areas();
+        self.renderer.clear();
+        self.doc.replace(None);
+        self.frames.replace(None);
+        self.pages.borrow_mut().clear();
+        self.texts.borrow_mut().clear();
+        self.highlights.clear();
+        self.set_navigation(Navigation::default());
+        self.sidebar.set_pages(&[], &self.renderer, &self.highlights);
+        self.anchor.replace(None);
+        *(self.curr_page.borrow_mut()) = 0;
+    }
+
+    /// Stops the page renderers, when the document is closed.
+    pub fn shutdown(&self) {
+        self.clear_pages();
+        self.renderer.shutdown();
+        self.compare.shutdown();
+    }
+
+    pub fn new(zoom_action : &gio::SimpleAction) -> Self {
+        let scroll = ScrolledWindow::new();
+        scroll.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+        let pages_bx = Box::new(Orientation::Vertical, 12);
+        // scroll.set_child(Some(&pages_bx));
+        let das = Rc::new(RefCell::new(Vec::new()));
+        let da1 = DrawingArea::new();
+        let da2 = DrawingArea::new();
+        let stack = Stack::new();
+        stack.set_vexpand(true);
+        let click = GestureClick::new();
+        let curr_page = Rc::new(RefCell::new(0));
+        let doc = Rc::new(RefCell::new(None));
+        let pages : Rc<RefCell<Vec<(f64, f64)>>> = Default::default();
+
+        click.connect_pressed({
+            let stack = stack.clone();
+            move|_, _, _, _| {
+                stack.grab_focus();
+            }
+        });
+        stack.add_controller(&click);
+        let controller = EventControllerKey::new();
+        stack.add_controller(&controller);
+        controller.connect_key_pressed(|ev, key, code, modifier| {
+            glib::signal::Inhibit(false)
+        });
+        let scroll_ev = EventControllerScroll::new(EventControllerScrollFlags::HORIZONTAL);
+        let turn_action = gio::SimpleAction::new_stateful("sidebar_hide", None, &(0i32).to_variant());
+        let view_mode_action = gio::SimpleAction::new_stateful(
+            "view_mode",
+            Some(glib::VariantTy::STRING),
+            &ViewMode::Single.name().to_variant()
+        );
+        let fit_mode_action = gio::SimpleAction::new_stateful(
+            "fit_mode",
+            Some(glib::VariantTy::STRING),
+            &FitMode::Manual.name().to_variant()
+        );
+        let frame_preview_action = gio::SimpleAction::new_stateful("frame_preview", None, &false.to_variant());
+        scroll_ev.connect_scroll({
+            let view_mode_action = view_mode_action.clone();
+            let stack = stack.clone();
+            let sw = scroll.clone();
+            let pages = pages.clone();
+            let curr_page = curr_page.clone();
+            let (da1, da2) = (da1.clone(), da2.clone());
+            let turn_action = turn_action.clone();
+            move|ev, a, b| {
+
+                // Other modes are scrolled as a whole.
+                if view_mode_action.state().and_then(|s| s.get::<String>() ).as_deref() != Some(ViewMode::Single.name()) {
+                    return glib::signal::Inhibit(false);
+                }
+
+                // Automatically handled at edge_overshoot in this case. When we have
+                // a horizontal bar, we should not move the page!
+                let has_hbar = sw.allocation().width() != stack.allocation().width();
+                if has_hbar {
+                    return glib::signal::Inhibit(false);
+                }
+
+                if a < 0.0 {
+                    turn_page(&stack, &pages, &curr_page, &da1, &da2, &turn_action, true);
+                } else if a > 0.0 {
+                    turn_page(&stack, &pages, &curr_page, &da1, &da2, &turn_action, false);
+                }
+
+                glib::signal::Inhibit(false)
+            }
+        });
+        /*scroll.connect_edge_reached({
+            let stack = stack.clone();
+            move |s, pos| {
+                println!("reached");
+            }
+        });
+        scroll.connect_edge_overshot({
+            let stack = stack.clone();
+            move|s, pos| {
+                println!("overshoot");
+                /*match pos {
+                    Position::Left => { turn_page(&stack, true); },
+                    Position::Right => { turn_page(&stack, false); },
+                    _ => { }
+                }*/
+            }
+        });*/
+        scroll.add_controller(&scroll_ev);
+
+        // When passing a page, return zoom to best window fit, so the user does not
+        // need to worry about moving to the edge of the screen again before moving
+        // to the next page.
+
+        // TODO only pass page at the second overshoot (never at the first).
+
+        /*scroll.connect_scroll_end({
+            // let trajx = trajx.clone();
+            let curr_page = self.curr_page.clone();
+            let doc = self.doc.clone();
+            let stack = stack.clone();
+            let sw = self.scroll.clone();
+            move |ev| {
+
+                println!("End {:?}", ev);
+                let has_hbar = sw.allocation().width != stack.allocation().width;
+
+                // Automatically handled at edge_overshoot
+                if has_hbar {
+                    return;
+                }
+
+                // let s = sw.hscrollbar().unwrap().downcast_ref::<Scrollbar>().unwrap();
+                println!("Scroll end: {}", has_hbar );
+
+                let mut trajx = trajx.borrow_mut();
+                if let (Some(fst), Some(lst)) = (trajx.first(), trajx.last()) {
+                    let dx = *lst - *fst;
+                    let tl = trajx.len();
+                    println!("traj len: {tl}, scroll: {dx}");
+
+                    // Move to next page
+                    if dx < 0.0 {
+                        stack.set_transition_type(StackTransitionType::SlideLeft);
+                        stack.set_visible_child_name("right");
+                    } else {
+                        // Move to prev page
+                        stack.set_transition_type(StackTransitionType::SlideRight);
+                        stack.set_visible_child_name("left");
+                    }
+                }
+                trajx.clear();
+            }
+        });*/
+
+        stack.add_named(&da1, Some("left"));
+        stack.add_named(&da2, Some("right"));
+        let renderer = PageRenderer::new();
+        let highlights = Highlights::default();
+        for (da_pos, da) in [(0, &da1), (1, &da2)] {
+            da.set_draw_func({
+                let zoom_action = zoom_action.clone();
+                let pages = pages.clone();
+                let curr_page = curr_page.clone();
+                let renderer = renderer.clone();
+                let highlights = highlights.clone();
+                move |da, ctx, _, _| {
+                    let cp = curr_page.borrow();
+                    let pages = pages.borrow();
+                    if pages.is_empty() {
+                        eprintln!("No doc at draw");
+                    } else if let Some(size) = pages.get(*cp) {
+                        crate::adjust_dimension_for_size(da, &zoom_action, *size);
+                        crate::draw_rendered_page_content(da, ctx, &zoom_action, &renderer, *cp, true);
+                        let z = zoom_action.state().unwrap().get::<f64>().unwrap();
+                        highlights.draw(ctx, *cp, z);
+                    } else {
+                        eprintln!("No page {} at draw", *cp);
+                    }
+                }
+            });
+        }
+
+        crate::configure_da_for_doc(&da1);
+        crate::configure_da_for_doc(&da2);
+
+        scroll.set_child(Some(&stack));
+        let bar = ActionBar::new();
+        bar.set_height_request(48);
+        bar.set_revealed(true);
+        let bar_lbl = Label::new(Some("Unsaved changes must be committed to file before typesetting"));
+        let bar_bx = Box::new(Orientation::Horizontal, 0);
+        bar_bx.append(&bar_lbl);
+        bar_bx.set_hexpand(true);
+        bar_bx.set_halign(Align::Fill);
+        let close_btn = Button::from_icon_name("window-close-symbolic");
+        close_btn.connect_clicked({
+            let bar = bar.clone();
+            move |_| {
+                if bar.is_revealed() {
+                    bar.set_revealed(false);
+                }
+            }
+        });
+        bar_bx.append(&close_btn);
+        close_btn.style_context().add_class("flat");
+        close_btn.set_hexpand(true);
+        close_btn.set_halign(Align::End);
+        bar_lbl.set_hexpand(true);
+        bar_lbl.set_justify(Justification::Left);
+        bar_lbl.set_halign(Align::Start);
+
+        bar.set_center_widget(Some(&bar_bx));
+        // Focusable so that the preview can take the copy shortcut after a selection.
+        scroll.set_focusable(true);
+        let find_bar = FindBar::build();
+        let sidebar = PreviewSidebar::build();
+        scroll.set_hexpand(true);
+        let content_bx = Box::new(Orientation::Horizontal, 0);
+        content_bx.append(&sidebar.revealer);
+        content_bx.append(&scroll);
+
+        // Compare mode replaces the pages by the comparison with the previous version.
+        let compare = ComparePanel::build(&renderer, zoom_action);
+        let view_stack = Stack::new();
+        view_stack.add_named(&content_bx, Some("pages"));
+        view_stack.add_named(&compare.bx, Some("compare"));
+        view_stack.set_visible_child_name("pages");
+        let compare_action = gio::SimpleAction::new_stateful("compare_versions", None, &false.to_variant());
+
+        let bx = Box::new(Orientation::Vertical, 0);
+        bx.append(&find_bar.bar);
+        bx.append(&view_stack);
+        bx.append(&bar);
+        let sidebar_action = gio::SimpleAction::new_stateful("preview_sidebar", None, &false.to_variant());
+        let find_action = gio::SimpleAction::new("find_preview", None);
+        let copy_action = gio::SimpleAction::new("copy_preview", None);
+
+        let viewer = Self {
+            scroll,
+            das,
+            pages_bx,
+            doc,
+            frames : Default::default(),
+            pages,
+            da1,
+            da2,
+            curr_page,
+            stack,
+            turn_action,
+            view_mode_action,
+            fit_mode_action,
+            frame_preview_action,
+            find_action,
+            copy_action,
+            find_bar,
+            highlights,
+            sidebar_action,
+            sidebar,
+            compare_action,
+            compare,
+            view_stack,
+            nav : Default::default(),
+            anchor : Default::default(),
+            texts : Default::default(),
+            on_source_clicked : Default::default(),
+            zoom_action : zoom_action.clone(),
+            renderer,
+            bar,
+            bar_lbl,
+            bx
+        };
+        viewer.connect_modes();
+        viewer.connect_search();
+        viewer.connect_navigation();
+        viewer.connect_compare();
+        viewer.connect_page_gestures(&viewer.da1, None);
+        viewer.connect_page_gestures(&viewer.da2, None);
+        viewer.connect_preferences();
+        viewer
+    }
+
+    // Page colors follow the user preferences, and the bar follows the application style.
+    fn connect_preferences(&self) {
+        self.renderer.set_filter(PageFilter::from_name(&crate::settings::Preferences::current().page_filter));
+        crate::settings::connect_changed({
+            let viewer = self.clone();
+            move |prefs| {
+                if viewer.bx.root().is_none() {
+                    return Continue(false);
+                }
+                let filter = PageFilter::from_name(&prefs.page_filter);
+                if filter != viewer.renderer.filter() {
+                    viewer.renderer.set_filter(filter);
+                    viewer.queue_draw_pages();
+                    viewer.compare.queue_draw();
+                }
+                Continue(true)
+            }
+        });
+        super::add_bar_css(&self.bar);
+    }
+
+    // Double-clicking a page rendered from typst frames reports the source line of the content
+    // under the pointer, and dragging over a page selects its text. Areas without a page
+    // index show the current page.
+    fn connect_page_gestures(&self, da : &DrawingArea, page_ix : Option<usize>) {
+        let drag = GestureDrag::new();
+        let start : Rc<Cell<(f64, f64)>> = Rc::new(Cell::new((0.0, 0.0)));
+        drag.connect_drag_begin({
+            let viewer = self.clone();
+            let start = start.clone();
+            move |_, x, y| {
+                start.set((x, y));
+                viewer.scroll.grab_focus();
+                viewer.highlights.clear_selection();
+                viewer.queue_draw_pages();
+            }
+        });
+        drag.connect_drag_update({
+            let viewer = self.clone();
+            let start = start.clone();
+            let da = da.clone();
+            move |_, dx, dy| {
+                let page_ix = page_ix.unwrap_or_else(|| *viewer.curr_page.borrow() );
+                let z = viewer.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+                let (x0, y0) = start.get();
+                let area = ((x0 + dx.min(0.0)) / z, (y0 + dy.min(0.0)) / z, dx.abs() / z, dy.abs() / z);
+                if let Some((text, rects)) = viewer.with_page_text(page_ix, |text| text.select(area) ) {
+                    viewer.highlights.set_selection(page_ix, rects, text);
+                    da.queue_draw();
+                }
+            }
+        });
+        da.add_controller(&drag);
+
+        // Hovering a link shows its target, and clicking it follows the link.
+        let motion = EventControllerMotion::new();
+        motion.connect_motion({
+            let viewer = self.clone();
+            let da = da.clone();
+            move |_, x, y| {
+                match viewer.link_at(page_ix, x, y) {
+                    Some(LinkTarget::Url(url)) => {
+                        da.set_cursor_from_name(Some("pointer"));
+                        da.set_tooltip_text(Some(&url));
+                    },
+                    Some(LinkTarget::Page(page, _)) => {
+                        da.set_cursor_from_name(Some("pointer"));
+                        da.set_tooltip_text(Some(&format!("Go to page {}", page + 1)));
+                    },
+                    None => {
+                        da.set_cursor_from_name(None);
+                        da.set_tooltip_text(None);
+                    }
+                }
+            }
+        });
+        da.add_controller(&motion);
+
+        let click = GestureClick::new();
+        click.connect_released({
+            let viewer = self.clone();
+            let da = da.clone();
+            move |_, n_press, x, y| {
+                if n_press != 1 {
+                    return;
+                }
+                match viewer.link_at(page_ix, x, y) {
+                    Some(LinkTarget::Page(page, y)) => {
+                        viewer.show_position(page, y);
+                    },
+                    Some(LinkTarget::Url(url)) => {
+                        // Opened through the desktop portal when running sandboxed.
+                        let win = da.root().and_then(|root| root.downcast::<Window>().ok() );
+                        gtk4::show_uri(win.as_ref(), &url, gdk::CURRENT_TIME);
+                    },
+                    None => { }
+                }
+            }
+        });
+        click.connect_pressed({
+            let viewer = self.clone();
+            move |_, n_press, x, y| {
+                if n_press != 2 {
+                    return;
+                }
+                let page_ix = page_ix.unwrap_or_else(|| *viewer.curr_page.borrow() );
+                let z = viewer.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+                let line = viewer.frames.borrow().as_ref()
+                    .and_then(|frames| frames.pages.get(page_ix) )
+                    .and_then(|page| page.line_at(x / z, y / z) );
+                if let Some(line) = line {
+                    viewer.on_source_clicked.call(line);
+                }
+            }
+        });
+        da.add_controller(&click);
+    }
+
+    // Link under the pointer, given the position at a page area.
+    fn link_at(&self, page_ix : Option<usize>, x : f64, y : f64) -> Option<LinkTarget> {
+        let page_ix = page_ix.unwrap_or_else(|| *self.curr_page.borrow() );
+        let z = self.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+        self.nav.borrow().links.get(page_ix)?
+            .iter()
+            .find(|link| link.contains(x / z, y / z) )
+            .map(|link| link.target.clone() )
+    }
+
+    fn connect_compare(&self) {
+        self.compare_action.connect_activate({
+            let viewer = self.clone();
+            move |action, _| {
+                let active = !action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+                action.set_state(&active.to_variant());
+                if active {
+                    viewer.view_stack.set_visible_child_name("compare");
+                    viewer.compare.compare();
+                } else {
+                    viewer.view_stack.set_visible_child_name("pages");
+                }
+            }
+        });
+    }
+
+    fn connect_navigation(&self) {
+        self.sidebar_action.connect_activate({
+            let revealer = self.sidebar.revealer.clone();
+            move |action, _| {
+                let active = !action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+                action.set_state(&active.to_variant());
+                revealer.set_reveal_child(active);
+            }
+        });
+        self.sidebar.connect_outline_selected({
+            let viewer = self.clone();
+            move |entry| {
+                viewer.show_position(entry.page, entry.y);
+            }
+        });
+        self.sidebar.connect_page_selected({
+            let viewer = self.clone();
+            move |page| {
+                viewer.show_position(page, 0.0);
+            }
+        });
+
+        // The current page thumbnail follows the pages turned or scrolled at the viewer.
+        self.turn_action.connect_state_notify({
+            let sidebar = self.sidebar.clone();
+            move |action| {
+                if let Some(page) = action.state().and_then(|s| s.get::<i32>() ) {
+                    sidebar.select_page(page.max(0) as usize);
+                }
+            }
+        });
+    }
+
+    /// Sets the links and outline of the document about to be shown.
+    pub fn set_navigation(&self, nav : Navigation) {
+        if self.n_pages() > 0 {
+            self.anchor.replace(Some(self.current_anchor()));
+        }
+        self.sidebar.set_outline(nav.outline.clone());
+        self.nav.replace(nav);
+    }
+
+    pub fn current_page(&self) -> usize {
+        *self.curr_page.borrow()
+    }
+
+    // Vertical position of the page top relative to the scrolled content.
+    fn page_top(&self, page : usize) -> f64 {
+        if self.view_mode() == ViewMode::Single {
+            return 0.0;
+        }
+        self.das.borrow().get(page)
+            .and_then(|da| da.compute_bounds(&self.pages_bx) )
+            .map(|b| b.y() as f64 )
+            .unwrap_or(0.0)
+    }
+
+    fn current_anchor(&self) -> ViewAnchor {
+        let page = self.current_page();
+        let z = self.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+        let offset = (self.scroll.vadjustment().value() - self.page_top(page)) / z;
+        let heading = self.nav.borrow().outline.iter()
+            .filter(|entry| entry.page == page && entry.y <= offset )
+            .last()
+            .map(|entry| (entry.title.clone(), entry.level, offset - entry.y) );
+        ViewAnchor { page, offset, heading }
+    }
+
+    // Page and offset (in points) the anchor corresponds to at the current document.
+    fn resolve_anchor(&self, anchor : &ViewAnchor) -> (usize, f64) {
+        let n_pages = self.n_pages();
+        if n_pages == 0 {
+            return (0, 0.0);
+        }
+        if let Some((title, level, delta)) = &anchor.heading {
+            let nav = self.nav.borrow();
+            let entry = nav.outline.iter()
+                .filter(|entry| &entry.title == title && entry.level == *level )
+                .min_by_key(|entry| (entry.page as i64 - anchor.page as i64).abs() );
+            if let Some(entry) = entry {
+                return (entry.page, entry.y + delta);
+            }
+        }
+        (anchor.page.min(n_pages - 1), anchor.offset)
+    }
+
+    // Scrolls so that the position (in points) at the page is at the top of the viewport.
+    // Page positions are only known after the page areas are allocated.
+    fn restore_position(&self, page : usize, offset : f64) {
+        glib::idle_add_local_once({
+            let viewer = self.clone();
+            move || {
+                let z = viewer.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+                let top = viewer.page_top(page);
+                viewer.scroll.vadjustment().set_value(top + offset * z);
+            }
+        });
+    }
+
+    /// Called with the (zero-based) source line when the user double-clicks
+    /// a page rendered from typst frames.
+    pub fn connect_source_clicked<F>(&self, f : F)
+    where
+        F : Fn(usize) + 'static
+    {
+        self.on_source_clicked.bind(f);
+    }
+
+    fn connect_search(&self) {
+        let entry = &self.find_bar.entry;
+        self.find_action.connect_activate({
+            let find_bar = self.find_bar.clone();
+            move |_, _| {
+                find_bar.bar.set_search_mode(true);
+                find_bar.entry.grab_focus();
+            }
+        });
+        entry.connect_search_changed({
+            let viewer = self.clone();
+            move |entry| {
+                viewer.find(&entry.text());
+            }
+        });
+        entry.connect_activate({
+            let viewer = self.clone();
+            move |_| {
+                viewer.step_match(true);
+            }
+        });
+        entry.connect_next_match({
+            let viewer = self.clone();
+            move |_| {
+                viewer.step_match(true);
+            }
+        });
+        entry.connect_previous_match({
+            let viewer = self.clone();
+            move |_| {
+                viewer.step_match(false);
+            }
+        });
+        for (btn, forward) in [(&self.find_bar.next_btn, true), (&self.find_bar.prev_btn, false)] {
+            btn.connect_clicked({
+                let viewer = self.clone();
+                move |_| {
+                    viewer.step_match(forward);
+                }
+            });
+        }
+        self.find_bar.bar.connect_search_mode_enabled_notify({
+            let viewer = self.clone();
+            move |bar| {
+                if !bar.is_search_mode() {
+                    viewer.highlights.set_matches(Vec::new());
+                    viewer.find_bar.set_count(None, 0);
+                    viewer.queue_draw_pages();
+                }
+            }
+        });
+
+        self.copy_action.connect_activate({
+            let viewer = self.clone();
+            move |_, _| {
+                if let Some(text) = viewer.highlights.selected_text() {
+                    viewer.scroll.clipboard().set_text(&text);
+                }
+            }
+        });
+        let keys = EventControllerKey::new();
+        keys.connect_key_pressed({
+            let copy_action = self.copy_action.clone();
+            move |_, key, _, modifier| {
+                if key == gdk::Key::c && modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    copy_action.activate(None);
+                    return glib::signal::Inhibit(true);
+                }
+                glib::signal::Inhibit(false)
+            }
+        });
+        self.scroll.add_controller(&keys);
+    }
+
+    // Calls f with the text of the page, extracting it from the current document if required.
+    fn with_page_text<R>(&self, page_ix : usize, f : impl FnOnce(&PageText) -> R) -> Option<R> {
+        let mut texts = self.texts.borrow_mut();
+        if !texts.contains_key(&page_ix) {
+            let text = if let Some(frames) = &*self.frames.borrow() {
+                frames.pages.get(page_ix).map(PageText::from_frame)
+            } else if let Some(doc) = &*self.doc.borrow() {
+                doc.page(page_ix as i32).map(|page| PageText::from_poppler(&page) )
+            } else {
+                None
+            };
+            texts.insert(page_ix, text?);
+        }
+        texts.get(&page_ix).map(f)
+    }
+
+    /// Highlights all occurences of the text across pages, and shows the first one.
+    pub fn find(&self, query : &str) {
+        self.search(query, true);
+    }
+
+    // Searches the text, moving to the first match only when show_first is set.
+    fn search(&self, query : &str, show_first : bool) {
+        let mut matches = Vec::new();
+        if !query.is_empty() {
+            for page in 0..self.n_pages() {
+                if let Some(found) = self.with_page_text(page, |text| text.find(query) ) {
+                    matches.extend(found.into_iter().map(|rects| TextMatch { page, rects } ));
+                }
+            }
+        }
+        self.highlights.set_matches(matches);
+        if let Some((_, m)) = self.highlights.current().filter(|_| show_first ) {
+            self.show_match(&m);
+        }
+        self.find_bar.set_count(self.highlights.current().map(|(ix, _)| ix ), self.highlights.n_matches());
+        self.queue_draw_pages();
+    }
+
+    fn step_match(&self, forward : bool) {
+        if let Some((ix, m)) = self.highlights.step(forward) {
+            self.show_match(&m);
+            self.find_bar.set_count(Some(ix), self.highlights.n_matches());
+            self.queue_draw_pages();
+        }
+    }
+
+    // Moves to the page of the match, and scrolls until the match is visible.
+    fn show_match(&self, m : &TextMatch) {
+        if let Some(&(_, y, _, _)) = m.rects.first() {
+            self.show_position(m.page, y);
+        }
+    }
+
+    /// Moves to the page, and scrolls until the vertical position (in points) is visible.
+    pub fn show_position(&self, page : usize, y : f64) {
+        let z = self.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(1.0);
+        if self.view_mode() == ViewMode::Single {
+            go_to_page(
+                &self.pages,
+                &self.da1,
+                &self.da2,
+                &self.curr_page,
+                &self.turn_action,
+                &self.stack,
+                page as i32 + 1
+            );
+        }
+        let page_top = self.page_top(page);
+        let adj = self.scroll.vadjustment();
+        let target = page_top + y * z;
+        if target < adj.value() || target > adj.value() + adj.page_size() - PAGE_MARGIN {
+            adj.set_value(target - adj.page_size() / 3.0);
+        }
+    }
+
+    fn queue_draw_pages(&self) {
+        self.das.borrow().iter().for_each(|da| da.queue_draw() );
+        self.da1.queue_draw();
+        self.da2.queue_draw();
+        self.sidebar.queue_draw();
+    }
+
+    fn connect_modes(&self) {
+
+        // Pages are drawn again when their surface is ready.
+        self.renderer.connect_rendered({
+            let viewer = self.clone();
+            move |page| {
+                if viewer.view_mode() == ViewMode::Single {
+                    if *viewer.curr_page.borrow() == page {
+                        viewer.da1.queue_draw();
+                        viewer.da2.queue_draw();
+                    }
+                } else if let Some(da) = viewer.das.borrow().get(page) {
+                    da.queue_draw();
+                }
+                viewer.sidebar.queue_draw_page(page);
+            }
+        });
+
+        self.view_mode_action.connect_activate({
+            let viewer = self.clone();
+            move |action, param| {
+                let Some(name) = param.and_then(|p| p.get::<String>() ) else { return };
+                if ViewMode::from_name(&name).is_some() {
+                    action.set_state(&name.to_variant());
+                    viewer.layout();
+                }
+            }
+        });
+        self.frame_preview_action.connect_activate(|action, _| {
+            let active = action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false);
+            action.set_state(&(!active).to_variant());
+        });
+        self.fit_mode_action.connect_activate({
+            let viewer = self.clone();
+            move |action, param| {
+                let Some(name) = param.and_then(|p| p.get::<String>() ) else { return };
+                if FitMode::from_name(&name).is_some() {
+                    action.set_state(&name.to_variant());
+                    viewer.refit();
+                }
+            }
+        });
+
+        // The page size of the adjustments change when the viewer is resized.
+        for adj in [self.scroll.hadjustment(), self.scroll.vadjustment()] {
+            adj.connect_page_size_notify({
+                let viewer = self.clone();
+                move |_| {
+                    viewer.refit();
+                }
+            });
+        }
+
+        // Pages scrolled into view are only rendered now, and the page entry
+        // follows the page at the top of the viewer.
+        self.scroll.vadjustment().connect_value_changed({
+            let viewer = self.clone();
+            move |adj| {
+                if viewer.view_mode() == ViewMode::Single {
+                    return;
+                }
+                viewer.das.borrow().iter().for_each(|da| da.queue_draw() );
+                viewer.update_current_page(adj.value());
+            }
+        });
+    }
+
+    pub fn view_mode(&self) -> ViewMode {
+        self.view_mode_action.state()
+            .and_then(|s| s.get::<String>() )
+            .and_then(|name| ViewMode::from_name(&name) )
+            .unwrap_or(ViewMode::Single)
+    }
+
+    pub fn fit_mode(&self) -> FitMode {
+        self.fit_mode_action.state()
+            .and_then(|s| s.get::<String>() )
+            .and_then(|name| FitMode::from_name(&name) )
+            .unwrap_or(FitMode::Manual)
+    }
+
+    pub fn view_state(&self) -> ViewerState {
+        ViewerState {
+            mode : self.view_mode(),
+            fit : self.fit_mode(),
+            frame_preview : self.frame_preview_action.state().and_then(|s| s.get::<bool>() ).unwrap_or(false)
+        }
+    }
+
+    pub fn set_view_state(&self, state : &ViewerState) {
+        self.fit_mode_action.set_state(&state.fit.name().to_variant());
+        self.view_mode_action.set_state(&state.mode.name().to_variant());
+        self.frame_preview_action.set_state(&state.frame_preview.to_variant());
+        self.layout();
+    }
+
+    // Shows the pages of the current document according to the current view mode.
+    fn layout(&self) {
+        let mode = self.view_mode();
+        if mode == ViewMode::Single {
+            self.remove_page_areas();
+            self.scroll.set_child(Some(&self.stack));
+            let cp = *self.curr_page.borrow();
+            draw_at_even_or_odd(&self.stack, &self.da1, &self.da2, cp);
+        } else {
+            self.add_page_areas(mode);
+            self.scroll.set_child(Some(&self.pages_bx));
+
+            // Page positions are only known after the new areas are allocated.
+            let cp = *self.curr_page.borrow();
+            glib::idle_add_local_once({
+                let viewer = self.clone();
+                move || {
+                    viewer.scroll_to_page(cp);
+                }
+            });
+        }
+        self.refit();
+    }
+
+    fn remove_page_areas(&self) {
+        while let Some(child) = self.pages_bx.last_child() {
+            self.pages_bx.remove(&child);
+        }
+        self.das.borrow_mut().clear();
+    }
+
+    // Adds one drawing area per page, in rows of one or two pages. The page content
+    // is only rendered when the area becomes visible.
+    fn add_page_areas(&self, mode : ViewMode) {
+        self.remove_page_areas();
+        let pages = self.pages.borrow();
+        let mut das = self.das.borrow_mut();
+        let mut row = Box::new(Orientation::Horizontal, 0);
+        for (page_ix, size) in pages.iter().enumerate() {
+            if mode.starts_row(page_ix) {
+                row = Box::new(Orientation::Horizontal, 0);
+                row.set_halign(Align::Center);
+                self.pages_bx.append(&row);
+            }
+            let da = DrawingArea::new();
+            let is_last = page_ix == pages.len() - 1;
+            crate::draw_visible_page_at_area(*size, page_ix, is_last, &da, &self.zoom_action, &self.scroll, &self.renderer, &self.highlights);
+            self.connect_page_gestures(&da, Some(page_ix));
+            row.append(&da);
+            das.push(da);
+        }
+    }
+
+    pub fn scroll_to_page(&self, page_ix : usize) {
+        let das = self.das.borrow();
+        if let Some(bounds) = das.get(page_ix).and_then(|da| da.compute_bounds(&self.pages_bx) ) {
+            self.scroll.vadjustment().set_value(bounds.y() as f64);
+        }
+    }
+
+    fn scroll_by_rows(&self, up : bool) {
+        let step = self.view_mode().pages_per_row();
+        let cp = *self.curr_page.borrow();
+        let n_pages = self.das.borrow().len();
+        let next = if up { cp.saturating_sub(step) } else { (cp + step).min(n_pages.saturating_sub(1)) };
+        self.scroll_to_page(next);
+    }
+
+    // Sets the current page to the first page whose bottom is below the top of the viewer.
+    fn update_current_page(&self, top : f64) {
+        let Some(page_ix) = self.das.borrow().iter().position(|da| {
+            da.compute_bounds(&self.pages_bx)
+                .map(|b| (b.y() + b.height()) as f64 > top + PAGE_MARGIN )
+                .unwrap_or(false)
+        }) else {
+            return
+        };
+        let changed = {
+            let mut cp = self.curr_page.borrow_mut();
+            let changed = *cp != page_ix;
+            *cp = page_ix;
+            changed
+        };
+        if changed {
+            self.turn_action.set_state(&(page_ix as i32).to_variant());
+            self.turn_action.activate(None);
+        }
+    }
+
+    // Re-calculates the zoom for the fitting modes.
+    fn refit(&self) {
+        let fit = self.fit_mode();
+        if fit == FitMode::Manual {
+            return;
+        }
+        let Some((w, h)) = self.pages.borrow().first().copied() else {
+            return
+        };
+        let per_row = self.view_mode().pages_per_row() as f64;
+        let avail_w = self.scroll.width() as f64 - PAGE_MARGIN * (per_row + 1.0);
+        let avail_h = self.scroll.height() as f64 - PAGE_MARGIN * 2.0;
+        if avail_w <= 0.0 || avail_h <= 0.0 {
+            return;
+        }
+        let mut z = avail_w / (w * per_row);
+        if fit == FitMode::Page {
+            z = z.min(avail_h / h);
+        }
+        let z = z.max(MIN_FIT_ZOOM).min(MAX_FIT_ZOOM);
+
+        // Avoids re-fitting again due to small changes in the allocation the new zoom causes.
+        let curr_z = self.zoom_action.state().and_then(|s| s.get::<f64>() ).unwrap_or(z);
+        if (z - curr_z).abs() > 0.01 {
+            self.zoom_action.set_state(&z.to_variant());
+            self.zoom_action.activate(None);
+        }
+    }
+
+    /// Shows a newly typeset document. The PDF content is sent to the page renderer.
+    pub fn update(&self, doc : &poppler::Document, pdf : Vec<u8>, zoom_action : &gio::SimpleAction) {
+        let sizes : Vec<_> = (0..doc.n_pages()).filter_map(|ix| doc.page(ix) ).map(|p| p.size() ).collect();
+        self.compare.push_version(TypesetterTarget::PDFContent(pdf.clone()), sizes.clone());
+        self.renderer.load(pdf);
+        self.pages.replace(sizes);
+        self.frames.replace(None);
+        self.doc.replace(Some(doc.clone()));
+        self.show_pages();
+    }
+
+    /// Paints the first page of the last typeset document at the given height (in pixels), if
+    /// there is one. Uses whatever surface the renderer has cached for it.
+    pub fn thumbnail(&self, height : i32) -> Option<cairo::ImageSurface> {
+        let (w, h) = *self.pages.borrow().first()?;
+        let zoom = height as f64 / h;
+        let width = (w * zoom).round() as i32;
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).ok()?;
+        let ctx = cairo::Context::new(&surface).ok()?;
+        crate::draw_page_background(&ctx, width as f64, height as f64, false);
+        self.renderer.draw_page(&ctx, 0, zoom);
+        drop(ctx);
+        Some(surface)
+    }
+
+    /// Writes the thumbnail of the first page to a PNG file, returning whether it was written.
+    pub fn save_thumbnail(&self, height : i32, path : &Path) -> bool {
+        let Some(surface) = self.thumbnail(height) else { return false };
+        let (w, h) = (surface.width(), surface.height());
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        gdk::pixbuf_get_from_surface(&surface, 0, 0, w, h)
+            .map(|pxb| pxb.savev(path, "png", &[]).is_ok() )
+            .unwrap_or(false)
+    }
+
+    /// Shows a newly compiled typst document. Pages are rendered directly from
+    /// their frames, and pages that did not change keep their rendered surfaces.
+    pub fn update_frames(&self, preview : &FramePreview) {
+        let sizes : Vec<_> = preview.pages.iter().map(|p| p.size ).collect();
+        self.compare.push_version(TypesetterTarget::Frames(preview.clone()), sizes.clone());
+        self.renderer.load_frames(&preview.pages);
+        self.pages.replace(sizes);
+        self.doc.replace(None);
+        self.frames.replace(Some(preview.clone()));
+        self.show_pages();
+    }
+
+    /// Saves the current document as a PDF file.
+    pub fn save_pdf(&self, path : &Path) -> Result<(), String> {
+        if let Some(doc) = &*self.doc.borrow() {
+            return doc.save(&format!("file://{}", path.display()))
+                .map(|_| () )
+                .map_err(|e| e.to_string() );
+        }
+        if let Some(frames) = &*self.frames.borrow() {
+            return std::fs::write(path, typst::export::pdf(&frames.doc))
+                .map_err(|e| e.to_string() );
+        }
+        Err(String::from("No current document to export"))
+    }
+
+    // Shows the pages of a new document at the position the user was at the previous one.
+    fn show_pages(&self) {
+        let anchor = self.anchor.borrow_mut().take().unwrap_or_else(|| self.current_anchor() );
+        let (page, offset) = self.resolve_anchor(&anchor);
+        {
+            *(self.curr_page.borrow_mut()) = page;
+        }
+        self.turn_action.set_state(&(page as i32).to_variant());
+        self.turn_action.activate(None);
+        match self.view_mode() {
+            ViewMode::Single => {
+                self.da1.queue_draw();
+                self.da2.queue_draw();
+                draw_at_even_or_odd(&self.stack, &self.da1, &self.da2, page);
+            },
+            mode => {
+                self.add_page_areas(mode);
+            }
+        }
+        self.refit();
+        self.restore_position(page, offset);
+        self.sidebar.set_pages(&self.pages.borrow(), &self.renderer, &self.highlights);
+        self.sidebar.select_page(page);
+
+        // Matches are searched again at the new document.
+        self.texts.borrow_mut().clear();
+        self.highlights.clear();
+        if self.find_bar.bar.is_search_mode() {
+            self.search(&self.find_bar.entry.text(), false);
+        }
+    }
+
+}
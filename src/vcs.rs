@@ -0,0 +1,214 @@
+/*Copyright (c) 2022 Diego da Silva Lima. All rights reserved.
+
+This work is licensed under the terms of the GPL v3.0 License.
+For a copy, see http://www.gnu.org/licenses.*/
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::history::{self, Hunk};
+
+/*
+Git integration for the open document. All operations call the git executable on the
+local work tree containing the file, so no network access is required. The flatpak builds
+include git (see the git module of the manifests), since the GNOME runtime does not.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Unmodified,
+    Untracked,
+    Ignored,
+
+    // Changes at the work tree only.
+    Modified,
+
+    // Changes at the index only.
+    Staged,
+
+    // Changes at both the index and the work tree.
+    StagedModified,
+
+    Conflicted
+}
+
+impl FileStatus {
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            FileStatus::Unmodified => "No changes since the last commit",
+            FileStatus::Untracked => "Not tracked",
+            FileStatus::Ignored => "Ignored",
+            FileStatus::Modified => "Modified",
+            FileStatus::Staged => "Staged for commit",
+            FileStatus::StagedModified => "Staged, with further changes",
+            FileStatus::Conflicted => "Conflicted"
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub hash : String,
+    pub author : String,
+
+    // Seconds since the UNIX epoch.
+    pub time : i64,
+
+    pub summary : String
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    Added,
+    Modified,
+
+    // Lines were removed just before this line.
+    Removed
+}
+
+#[derive(Debug, Clone)]
+pub struct GitRepo {
+    pub root : PathBuf
+}
+
+// Tells apart a git command that failed from git not being available at all.
+enum GitError {
+    Unavailable(String),
+    Failed(String)
+}
+
+fn run_git(dir : &Path, args : &[&str]) -> Result<String, GitError> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| GitError::Unavailable(format!("Unable to run git ({}). Install git to track the changes of documents at git repositories.", e)) )?;
+    if out.status.success() {
+        Ok(String::from_utf8_lossy(&out.stdout).to_string())
+    } else {
+        Err(GitError::Failed(String::from_utf8_lossy(&out.stderr).trim().to_string()))
+    }
+}
+
+fn git(dir : &Path, args : &[&str]) -> Result<String, String> {
+    run_git(dir, args).map_err(|e| match e {
+        GitError::Unavailable(msg) | GitError::Failed(msg) => msg
+    })
+}
+
+impl GitRepo {
+
+    /// The repository whose work tree contains the file, if any. Fails when git cannot be run.
+    pub fn discover(file : &Path) -> Result<Option<Self>, String> {
+        let Some(dir) = file.parent() else { return Ok(None) };
+        match run_git(dir, &["rev-parse", "--show-toplevel"]) {
+            Ok(root) => Ok(Some(Self { root : PathBuf::from(root.trim()) })),
+            Err(GitError::Unavailable(e)) => Err(e),
+            Err(GitError::Failed(_)) => Ok(None)
+        }
+    }
+
+    fn relative(&self, file : &Path) -> Option<String> {
+        let file = file.canonicalize().ok()?;
+        let root = self.root.canonicalize().ok()?;
+        Some(file.strip_prefix(&root).ok()?.to_str()?.to_string())
+    }
+
+    pub fn branch(&self) -> Option<String> {
+        git(&self.root, &["rev-parse", "--abbrev-ref", "HEAD"]).ok().map(|b| b.trim().to_string() )
+    }
+
+    pub fn status(&self, file : &Path) -> Option<FileStatus> {
+        let rel = self.relative(file)?;
+        let out = git(&self.root, &["status", "--porcelain=v1", "--ignored", "--", &rel]).ok()?;
+        Some(parse_status(&out))
+    }
+
+    /// File content at the given revision (e.g. HEAD or a commit hash).
+    pub fn content_at(&self, file : &Path, rev : &str) -> Option<String> {
+        let rel = self.relative(file)?;
+        git(&self.root, &["show", &format!("{}:{}", rev, rel)]).ok()
+    }
+
+    /// Commits that changed the file, from the most recent to the oldest.
+    pub fn log(&self, file : &Path) -> Vec<Commit> {
+        let Some(rel) = self.relative(file) else { return Vec::new() };
+        git(&self.root, &["log", "--follow", "--format=%H%x1f%an%x1f%at%x1f%s", "--", &rel])
+            .map(|out| parse_log(&out) )
+            .unwrap_or_default()
+    }
+
+    pub fn stage(&self, file : &Path) -> Result<(), String> {
+        let rel = self.relative(file).ok_or(String::from("File outside repository"))?;
+        git(&self.root, &["add", "--", &rel]).map(|_| () )
+    }
+
+    /// Commits the current content of the file (and only this file).
+    pub fn commit(&self, file : &Path, msg : &str) -> Result<(), String> {
+        if msg.trim().is_empty() {
+            return Err(String::from("Empty commit message"));
+        }
+        self.stage(file)?;
+        let rel = self.relative(file).ok_or(String::from("File outside repository"))?;
+        git(&self.root, &["commit", "-m", msg, "--", &rel]).map(|_| () )
+    }
+
+}
+
+fn parse_status(porcelain : &str) -> FileStatus {
+    let Some(line) = porcelain.lines().next() else { return FileStatus::Unmodified };
+    let mut codes = line.chars();
+    let (x, y) = (codes.next().unwrap_or(' '), codes.next().unwrap_or(' '));
+    match (x, y) {
+        ('?', '?') => FileStatus::Untracked,
+        ('!', '!') => FileStatus::Ignored,
+        ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => FileStatus::Conflicted,
+        (' ', _) => FileStatus::Modified,
+        (_, ' ') => FileStatus::Staged,
+        _ => FileStatus::StagedModified
+    }
+}
+
+fn parse_log(out : &str) -> Vec<Commit> {
+    out.lines().filter_map(|line| {
+        let mut fields = line.split('\u{1f}');
+        Some(Commit {
+            hash : fields.next()?.to_string(),
+            author : fields.next()?.to_string(),
+            time : fields.next()?.parse().ok()?,
+            summary : fields.next().unwrap_or("").to_string()
+        })
+    }).collect()
+}
+
+/// Changes of each line of the current text relative to the committed text, as (zero-based line, change).
+pub fn line_changes(committed : &str, current : &str) -> Vec<(usize, LineChange)> {
+    let mut changes = Vec::new();
+    for Hunk { old_lines, new_start, new_lines, .. } in history::hunks(&history::diff_lines(committed, current)) {
+        if new_lines.is_empty() {
+            changes.push((new_start, LineChange::Removed));
+        } else {
+            let change = if old_lines.is_empty() { LineChange::Added } else { LineChange::Modified };
+            changes.extend((new_start..(new_start + new_lines.len())).map(|line| (line, change) ));
+        }
+    }
+    changes
+}
+
+#[test]
+fn git_output() {
+    assert_eq!(parse_status(""), FileStatus::Unmodified);
+    assert_eq!(parse_status("?? main.typ\n"), FileStatus::Untracked);
+    assert_eq!(parse_status(" M main.typ\n"), FileStatus::Modified);
+    assert_eq!(parse_status("M  main.typ\n"), FileStatus::Staged);
+    assert_eq!(parse_status("MM main.typ\n"), FileStatus::StagedModified);
+    assert_eq!(parse_status("UU main.typ\n"), FileStatus::Conflicted);
+    let log = parse_log("abc123\u{1f}Alice\u{1f}1700000000\u{1f}Add methods section\n");
+    assert_eq!(log, vec![Commit { hash : "abc123".into(), author : "Alice".into(), time : 1700000000, summary : "Add methods section".into() }]);
+    let changes = line_changes("a\nb\nc\nd", "a\nB\nc\nnew\n");
+    assert_eq!(changes, vec![(1, LineChange::Modified), (3, LineChange::Modified)]);
+    assert_eq!(line_changes("a\nb", "a\nb\nc"), vec![(2, LineChange::Added)]);
+    assert_eq!(line_changes("a\nb\nc", "a\nc"), vec![(1, LineChange::Removed)]);
+}